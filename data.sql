@@ -1,11 +1,11 @@
 INSERT INTO
-    `updated_info`
+    `updated_info`(`id`, `name`, `version`, `source`, `url`, `create_time`, `updated_time`)
 VALUES
     (
         1,
         'btm',
         'v0.6.0',
-        '{"github":{"owner":"ClementTsang","repo":"bottom"}}',
+        'github:ClementTsang/bottom',
         'https://github.com/ClementTsang/bottom/releases/download/0.6.8/bottom_x86_64-unknown-linux-gnu.tar.gz',
         '2020-06-17 20:10:23',
         '2020-06-17 20:10:23'
@@ -14,7 +14,7 @@ VALUES
         2,
         'tldr',
         'v0.2.0',
-        '{"github":{"owner":"dbrgn","repo":"tealdeer"}}',
+        'github:dbrgn/tealdeer',
         'https://github.com/dbrgn/tealdeer/releases/download/v1.5.0/tealdeer-linux-x86_64-musl',
         '2020-06-17 20:10:23',
         '2020-07-17 21:10:23'
@@ -23,7 +23,7 @@ VALUES
         3,
         'btm',
         'v0.7.0',
-        '{"github":{"owner":"ClementTsang","repo":"bottom"}}',
+        'github:ClementTsang/bottom',
         'https://github.com/ClementTsang/bottom/releases/download/0.7.0/bottom_x86_64-unknown-linux-gnu.tar.gz',
         '2021-06-17 20:10:23',
         '2021-06-17 20:10:23'
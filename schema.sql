@@ -1,3 +1,5 @@
+-- the latest schema used by tests. dbs are created and upgraded by src/migration.rs
+
 DROP TABLE IF EXISTS updated_info;
 
 CREATE TABLE IF NOT EXISTS `updated_info` (
@@ -9,5 +11,138 @@ CREATE TABLE IF NOT EXISTS `updated_info` (
     `url` varchar(1024) NOT NULL,
     `create_time` datetime NOT NULL,
     `updated_time` datetime NOT NULL,
+    -- the version rolled back from
+    `rollback_from` VARCHAR(20),
     UNIQUE(`name`, `version`)
-);
\ No newline at end of file
+);
+
+DROP TABLE IF EXISTS pinned_info;
+
+CREATE TABLE IF NOT EXISTS `pinned_info` (
+    `name` VARCHAR(20) PRIMARY KEY NOT NULL,
+    `version` VARCHAR(20) NOT NULL,
+    `create_time` datetime NOT NULL
+);
+
+DROP TABLE IF EXISTS installed_file;
+
+CREATE TABLE IF NOT EXISTS `installed_file` (
+    `id` INTEGER PRIMARY KEY AUTOINCREMENT,
+    `name` VARCHAR(20) NOT NULL,
+    `version` VARCHAR(20) NOT NULL,
+    -- the path relative to the version dir
+    `path` varchar(1024) NOT NULL,
+    -- md5 digest of the file
+    `digest` VARCHAR(64) NOT NULL,
+    UNIQUE(`name`, `version`, `path`)
+);
+
+DROP TABLE IF EXISTS install_stat;
+
+CREATE TABLE IF NOT EXISTS `install_stat` (
+    `id` INTEGER PRIMARY KEY AUTOINCREMENT,
+    `name` VARCHAR(20) NOT NULL,
+    `version` VARCHAR(20) NOT NULL,
+    -- one of install, update, rollback and repair
+    `action` VARCHAR(20) NOT NULL,
+    -- the filename of the downloaded asset
+    `asset` varchar(1024) NOT NULL,
+    `duration_ms` INTEGER NOT NULL,
+    -- 0 if the cached asset is used
+    `downloaded_bytes` INTEGER NOT NULL,
+    `success` BOOLEAN NOT NULL,
+    `error` TEXT,
+    `create_time` datetime NOT NULL
+);
+
+DROP TABLE IF EXISTS disabled_info;
+
+-- the bins disabled by `disable` command. skipped by install, update and sync
+CREATE TABLE IF NOT EXISTS `disabled_info` (
+    `name` VARCHAR(20) PRIMARY KEY NOT NULL,
+    `create_time` datetime NOT NULL
+);
+
+DROP TABLE IF EXISTS asset_pattern;
+
+-- the regex of the asset chosen for a bin, reused by later installs and updates
+CREATE TABLE IF NOT EXISTS `asset_pattern` (
+    `name` VARCHAR(20) PRIMARY KEY NOT NULL,
+    `pattern` varchar(1024) NOT NULL,
+    `create_time` datetime NOT NULL
+);
+
+DROP TABLE IF EXISTS link_conflict;
+
+-- the existing files at link paths replaced by `install --force` or adopted by `install --adopt`
+CREATE TABLE IF NOT EXISTS `link_conflict` (
+    -- the link path of the existing file
+    `path` varchar(1024) PRIMARY KEY NOT NULL,
+    `name` VARCHAR(20) NOT NULL,
+    -- one of replace and adopt
+    `action` VARCHAR(20) NOT NULL,
+    -- where the adopted file is backed up
+    `backup` varchar(1024),
+    `create_time` datetime NOT NULL
+);
+
+DROP TABLE IF EXISTS installed_artifact;
+
+-- the files created outside the data dir like links and completions. removed by uninstall
+CREATE TABLE IF NOT EXISTS `installed_artifact` (
+    `path` varchar(1024) PRIMARY KEY NOT NULL,
+    `name` VARCHAR(20) NOT NULL,
+    `create_time` datetime NOT NULL
+);
+
+DROP TABLE IF EXISTS cache_access;
+
+-- the last time a downloaded file in the cache dir is used. evict the oldest if over max-cache-size
+CREATE TABLE IF NOT EXISTS `cache_access` (
+    `path` varchar(1024) PRIMARY KEY NOT NULL,
+    `name` VARCHAR(20) NOT NULL,
+    `access_time` datetime NOT NULL
+);
+
+DROP TABLE IF EXISTS asset_digest;
+
+-- the digest of the asset on the first install of a version. kept after uninstall
+CREATE TABLE IF NOT EXISTS `asset_digest` (
+    `name` VARCHAR(20) NOT NULL,
+    `version` VARCHAR(20) NOT NULL,
+    `digest` VARCHAR(256) NOT NULL,
+    `create_time` datetime NOT NULL,
+    PRIMARY KEY(`name`, `version`)
+);
+
+DROP TABLE IF EXISTS trusted_config;
+
+-- the configs outside the config dir whose hooks are approved by `binaries trust`
+CREATE TABLE IF NOT EXISTS `trusted_config` (
+    `path` varchar(1024) PRIMARY KEY NOT NULL,
+    -- sha256 of the hooks in the config
+    `digest` VARCHAR(64) NOT NULL,
+    `create_time` datetime NOT NULL
+);
+
+DROP TABLE IF EXISTS external_binary;
+
+-- the executables provided by the os and reused instead of downloading
+CREATE TABLE IF NOT EXISTS `external_binary` (
+    `name` VARCHAR(20) PRIMARY KEY NOT NULL,
+    `path` varchar(1024) NOT NULL,
+    `version` VARCHAR(20) NOT NULL,
+    -- one of apt, pacman, rpm, brew and path
+    `provider` VARCHAR(20) NOT NULL,
+    `create_time` datetime NOT NULL
+);
+
+DROP TABLE IF EXISTS skipped_version;
+
+-- the versions recorded by `binaries skip` to never install
+CREATE TABLE IF NOT EXISTS `skipped_version` (
+    `name` VARCHAR(20) NOT NULL,
+    `version` VARCHAR(20) NOT NULL,
+    `create_time` datetime NOT NULL,
+    PRIMARY KEY(`name`, `version`)
+);
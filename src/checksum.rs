@@ -0,0 +1,153 @@
+//! 发布的checksums文件的解析
+//!
+//! 支持BSD的`SHA256 (file) = hash`，GNU的`hash  file`与只有hash的单独文件如`<asset>.sha256`
+
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use md5::{Digest, Md5};
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+
+/// 在checksums文件content中查找file的digest，格式为`<algo>:<hex>`
+///
+/// single为只属于file的单独文件时，只有hash没有文件名的行也匹配
+pub fn find_digest(content: &str, file: &str, single: bool) -> Option<String> {
+    content.lines().find_map(|line| {
+        let (name, algo, hex) = parse_line(line)?;
+        let is_match = match name {
+            // 清单中可能是`./file`或`dist/file`这样的路径
+            Some(name) => name.rsplit(['/', '\\']).next() == Some(file),
+            None => single,
+        };
+        is_match.then(|| format!("{}:{}", algo, hex.to_lowercase()))
+    })
+}
+
+/// 解析一行为文件名，算法与hex
+fn parse_line(line: &str) -> Option<(Option<&str>, &'static str, &str)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    // BSD: `SHA256 (file) = hash`
+    if let Some((left, hex)) = line.rsplit_once(" = ") {
+        let (tag, name) = left.split_once(" (")?;
+        let algo = match tag.trim().to_lowercase().replace('-', "").as_str() {
+            "md5" => "md5",
+            "sha1" => "sha1",
+            "sha256" => "sha256",
+            "sha512" => "sha512",
+            _ => return None,
+        };
+        let hex = hex.trim();
+        return (algo_of(hex)? == algo).then_some((Some(name.strip_suffix(')')?), algo, hex));
+    }
+    // GNU: `hash  file`，二进制模式为`hash *file`
+    let (hex, name) = match line.split_once(char::is_whitespace) {
+        Some((hex, name)) => (hex, Some(name.trim_start().trim_start_matches('*'))),
+        None => (line, None),
+    };
+    Some((name, algo_of(hex)?, hex))
+}
+
+/// 按hex的长度判断算法
+fn algo_of(hex: &str) -> Option<&'static str> {
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    match hex.len() {
+        32 => Some("md5"),
+        40 => Some("sha1"),
+        64 => Some("sha256"),
+        128 => Some("sha512"),
+        _ => None,
+    }
+}
+
+/// 是否支持计算算法algo的digest
+pub fn is_supported(algo: &str) -> bool {
+    matches!(algo, "md5" | "sha1" | "sha256" | "sha512")
+}
+
+/// 计算path文件算法algo的digest，为小写的hex字符串
+pub fn digest_file(path: impl AsRef<Path>, algo: &str) -> Result<String> {
+    fn hex<D: Digest + std::io::Write>(path: &Path) -> Result<String> {
+        let mut hasher = D::new();
+        std::io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+        Ok(hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect())
+    }
+    let path = path.as_ref();
+    match algo {
+        "md5" => hex::<Md5>(path),
+        "sha1" => hex::<Sha1>(path),
+        "sha256" => hex::<Sha256>(path),
+        "sha512" => hex::<Sha512>(path),
+        _ => bail!("unsupported digest algorithm {}", algo),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA256: &str = "b1d9b8c2f2f1a4e8d2a1d3c1e0f5b6a7c8d9e0f1a2b3c4d5e6f708192a3b4c5d";
+
+    #[test]
+    fn test_find_digest() {
+        let gnu = format!(
+            "# comment\n{}  a.tar.gz\n{} *dist/b.zip\n",
+            "0".repeat(64),
+            SHA256.to_uppercase()
+        );
+        assert_eq!(
+            find_digest(&gnu, "b.zip", false),
+            Some(format!("sha256:{}", SHA256))
+        );
+        assert_eq!(find_digest(&gnu, "c.zip", false), None);
+
+        let bsd = format!(
+            "SHA256 (a.tar.gz) = {}\nSHA512 (b.zip) = {}",
+            SHA256,
+            "f".repeat(128)
+        );
+        assert_eq!(
+            find_digest(&bsd, "a.tar.gz", false),
+            Some(format!("sha256:{}", SHA256))
+        );
+        assert_eq!(
+            find_digest(&bsd, "b.zip", false),
+            Some(format!("sha512:{}", "f".repeat(128)))
+        );
+        // 算法与hex的长度不一致
+        assert_eq!(
+            find_digest(&format!("SHA1 (a) = {}", SHA256), "a", false),
+            None
+        );
+
+        let single = format!("{}\n", SHA256);
+        assert_eq!(
+            find_digest(&single, "a.tar.gz", true),
+            Some(format!("sha256:{}", SHA256))
+        );
+        assert_eq!(find_digest(&single, "a.tar.gz", false), None);
+        assert_eq!(find_digest("not a hash", "a.tar.gz", true), None);
+    }
+
+    #[test]
+    fn test_digest_file() -> Result<()> {
+        let path = "tests/a.tar.gz";
+        assert_eq!(
+            digest_file(path, "sha256")?,
+            "2b29a500c59f6be45e907655f71c8ae978d250bb16404b81c9d5293a7a001639"
+        );
+        assert_eq!(digest_file(path, "md5")?.len(), 32);
+        assert_eq!(digest_file(path, "sha512")?.len(), 128);
+        assert!(digest_file(path, "crc32").is_err());
+        Ok(())
+    }
+}
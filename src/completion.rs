@@ -0,0 +1,401 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use clap::{Arg, ArgEnum, Command};
+use indexmap::IndexSet;
+
+/// 支持补全的shell
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ArgEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    Powershell,
+}
+
+/// 补全bin名称的子命令中列出配置中bin名称的命令参数
+pub const BINS_ARG: &str = "completions --bins";
+
+/// 一个子命令的补全候选
+#[derive(Debug)]
+struct Spec {
+    name: String,
+    about: String,
+    /// the flags and nested subcommands
+    words: Vec<String>,
+    /// complete the configured bin names for the positional args
+    bin_names: bool,
+    /// the options whose values are bin names like `--names`
+    name_opts: Vec<String>,
+}
+
+/// 生成cmd在shell中的补全脚本，参数为bin名称的子命令会调用[BINS_ARG]动态补全
+pub fn generate(cmd: &Command, shell: Shell) -> String {
+    let name = cmd.get_name();
+    let globals = cmd
+        .get_arguments()
+        .filter(|a| !a.is_hide_set() && !a.is_positional())
+        .collect::<Vec<_>>();
+    let global_flags = globals
+        .iter()
+        .flat_map(|a| flags_of(a))
+        .collect::<IndexSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    // the global options whose values are not subcommands
+    let value_opts = globals
+        .iter()
+        .filter(|a| a.is_takes_value_set())
+        .flat_map(|a| flags_of(a))
+        .collect::<Vec<_>>();
+    let specs = cmd
+        .get_subcommands()
+        .filter(|c| !c.is_hide_set())
+        .map(|c| Spec {
+            name: c.get_name().to_owned(),
+            about: c.get_about().unwrap_or_default().replace('\n', " "),
+            words: c
+                .get_subcommands()
+                .map(|c| c.get_name().to_owned())
+                .chain(
+                    c.get_arguments()
+                        .filter(|a| !a.is_hide_set() && !a.is_positional())
+                        .flat_map(flags_of),
+                )
+                .chain(global_flags.iter().cloned())
+                .collect::<IndexSet<_>>()
+                .into_iter()
+                .collect(),
+            bin_names: c
+                .get_arguments()
+                .any(|a| a.is_positional() && is_bin_name(a)),
+            name_opts: c
+                .get_arguments()
+                .filter(|a| !a.is_positional() && is_bin_name(a))
+                .flat_map(flags_of)
+                .collect(),
+        })
+        .collect::<Vec<_>>();
+
+    match shell {
+        Shell::Bash => bash(name, &specs, &global_flags, &value_opts),
+        Shell::Zsh => format!(
+            "#compdef {name}\n\nautoload -U +X bashcompinit && bashcompinit\n\n{}",
+            bash(name, &specs, &global_flags, &value_opts),
+            name = name
+        ),
+        Shell::Fish => fish(name, &specs),
+        Shell::Powershell => powershell(name, &specs, &global_flags, &value_opts),
+    }
+}
+
+fn is_bin_name(arg: &Arg) -> bool {
+    ["name", "names"].contains(&arg.get_id())
+}
+
+fn flags_of(arg: &Arg) -> Vec<String> {
+    arg.get_short()
+        .map(|c| format!("-{}", c))
+        .into_iter()
+        .chain(arg.get_long().map(|l| format!("--{}", l)))
+        .collect()
+}
+
+fn bash(name: &str, specs: &[Spec], global_flags: &[String], value_opts: &[String]) -> String {
+    let func = format!("_{}", name.replace('-', "_"));
+    let mut cases = String::new();
+    for spec in specs {
+        let words = spec.words.join(" ");
+        let mut conds = vec![];
+        if !spec.name_opts.is_empty() {
+            conds.push(format!(
+                r#"[[ " {} " == *" $prev "* ]]"#,
+                spec.name_opts.join(" ")
+            ));
+        }
+        if spec.bin_names {
+            conds.push(r#"[[ "$cur" != -* ]]"#.to_owned());
+        }
+        let reply = if conds.is_empty() {
+            format!(r#"COMPREPLY=($(compgen -W "{}" -- "$cur"))"#, words)
+        } else {
+            format!(
+                r#"if {conds}; then
+                COMPREPLY=($(compgen -W "$({name} {bins} 2>/dev/null)" -- "$cur"))
+            else
+                COMPREPLY=($(compgen -W "{words}" -- "$cur"))
+            fi"#,
+                conds = conds.join(" || "),
+                words = words,
+                name = name,
+                bins = BINS_ARG
+            )
+        };
+        cases.push_str(&format!(
+            "        {})\n            {}\n            ;;\n",
+            spec.name, reply
+        ));
+    }
+    let subcommands = specs.iter().map(|s| s.name.as_str()).collect::<Vec<_>>();
+    format!(
+        r#"{func}() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}" prev="${{COMP_WORDS[COMP_CWORD-1]}}" cmd="" i
+    for ((i = 1; i < COMP_CWORD; i++)); do
+        case "${{COMP_WORDS[i]}}" in
+            {value_opts}) ((i++)) ;;
+            -*) ;;
+            *) cmd="${{COMP_WORDS[i]}}"; break ;;
+        esac
+    done
+    case "$cmd" in
+        "")
+            COMPREPLY=($(compgen -W "{subcommands} {global_flags}" -- "$cur"))
+            ;;
+{cases}    esac
+}}
+
+complete -o default -F {func} {name}
+"#,
+        func = func,
+        name = name,
+        value_opts = if value_opts.is_empty() {
+            "''".to_owned()
+        } else {
+            value_opts.join("|")
+        },
+        subcommands = subcommands.join(" "),
+        global_flags = global_flags.join(" "),
+        cases = cases,
+    )
+}
+
+fn fish(name: &str, specs: &[Spec]) -> String {
+    let quote = |s: &str| format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"));
+    let mut out = String::new();
+    for spec in specs {
+        out.push_str(&format!(
+            "complete -c {} -n __fish_use_subcommand -f -a {} -d {}\n",
+            name,
+            spec.name,
+            quote(&spec.about)
+        ));
+    }
+    for spec in specs {
+        let cond = quote(&format!("__fish_seen_subcommand_from {}", spec.name));
+        let bins = quote(&format!("({} {} 2>/dev/null)", name, BINS_ARG));
+        for word in &spec.words {
+            let mut opt = match word.strip_prefix("--") {
+                Some(long) => format!("-l {}", long),
+                None => match word.strip_prefix('-') {
+                    Some(short) => format!("-s {}", short),
+                    None => format!("-f -a {}", word),
+                },
+            };
+            if spec.name_opts.contains(word) {
+                opt.push_str(&format!(" -r -f -a {}", bins));
+            }
+            out.push_str(&format!("complete -c {} -n {} {}\n", name, cond, opt));
+        }
+        if spec.bin_names {
+            out.push_str(&format!(
+                "complete -c {} -n {} -f -a {}\n",
+                name, cond, bins
+            ));
+        }
+    }
+    out
+}
+
+fn powershell(
+    name: &str,
+    specs: &[Spec],
+    global_flags: &[String],
+    value_opts: &[String],
+) -> String {
+    let list = |words: &[String]| {
+        words
+            .iter()
+            .map(|w| format!("'{}'", w))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let mut cases = String::new();
+    for spec in specs {
+        let bins = format!("@(& {} {} 2>$null)", name, BINS_ARG);
+        let words = format!("@({})", list(&spec.words));
+        let candidates = match (spec.bin_names, spec.name_opts.is_empty()) {
+            (false, true) => words,
+            (true, true) => format!("{} + {}", words, bins),
+            (bin_names, false) => format!(
+                "if (@({}) -contains $prev) {{ {} }} else {{ {} }}",
+                list(&spec.name_opts),
+                bins,
+                if bin_names {
+                    format!("{} + {}", words, bins)
+                } else {
+                    words
+                }
+            ),
+        };
+        cases.push_str(&format!("        '{}' {{ {} }}\n", spec.name, candidates));
+    }
+    let subcommands = specs.iter().map(|s| s.name.clone()).collect::<Vec<_>>();
+    format!(
+        r#"Register-ArgumentCompleter -Native -CommandName '{name}' -ScriptBlock {{
+    param($wordToComplete, $commandAst, $cursorPosition)
+    $valueOpts = @({value_opts})
+    $cmd = ''
+    $elements = @($commandAst.CommandElements | Select-Object -Skip 1 | ForEach-Object {{ $_.ToString() }})
+    $prev = ''
+    foreach ($word in $elements) {{
+        if ($word -eq $wordToComplete) {{ break }}
+        $prev = $word
+    }}
+    for ($i = 0; $i -lt $elements.Count; $i++) {{
+        $word = $elements[$i]
+        if ($word -eq $wordToComplete) {{ break }}
+        if ($valueOpts -contains $word) {{ $i++; continue }}
+        if (-not $word.StartsWith('-')) {{ $cmd = $word; break }}
+    }}
+    $candidates = switch ($cmd) {{
+        '' {{ @({subcommands}, {global_flags}) }}
+{cases}    }}
+    $candidates | Where-Object {{ $_ -like "$wordToComplete*" }} | ForEach-Object {{
+        [System.Management.Automation.CompletionResult]::new($_, $_, 'ParameterValue', $_)
+    }}
+}}
+"#,
+        name = name,
+        value_opts = list(value_opts),
+        subcommands = list(&subcommands),
+        global_flags = list(global_flags),
+        cases = cases,
+    )
+}
+
+/// 在后台检查更新的配置
+#[derive(Debug)]
+pub struct UpdateCheck {
+    /// 调用检查的命令，如`binaries -f config.toml`
+    pub cmd: String,
+    /// 保存检查结果的文件，其修改时间作为上次检查的时间
+    pub stamp: PathBuf,
+    /// 两次检查的最小间隔分钟
+    pub interval_mins: u64,
+}
+
+/// 生成在shell rc中eval的初始化脚本：将bin_dir加入PATH，加载completion_dir中的补全，
+/// 可选在后台按间隔检查更新并在下次启动时提示可用的更新
+pub fn init_snippet(
+    shell: Shell,
+    bin_dir: &Path,
+    completion_dir: &Path,
+    check: Option<&UpdateCheck>,
+) -> Result<String> {
+    let (bin_dir, completion_dir) = (bin_dir.display(), completion_dir.display());
+    // the summary line of `update --dry-run` lists the updatable binaries
+    let filter = r#"sed -n 's/^  updated: /updates available: /p'"#;
+    let snippet = match shell {
+        Shell::Bash | Shell::Zsh => {
+            let mut out = format!(
+                r#"case ":$PATH:" in
+    *":{bin_dir}:"*) ;;
+    *) export PATH="{bin_dir}:$PATH" ;;
+esac
+"#,
+                bin_dir = bin_dir
+            );
+            if shell == Shell::Zsh {
+                out.push_str(&format!(
+                    r#"fpath=("{dir}/zsh" $fpath)
+for f in "{dir}/source"/*(N); do
+    source "$f"
+done
+"#,
+                    dir = completion_dir
+                ));
+            } else {
+                out.push_str(&format!(
+                    r#"for f in "{dir}/source"/*; do
+    [ -f "$f" ] && source "$f"
+done
+"#,
+                    dir = completion_dir
+                ));
+            }
+            if let Some(check) = check {
+                out.push_str(&format!(
+                    r#"[ -s "{stamp}" ] && cat "{stamp}"
+if [ -z "$(find "{stamp}" -mmin -{mins} 2>/dev/null)" ]; then
+    mkdir -p "{dir}"
+    ({cmd} --dry-run --non-interactive update --no-changelog 2>/dev/null | {filter} > "{stamp}" &)
+fi
+"#,
+                    stamp = check.stamp.display(),
+                    dir = check.stamp.parent().unwrap_or(&check.stamp).display(),
+                    mins = check.interval_mins,
+                    cmd = check.cmd,
+                    filter = filter
+                ));
+            }
+            out
+        }
+        Shell::Fish => {
+            let mut out = format!(
+                "contains -- \"{bin_dir}\" $PATH; or set -gx PATH \"{bin_dir}\" $PATH\n",
+                bin_dir = bin_dir
+            );
+            if let Some(check) = check {
+                out.push_str(&format!(
+                    r#"test -s "{stamp}"; and cat "{stamp}"
+if test (count (find "{stamp}" -mmin -{mins} 2>/dev/null)) -eq 0
+    mkdir -p "{dir}"
+    {cmd} --dry-run --non-interactive update --no-changelog 2>/dev/null | {filter} > "{stamp}" &
+    disown
+end
+"#,
+                    stamp = check.stamp.display(),
+                    dir = check.stamp.parent().unwrap_or(&check.stamp).display(),
+                    mins = check.interval_mins,
+                    cmd = check.cmd,
+                    filter = filter
+                ));
+            }
+            out
+        }
+        Shell::Powershell => {
+            let mut out = format!(
+                r#"if (($env:Path -split ';') -notcontains '{bin_dir}') {{
+    $env:Path = '{bin_dir};' + $env:Path
+}}
+Get-ChildItem '{dir}/source' -Filter *.ps1 -ErrorAction SilentlyContinue | ForEach-Object {{ . $_.FullName }}
+"#,
+                bin_dir = bin_dir,
+                dir = completion_dir
+            );
+            if let Some(check) = check {
+                out.push_str(&format!(
+                    r#"if (Test-Path '{stamp}') {{ Get-Content '{stamp}' }}
+$stamp = Get-Item '{stamp}' -ErrorAction SilentlyContinue
+if (-not $stamp -or $stamp.LastWriteTime -lt (Get-Date).AddMinutes(-{mins})) {{
+    New-Item -ItemType Directory -Force '{dir}' | Out-Null
+    Start-Job {{
+        {cmd} --dry-run --non-interactive update --no-changelog 2>$null |
+            Where-Object {{ $_ -like '  updated: *' }} |
+            ForEach-Object {{ $_ -replace '^  updated: ', 'updates available: ' }} |
+            Set-Content '{stamp}'
+    }} | Out-Null
+}}
+Remove-Variable stamp
+"#,
+                    stamp = check.stamp.display(),
+                    dir = check.stamp.parent().unwrap_or(&check.stamp).display(),
+                    mins = check.interval_mins,
+                    cmd = check.cmd,
+                ));
+            }
+            out
+        }
+    };
+    Ok(snippet)
+}
@@ -172,9 +172,11 @@ pub struct Exe {
     path: PathBuf,
 }
 
-#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+/// 链接执行文件到exe dir的方式
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "kebab-case")]
 pub enum ExeType {
+    /// 生成一个执行data dir中执行文件的shim script
     Shim {
         #[serde(default = "defaults::default_exe_type_template")]
         template: Template,
@@ -208,9 +210,13 @@ mod defaults {
         // "{{data.bin.name}}".into()
         todo!()
     }
+    /// windows上为`.cmd`的shim
     pub fn default_exe_type_template() -> Template {
+        if cfg!(windows) {
+            return "@\"{{{exe_path}}}\" %*\r\n".into();
+        }
         r#"#!/usr/bin/env sh
-"{{bins.exe_dir}}/{{name}}" "$@"
+exec "{{{exe_path}}}" "$@"
 "#
         .into()
     }
@@ -259,10 +265,14 @@ enum HookOn {
     Check,
 }
 
-#[derive(Debug, PartialEq, Eq, Getters, Serialize, Deserialize)]
+/// 从下载文件中选择completion文件的globs
+#[derive(Debug, Clone, Default, PartialEq, Eq, Getters, Serialize, Deserialize)]
+#[getset(get = "pub")]
 #[serde(rename_all = "kebab-case")]
-struct Completion {
+pub struct Completion {
+    /// 添加到zsh fpath的completion文件
     fpath: Option<Vec<String>>,
+    /// 需要在shell中source的completion文件
     source: Option<Vec<String>>,
 }
 
@@ -0,0 +1,88 @@
+use std::{
+    fs::{read_to_string, write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use derive_builder::Builder;
+use getset::Getters;
+use indexmap::IndexMap;
+use log::{debug, trace};
+use serde::{Deserialize, Serialize};
+
+/// 配置文件所在目录中的lock文件名
+pub const LOCK_FILE_NAME: &str = "binaries.lock";
+
+/// 记录每个bin解析后的版本，asset url与digest，用于在其它机器上重现相同的安装
+#[derive(Debug, Default, Clone, PartialEq, Eq, Getters, Serialize, Deserialize)]
+#[getset(get = "pub")]
+pub struct LockFile {
+    #[serde(default)]
+    bins: IndexMap<String, LockedBinary>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Getters, Builder, Serialize, Deserialize)]
+#[getset(get = "pub")]
+#[builder(setter(into))]
+pub struct LockedBinary {
+    version: String,
+    url: String,
+    /// the digest of the downloaded asset like `sha256:<hex>`. the legacy lock files have a
+    /// md5 digest without the algorithm
+    digest: String,
+}
+
+impl LockFile {
+    pub fn get(&self, name: &str) -> Option<&LockedBinary> {
+        self.bins.get(name)
+    }
+
+    pub fn insert(&mut self, name: impl Into<String>, bin: LockedBinary) {
+        self.bins.insert(name.into(), bin);
+    }
+
+    pub fn write_to(&self, path: impl AsRef<Path>) -> Result<()> {
+        let contents = toml::to_string_pretty(self)?;
+        trace!("writing lock file content: {}", contents);
+        write(path, contents).map_err(Into::into)
+    }
+}
+
+/// 获取配置文件config_path同级目录中的lock文件路径
+pub fn lock_path(config_path: impl AsRef<Path>) -> PathBuf {
+    config_path.as_ref().with_file_name(LOCK_FILE_NAME)
+}
+
+pub fn from_path(path: impl AsRef<Path>) -> Result<LockFile> {
+    debug!("loading lock file from {}", path.as_ref().display());
+    let contents = read_to_string(path)?;
+    toml::from_str(&contents).map_err(Into::into)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_write_and_load() -> Result<()> {
+        let mut lock = LockFile::default();
+        lock.insert(
+            "clash",
+            LockedBinaryBuilder::default()
+                .version("v1.10.0")
+                .url("https://github.com/Dreamacro/clash/releases/download/v1.10.0/clash-linux-amd64-v1.10.0.gz")
+                .digest("sha256:0b5ba1e5c1e0a4e0a0cd4f7c4ac7ee2b3c44eb2b4f3f8e2bbd5bb57c3e7d9c3f")
+                .build()?,
+        );
+
+        let dir = tempdir()?;
+        let path = lock_path(dir.path().join("config.toml"));
+        assert_eq!(path, dir.path().join(LOCK_FILE_NAME));
+
+        lock.write_to(&path)?;
+        assert_eq!(from_path(&path)?, lock);
+        Ok(())
+    }
+}
@@ -1,27 +1,238 @@
-use std::{fs::read_to_string, path::Path, str::FromStr};
+use std::{
+    collections::HashMap,
+    env::consts::{ARCH, OS},
+    fmt,
+    fs::read_to_string,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
 
-use anyhow::{bail, Error, Result};
+use anyhow::{anyhow, bail, Context, Error, Result};
 use derive_builder::Builder;
+use gethostname::gethostname;
 use getset::{Getters, Setters};
+use globset::GlobBuilder;
+use indexmap::IndexMap;
 use log::{debug, trace};
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize, Serializer};
+use walkdir::WalkDir;
 
-use self::raw::RawConfig;
+use self::raw::{RawBinary, RawConfig, RawDefault, SourceKind};
+use crate::util::{expand_env, parse_duration, parse_size};
 
 mod file;
 mod c;
+pub mod lock;
 pub mod raw;
+pub mod validate;
 
-#[derive(Debug, Getters, Setters, Clone, Builder)]
+pub use self::file::{Completion, ExeType, Template};
+
+/// 连接服务器的默认超时
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 等待响应或下载的下一块数据的默认超时。下载没有总时长限制
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// 每个host保留的默认最大空闲连接数
+pub const DEFAULT_POOL_MAX_IDLE: usize = 8;
+
+/// 空闲连接保留复用的默认时间
+pub const DEFAULT_POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// `config init`写入的带注释的初始配置
+pub const STARTER: &str = include_str!("starter.toml");
+
+/// 日志文件滚动前的默认最大字节数
+pub const DEFAULT_MAX_LOG_SIZE: u64 = 10 * 1024 * 1024;
+
+/// 合并了include与默认值的配置，序列化用于`config show --resolved`
+#[derive(Debug, Getters, Setters, Clone, Builder, Serialize)]
 #[getset(get = "pub")]
 #[builder(pattern = "mutable", setter(into, strip_option))]
+#[serde(rename_all = "kebab-case")]
 pub struct Config {
     bins: Vec<Binary>,
+
+    /// the max number of version history kept for each binary in db
+    #[builder(default)]
+    history_limit: Option<usize>,
+
+    /// the timeout of connecting to servers for all requests
+    #[builder(default = "DEFAULT_CONNECT_TIMEOUT")]
+    #[serde(serialize_with = "serialize_secs")]
+    connect_timeout: Duration,
+
+    /// the max idle connections of each host in the pool of the shared client
+    #[builder(default = "DEFAULT_POOL_MAX_IDLE")]
+    pool_max_idle: usize,
+
+    /// how long an idle connection is kept for reuse
+    #[builder(default = "DEFAULT_POOL_IDLE_TIMEOUT")]
+    #[serde(serialize_with = "serialize_secs")]
+    pool_idle_timeout: Duration,
+
+    /// use the platform default dirs if none
+    #[builder(default)]
+    data_dir: Option<PathBuf>,
+    #[builder(default)]
+    cache_dir: Option<PathBuf>,
+    /// the max bytes of the cache dir. keep all downloads if none
+    #[builder(default)]
+    max_cache_size: Option<u64>,
+    #[builder(default)]
+    bin_dir: Option<PathBuf>,
+
+    /// the file of trace logs. use `binaries.log` in the cache dir if none
+    #[builder(default)]
+    log_file: Option<PathBuf>,
+    /// the max bytes of the log file before rotated. 0 disables the log file
+    #[builder(default = "DEFAULT_MAX_LOG_SIZE")]
+    max_log_size: u64,
+
+    /// the token for github api requests
+    #[builder(default)]
+    github_token: Option<GithubToken>,
+
+    /// the aliases of archs in asset names that replace the builtin
+    #[builder(default)]
+    archs: IndexMap<String, Vec<String>>,
+
+    /// the url prefixes of downloads to the mirror prefixes in order
+    #[builder(default)]
+    mirrors: IndexMap<String, String>,
+
+    /// the sha256 of the commands before expanded and the loaded files. none if no commands
+    #[builder(default)]
+    #[serde(skip)]
+    hooks_digest: Option<String>,
+
+    /// the loaded config file and its included files in order. empty if not loaded from files
+    #[builder(default)]
+    #[serde(skip)]
+    files: Vec<PathBuf>,
 }
 
-#[derive(Debug, Getters, Setters, Clone, Builder)]
+impl Config {
+    /// 移除所有会执行命令或可能泄露环境变量与文件的字段，用于未信任的配置
+    ///
+    /// 包括bins的hooks，fallback，version-command，下载的headers，basic auth与asset url，
+    /// 禁用urls source的bins，移除github token的command与file
+    pub fn disable_hooks(&mut self) {
+        for bin in &mut self.bins {
+            bin.hook = None;
+            bin.hooks.clear();
+            bin.fallback = None;
+            bin.version_command = None;
+            bin.headers.clear();
+            bin.basic_auth = None;
+            bin.asset_url = None;
+            if matches!(bin.source, Source::Urls(_)) {
+                bin.enabled = false;
+            }
+        }
+        if matches!(
+            self.github_token,
+            Some(GithubToken::Command(_) | GithubToken::File(_))
+        ) {
+            self.github_token = None;
+        }
+    }
+}
+
+/// github token的来源
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GithubToken {
+    Value(String),
+    /// read from the env var
+    Env(String),
+    /// read from the file
+    File(PathBuf),
+    /// read from the stdout of the command like `gh auth token`
+    Command(String),
+}
+
+impl FromStr for GithubToken {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let token = if let Some(var) = s.strip_prefix("env:") {
+            Self::Env(var.trim().to_owned())
+        } else if let Some(path) = s.strip_prefix("file:") {
+            Self::File(expand_env(path.trim())?.into())
+        } else if let Some(cmd) = s.strip_prefix("command:") {
+            Self::Command(cmd.trim().to_owned())
+        } else {
+            Self::Value(s.to_owned())
+        };
+        let is_empty = match &token {
+            Self::Value(v) | Self::Env(v) | Self::Command(v) => v.is_empty(),
+            Self::File(p) => p.as_os_str().is_empty(),
+        };
+        if is_empty {
+            bail!("empty github token `{}`", s);
+        }
+        Ok(token)
+    }
+}
+
+impl Serialize for GithubToken {
+    /// 与[fmt::Display]一样不暴露token的值
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl fmt::Display for GithubToken {
+    /// 显示来源而不是token的值
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Value(_) => write!(f, "config value"),
+            Self::Env(var) => write!(f, "env `{}`", var),
+            Self::File(path) => write!(f, "file {}", path.display()),
+            Self::Command(cmd) => write!(f, "command `{}`", cmd),
+        }
+    }
+}
+
+impl GithubToken {
+    /// 读取token的值
+    pub async fn resolve(&self) -> Result<String> {
+        let token = match self {
+            Self::Value(s) => s.to_owned(),
+            Self::Env(var) => std::env::var(var)?,
+            Self::File(path) => tokio::fs::read_to_string(path).await?,
+            Self::Command(cmd) => {
+                let args = shell_words::split(cmd)?;
+                let (program, args) = args.split_first().ok_or_else(|| anyhow!("empty command"))?;
+                let out = tokio::process::Command::new(program)
+                    .args(args)
+                    .output()
+                    .await?;
+                if !out.status.success() {
+                    bail!(
+                        "failed to run `{}`: {}",
+                        cmd,
+                        String::from_utf8_lossy(&out.stderr).trim()
+                    );
+                }
+                String::from_utf8(out.stdout)?
+            }
+        };
+        let token = token.trim();
+        if token.is_empty() {
+            bail!("empty github token from {}", self);
+        }
+        Ok(token.to_owned())
+    }
+}
+
+#[derive(Debug, Getters, Setters, Clone, Builder, Serialize)]
 #[getset(get = "pub", set)]
 #[builder(pattern = "mutable", setter(into, strip_option))]
+#[serde(rename_all = "kebab-case")]
 pub struct Binary {
     #[builder(default)]
     name: String,
@@ -32,6 +243,14 @@ pub struct Binary {
     #[builder(default)]
     hook: Option<HookAction>,
 
+    /// the hooks run on events after the [HookAction] of the same event
+    #[builder(default)]
+    hooks: Vec<Hook>,
+
+    /// run hooks through a shell if the hook does not set
+    #[builder(default = "true")]
+    shell: bool,
+
     /// a glob of executable file in zip. for help to comfirm exe bin
     #[builder(default)]
     bin_glob: Option<String>,
@@ -39,10 +258,147 @@ pub struct Binary {
     #[builder(default)]
     pick_regex: Option<String>,
 
+    /// a regex template of the assets never picked
+    #[builder(default)]
+    ignore_regex: Option<String>,
+
+    /// a url template of the asset to download directly without picking
+    #[builder(default)]
+    asset_url: Option<String>,
+
+    /// the id of a github release asset to download directly without picking
+    #[builder(default)]
+    asset_id: Option<i64>,
+
+    /// the way to link the executable file. use the platform default if none
+    #[builder(default)]
+    exe_type: Option<ExeType>,
+
+    #[builder(default)]
+    completion: Option<Completion>,
+
+    /// the tags to filter binaries by `--only-tags` and `--skip-tags`
+    #[builder(default)]
+    tags: Vec<String>,
+
+    /// a disabled bin is skipped by install, update and sync but not uninstalled as unused
+    #[builder(default = "true")]
+    enabled: bool,
+
+    /// 在exe dir中的操作权限不足时使用sudo或doas以root执行
+    #[builder(default)]
+    privileged: bool,
+
+    /// the names to link the executable as. use the name if empty
+    #[builder(default)]
+    alias: Vec<String>,
+
+    /// the timeout of waiting for a response or the next chunk of a download
+    #[builder(default = "DEFAULT_READ_TIMEOUT")]
+    #[serde(serialize_with = "serialize_secs")]
+    read_timeout: Duration,
+
+    /// the extra headers of downloads. `${VAR}` is expanded on downloading
+    #[builder(default)]
+    headers: IndexMap<String, String>,
+
+    /// the basic auth of downloads in `user:password`. `${VAR}` is expanded on downloading
+    #[builder(default)]
+    basic_auth: Option<String>,
+
+    /// 更新时使用`.zsync`文件从缓存的旧版本中增量下载
+    #[builder(default)]
+    delta: bool,
+
+    /// 安装前验证下载的asset的github artifact attestation
+    #[builder(default)]
+    verify_attestation: bool,
+
+    /// 拒绝没有有效attestation的asset
+    #[builder(default)]
+    require_attestation: bool,
+
+    /// macos上保留执行文件的quarantine属性
+    #[builder(default)]
+    keep_quarantine: bool,
+
+    /// macos上使用`codesign`验证执行文件的签名
+    #[builder(default)]
+    codesign: bool,
+
+    /// 优先使用PATH中由os提供的可执行文件
+    #[builder(default)]
+    system: bool,
+
+    /// 获取非本程序安装的可执行文件版本时使用的参数
+    #[builder(default)]
+    version_command: Option<String>,
+
+    /// 在`version_command`输出中查找版本的regex
+    #[builder(default)]
+    version_regex: Option<String>,
+
+    /// script source下载的脚本没有`#!`时写入的解释器
+    #[builder(default)]
+    shebang: Option<String>,
+
+    /// script source下载的脚本的digest如`sha256:<hex>`
+    #[builder(default)]
+    checksum: Option<String>,
+
+    /// script source作为最新版本的响应header
+    #[builder(default)]
+    version_header: Option<String>,
+
+    /// 没有匹配当前平台的asset时构建bin的命令
+    #[builder(default)]
+    fallback: Option<String>,
+
+    /// 获取最新版本的url
+    #[builder(default)]
+    version_from: Option<VersionFrom>,
+
+    /// update时跳过发布时间不足这个时长的版本
+    #[builder(default)]
+    #[serde(serialize_with = "serialize_opt_secs")]
+    min_release_age: Option<Duration>,
+
     #[builder(setter(custom))]
+    #[serde(serialize_with = "serialize_display")]
     source: Source,
 }
 
+impl Binary {
+    /// 可执行文件在exe dir中的链接名，没有alias时为name
+    pub fn link_names(&self) -> Vec<&str> {
+        if self.alias.is_empty() {
+            vec![&self.name]
+        } else {
+            self.alias.iter().map(String::as_str).collect()
+        }
+    }
+
+    /// 在event上执行的hooks，先是[HookAction]中的cmd，然后按配置顺序的[Hook]
+    pub fn hooks_on(&self, event: HookEvent) -> Vec<Hook> {
+        let action = self.hook.as_ref().and_then(|h| {
+            match event {
+                HookEvent::Install => h.install(),
+                HookEvent::Update => h.update(),
+                HookEvent::Uninstall => h.uninstall(),
+                HookEvent::Extract => h.extract(),
+                HookEvent::Build => h.build(),
+                HookEvent::Check => &None,
+            }
+            .as_deref()
+        });
+        action
+            .map(|cmd| Hook::new(cmd, vec![event]))
+            .into_iter()
+            .chain(self.hooks.iter().filter(|h| h.on.contains(&event)).cloned())
+            .collect()
+    }
+}
+
 impl BinaryBuilder {
     pub fn source<T>(&mut self, source: T) -> Result<&mut Self>
     where
@@ -58,6 +414,7 @@ impl BinaryBuilder {
 )]
 #[getset(get = "pub", set)]
 #[builder(pattern = "mutable", setter(into, strip_option))]
+#[serde(deny_unknown_fields)]
 pub struct HookAction {
     #[builder(default)]
     install: Option<String>,
@@ -67,6 +424,140 @@ pub struct HookAction {
     extract: Option<String>,
     #[builder(default)]
     uninstall: Option<String>,
+    /// run in the extracted dir to build the executable before linking
+    #[builder(default, setter(name = "build_hook"))]
+    build: Option<String>,
+}
+
+/// 在多个事件上执行的同一个hook command
+#[derive(Debug, Default, PartialEq, Eq, Getters, Setters, Clone, Serialize, Deserialize)]
+#[getset(get = "pub", set = "pub")]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Hook {
+    command: String,
+    /// the events to run the command on
+    on: Vec<HookEvent>,
+    /// the dir to run the command in. relative to the current version dir
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    work_dir: Option<String>,
+    /// the interpreter to run the command with like `bash -c`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    shebang: Option<String>,
+    /// run the command as the user by sudo
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    user: Option<String>,
+    /// the extra environment variables of the command
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    env: IndexMap<String, String>,
+    /// kill the command after the seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timeout: Option<u64>,
+    /// run the command by `$SHELL -c`. use the global `shell` if none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    shell: Option<bool>,
+}
+
+impl Hook {
+    pub fn new(command: impl Into<String>, on: Vec<HookEvent>) -> Self {
+        Self {
+            command: command.into(),
+            on,
+            ..Default::default()
+        }
+    }
+
+    fn expanded(self) -> Result<Self> {
+        Ok(Self {
+            command: expand_env(&self.command)?,
+            work_dir: expand_opt(self.work_dir)?,
+            env: self
+                .env
+                .into_iter()
+                .map(|(k, v)| expand_env(&v).map(|v| (k, v)))
+                .collect::<Result<_>>()?,
+            ..self
+        })
+    }
+}
+
+/// 执行hook的事件
+///
+/// install, update与uninstall在操作完成后执行。check在激活新版本后，记录到db之前执行，失败时恢复。
+/// extract替换通用的解压，build在解压后执行
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HookEvent {
+    Install,
+    Update,
+    Uninstall,
+    Extract,
+    Build,
+    Check,
+}
+
+impl HookEvent {
+    pub const ALL: [HookEvent; 6] = [
+        Self::Install,
+        Self::Update,
+        Self::Uninstall,
+        Self::Extract,
+        Self::Build,
+        Self::Check,
+    ];
+}
+
+impl fmt::Display for HookEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Install => "install",
+            Self::Update => "update",
+            Self::Uninstall => "uninstall",
+            Self::Extract => "extract",
+            Self::Build => "build",
+            Self::Check => "check",
+        };
+        f.write_str(s)
+    }
+}
+
+/// bin生效的条件，所有设置的条件都匹配时生效
+///
+/// 值为大小写不敏感的glob，os与arch匹配[OS]与[ARCH]的值如`linux`与`aarch64`
+#[derive(Debug, Default, PartialEq, Eq, Getters, Clone, Serialize, Deserialize)]
+#[getset(get = "pub")]
+#[serde(default, deny_unknown_fields)]
+pub struct Condition {
+    os: Option<String>,
+    arch: Option<String>,
+    hostname: Option<String>,
+}
+
+impl Condition {
+    /// 检查当前机器是否满足条件
+    pub fn is_match(&self) -> Result<bool> {
+        let hostname = gethostname().to_string_lossy().into_owned();
+        self.matches(OS, ARCH, &hostname)
+    }
+
+    fn matches(&self, os: &str, arch: &str, hostname: &str) -> Result<bool> {
+        for (pat, val) in [
+            (&self.os, os),
+            (&self.arch, arch),
+            (&self.hostname, hostname),
+        ] {
+            if let Some(pat) = pat {
+                let glob = GlobBuilder::new(pat)
+                    .case_insensitive(true)
+                    .build()?
+                    .compile_matcher();
+                if !glob.is_match(val) {
+                    trace!("{} does not match condition {}", val, pat);
+                    return Ok(false);
+                }
+            }
+        }
+        Ok(true)
+    }
 }
 
 /// A GitHub repository identifier.
@@ -81,7 +572,56 @@ pub struct GitHubRepository {
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "kebab-case")]
 pub enum Source {
-    Github { owner: String, repo: String },
+    Github {
+        owner: String,
+        repo: String,
+    },
+    /// 没有release的repo，下载tag或branch的tarball
+    GithubTag {
+        owner: String,
+        repo: String,
+    },
+    /// 只在ci中发布的nightly build，下载workflow在branch上最新成功运行的artifact
+    GithubActions {
+        owner: String,
+        repo: String,
+        /// the file name or id of the workflow like `nightly.yml`
+        workflow: String,
+        /// the branch of the workflow runs. any branch if none
+        branch: Option<String>,
+    },
+    /// 普通http服务器上每个平台的文件
+    Urls(PlatformUrls),
+    /// 第三方注册的source如`gitlab:group/project`，由[crate::source::SourceRegistry]创建
+    Custom {
+        name: String,
+        value: String,
+    },
+}
+
+impl Source {
+    /// 在[crate::source::SourceRegistry]中注册的名称
+    pub fn name(&self) -> &str {
+        match self {
+            Source::Github { .. } => "github",
+            Source::GithubTag { .. } => "github-tag",
+            Source::GithubActions { .. } => "github-actions",
+            Source::Urls(_) => "urls",
+            Source::Custom { name, .. } => name,
+        }
+    }
+
+    /// github上的repo地址，其它source为none
+    pub fn repo_url(&self) -> Option<String> {
+        match self {
+            Source::Github { owner, repo }
+            | Source::GithubTag { owner, repo }
+            | Source::GithubActions { owner, repo, .. } => {
+                Some(format!("https://github.com/{}/{}", owner, repo))
+            }
+            Source::Urls(_) | Source::Custom { .. } => None,
+        }
+    }
 }
 
 impl FromStr for Source {
@@ -90,13 +630,12 @@ impl FromStr for Source {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         trace!("parsing Source from str: {}", s);
         const DELIMITER: char = ':';
-        let a = s.split(DELIMITER).collect::<Vec<_>>();
-        if a.len() != 2 {
-            bail!("failed to parse Source: then len {} is not 2", a.len());
-        }
-        let (name, value) = (a[0].trim().to_lowercase(), a[1].trim());
+        let (name, value) = s
+            .split_once(DELIMITER)
+            .ok_or_else(|| anyhow!("failed to parse Source: not found `{}`", DELIMITER))?;
+        let (name, value) = (name.trim().to_lowercase(), value.trim());
         match name.as_str() {
-            "github" => {
+            "github" | "github-tag" => {
                 let delimiter = '/';
                 let v = value.split(delimiter).collect::<Vec<_>>();
                 if v.len() != 2 {
@@ -106,9 +645,40 @@ impl FromStr for Source {
                         delimiter
                     );
                 }
-                Ok(Source::Github {
-                    owner: v[0].to_owned(),
-                    repo: v[1].to_owned(),
+                let (owner, repo) = (v[0].to_owned(), v[1].to_owned());
+                Ok(if name == "github" {
+                    Source::Github { owner, repo }
+                } else {
+                    Source::GithubTag { owner, repo }
+                })
+            }
+            "urls" => Ok(Source::Urls(value.parse()?)),
+            "github-actions" => {
+                let (path, branch) = match value.split_once('@') {
+                    Some((path, branch)) => (path, Some(branch.to_owned())),
+                    None => (value, None),
+                };
+                match path.split('/').collect::<Vec<_>>()[..] {
+                    [owner, repo, workflow] => Ok(Source::GithubActions {
+                        owner: owner.to_owned(),
+                        repo: repo.to_owned(),
+                        workflow: workflow.to_owned(),
+                        branch,
+                    }),
+                    _ => bail!(
+                        "source parse error: `{}` is not `owner/repo/workflow[@branch]`",
+                        value
+                    ),
+                }
+            }
+            _ if !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') =>
+            {
+                Ok(Source::Custom {
+                    name,
+                    value: value.to_owned(),
                 })
             }
             _ => bail!("unsupported name: {}", name),
@@ -116,6 +686,105 @@ impl FromStr for Source {
     }
 }
 
+impl fmt::Display for Source {
+    /// Displays as the format parsed by [Source::from_str]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Github { owner, repo } => write!(f, "github:{}/{}", owner, repo),
+            Source::GithubTag { owner, repo } => write!(f, "github-tag:{}/{}", owner, repo),
+            Source::GithubActions {
+                owner,
+                repo,
+                workflow,
+                branch,
+            } => {
+                write!(f, "github-actions:{}/{}/{}", owner, repo, workflow)?;
+                match branch {
+                    Some(branch) => write!(f, "@{}", branch),
+                    None => Ok(()),
+                }
+            }
+            Source::Urls(urls) => write!(f, "urls:{}", urls),
+            Source::Custom { name, value } => write!(f, "{}:{}", name, value),
+        }
+    }
+}
+
+/// 每个平台的url模板，模板中可用`name`,`version`与平台的值
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum PlatformUrls {
+    /// `os-arch` like `linux-x86_64` or `os` to the url template
+    Map(IndexMap<String, String>),
+    /// the url templates picked by the platform in their file names like the github assets
+    List(Vec<String>),
+}
+
+impl FromStr for PlatformUrls {
+    type Err = Error;
+
+    /// 解析`,`分隔的url模板，都为`<os-arch>=<url>`格式时为[PlatformUrls::Map]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let items = s
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>();
+        if items.is_empty() {
+            bail!("empty urls");
+        }
+        let is_key = |k: &str| {
+            !k.is_empty()
+                && k.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        };
+        let pairs = items
+            .iter()
+            .map(|item| item.split_once('=').filter(|(k, _)| is_key(k)))
+            .collect::<Option<Vec<_>>>();
+        Ok(match pairs {
+            Some(pairs) => PlatformUrls::Map(
+                pairs
+                    .into_iter()
+                    .map(|(k, v)| (k.to_owned(), v.to_owned()))
+                    .collect(),
+            ),
+            None => PlatformUrls::List(items.into_iter().map(ToOwned::to_owned).collect()),
+        })
+    }
+}
+
+impl fmt::Display for PlatformUrls {
+    /// Displays as the format parsed by [PlatformUrls::from_str]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let items = match self {
+            PlatformUrls::Map(map) => map
+                .iter()
+                .map(|(k, v)| format!("{}={}", k, v))
+                .collect::<Vec<_>>(),
+            PlatformUrls::List(urls) => urls.clone(),
+        };
+        f.write_str(&items.join(","))
+    }
+}
+
+/// 从任意的url如下载页或`latest.json`中获取最新版本，与下载asset的source无关
+#[derive(Debug, Clone, Getters, Serialize, Deserialize, PartialEq, Eq)]
+#[getset(get = "pub")]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct VersionFrom {
+    /// the url of the page containing the latest version
+    url: String,
+    /// the regex to find the version in the page. use the first group if any otherwise the
+    /// whole match. default a semver like regex if no `pointer`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    regex: Option<String>,
+    /// the json pointer like `/stable/version` of the version in a json response. the
+    /// `regex` if any is applied to the pointed value
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pointer: Option<String>,
+}
+
 impl TryFrom<&str> for Source {
     type Error = Error;
 
@@ -132,45 +801,1061 @@ impl TryFrom<&Source> for Source {
     }
 }
 
+impl HookAction {
+    /// 展开所有hook中的环境变量与`~`
+    fn expanded(self) -> Result<Self> {
+        Ok(Self {
+            install: expand_opt(self.install)?,
+            update: expand_opt(self.update)?,
+            extract: expand_opt(self.extract)?,
+            uninstall: expand_opt(self.uninstall)?,
+            build: expand_opt(self.build)?,
+        })
+    }
+}
+
+fn expand_opt(s: Option<String>) -> Result<Option<String>> {
+    s.as_deref().map(expand_env).transpose()
+}
+
+/// 序列化为可被[FromStr]解析的字符串
+fn serialize_display<T: fmt::Display, S: Serializer>(
+    v: &T,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(v)
+}
+
+/// 序列化为与配置中一样的秒数
+fn serialize_secs<S: Serializer>(d: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(d.as_secs())
+}
+
+fn serialize_opt_secs<S: Serializer>(
+    d: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match d {
+        Some(d) => serialize_secs(d, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
 impl TryFrom<RawConfig> for Config {
     type Error = Error;
 
+    /// 配置中的路径，hook与glob字符串都使用[expand_env]展开，pick regex除外
+    ///
+    /// 不满足`when`条件的bin会被跳过
     fn try_from(raw: RawConfig) -> Result<Self, Self::Error> {
+        let mut skipped = vec![];
+        for (name, bin) in &raw.bins {
+            if let Some(cond) = bin.when() {
+                if !cond.is_match()? {
+                    debug!("skipped bin {} not matching the condition {:?}", name, cond);
+                    skipped.push(name.to_owned());
+                }
+            }
+        }
+        let default = raw.default.or(RawDefault {
+            bin_glob: raw.bin_glob,
+            pick_regex: raw.pick_regex,
+            ignore_regex: raw.ignore_regex,
+            hook: raw.hook,
+            read_timeout: raw.read_timeout,
+            ..Default::default()
+        });
+        let github_token = raw
+            .github
+            .and_then(|g| g.token)
+            .map(|s| s.parse())
+            .transpose()?;
+        let hooks_digest = hooks_digest(&default, &raw.bins, github_token.as_ref(), &raw.files)?;
         let bins = raw
             .bins
             .into_iter()
+            .filter(|(name, _)| !skipped.contains(name))
             .map(|(name, bin)| {
-                let source = match bin.github() {
-                    Some(g) => Source::Github {
+                let source = match (bin.from(), bin.github(), bin.source()) {
+                    (Some(_), Some(_), _) => bail!("both from and github of {}", name),
+                    (Some(from), None, _) => from.parse()?,
+                    (None, Some(g), None | Some(SourceKind::Github)) => Source::Github {
+                        owner: g.owner.to_owned(),
+                        repo: g.name.to_owned(),
+                    },
+                    (None, Some(g), Some(SourceKind::GithubTag)) => Source::GithubTag {
+                        owner: g.owner.to_owned(),
+                        repo: g.name.to_owned(),
+                    },
+                    (None, Some(g), Some(SourceKind::GithubActions)) => Source::GithubActions {
                         owner: g.owner.to_owned(),
                         repo: g.name.to_owned(),
+                        workflow: bin
+                            .workflow()
+                            .clone()
+                            .ok_or_else(|| anyhow!("not found workflow of {}", name))?,
+                        branch: bin.branch().clone(),
                     },
-                    None => bail!("not found source"),
+                    (None, None, _) => match bin.urls() {
+                        Some(urls) => Source::Urls(urls.clone()),
+                        None => bail!("not found source"),
+                    },
+                };
+                if let Some(alias) = bin
+                    .alias()
+                    .iter()
+                    .find(|a| a.is_empty() || a.contains(['/', '\\']))
+                {
+                    bail!("invalid alias `{}` of {}", alias, name);
+                }
+                let hook = bin.hook().as_ref().or(default.hook.as_ref()).cloned();
+                let hooks = if bin.hooks().is_empty() {
+                    &default.hooks
+                } else {
+                    bin.hooks()
                 };
+                let min_release_age = bin
+                    .min_release_age()
+                    .as_ref()
+                    .or(default.min_release_age.as_ref())
+                    .map(|age| parse_duration(age))
+                    .transpose()
+                    .with_context(|| format!("invalid min-release-age of {}", name))?;
+                let read_timeout = bin
+                    .read_timeout()
+                    .or(default.read_timeout)
+                    .map_or(DEFAULT_READ_TIMEOUT, Duration::from_secs);
+                let require_attestation = bin
+                    .require_attestation()
+                    .or(default.require_attestation)
+                    .unwrap_or_default();
                 Ok(Binary {
-                    bin_glob: bin.bin_glob().as_ref().or(raw.bin_glob.as_ref()).cloned(),
-                    hook: bin.hook().as_ref().or(raw.hook.as_ref()).cloned(),
+                    asset_id: *bin.asset_id(),
+                    asset_url: expand_opt(bin.asset_url().clone())?,
+                    bin_glob: expand_opt(
+                        bin.bin_glob()
+                            .as_ref()
+                            .or(default.bin_glob.as_ref())
+                            .cloned(),
+                    )?,
+                    completion: bin.completion().clone(),
+                    exe_type: bin.exe().as_ref().or(default.exe.as_ref()).cloned(),
+                    hook: hook.map(HookAction::expanded).transpose()?,
+                    hooks: hooks
+                        .iter()
+                        .cloned()
+                        .map(Hook::expanded)
+                        .collect::<Result<_>>()?,
+                    shell: raw.shell.unwrap_or(true),
                     name,
                     pick_regex: bin
                         .pick_regex()
                         .as_ref()
-                        .or(raw.pick_regex.as_ref())
+                        .or(default.pick_regex.as_ref())
+                        .cloned(),
+                    ignore_regex: bin
+                        .ignore_regex()
+                        .as_ref()
+                        .or(default.ignore_regex.as_ref())
                         .cloned(),
                     source,
+                    tags: bin.tags().clone(),
+                    enabled: bin.enabled().unwrap_or(true),
+                    privileged: bin.privileged().or(default.privileged).unwrap_or_default(),
+                    alias: bin.alias().clone(),
+                    read_timeout,
+                    headers: bin.headers().clone(),
+                    basic_auth: bin.basic_auth().clone(),
+                    delta: bin.delta().or(default.delta).unwrap_or_default(),
+                    verify_attestation: require_attestation
+                        || bin
+                            .verify_attestation()
+                            .or(default.verify_attestation)
+                            .unwrap_or_default(),
+                    require_attestation,
+                    keep_quarantine: bin
+                        .keep_quarantine()
+                        .or(default.keep_quarantine)
+                        .unwrap_or_default(),
+                    codesign: bin.codesign().or(default.codesign).unwrap_or_default(),
+                    system: bin.system().or(default.system).unwrap_or_default(),
+                    version_command: bin.version_command().clone(),
+                    version_regex: bin.version_regex().clone(),
+                    shebang: bin.shebang().clone(),
+                    checksum: bin.checksum().clone(),
+                    version_header: bin.version_header().clone(),
+                    fallback: bin.fallback().clone(),
+                    version_from: bin.version_from().clone(),
+                    min_release_age,
                     version: bin.version().clone(),
                 })
             })
             .collect::<Result<Vec<_>>>()?;
 
-        Ok(Config { bins })
+        let expand_dir = |dir| expand_opt(dir).map(|dir| dir.map(PathBuf::from));
+        Ok(Config {
+            bins,
+            history_limit: raw.history_limit,
+            connect_timeout: raw
+                .connect_timeout
+                .map_or(DEFAULT_CONNECT_TIMEOUT, Duration::from_secs),
+            pool_max_idle: raw.pool_max_idle.unwrap_or(DEFAULT_POOL_MAX_IDLE),
+            pool_idle_timeout: raw
+                .pool_idle_timeout
+                .map_or(DEFAULT_POOL_IDLE_TIMEOUT, Duration::from_secs),
+            data_dir: expand_dir(raw.data_dir)?,
+            cache_dir: expand_dir(raw.cache_dir)?,
+            max_cache_size: raw.max_cache_size.as_deref().map(parse_size).transpose()?,
+            bin_dir: expand_dir(raw.bin_dir)?,
+            log_file: expand_dir(raw.log_file)?,
+            max_log_size: raw
+                .max_log_size
+                .as_deref()
+                .map(parse_size)
+                .transpose()?
+                .unwrap_or(DEFAULT_MAX_LOG_SIZE),
+            github_token,
+            archs: raw.archs,
+            mirrors: raw.mirrors,
+            hooks_digest,
+            files: raw.files,
+        })
     }
 }
 
+/// 计算default与bins中未展开的会执行命令的字段，下载的url与认证字段，github token的command
+/// 或file与加载的文件的sha256，没有任何这些字段时为none
+///
+/// 这些字段或include的文件改变时digest也改变，需要重新信任配置
+fn hooks_digest(
+    default: &RawDefault,
+    bins: &IndexMap<String, RawBinary>,
+    github_token: Option<&GithubToken>,
+    files: &[PathBuf],
+) -> Result<Option<String>> {
+    use sha2::{Digest, Sha256};
+
+    let hooks = std::iter::once(("", (&default.hook, &default.hooks, &None, &None)))
+        .chain(bins.iter().map(|(name, bin)| {
+            (
+                name.as_str(),
+                (
+                    bin.hook(),
+                    bin.hooks(),
+                    bin.fallback(),
+                    bin.version_command(),
+                ),
+            )
+        }))
+        .filter(|(_, (hook, hooks, fallback, version_command))| {
+            hook.is_some() || !hooks.is_empty() || fallback.is_some() || version_command.is_some()
+        })
+        .collect::<Vec<_>>();
+    // the env vars in the urls and auth are expanded and sent to the hosts
+    let downloads = bins
+        .iter()
+        .map(|(name, bin)| {
+            let from = bin.from().as_ref().filter(|from| from.starts_with("urls:"));
+            (
+                name.as_str(),
+                (
+                    bin.asset_url(),
+                    bin.urls(),
+                    from,
+                    bin.headers(),
+                    bin.basic_auth(),
+                ),
+            )
+        })
+        .filter(|(_, (asset_url, urls, from, headers, basic_auth))| {
+            asset_url.is_some()
+                || urls.is_some()
+                || from.is_some()
+                || !headers.is_empty()
+                || basic_auth.is_some()
+        })
+        .collect::<Vec<_>>();
+    let token = github_token
+        .filter(|token| matches!(token, GithubToken::Command(_) | GithubToken::File(_)));
+    if hooks.is_empty() && downloads.is_empty() && token.is_none() {
+        return Ok(None);
+    }
+    let digest = Sha256::digest(serde_json::to_vec(&(hooks, downloads, token, files))?);
+    Ok(Some(digest.iter().map(|b| format!("{:02x}", b)).collect()))
+}
+
+/// 配置文件支持的格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl Format {
+    /// 按顺序查找默认配置文件时使用的扩展名
+    pub const EXTENSIONS: &'static [&'static str] = &["toml", "yaml", "yml", "json"];
+
+    /// 从文件扩展名中识别格式
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        match path.as_ref().extension()?.to_str()? {
+            "toml" => Some(Self::Toml),
+            "yaml" | "yml" => Some(Self::Yaml),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+
+    /// 从内容中猜测格式：以`{`开始的是json，可解析为toml的是toml，否则是yaml
+    pub fn sniff(contents: &str) -> Self {
+        if contents.trim_start().starts_with('{') {
+            Self::Json
+        } else if toml::from_str::<toml::Value>(contents).is_ok() {
+            Self::Toml
+        } else {
+            Self::Yaml
+        }
+    }
+
+    pub fn parse<T: DeserializeOwned>(&self, contents: &str) -> Result<T> {
+        Ok(match self {
+            Self::Toml => toml::from_str(contents)?,
+            Self::Yaml => serde_yaml::from_str(contents)?,
+            Self::Json => serde_json::from_str(contents)?,
+        })
+    }
+
+    pub fn to_string_pretty<T: Serialize>(&self, value: &T) -> Result<String> {
+        Ok(match self {
+            // serialize via Value to emit plain values before tables
+            Self::Toml => toml::to_string_pretty(&toml::Value::try_from(value)?)?,
+            Self::Yaml => serde_yaml::to_string(value)?,
+            Self::Json => serde_json::to_string_pretty(value)?,
+        })
+    }
+}
+
+/// 加载配置文件及其include的文件。格式由扩展名确定，无法识别扩展名时从内容中猜测
+///
+/// # Error
+///
+/// * 如果include存在循环
+/// * 如果多个文件中存在同名的bin
 pub fn from_path(path: impl AsRef<Path>) -> Result<Config> {
-    debug!("loading config from {}", path.as_ref().display());
-    let contents = read_to_string(path)?;
-    trace!("loaded raw config content: {}", contents);
-    let raw: RawConfig = toml::from_str(&contents)?;
+    let raw = raw_from_path(path)?;
     trace!("parsing raw config: {:?}", raw);
     raw.try_into().map_err(Into::into)
 }
+
+/// 加载path并合并其include的配置，不应用默认值
+pub fn raw_from_path(path: impl AsRef<Path>) -> Result<RawConfig> {
+    let mut raws = vec![];
+    load_raws(path.as_ref(), &mut vec![], &mut raws)?;
+    merge_raws(raws)
+}
+
+fn load_raw(path: &Path) -> Result<RawConfig> {
+    debug!("loading config from {}", path.display());
+    let contents = read_to_string(path)
+        .with_context(|| format!("failed to read config {}", path.display()))?;
+    trace!("loaded raw config content: {}", contents);
+    let format = Format::from_path(path).unwrap_or_else(|| Format::sniff(&contents));
+    debug!("parsing config as {:?}", format);
+    format
+        .parse(&contents)
+        .with_context(|| format!("failed to parse {:?} config {}", format, path.display()))
+}
+
+/// 深度优先加载path及其include的配置文件到raws中，stack为正在加载的文件
+fn load_raws(
+    path: &Path,
+    stack: &mut Vec<PathBuf>,
+    raws: &mut Vec<(PathBuf, RawConfig)>,
+) -> Result<()> {
+    let path = path
+        .canonicalize()
+        .with_context(|| format!("failed to read config {}", path.display()))?;
+    if stack.contains(&path) {
+        bail!("found include cycle: {:?} -> {}", stack, path.display());
+    }
+    let mut raw = load_raw(&path)?;
+    let includes = std::mem::take(&mut raw.include);
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    stack.push(path.clone());
+    raws.push((path.clone(), raw));
+    for pattern in includes {
+        for p in include_paths(dir, &pattern)?
+            .into_iter()
+            // a glob like `*.toml` may match the including file itself
+            .filter(|p| p != &path)
+        {
+            load_raws(&p, stack, raws)?;
+        }
+    }
+    stack.pop();
+    Ok(())
+}
+
+/// 获取相对dir的include路径。glob按文件名排序，不匹配任何文件时为空
+fn include_paths(dir: &Path, pattern: &str) -> Result<Vec<PathBuf>> {
+    let is_glob = |s: &str| s.contains(['*', '?', '[', '{']);
+    let pattern = dir.join(expand_env(pattern)?);
+    let pattern_str = pattern.to_string_lossy();
+    if !is_glob(&pattern_str) {
+        return Ok(vec![pattern]);
+    }
+
+    let base = pattern
+        .ancestors()
+        .find(|p| !is_glob(&p.to_string_lossy()))
+        .unwrap_or(dir);
+    let glob = GlobBuilder::new(&pattern_str)
+        .literal_separator(true)
+        .build()?
+        .compile_matcher();
+    let mut paths = WalkDir::new(base)
+        .min_depth(1)
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .filter(|e| e.file_type().is_file() && glob.is_match(e.path()))
+        .map(|e| e.into_path())
+        .collect::<Vec<_>>();
+    paths.sort();
+    trace!("found {} includes with {}", paths.len(), pattern_str);
+    Ok(paths)
+}
+
+/// 按加载顺序合并配置，后加载文件中存在的值覆盖之前的
+fn merge_raws(raws: Vec<(PathBuf, RawConfig)>) -> Result<RawConfig> {
+    let mut merged = RawConfig::default();
+    let mut origins = HashMap::<String, PathBuf>::new();
+    let mut duplicates = vec![];
+    for (path, raw) in raws {
+        for (name, bin) in raw.bins {
+            if let Some(origin) = origins.get(&name) {
+                duplicates.push(format!(
+                    "`{}` in {} and {}",
+                    name,
+                    origin.display(),
+                    path.display()
+                ));
+                continue;
+            }
+            origins.insert(name.clone(), path.clone());
+            merged.bins.insert(name, bin);
+        }
+        merged.files.push(path);
+        merged.default = raw.default.or(merged.default);
+        merged.bin_glob = raw.bin_glob.or(merged.bin_glob);
+        merged.pick_regex = raw.pick_regex.or(merged.pick_regex);
+        merged.ignore_regex = raw.ignore_regex.or(merged.ignore_regex);
+        merged.hook = raw.hook.or(merged.hook);
+        merged.shell = raw.shell.or(merged.shell);
+        merged.history_limit = raw.history_limit.or(merged.history_limit);
+        merged.connect_timeout = raw.connect_timeout.or(merged.connect_timeout);
+        merged.read_timeout = raw.read_timeout.or(merged.read_timeout);
+        merged.pool_max_idle = raw.pool_max_idle.or(merged.pool_max_idle);
+        merged.pool_idle_timeout = raw.pool_idle_timeout.or(merged.pool_idle_timeout);
+        merged.data_dir = raw.data_dir.or(merged.data_dir);
+        merged.cache_dir = raw.cache_dir.or(merged.cache_dir);
+        merged.max_cache_size = raw.max_cache_size.or(merged.max_cache_size);
+        merged.bin_dir = raw.bin_dir.or(merged.bin_dir);
+        merged.log_file = raw.log_file.or(merged.log_file);
+        merged.max_log_size = raw.max_log_size.or(merged.max_log_size);
+        merged.github = raw.github.or(merged.github);
+        merged.archs.extend(raw.archs);
+        merged.mirrors.extend(raw.mirrors);
+    }
+    if !duplicates.is_empty() {
+        bail!("found duplicate bins: {}", duplicates.join(", "));
+    }
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir_all, write};
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn raw_config() -> Result<RawConfig> {
+        Format::Toml.parse(
+            r#"
+bin-glob = "{{bin}}"
+history-limit = 3
+data-dir = "/tmp/data"
+
+[bins.a]
+github = "a/b"
+exe.type = "symlink"
+hook.install = "echo a"
+tags = ["dev", "server"]
+
+[bins.c]
+github = "c/d"
+"#,
+        )
+    }
+
+    #[test]
+    fn test_format_round_trip() -> Result<()> {
+        let raw = raw_config()?;
+        for format in [Format::Toml, Format::Yaml, Format::Json] {
+            let s = format.to_string_pretty(&raw)?;
+            assert_eq!(Format::sniff(&s), format, "{}", s);
+            assert_eq!(format.parse::<RawConfig>(&s)?, raw, "{:?}", format);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_path_detect() -> Result<()> {
+        let raw = raw_config()?;
+        let dir = tempdir()?;
+        for (name, format) in [
+            ("config.toml", Format::Toml),
+            ("config.yml", Format::Yaml),
+            ("config.json", Format::Json),
+            ("config", Format::Yaml),
+            ("config.conf", Format::Json),
+        ] {
+            let path = dir.path().join(name);
+            write(&path, format.to_string_pretty(&raw)?)?;
+            let config = from_path(&path)?;
+            assert_eq!(config.bins().len(), 2, "{}", name);
+            assert_eq!(config.history_limit(), &Some(3));
+            assert_eq!(config.bins()[0].exe_type(), &Some(ExeType::Symlink));
+            assert_eq!(config.bins()[0].tags(), &["dev", "server"]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_include() -> Result<()> {
+        let dir = tempdir()?;
+        create_dir_all(dir.path().join("gaming"))?;
+        let main = dir.path().join("config.toml");
+        write(
+            &main,
+            r#"
+include = ["work.yaml", "gaming/*.toml"]
+history-limit = 3
+bin-glob = "a"
+
+[bins.a]
+github = "a/b"
+"#,
+        )?;
+        write(
+            dir.path().join("work.yaml"),
+            "history-limit: 5\nbins:\n  b:\n    github: c/d\n",
+        )?;
+        write(
+            dir.path().join("gaming/2.toml"),
+            "history-limit = 7\n[bins.d]\ngithub = \"g/h\"",
+        )?;
+        write(
+            dir.path().join("gaming/1.toml"),
+            "[bins.c]\ngithub = \"e/f\"",
+        )?;
+
+        let config = from_path(&main)?;
+        let names = config
+            .bins()
+            .iter()
+            .map(|b| b.name().as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a", "b", "c", "d"]);
+        assert_eq!(config.history_limit(), &Some(7));
+
+        write(
+            dir.path().join("gaming/3.toml"),
+            "[bins.b]\ngithub = \"x/y\"",
+        )?;
+        let e = from_path(&main).unwrap_err().to_string();
+        assert!(e.contains("`b`"), "{}", e);
+
+        write(
+            dir.path().join("gaming/3.toml"),
+            "include = [\"../config.toml\"]",
+        )?;
+        let e = from_path(&main).unwrap_err().to_string();
+        assert!(e.contains("cycle"), "{}", e);
+        Ok(())
+    }
+
+    #[test]
+    fn test_condition() -> Result<()> {
+        let cond = Condition {
+            os: Some("linux".to_owned()),
+            hostname: Some("work-*".to_owned()),
+            ..Default::default()
+        };
+        assert!(cond.matches("linux", "x86_64", "work-pc")?);
+        assert!(cond.matches("linux", "aarch64", "WORK-1")?);
+        assert!(!cond.matches("macos", "x86_64", "work-pc")?);
+        assert!(!cond.matches("linux", "x86_64", "home")?);
+        assert!(Condition::default().matches("windows", "x86", "a")?);
+
+        let raw = Format::Toml.parse::<RawConfig>(&format!(
+            r#"
+[bins.a]
+github = "a/b"
+when.os = "{}"
+
+[bins.b]
+github = "c/d"
+when = {{ os = "none", arch = "{}" }}
+"#,
+            OS, ARCH
+        ))?;
+        let config = Config::try_from(raw)?;
+        assert_eq!(config.bins().len(), 1);
+        assert_eq!(config.bins()[0].name(), "a");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_github_token() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("token");
+        write(&path, "abc\n")?;
+
+        for s in [
+            "abc".to_owned(),
+            format!("file:{}", path.display()),
+            "command: echo abc".to_owned(),
+        ] {
+            let token = s.parse::<GithubToken>()?;
+            assert_eq!(token.resolve().await?, "abc", "{}", s);
+        }
+        // cargo sets the env var for tests
+        assert_eq!(
+            "env:CARGO_PKG_NAME"
+                .parse::<GithubToken>()?
+                .resolve()
+                .await?,
+            env!("CARGO_PKG_NAME")
+        );
+        assert_eq!("abc".parse::<GithubToken>()?.to_string(), "config value");
+        assert_eq!(
+            "command:gh auth token".parse::<GithubToken>()?,
+            GithubToken::Command("gh auth token".to_owned())
+        );
+        assert!("env:".parse::<GithubToken>().is_err());
+        assert!("env:BINARIES_TEST_NO_TOKEN"
+            .parse::<GithubToken>()?
+            .resolve()
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_expand_raw() -> Result<()> {
+        // cargo sets the env var for tests
+        let pkg = env!("CARGO_PKG_NAME");
+        let raw = toml::from_str::<RawConfig>(
+            r#"
+data-dir = "/opt/${CARGO_PKG_NAME}/data"
+pick-regex = "linux$"
+ignore-regex = "static"
+
+[bins.a]
+github = "a/b"
+hook.install = "echo $$1 ${CARGO_PKG_NAME}"
+"#,
+        )?;
+        let config = Config::try_from(raw)?;
+        assert_eq!(
+            config.data_dir().as_deref(),
+            Some(Path::new(&format!("/opt/{}/data", pkg)))
+        );
+
+        let bin = &config.bins()[0];
+        assert_eq!(
+            bin.hook().as_ref().and_then(|h| h.install().clone()),
+            Some(format!("echo $1 {}", pkg))
+        );
+        assert_eq!(bin.pick_regex().as_deref(), Some("linux$"));
+        assert_eq!(bin.ignore_regex().as_deref(), Some("static"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_hooks() -> Result<()> {
+        let raw = toml::from_str::<RawConfig>(
+            r#"
+shell = false
+
+[bins.a]
+github = "a/b"
+privileged = true
+hook.install = "echo action"
+hooks = [
+    { command = "echo 1", on = ["install", "update"] },
+    { command = "echo 2", on = ["install"], shebang = "bash -c", env = { A = "${CARGO_PKG_NAME}" }, timeout = 10 },
+]
+"#,
+        )?;
+        let config = Config::try_from(raw)?;
+        let bin = &config.bins()[0];
+        let cmds = |event| {
+            bin.hooks_on(event)
+                .into_iter()
+                .map(|h| h.command().to_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            cmds(HookEvent::Install),
+            ["echo action", "echo 1", "echo 2"]
+        );
+        assert_eq!(cmds(HookEvent::Update), ["echo 1"]);
+        assert!(cmds(HookEvent::Check).is_empty());
+
+        let hook = &bin.hooks()[1];
+        assert_eq!(hook.shebang().as_deref(), Some("bash -c"));
+        assert_eq!(
+            hook.env().get("A").map(String::as_str),
+            Some(env!("CARGO_PKG_NAME"))
+        );
+        assert_eq!(*hook.timeout(), Some(10));
+        assert!(!bin.shell());
+        assert!(bin.privileged());
+
+        let res = toml::from_str::<RawConfig>(
+            r#"
+[bins.a]
+github = "a/b"
+hooks = [{ command = "echo 1", on = ["none"] }]
+"#,
+        );
+        assert!(res.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_hooks_digest() -> Result<()> {
+        let parse = |s: &str| Config::try_from(toml::from_str::<RawConfig>(s)?);
+        let config = parse("[bins.a]\ngithub = \"a/b\"")?;
+        assert_eq!(config.hooks_digest(), &None);
+
+        let mut config = parse("[bins.a]\ngithub = \"a/b\"\nhook.install = \"echo 1\"")?;
+        let digest = config.hooks_digest().clone();
+        assert!(digest.is_some());
+        assert_eq!(
+            parse("[bins.a]\ngithub = \"a/c\"\nhook.install = \"echo 1\"")?.hooks_digest(),
+            &digest
+        );
+        assert_ne!(
+            parse("[bins.a]\ngithub = \"a/b\"\nhook.install = \"echo 2\"")?.hooks_digest(),
+            &digest
+        );
+        assert_ne!(
+            parse("[default]\nhook.install = \"echo 1\"\n[bins.a]\ngithub = \"a/b\"")?
+                .hooks_digest(),
+            &digest
+        );
+
+        assert!(
+            parse("[bins.a]\ngithub = \"a/b\"\nfallback = \"cargo install b\"")?
+                .hooks_digest()
+                .is_some()
+        );
+        assert!(
+            parse("[bins.a]\ngithub = \"a/b\"\nversion-command = \"-V\"")?
+                .hooks_digest()
+                .is_some()
+        );
+        assert!(
+            parse("github.token = \"command:gh auth token\"\n[bins.a]\ngithub = \"a/b\"")?
+                .hooks_digest()
+                .is_some()
+        );
+        assert!(
+            parse("github.token = \"env:TOKEN\"\n[bins.a]\ngithub = \"a/b\"")?
+                .hooks_digest()
+                .is_none()
+        );
+        assert!(
+            parse("github.token = \"file:/a/token\"\n[bins.a]\ngithub = \"a/b\"")?
+                .hooks_digest()
+                .is_some()
+        );
+        let downloads = [
+            "asset-url = \"https://a.b/${HOME}\"",
+            "headers = { a = \"${HOME}\" }",
+            "basic-auth = \"me:${HOME}\"",
+        ]
+        .map(|s| parse(&format!("[bins.a]\ngithub = \"a/b\"\n{}", s)));
+        for config in &downloads {
+            assert!(config.as_ref().unwrap().hooks_digest().is_some());
+        }
+        assert!(parse("[bins.a]\nurls.linux = \"https://a.b/a\"")?
+            .hooks_digest()
+            .is_some());
+        assert!(parse("[bins.a]\nfrom = \"urls:https://a.b/a\"")?
+            .hooks_digest()
+            .is_some());
+
+        config.disable_hooks();
+        assert!(config.bins()[0].hooks_on(HookEvent::Install).is_empty());
+
+        let mut config = parse(
+            "github.token = \"command:gh auth token\"\n[bins.a]\ngithub = \"a/b\"\nversion-command = \"-V\"",
+        )?;
+        config.disable_hooks();
+        assert_eq!(config.github_token(), &None);
+        assert_eq!(config.bins()[0].version_command(), &None);
+
+        let mut config = parse(
+            "github.token = \"file:/a/token\"\n[bins.a]\ngithub = \"a/b\"\nheaders = { a = \"${HOME}\" }\nbasic-auth = \"me:${HOME}\"\nasset-url = \"https://a.b/a\"\n[bins.b]\nurls.linux = \"https://a.b/b\"",
+        )?;
+        config.disable_hooks();
+        assert_eq!(config.github_token(), &None);
+        assert!(config.bins()[0].headers().is_empty());
+        assert_eq!(config.bins()[0].basic_auth(), &None);
+        assert_eq!(config.bins()[0].asset_url(), &None);
+        assert!(config.bins()[0].enabled());
+        assert!(!config.bins()[1].enabled());
+        Ok(())
+    }
+
+    #[test]
+    fn test_hooks_digest_includes() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("config.toml");
+        write(
+            &path,
+            "include = [\"*.yaml\"]\n[bins.a]\ngithub = \"a/b\"\nhook.install = \"echo 1\"",
+        )?;
+        let config = from_path(&path)?;
+        assert_eq!(config.files(), &[path.canonicalize()?]);
+        let digest = config.hooks_digest().clone();
+        assert!(digest.is_some());
+
+        // a new included file changes the digest even without commands
+        write(dir.path().join("b.yaml"), "bins:\n  b:\n    github: c/d\n")?;
+        let config = from_path(&path)?;
+        assert_eq!(config.files().len(), 2);
+        assert_ne!(config.hooks_digest(), &digest);
+        Ok(())
+    }
+
+    #[test]
+    fn test_default() -> Result<()> {
+        let raw = Format::Toml.parse::<RawConfig>(
+            r#"
+pick-regex = "top"
+bin-glob = "top"
+
+[default]
+pick-regex = "default"
+exe.type = "symlink"
+hooks = [{ command = "echo default", on = ["install"] }]
+
+[bins.a]
+github = "a/b"
+
+[bins.b]
+github = "c/d"
+pick-regex = "b"
+exe.type = "copy"
+hooks = [{ command = "echo b", on = ["update"] }]
+"#,
+        )?;
+        let config = Config::try_from(raw)?;
+        let (a, b) = (&config.bins()[0], &config.bins()[1]);
+        assert_eq!(a.pick_regex().as_deref(), Some("default"));
+        assert_eq!(a.bin_glob().as_deref(), Some("top"));
+        assert_eq!(a.exe_type(), &Some(ExeType::Symlink));
+        assert_eq!(
+            a.hooks(),
+            &[Hook::new("echo default", vec![HookEvent::Install])]
+        );
+        assert_eq!(b.pick_regex().as_deref(), Some("b"));
+        assert_eq!(b.exe_type(), &Some(ExeType::Copy));
+        assert_eq!(b.hooks(), &[Hook::new("echo b", vec![HookEvent::Update])]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_starter() -> Result<()> {
+        let config = Config::try_from(Format::Toml.parse::<RawConfig>(STARTER)?)?;
+        assert!(config.bins().is_empty());
+
+        // 注释中的示例也是有效的配置
+        let uncommented = STARTER
+            .lines()
+            .map(|line| match line.strip_prefix("# ") {
+                Some(l) if l.contains('=') || l.starts_with('[') => l,
+                _ => line,
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let config = Config::try_from(Format::Toml.parse::<RawConfig>(&uncommented)?)?;
+        let names = config.bins().iter().map(|b| b.name()).collect::<Vec<_>>();
+        assert_eq!(names, ["rg", "fd"]);
+        assert_eq!(config.max_cache_size(), &Some(1024 * 1024 * 1024));
+        Ok(())
+    }
+
+    #[test]
+    fn test_serialize_resolved() -> Result<()> {
+        let raw = Format::Toml.parse::<RawConfig>(
+            r#"
+read-timeout = 5
+github.token = "abc"
+
+[bins.a]
+github = "a/b"
+"#,
+        )?;
+        let config = Config::try_from(raw)?;
+        let val = toml::Value::try_from(&config)?;
+        assert_eq!(val["github-token"].as_str(), Some("config value"));
+        assert_eq!(val["connect-timeout"].as_integer(), Some(10));
+        assert_eq!(val["bins"][0]["source"].as_str(), Some("github:a/b"));
+        assert_eq!(val["bins"][0]["read-timeout"].as_integer(), Some(5));
+        Ok(())
+    }
+
+    #[test]
+    fn test_http_options() -> Result<()> {
+        let raw = toml::from_str::<RawConfig>(
+            r#"
+connect-timeout = 5
+read-timeout = 60
+pool-max-idle = 2
+
+[bins.a]
+github = "a/b"
+
+[bins.b]
+github = "c/d"
+read-timeout = 120
+"#,
+        )?;
+        let config = Config::try_from(raw)?;
+        assert_eq!(config.connect_timeout(), &Duration::from_secs(5));
+        assert_eq!(config.pool_max_idle(), &2);
+        assert_eq!(config.bins()[0].read_timeout(), &Duration::from_secs(60));
+        assert_eq!(config.bins()[1].read_timeout(), &Duration::from_secs(120));
+
+        let raw = toml::from_str::<RawConfig>("[bins.a]\ngithub = \"a/b\"")?;
+        let config = Config::try_from(raw)?;
+        assert_eq!(config.connect_timeout(), &DEFAULT_CONNECT_TIMEOUT);
+        assert_eq!(config.pool_idle_timeout(), &DEFAULT_POOL_IDLE_TIMEOUT);
+        assert_eq!(config.bins()[0].read_timeout(), &DEFAULT_READ_TIMEOUT);
+        Ok(())
+    }
+
+    #[test]
+    fn test_github_sources() -> Result<()> {
+        let raw = toml::from_str::<RawConfig>(
+            r#"
+[bins.a]
+github = "a/b"
+source = "github-tag"
+hook.build = "make"
+
+[bins.c]
+github = "c/d"
+
+[bins.e]
+github = "e/f"
+source = "github-actions"
+workflow = "nightly.yml"
+branch = "main"
+"#,
+        )?;
+        let config = Config::try_from(raw)?;
+        let sources = config
+            .bins()
+            .iter()
+            .map(|b| b.source().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sources,
+            [
+                "github-tag:a/b",
+                "github:c/d",
+                "github-actions:e/f/nightly.yml@main"
+            ]
+        );
+        for (s, bin) in sources.iter().zip(config.bins()) {
+            assert_eq!(&s.parse::<Source>()?, bin.source());
+        }
+        let source = "github-actions:e/f/nightly.yml".parse::<Source>()?;
+        assert_eq!(source.to_string(), "github-actions:e/f/nightly.yml");
+        assert!("github-actions:e/f".parse::<Source>().is_err());
+        assert_eq!(source.repo_url().as_deref(), Some("https://github.com/e/f"));
+        assert_eq!("gitlab:a/b".parse::<Source>()?.repo_url(), None);
+
+        let raw =
+            toml::from_str::<RawConfig>("[bins.a]\ngithub = \"a/b\"\nsource = \"github-actions\"")?;
+        assert!(Config::try_from(raw).is_err());
+        assert_eq!(
+            config.bins()[0]
+                .hook()
+                .as_ref()
+                .and_then(|h| h.build().as_deref()),
+            Some("make")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_source() -> Result<()> {
+        let raw = toml::from_str::<RawConfig>(
+            r#"
+[bins.a]
+from = "gitlab:g/p"
+
+[bins.b]
+from = "github:c/d"
+"#,
+        )?;
+        let config = Config::try_from(raw)?;
+        assert_eq!(
+            config.bins()[0].source(),
+            &Source::Custom {
+                name: "gitlab".to_owned(),
+                value: "g/p".to_owned()
+            }
+        );
+        assert_eq!(config.bins()[0].source().to_string(), "gitlab:g/p");
+        assert_eq!(config.bins()[1].source().name(), "github");
+        assert_eq!(
+            "http:https://a.b/c".parse::<Source>()?.to_string(),
+            "http:https://a.b/c"
+        );
+        assert!(r#"{"github":{}}"#.parse::<Source>().is_err());
+
+        let raw = toml::from_str::<RawConfig>("[bins.a]\nfrom = \"a:b\"\ngithub = \"a/b\"")?;
+        assert!(Config::try_from(raw).is_err());
+
+        let raw = toml::from_str::<RawConfig>(
+            r#"
+[bins.a.urls]
+linux-x86_64 = "https://a.b/{{version}}/a-linux.tgz"
+macos = "https://a.b/{{version}}/a-macos.tgz"
+"#,
+        )?;
+        let source = Config::try_from(raw)?.bins()[0].source().clone();
+        assert_eq!(source.name(), "urls");
+        assert_eq!(source.to_string().parse::<Source>()?, source);
+        let source = "urls:https://a.b/a-linux.tgz, https://a.b/a-macos.tgz".parse::<Source>()?;
+        assert_eq!(
+            source,
+            Source::Urls(PlatformUrls::List(vec![
+                "https://a.b/a-linux.tgz".to_owned(),
+                "https://a.b/a-macos.tgz".to_owned()
+            ]))
+        );
+        Ok(())
+    }
+}
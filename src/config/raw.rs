@@ -1,4 +1,4 @@
-use std::{fmt, str::FromStr};
+use std::{fmt, path::PathBuf, str::FromStr};
 
 use anyhow::anyhow;
 use anyhow::{Error, Result};
@@ -6,34 +6,308 @@ use getset::Getters;
 use indexmap::IndexMap;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-use super::{GitHubRepository, HookAction};
+use super::{
+    Completion, Condition, ExeType, GitHubRepository, Hook, HookAction, PlatformUrls, VersionFrom,
+};
 
-#[derive(Debug, Default, Deserialize, PartialEq, Eq)]
-#[serde(default, rename_all = "kebab-case")]
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
 pub struct RawConfig {
+    /// the paths or globs of other config files relative to this file. the bins are
+    /// merged and other values in later files override the earlier
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+
     pub bins: IndexMap<String, RawBinary>,
 
+    /// the values applied to all bins unless a bin overrides them. fall back to the top
+    /// level `bin-glob`, `pick-regex`, `ignore-regex`, `hook` and `read-timeout`
+    #[serde(skip_serializing_if = "RawDefault::is_empty")]
+    pub default: RawDefault,
+
     pub bin_glob: Option<String>,
 
     pub pick_regex: Option<String>,
 
+    /// a regex template of the assets never picked like checksums or static builds
+    pub ignore_regex: Option<String>,
+
     pub hook: Option<HookAction>,
+
+    /// run hooks by `$SHELL -c` for pipes and redirections. default true
+    pub shell: Option<bool>,
+
+    /// the max number of version history kept for each binary
+    pub history_limit: Option<usize>,
+
+    /// the seconds to wait for connecting to a server. default 10
+    pub connect_timeout: Option<u64>,
+
+    /// the seconds to wait for a response or the next chunk of a download. default 30
+    pub read_timeout: Option<u64>,
+
+    /// the max idle connections kept for each host and shared by all bins. default 8
+    pub pool_max_idle: Option<usize>,
+
+    /// the seconds to keep an idle connection for reuse. default 90
+    pub pool_idle_timeout: Option<u64>,
+
+    /// the dir of installed files and db. support `~` and `${VAR}`
+    pub data_dir: Option<String>,
+
+    /// the dir of downloaded files. support `~` and `${VAR}`
+    pub cache_dir: Option<String>,
+
+    /// the max size of the cache dir like `1GiB`. evict the least recently used downloads
+    /// after install if exceeded
+    pub max_cache_size: Option<String>,
+
+    /// the dir to link executables. support `~` and `${VAR}`
+    pub bin_dir: Option<String>,
+
+    /// the file to write the trace logs of every run. default `binaries.log` in the cache dir
+    pub log_file: Option<String>,
+
+    /// the max size of the log file like `10MiB` before rotated. `0` disables the log file
+    pub max_log_size: Option<String>,
+
+    pub github: Option<RawGithub>,
+
+    /// the aliases of an arch in asset names like `armv7 = ["armv7", "armhf"]`. replace
+    /// the builtin aliases of the arch
+    #[serde(skip_serializing_if = "IndexMap::is_empty")]
+    pub archs: IndexMap<String, Vec<String>>,
+
+    /// the url prefixes of downloads replaced by mirrors like
+    /// `"https://github.com/" = "https://ghproxy.com/https://github.com/"`. download from
+    /// the original url if the mirror failed
+    #[serde(skip_serializing_if = "IndexMap::is_empty")]
+    pub mirrors: IndexMap<String, String>,
+
+    /// the loaded config file and its included files in order. empty if not loaded from files
+    #[serde(skip)]
+    pub files: Vec<PathBuf>,
+}
+
+/// `[default]`中应用到所有bin的值
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct RawDefault {
+    pub bin_glob: Option<String>,
+
+    pub pick_regex: Option<String>,
+
+    pub ignore_regex: Option<String>,
+
+    pub hook: Option<HookAction>,
+
+    /// the hooks of bins without their own `hooks`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub hooks: Vec<Hook>,
+
+    pub exe: Option<ExeType>,
+
+    pub privileged: Option<bool>,
+
+    pub read_timeout: Option<u64>,
+
+    pub delta: Option<bool>,
+
+    pub verify_attestation: Option<bool>,
+
+    pub require_attestation: Option<bool>,
+
+    pub keep_quarantine: Option<bool>,
+
+    pub codesign: Option<bool>,
+
+    pub system: Option<bool>,
+
+    pub min_release_age: Option<String>,
+}
+
+impl RawDefault {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// 按字段合并，优先使用self中的值
+    pub fn or(self, other: Self) -> Self {
+        Self {
+            bin_glob: self.bin_glob.or(other.bin_glob),
+            pick_regex: self.pick_regex.or(other.pick_regex),
+            ignore_regex: self.ignore_regex.or(other.ignore_regex),
+            hook: self.hook.or(other.hook),
+            hooks: if self.hooks.is_empty() {
+                other.hooks
+            } else {
+                self.hooks
+            },
+            exe: self.exe.or(other.exe),
+            privileged: self.privileged.or(other.privileged),
+            read_timeout: self.read_timeout.or(other.read_timeout),
+            delta: self.delta.or(other.delta),
+            verify_attestation: self.verify_attestation.or(other.verify_attestation),
+            require_attestation: self.require_attestation.or(other.require_attestation),
+            keep_quarantine: self.keep_quarantine.or(other.keep_quarantine),
+            codesign: self.codesign.or(other.codesign),
+            system: self.system.or(other.system),
+            min_release_age: self.min_release_age.or(other.min_release_age),
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct RawGithub {
+    /// a token value, `env:VAR`, `file:path` or `command:cmd`
+    pub token: Option<String>,
 }
 
 #[derive(Debug, PartialEq, Eq, Default, Getters, Serialize, Deserialize)]
 #[getset(get = "pub", get_mut = "pub")]
-#[serde(default, rename_all = "kebab-case")]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
 pub struct RawBinary {
     version: Option<String>,
 
     hook: Option<HookAction>,
 
+    /// the commands run on multiple events like `{ command = "..", on = ["install", "update"] }`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    hooks: Vec<Hook>,
+
     /// a glob of executable file in zip. for help to comfirm exe bin
     bin_glob: Option<String>,
 
     pick_regex: Option<String>,
 
+    /// a regex template of the assets never picked like checksums or static builds
+    ignore_regex: Option<String>,
+
+    /// a url template of the asset. skip picking if it is set
+    asset_url: Option<String>,
+
+    /// a github release asset id. skip picking if it is set
+    asset_id: Option<i64>,
+
+    /// the way to link the executable file: shim, link, symlink or copy
+    exe: Option<ExeType>,
+
+    /// the globs of completion files in the extracted
+    completion: Option<Completion>,
+
     github: Option<GitHubRepository>,
+
+    /// the source in `<name>:<value>` like `github:owner/repo` or a registered third-party source
+    from: Option<String>,
+
+    /// the kind of the github source. default `github` for release assets
+    source: Option<SourceKind>,
+
+    /// the workflow file name of the `github-actions` source
+    workflow: Option<String>,
+
+    /// the branch of the `github-actions` workflow runs
+    branch: Option<String>,
+
+    /// skip the bin if the condition does not match this machine
+    when: Option<Condition>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+
+    /// skip the bin in install, update and sync but keep it installed. default true
+    enabled: Option<bool>,
+
+    /// retry the operations in the bin dir by sudo or doas on permission denied. default false
+    privileged: Option<bool>,
+
+    /// the names to link the executable as instead of the bin name
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    alias: Vec<String>,
+
+    /// the seconds to wait for a response or the next chunk of a download. use the global if none
+    read_timeout: Option<u64>,
+
+    /// the headers of downloads like `{ PRIVATE-TOKEN = "${GITLAB_TOKEN}" }`. reference
+    /// secrets by env vars that are expanded only on downloading
+    #[serde(skip_serializing_if = "IndexMap::is_empty")]
+    headers: IndexMap<String, String>,
+
+    /// the basic auth of downloads in `user:password` like `me:${PASSWORD}`
+    basic_auth: Option<String>,
+
+    /// download only the changed blocks on updating if the asset has a `.zsync` file next to it
+    /// and the previous asset is in the cache. default false
+    delta: Option<bool>,
+
+    /// verify the github artifact attestation of the downloaded asset by `gh attestation verify`
+    /// if the repo publishes it. skipped for the non-github sources. default false
+    verify_attestation: Option<bool>,
+
+    /// refuse the assets without a valid github artifact attestation. implies
+    /// `verify-attestation`. default false
+    require_attestation: Option<bool>,
+
+    /// keep the `com.apple.quarantine` attribute of the executable on macos. default false
+    keep_quarantine: Option<bool>,
+
+    /// verify the signature of the executable by `codesign` on macos and warn if ad-hoc
+    /// signed. default false
+    codesign: Option<bool>,
+
+    /// reuse the executable on PATH provided by the os like apt, brew or pacman instead of
+    /// downloading if its version matches `version`. default false
+    system: Option<bool>,
+
+    /// the args to print the version of the executable on PATH not installed by this.
+    /// default `--version`
+    version_command: Option<String>,
+
+    /// the regex to find the version in the output of `version-command`. use the first
+    /// group if any otherwise the whole match. default a semver like regex
+    version_regex: Option<String>,
+
+    /// the interpreter like `/usr/bin/env python3` written as the `#!` line of the script
+    /// without one downloaded by the `script` source
+    shebang: Option<String>,
+
+    /// the digest like `sha256:<hex>` to verify the script downloaded by the `script` source
+    checksum: Option<String>,
+
+    /// the response header used as the latest version of the url of the `script` source.
+    /// default `etag` then `last-modified`
+    version_header: Option<String>,
+
+    /// the command template like `cargo install foo --version {{version}}` to build the
+    /// binary if no asset matches the current platform. it runs in an empty dir with
+    /// `CARGO_INSTALL_ROOT` and `GOBIN` pointing into it
+    fallback: Option<String>,
+
+    /// the url templates of the files on a plain http server. a map of `os-arch` or `os` to
+    /// the url, or a list picked by the platform in the file names
+    urls: Option<PlatformUrls>,
+
+    /// the url with a regex or json pointer to discover the latest version from instead of the
+    /// source like `{ url = "https://a.b/download", regex = 'foo-(\d+\.\d+\.\d+)' }` or
+    /// `{ url = "https://a.b/latest.json", pointer = "/version" }`
+    version_from: Option<VersionFrom>,
+
+    /// skip the releases published less than the age like `3d` or `12h` ago in `update`.
+    /// they are shown as pending in `check`
+    min_release_age: Option<String>,
+}
+
+/// github repo中下载的内容
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SourceKind {
+    /// the assets of releases
+    Github,
+    /// the tarball of a tag or branch for repos without releases
+    GithubTag,
+    /// the artifacts of the latest successful workflow run. requires a token
+    GithubActions,
 }
 
 impl FromStr for GitHubRepository {
@@ -41,7 +315,9 @@ impl FromStr for GitHubRepository {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let re = regex::Regex::new("^(?P<owner>[a-zA-Z0-9_-]+)/(?P<name>[a-zA-Z0-9\\._-]+)$")?;
-        let captures = re.captures(s).ok_or_else(|| anyhow!("{}", s))?;
+        let captures = re
+            .captures(s)
+            .ok_or_else(|| anyhow!("invalid github repository `{}`. expected `owner/repo`", s))?;
         let owner = captures.name("owner").unwrap().as_str().to_string();
         let name = captures.name("name").unwrap().as_str().to_string();
         Ok(Self { owner, name })
@@ -117,10 +393,29 @@ mod tests {
         let config = RawConfig {
             bin_glob: Some("{{bin}}".to_owned()),
             pick_regex: Some("{{a}}".to_owned()),
+            ignore_regex: None,
+            shell: None,
             hook: Some(HookAction {
                 extract: Some("a".to_owned()),
                 ..Default::default()
             }),
+            history_limit: Some(5),
+            connect_timeout: None,
+            read_timeout: None,
+            pool_max_idle: None,
+            pool_idle_timeout: None,
+            include: vec![],
+            default: RawDefault::default(),
+            data_dir: None,
+            cache_dir: None,
+            max_cache_size: None,
+            bin_dir: None,
+            log_file: None,
+            max_log_size: None,
+            github: None,
+            archs: IndexMap::new(),
+            mirrors: IndexMap::new(),
+            files: vec![],
             bins: [
                 (
                     "clash",
@@ -149,6 +444,7 @@ mod tests {
             r#"
 bin-glob = "{bin_glob}"
 pick-regex = "{pick_regex}"
+history-limit = {history_limit}
 
 [hook]
 #install = "install"
@@ -163,6 +459,7 @@ github = "{github2}"
 "#,
             bin_glob = config.bin_glob.as_ref().unwrap(),
             pick_regex = config.pick_regex.as_ref().unwrap(),
+            history_limit = config.history_limit.unwrap(),
             extract = config
                 .hook
                 .as_ref()
@@ -207,6 +504,28 @@ github = "{github2}"
     }
 
     #[test]
+    fn test_de_exe() -> Result<()> {
+        let s = r#"
+[bins.a]
+github = "a/b"
+exe.type = "copy"
+
+[bins.b]
+github = "c/d"
+[bins.b.exe]
+type = "shim"
+"#;
+        let raw: RawConfig = toml::from_str(s)?;
+        assert_eq!(raw.bins["a"].exe(), &Some(ExeType::Copy));
+        assert!(matches!(
+            raw.bins["b"].exe(),
+            Some(ExeType::Shim { template }) if template.contains("exe_path")
+        ));
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
     fn test_commands() -> Result<()> {
         use std::os::unix::process::CommandExt;
         use std::process::*;
@@ -0,0 +1,527 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+
+use getset::Getters;
+use globset::Glob;
+use log::{debug, trace};
+use regex::Regex;
+use reqwest::header::{HeaderName, HeaderValue};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use url::Url;
+
+use super::{
+    include_paths,
+    raw::{RawBinary, RawConfig, RawDefault, SourceKind},
+    ExeType, Format, GithubToken, Hook, HookAction, HookEvent, PlatformUrls, Source,
+};
+use crate::checksum::is_supported;
+use crate::util::{expand_env, parse_duration, parse_size};
+
+const PLAINTEXT_SECRET: &str = "plaintext secret. reference an env var like `${TOKEN}` instead";
+
+/// 配置中发现的一个问题
+#[derive(Debug, Clone, PartialEq, Eq, Getters, Serialize)]
+#[getset(get = "pub")]
+pub struct Problem {
+    path: PathBuf,
+    /// the key of the problem value like `bins.a.pick-regex`. none for the whole file
+    key: Option<String>,
+    /// the parser error with the line and column if any
+    message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.key {
+            Some(key) => write!(f, "{}: {}: {}", self.path.display(), key, self.message),
+            None => write!(f, "{}: {}", self.path.display(), self.message),
+        }
+    }
+}
+
+/// 检查配置文件及其include的文件，返回所有发现的问题而不是在第一个错误时失败
+pub fn validate(path: impl AsRef<Path>) -> Vec<Problem> {
+    let mut validator = Validator::default();
+    validator.check_file(path.as_ref(), &mut vec![]);
+    debug!("found {} problems in config", validator.problems.len());
+    validator.problems
+}
+
+#[derive(Debug, Default)]
+struct Validator {
+    problems: Vec<Problem>,
+    /// the file of each loaded bin for duplicate detection
+    origins: HashMap<String, PathBuf>,
+}
+
+impl Validator {
+    fn push(&mut self, path: &Path, key: Option<String>, message: impl fmt::Display) {
+        let problem = Problem {
+            path: path.to_owned(),
+            key,
+            message: message.to_string(),
+        };
+        trace!("found problem: {}", problem);
+        self.problems.push(problem);
+    }
+
+    fn check<T, E: fmt::Display>(&mut self, path: &Path, key: String, res: Result<T, E>) {
+        if let Err(e) = res {
+            self.push(path, Some(key), e);
+        }
+    }
+
+    fn check_file(&mut self, path: &Path, stack: &mut Vec<PathBuf>) {
+        let path = match path.canonicalize() {
+            Ok(p) => p,
+            Err(e) => return self.push(path, None, e),
+        };
+        if stack.contains(&path) {
+            return self.push(&path, Some("include".to_owned()), "found include cycle");
+        }
+        let contents = match read_to_string(&path) {
+            Ok(s) => s,
+            Err(e) => return self.push(&path, None, e),
+        };
+        let format = Format::from_path(&path).unwrap_or_else(|| Format::sniff(&contents));
+        let mut table = match format.parse::<Value>(&contents) {
+            Ok(Value::Object(table)) => table,
+            Ok(Value::Null) => Map::new(),
+            Ok(_) => return self.push(&path, None, "expected a table at the top level"),
+            Err(e) => return self.push(&path, None, format!("{:?} syntax: {:#}", format, e)),
+        };
+
+        let bins = table.remove("bins");
+        let includes = match serde_json::from_value::<RawConfig>(Value::Object(table)) {
+            Ok(raw) => {
+                self.check_common(&path, &raw);
+                raw.include
+            }
+            Err(e) => {
+                self.push(&path, None, e);
+                vec![]
+            }
+        };
+        match bins {
+            Some(Value::Object(bins)) => {
+                for (name, bin) in bins {
+                    self.check_bin(&path, name, bin);
+                }
+            }
+            Some(Value::Null) | None => {}
+            Some(_) => self.push(&path, Some("bins".to_owned()), "expected a table"),
+        }
+
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        stack.push(path.clone());
+        for pattern in includes {
+            match include_paths(dir, &pattern) {
+                Ok(paths) => paths
+                    .iter()
+                    .filter(|p| p != &&path)
+                    .for_each(|p| self.check_file(p, stack)),
+                Err(e) => self.push(&path, Some(format!("include.{}", pattern)), e),
+            }
+        }
+        stack.pop();
+    }
+
+    /// 检查顶层的默认值
+    fn check_common(&mut self, path: &Path, raw: &RawConfig) {
+        if let Some(glob) = &raw.bin_glob {
+            self.check(path, "bin-glob".to_owned(), check_glob_template(glob));
+        }
+        if let Some(re) = &raw.pick_regex {
+            self.check(path, "pick-regex".to_owned(), check_regex_template(re));
+        }
+        if let Some(re) = &raw.ignore_regex {
+            self.check(path, "ignore-regex".to_owned(), check_regex_template(re));
+        }
+        if let Some(hook) = &raw.hook {
+            self.check_hook(path, "hook", hook);
+        }
+        self.check_default(path, &raw.default);
+        for (key, timeout) in [
+            ("connect-timeout", raw.connect_timeout),
+            ("read-timeout", raw.read_timeout),
+        ] {
+            if timeout == Some(0) {
+                self.push(path, Some(key.to_owned()), "zero timeout");
+            }
+        }
+        for (key, dir) in [
+            ("data-dir", &raw.data_dir),
+            ("cache-dir", &raw.cache_dir),
+            ("bin-dir", &raw.bin_dir),
+            ("log-file", &raw.log_file),
+        ] {
+            if let Some(dir) = dir {
+                self.check(path, key.to_owned(), expand_env(dir));
+            }
+        }
+        for (key, size) in [
+            ("max-cache-size", &raw.max_cache_size),
+            ("max-log-size", &raw.max_log_size),
+        ] {
+            if let Some(size) = size {
+                self.check(path, key.to_owned(), parse_size(size));
+            }
+        }
+        for (prefix, mirror) in &raw.mirrors {
+            let res = Url::parse(mirror).map(|_| ());
+            self.check(path, format!("mirrors.{}", prefix), res);
+        }
+        if let Some(token) = raw.github.as_ref().and_then(|g| g.token.as_ref()) {
+            let res = token.parse::<GithubToken>();
+            self.check(path, "github.token".to_owned(), res);
+        }
+    }
+
+    fn check_bin(&mut self, path: &Path, name: String, value: Value) {
+        let key = format!("bins.{}", name);
+        if let Some(origin) = self.origins.get(&name) {
+            let msg = format!("duplicate bin already defined in {}", origin.display());
+            self.push(path, Some(key.clone()), msg);
+        } else {
+            self.origins.insert(name, path.to_owned());
+        }
+
+        let bin = match serde_json::from_value::<RawBinary>(value) {
+            Ok(bin) => bin,
+            Err(e) => return self.push(path, Some(key), e),
+        };
+        match (bin.from(), bin.github()) {
+            (None, None) if bin.urls().is_none() => {
+                self.push(path, Some(key.clone()), "not found source")
+            }
+            (None, None) => {}
+            (Some(_), Some(_)) => self.push(path, Some(key.clone()), "both from and github"),
+            (Some(from), None) => {
+                let res = from.parse::<Source>().map(|_| ());
+                self.check(path, format!("{}.from", key), res);
+            }
+            (None, Some(_)) => {}
+        }
+        if bin.source() == &Some(SourceKind::GithubActions) && bin.workflow().is_none() {
+            self.push(path, Some(key.clone()), "not found workflow");
+        }
+        if let Some(re) = bin.pick_regex() {
+            let res = check_regex_template(re);
+            self.check(path, format!("{}.pick-regex", key), res);
+        }
+        if let Some(re) = bin.ignore_regex() {
+            let res = check_regex_template(re);
+            self.check(path, format!("{}.ignore-regex", key), res);
+        }
+        if let Some(cmd) = bin.version_command() {
+            let res = shell_words::split(cmd).map(|_| ());
+            self.check(path, format!("{}.version-command", key), res);
+        }
+        if let Some(re) = bin.version_regex() {
+            let res = Regex::new(re).map(|_| ());
+            self.check(path, format!("{}.version-regex", key), res);
+        }
+        if let Some(shebang) = bin.shebang() {
+            let res = check_shebang(shebang);
+            self.check(path, format!("{}.shebang", key), res);
+        }
+        if let Some(checksum) = bin.checksum() {
+            let res = match checksum.split_once(':') {
+                Some((algo, hex)) if is_supported(algo) && !hex.is_empty() => Ok(()),
+                _ => Err(anyhow::anyhow!("expected `<md5|sha1|sha256|sha512>:<hex>`")),
+            };
+            self.check(path, format!("{}.checksum", key), res);
+        }
+        match bin.urls() {
+            Some(PlatformUrls::Map(map)) => {
+                for (k, url) in map {
+                    let res = check_template(url);
+                    self.check(path, format!("{}.urls.{}", key, k), res);
+                }
+            }
+            Some(PlatformUrls::List(urls)) => {
+                for (i, url) in urls.iter().enumerate() {
+                    let res = check_template(url);
+                    self.check(path, format!("{}.urls.{}", key, i), res);
+                }
+            }
+            None => {}
+        }
+        if let Some(from) = bin.version_from() {
+            let res = from.url().parse::<Url>().map(|_| ());
+            self.check(path, format!("{}.version-from.url", key), res);
+            if let Some(re) = from.regex() {
+                let res = Regex::new(re).map(|_| ());
+                self.check(path, format!("{}.version-from.regex", key), res);
+            }
+            if from.pointer().as_ref().is_some_and(|p| !p.starts_with('/')) {
+                let k = format!("{}.version-from.pointer", key);
+                self.push(path, Some(k), "json pointer does not start with `/`");
+            }
+        }
+        if let Some(age) = bin.min_release_age() {
+            let res = parse_duration(age).map(|_| ());
+            self.check(path, format!("{}.min-release-age", key), res);
+        }
+        if let Some(cmd) = bin.fallback() {
+            let res = check_template(cmd);
+            self.check(path, format!("{}.fallback", key), res);
+        }
+        if let Some(glob) = bin.bin_glob() {
+            let res = check_glob_template(glob);
+            self.check(path, format!("{}.bin-glob", key), res);
+        }
+        if let Some(url) = bin.asset_url() {
+            let res = expand_env(url).and_then(|url| check_template(&url));
+            self.check(path, format!("{}.asset-url", key), res);
+        }
+        if let Some(ExeType::Shim { template }) = bin.exe() {
+            let res = check_template(template);
+            self.check(path, format!("{}.exe.template", key), res);
+        }
+        if let Some(completion) = bin.completion() {
+            for (k, globs) in [
+                ("fpath", completion.fpath()),
+                ("source", completion.source()),
+            ] {
+                for glob in globs.iter().flatten() {
+                    let res = Glob::new(glob);
+                    self.check(path, format!("{}.completion.{}", key, k), res);
+                }
+            }
+        }
+        if bin.read_timeout() == &Some(0) {
+            self.push(path, Some(format!("{}.read-timeout", key)), "zero timeout");
+        }
+        for (name, val) in bin.headers() {
+            let k = format!("{}.headers.{}", key, name);
+            let res = HeaderName::from_bytes(name.as_bytes())
+                .map_err(anyhow::Error::from)
+                .and_then(|_| expand_env(val))
+                .and_then(|val| HeaderValue::from_str(&val).map_err(Into::into));
+            self.check(path, k.clone(), res);
+            if name.eq_ignore_ascii_case("authorization") && !val.contains("${") {
+                self.push(path, Some(k), PLAINTEXT_SECRET);
+            }
+        }
+        if let Some(auth) = bin.basic_auth() {
+            let k = format!("{}.basic-auth", key);
+            self.check(path, k.clone(), expand_env(auth));
+            if auth
+                .split_once(':')
+                .is_some_and(|(_, pass)| !pass.contains("${"))
+            {
+                self.push(path, Some(k), PLAINTEXT_SECRET);
+            }
+        }
+        if let Some(hook) = bin.hook() {
+            self.check_hook(path, &format!("{}.hook", key), hook);
+        }
+        self.check_hooks(path, &key, bin.hooks());
+        let extracts = bin.hook().iter().filter(|h| h.extract().is_some()).count()
+            + bin
+                .hooks()
+                .iter()
+                .filter(|h| h.on().contains(&HookEvent::Extract))
+                .count();
+        if extracts > 1 {
+            let msg = format!("found {} extract hooks but only one is supported", extracts);
+            self.push(path, Some(key.clone()), msg);
+        }
+        for alias in bin.alias() {
+            if alias.is_empty() || alias.contains(['/', '\\']) {
+                let msg = format!("invalid link name `{}`", alias);
+                self.push(path, Some(format!("{}.alias", key)), msg);
+            }
+        }
+        if let Some(cond) = bin.when() {
+            for (k, pat) in [
+                ("os", cond.os()),
+                ("arch", cond.arch()),
+                ("hostname", cond.hostname()),
+            ] {
+                if let Some(pat) = pat {
+                    self.check(path, format!("{}.when.{}", key, k), Glob::new(pat));
+                }
+            }
+        }
+    }
+
+    /// 检查`[default]`中的值
+    fn check_default(&mut self, path: &Path, default: &RawDefault) {
+        for (k, re) in [
+            ("pick-regex", &default.pick_regex),
+            ("ignore-regex", &default.ignore_regex),
+        ] {
+            if let Some(re) = re {
+                self.check(path, format!("default.{}", k), check_regex_template(re));
+            }
+        }
+        if let Some(glob) = &default.bin_glob {
+            let res = check_glob_template(glob);
+            self.check(path, "default.bin-glob".to_owned(), res);
+        }
+        if let Some(ExeType::Shim { template }) = &default.exe {
+            let res = check_template(template);
+            self.check(path, "default.exe.template".to_owned(), res);
+        }
+        if default.read_timeout == Some(0) {
+            self.push(
+                path,
+                Some("default.read-timeout".to_owned()),
+                "zero timeout",
+            );
+        }
+        if let Some(hook) = &default.hook {
+            self.check_hook(path, "default.hook", hook);
+        }
+        self.check_hooks(path, "default", &default.hooks);
+    }
+
+    fn check_hooks(&mut self, path: &Path, key: &str, hooks: &[Hook]) {
+        for (i, hook) in hooks.iter().enumerate() {
+            let k = format!("{}.hooks.{}", key, i);
+            if hook.on().is_empty() {
+                self.push(path, Some(k.clone()), "empty events in `on`");
+            }
+            self.check(path, format!("{}.command", k), expand_env(hook.command()));
+            if let Some(dir) = hook.work_dir() {
+                self.check(path, format!("{}.work-dir", k), expand_env(dir));
+            }
+            if let Some(shebang) = hook.shebang() {
+                let res = check_shebang(shebang);
+                self.check(path, format!("{}.shebang", k), res);
+            }
+            if hook.timeout() == &Some(0) {
+                self.push(path, Some(format!("{}.timeout", k)), "zero timeout");
+            }
+        }
+    }
+
+    fn check_hook(&mut self, path: &Path, key: &str, hook: &HookAction) {
+        for (k, cmd) in [
+            ("install", hook.install()),
+            ("update", hook.update()),
+            ("extract", hook.extract()),
+            ("uninstall", hook.uninstall()),
+            ("build", hook.build()),
+        ] {
+            if let Some(cmd) = cmd {
+                self.check(path, format!("{}.{}", key, k), expand_env(cmd));
+            }
+        }
+    }
+}
+
+fn check_template(s: &str) -> anyhow::Result<()> {
+    handlebars::Template::compile(s)
+        .map(|_| ())
+        .map_err(Into::into)
+}
+
+fn check_shebang(s: &str) -> anyhow::Result<()> {
+    if shell_words::split(s)?.is_empty() {
+        anyhow::bail!("empty shebang");
+    }
+    Ok(())
+}
+
+/// 检查模板语法，没有模板变量时同时检查regex语法
+fn check_regex_template(s: &str) -> anyhow::Result<()> {
+    check_template(s)?;
+    if !s.contains("{{") {
+        Regex::new(s.trim())?;
+    }
+    Ok(())
+}
+
+/// 检查模板语法，没有模板变量时同时检查glob语法
+fn check_glob_template(s: &str) -> anyhow::Result<()> {
+    let s = expand_env(s)?;
+    check_template(&s)?;
+    if !s.contains("{{") {
+        Glob::new(s.trim())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::write;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_validate() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("config.toml");
+        write(
+            &path,
+            r#"
+include = ["other.toml"]
+pick-regex = "linux("
+read-timeout = 0
+max-cache-size = "1 PB"
+max-log-size = "ten"
+default.pick-regex = "a("
+mirrors = { "https://github.com/" = "ghproxy.com/" }
+
+[bins.a]
+github = "a/b"
+unknown-key = 1
+
+[bins.b]
+github = "c/d"
+bin-glob = "{{name"
+completion.fpath = ["a[b"]
+
+[bins.c]
+version = "1"
+
+[bins.d]
+github = "e/f"
+pick-regex = "{{os}}("
+read-timeout = 0
+headers = { Authorization = "token abc", "bad name" = "a" }
+basic-auth = "me:${HOME}"
+"#,
+        )?;
+        write(dir.path().join("other.toml"), "[bins.a]\ngithub = \"x/y\"")?;
+
+        let keys = validate(&path)
+            .into_iter()
+            .map(|p| p.key)
+            .collect::<Vec<_>>();
+        let expected = [
+            "pick-regex",
+            "default.pick-regex",
+            "read-timeout",
+            "max-cache-size",
+            "max-log-size",
+            "mirrors.https://github.com/",
+            "bins.a",
+            "bins.b.bin-glob",
+            "bins.b.completion.fpath",
+            "bins.c",
+            "bins.d.read-timeout",
+            "bins.d.headers.Authorization",
+            "bins.d.headers.bad name",
+            "bins.a",
+        ];
+        assert_eq!(keys, expected.map(|k| Some(k.to_owned())));
+
+        write(&path, "[bins.a]\ngithub = ")?;
+        let problems = validate(&path);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].key, None);
+        assert!(problems[0].message.contains("line 2"), "{}", problems[0]);
+        Ok(())
+    }
+}
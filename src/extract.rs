@@ -1,22 +1,28 @@
 use std::{
-    fs::{self, create_dir_all, File, Permissions},
+    fs::{self, create_dir_all, File},
     io::{self, Read, Seek},
-    os::unix::prelude::PermissionsExt,
     path::Path,
 };
 
 use anyhow::{anyhow, bail, Result};
 use flate2::read::GzDecoder;
 use infer::MatcherType;
-use log::{debug, info, trace};
+use log::{debug, info, trace, warn};
 use mime::Mime;
 use once_cell::sync::Lazy;
 use tar::Archive;
 use tokio::fs as afs;
 use zip::ZipArchive;
 
-use crate::util::run_cmd;
+use crate::util::{md5_file, run_cmd, set_executable};
 
+/// 记录已解压文件digest的文件名，位于解压目录中
+pub static EXTRACTED_MARK_NAME: &str = ".binaries-extracted";
+
+/// 解压from到to目录中
+///
+/// 如果to中存在与from digest一致的[EXTRACTED_MARK_NAME]则认为已解压过，
+/// 否则清空to目录后重新解压，避免之前失败的解压残留
 pub async fn decompress<P>(from: P, to: P, cmd: Option<&str>) -> Result<()>
 where
     P: AsRef<Path>,
@@ -39,12 +45,31 @@ where
         _ => {}
     }
 
+    let mark_path = to.join(EXTRACTED_MARK_NAME);
+    let digest = {
+        let from = from.clone();
+        tokio::task::spawn_blocking(move || md5_file(from)).await??
+    };
+
     if afs::read_dir(&to).await?.next_entry().await?.is_some() {
-        info!(
-            "a non empty directory {} was found when decompress",
-            to.display()
+        if afs::read_to_string(&mark_path)
+            .await
+            .is_ok_and(|d| d.trim() == digest)
+        {
+            info!(
+                "skipped decompress for the extracted directory {} with digest {}",
+                to.display(),
+                digest
+            );
+            return Ok(());
+        }
+        warn!(
+            "removing stale files in a non empty directory {} for digest {}",
+            to.display(),
+            digest
         );
-        return Ok(());
+        afs::remove_dir_all(&to).await?;
+        afs::create_dir_all(&to).await?;
     }
 
     if let Some(cmd) = cmd {
@@ -61,32 +86,94 @@ where
                 cmd
             );
         }
-        return Ok(());
-    }
-
-    let ty = infer::get_from_path(&from)
-        .map_err(Into::into)
-        .and_then(|ty| ty.ok_or_else(|| anyhow!("not infer mime type for {}", from.display())))?;
-    if matches!(ty.matcher_type(), MatcherType::App | MatcherType::Text if ty.mime_type().contains('x'))
-    {
-        trace!(
-            "skipped decompress for executable file {}: {}",
-            from.display(),
-            ty.mime_type()
-        );
-        let to = to.join(
-            from.file_name()
-                .and_then(|s| s.to_str())
-                .ok_or_else(|| anyhow!("os str"))?,
-        );
-        debug!("renaming {} to {} in extract", from.display(), to.display());
-        afs::rename(from, to).await?;
-        Ok(())
     } else {
-        tokio::task::spawn_blocking(move || extract(from, to))
-            .await?
+        let ty = infer::get_from_path(&from)
             .map_err(Into::into)
+            .and_then(|ty| {
+                ty.ok_or_else(|| anyhow!("not infer mime type for {}", from.display()))
+            })?;
+        if matches!(ty.matcher_type(), MatcherType::App | MatcherType::Text if ty.mime_type().contains('x'))
+        {
+            trace!(
+                "skipped decompress for executable file {}: {}",
+                from.display(),
+                ty.mime_type()
+            );
+            let to = to.join(
+                from.file_name()
+                    .and_then(|s| s.to_str())
+                    .ok_or_else(|| anyhow!("os str"))?,
+            );
+            debug!("renaming {} to {} in extract", from.display(), to.display());
+            afs::rename(&from, to).await?;
+        } else {
+            let (from, to) = (from.clone(), to.clone());
+            tokio::task::spawn_blocking(move || extract(from, to)).await??;
+        }
+    }
+
+    trace!(
+        "writing extracted digest {} to {}",
+        digest,
+        mark_path.display()
+    );
+    afs::write(&mark_path, digest).await?;
+    Ok(())
+}
+
+/// 复制单文件脚本from为to目录中的可执行文件name，没有`#!`时使用shebang写入解释器
+///
+/// 与[decompress]一样在to中记录from的digest，to中已有其它文件时会被清空
+pub async fn place_script<P>(from: P, to: P, name: &str, shebang: Option<&str>) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    let (from, to) = (from.as_ref(), to.as_ref());
+    if is_extracted(from, to).await? {
+        info!("skipped placing the placed script {}", from.display());
+        return Ok(());
+    }
+    if afs::metadata(to).await.is_ok() {
+        afs::remove_dir_all(to).await?;
     }
+    afs::create_dir_all(to).await?;
+
+    let content = afs::read(from).await?;
+    let mut script = vec![];
+    if !content.starts_with(b"#!") {
+        match shebang {
+            Some(shebang) => {
+                let line = format!("#!{}\n", shebang.trim().trim_start_matches("#!"));
+                script.extend_from_slice(line.as_bytes());
+            }
+            None => warn!("no shebang in script {}", from.display()),
+        }
+    }
+    script.extend_from_slice(&content);
+
+    let path = to.join(name);
+    debug!("placing script {} to {}", from.display(), path.display());
+    afs::write(&path, script).await?;
+    set_executable(&path)?;
+
+    let digest = {
+        let from = from.to_owned();
+        tokio::task::spawn_blocking(move || md5_file(from)).await??
+    };
+    afs::write(to.join(EXTRACTED_MARK_NAME), digest).await?;
+    Ok(())
+}
+
+/// 检查to中是否已解压过与from digest一致的文件
+pub async fn is_extracted(from: impl AsRef<Path>, to: impl AsRef<Path>) -> Result<bool> {
+    let mark_path = to.as_ref().join(EXTRACTED_MARK_NAME);
+    let mark = match afs::read_to_string(&mark_path).await {
+        Ok(mark) => mark,
+        Err(_) => return Ok(false),
+    };
+    let from = from.as_ref().to_owned();
+    let digest = tokio::task::spawn_blocking(move || md5_file(from)).await??;
+    Ok(mark.trim() == digest)
 }
 
 fn extract<P>(from: P, to: P) -> Result<()>
@@ -185,6 +272,7 @@ fn ex_zip(from: impl Read + Seek, to: impl AsRef<Path>) -> Result<()> {
         // Get and Set permissions
         #[cfg(unix)]
         {
+            use std::{fs::Permissions, os::unix::prelude::PermissionsExt};
             if let Some(mode) = file.unix_mode() {
                 fs::set_permissions(&outpath, Permissions::from_mode(mode)).unwrap();
             }
@@ -281,6 +369,47 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_decompress_stale_dir() -> Result<()> {
+        let from = "tests/a.tar.gz".parse::<PathBuf>()?;
+        let root = tempdir()?;
+        let to = root.path().join("to");
+        create_dir_all(&to)?;
+        fs::write(to.join("stale"), "a")?;
+
+        decompress(from.as_path(), to.as_path(), None).await?;
+        assert!(!to.join("stale").exists());
+        assert!(to.join("a/a.txt").is_file());
+        assert!(to.join(EXTRACTED_MARK_NAME).is_file());
+
+        // skip if the digest is not changed
+        fs::remove_file(to.join("a/a.txt"))?;
+        decompress(from.as_path(), to.as_path(), None).await?;
+        assert!(!to.join("a/a.txt").exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_place_script() -> Result<()> {
+        let root = tempdir()?;
+        let from = root.path().join("a.py");
+        fs::write(&from, "print(1)\n")?;
+        let to = root.path().join("to");
+
+        place_script(&from, &to, "a", Some("/usr/bin/env python3")).await?;
+        assert_eq!(
+            fs::read_to_string(to.join("a"))?,
+            "#!/usr/bin/env python3\nprint(1)\n"
+        );
+        assert!(to.join(EXTRACTED_MARK_NAME).is_file());
+
+        // keep the shebang of the script
+        fs::write(&from, "#!/bin/sh\necho 1\n")?;
+        place_script(&from, &to, "a", Some("/usr/bin/env python3")).await?;
+        assert_eq!(fs::read_to_string(to.join("a"))?, "#!/bin/sh\necho 1\n");
+        Ok(())
+    }
+
     #[test]
     fn test_gzip() -> Result<()> {
         let zip_path = "tests/a.tar.gz".parse::<PathBuf>()?;
@@ -1,11 +1,17 @@
 // #![allow(unused)]
 
+pub mod checksum;
 pub mod config;
 pub mod extract;
+pub mod manager;
+pub mod migration;
 pub mod package;
 pub mod source;
 pub mod updated_info;
 pub mod util;
+pub mod zsync;
+
+pub use manager::Manager;
 
 pub static CRATE_NAME: &str = env!("CARGO_CRATE_NAME");
 
@@ -1,37 +1,82 @@
 use std::{
+    env::{self, consts::EXE_SUFFIX},
+    fmt,
+    io::{self, IsTerminal, Write},
+    iter::once,
     path::{Path, PathBuf},
     process::exit,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Instant,
 };
 
-use anyhow::{anyhow, bail, Error, Result};
+use anyhow::{anyhow, bail, Context, Error, Result};
 use binaries::{
-    config::{self, Binary, BinaryBuilder, Config, Source},
-    package::{BinaryPackage, BinaryPackageBuilder},
-    updated_info::Mapper,
+    config::{
+        self,
+        lock::{LockFile, LockedBinaryBuilder},
+        Binary, BinaryBuilder, Completion, Config, ExeType, Format, GithubToken, Hook, HookAction,
+        HookEvent, Source,
+    },
+    manager::{
+        build_mapper, evict_cache, BinStatus, InstallOptions, Manager, ManagerBuilder, Outcome,
+        Status,
+    },
+    package::{BinaryPackage, Cancelled, Drift, Provenance, CURRENT_LINK_NAME},
+    source::{github::RateLimited, is_skipped},
+    updated_info::{
+        InstallStat, InstalledFile, Mapper, PinnedInfo, PinnedInfoBuilder, UpdatedInfo,
+    },
+    util::{dir_size, human_size, is_on_path, md5_file, RotatingFile},
     CRATE_NAME,
 };
-use clap::{Args, Parser, Subcommand};
+use chrono::{DateTime, Local, Utc};
+use clap::{ArgEnum, Args, CommandFactory, Parser, Subcommand};
+use completion::{Shell, UpdateCheck};
 use directories::{BaseDirs, ProjectDirs};
+use env_logger::WriteStyle;
+use fs2::FileExt;
 use futures_util::{
     future::{join_all, try_join_all},
+    stream::FuturesUnordered,
     StreamExt,
 };
-use log::{debug, error, info, trace, warn};
-use once_cell::sync::Lazy;
-use reqwest::{
-    header::{self, HeaderMap},
-    Client, ClientBuilder,
-};
-use sqlx::{sqlite::SqlitePoolOptions, Executor};
+use indexmap::{IndexMap, IndexSet};
+use log::{debug, error, info, trace, warn, LevelFilter, Metadata, Record};
+use once_cell::sync::{Lazy, OnceCell};
+use parking_lot::Mutex;
+use reqwest::{StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
 
 use tokio::{
     fs::{self as afs, create_dir_all},
     task::JoinHandle,
 };
+use tokio_util::sync::CancellationToken;
+
+mod completion;
 
 static PROJECT_DIRS: Lazy<ProjectDirs> =
     Lazy::new(|| ProjectDirs::from("xyz", "navyd", CRATE_NAME).expect("no project dirs"));
 
+/// 加载配置后打开的日志文件
+static LOG_FILE: OnceCell<Mutex<RotatingFile>> = OnceCell::new();
+
+/// 日志文件滚动时保留的旧文件数量
+const LOG_FILE_KEEP: usize = 3;
+
+/// `--quiet`时只输出错误
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// 打印操作的状态到stderr，不混入stdout上的命令结果。`--quiet`时不打印
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if !QUIET.load(Ordering::Relaxed) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
 #[tokio::main]
 async fn main() {
     if let Err(e) = Opt::parse().run().await {
@@ -43,12 +88,59 @@ async fn main() {
 #[derive(Debug, Parser)]
 #[clap(author, version, about, long_about = None)]
 struct Opt {
-    #[clap(short, long, parse(from_occurrences))]
+    /// increase the log level by repeating like `-vv`. override `RUST_LOG`
+    #[clap(short, long, action = clap::ArgAction::Count)]
     verbose: u8,
 
+    /// set the level of a module like `--log source::github=trace`. prefix `::` for other
+    /// crates like `--log ::hyper=debug`. repeatable and override `RUST_LOG`
+    #[clap(long, multiple_occurrences = true, value_name = "FILTER")]
+    log: Vec<String>,
+
+    /// only print errors and command results for scripts
+    #[clap(short, long, global = true)]
+    quiet: bool,
+
+    /// when to color the logs. `auto` respects `NO_COLOR`
+    #[clap(long, arg_enum, global = true, default_value = "auto")]
+    color: ColorChoice,
+
+    /// the config file in toml, yaml or json. detected by extension or content
     #[clap(short = 'f', long)]
     config_path: Option<PathBuf>,
 
+    /// print what would be done without touching disk and db
+    #[clap(long, global = true)]
+    dry_run: bool,
+
+    /// the format of command results on stdout
+    #[clap(short, long, arg_enum, global = true, default_value = "text")]
+    output: OutputFormat,
+
+    /// wait for another running instance to finish instead of failing
+    #[clap(long, global = true)]
+    wait: bool,
+
+    /// pick the most downloaded asset instead of prompting when multiple assets match
+    #[clap(long, global = true)]
+    non_interactive: bool,
+
+    /// never escalate the operations of privileged bins by sudo or doas
+    #[clap(long, global = true)]
+    no_sudo: bool,
+
+    /// the dir of installed files and db. override `data-dir` in config
+    #[clap(long, global = true)]
+    data_dir: Option<PathBuf>,
+
+    /// the dir of downloaded files. override `cache-dir` in config
+    #[clap(long, global = true)]
+    cache_dir: Option<PathBuf>,
+
+    /// the dir to link executables. override `bin-dir` in config
+    #[clap(long, global = true)]
+    bin_dir: Option<PathBuf>,
+
     #[clap(subcommand)]
     commands: Commands,
 }
@@ -56,348 +148,3493 @@ struct Opt {
 impl Opt {
     async fn run(&self) -> Result<()> {
         self.init_log()?;
-        let config = self.load_config().await?;
+        let config_path = self.config_path();
+        // validate and edit before loading the config that fails on the first error
+        if let Commands::Config(cmd) = &self.commands {
+            return self.config(cmd, &config_path).await;
+        }
+        if let Commands::Completions(args) = &self.commands {
+            return completions(args, &config_path);
+        }
+        if !config_path.exists() {
+            self.bootstrap_config(&config_path).await?;
+        }
+        let config = config::from_path(&config_path)?;
+        let dirs = self.dirs(&config)?;
+        self.init_log_file(&config, &dirs);
+        if let Commands::Init(args) = &self.commands {
+            return self.init(args, &dirs);
+        }
+        if let Commands::Trust(args) = &self.commands {
+            return self.trust(args, &config_path, &dirs).await;
+        }
 
-        let pm = PackageManager::new(config).await?;
+        // dry run does not write anything so it is safe to run concurrently
+        let _lock = if self.dry_run {
+            None
+        } else {
+            Some(lock_instance(&dirs.data, self.wait).await?)
+        };
+        let interactive =
+            !self.non_interactive && self.output == OutputFormat::Text && io::stdin().is_terminal();
+        let cancel = CancellationToken::new();
+        let pm = PackageManager::new(
+            Manager::builder()
+                .config(config)
+                .data_dir(dirs.data)
+                .cache_dir(dirs.cache)
+                .bin_dir(dirs.bin)
+                .dry_run(self.dry_run)
+                .interactive(interactive)
+                .no_sudo(self.no_sudo)
+                .cancel(cancel.clone())
+                .trusted_dir(PROJECT_DIRS.config_dir()),
+            &config_path,
+            self.output,
+        )
+        .await?;
+        // the commands changing the installs finish or roll back the running jobs and print
+        // the summary. others keep the default behavior that exits immediately
+        let mutating = match &self.commands {
+            Commands::Install(_)
+            | Commands::Update(_)
+            | Commands::Sync(_)
+            | Commands::Import(_)
+            | Commands::Rollback(_)
+            | Commands::Use(_) => true,
+            Commands::Bundle(args) => args.install,
+            _ => false,
+        };
+        if mutating {
+            cancel_on_signal(cancel);
+        }
         match &self.commands {
-            Commands::Install => pm.install().await?,
+            Commands::Install(args) => pm.install(args).await?,
+            Commands::Update(args) => pm.update(args).await?,
             Commands::Uninstall(args) => pm.uninstall(args).await?,
-            Commands::List => pm.list().await?,
+            Commands::Clean(args) => pm.clean(args).await?,
+            Commands::List(args) => pm.list(args).await?,
+            Commands::Pin(args) => pm.pin(args).await?,
+            Commands::Unpin(args) => pm.unpin(args).await?,
+            Commands::Skip(args) => pm.skip(args).await?,
+            Commands::Disable(args) => pm.disable(args).await?,
+            Commands::Repick(args) => pm.repick(args).await?,
+            Commands::Enable(args) => pm.enable(args).await?,
+            Commands::Rollback(args) => pm.rollback(args).await?,
+            Commands::Use(args) => pm.use_version(args).await?,
+            Commands::Info(args) => pm.info(args).await?,
+            Commands::Sync(args) => pm.sync(args).await?,
+            Commands::Prune(args) => pm.prune(args).await?,
+            Commands::Export => pm.export().await?,
+            Commands::Doctor(args) => pm.doctor(args).await?,
+            Commands::Verify(args) => pm.verify(args).await?,
+            Commands::History(args) => pm.history(args).await?,
+            Commands::Import(args) => pm.import(args).await?,
+            Commands::Bundle(args) => pm.bundle(args).await?,
+            Commands::Sbom => pm.sbom().await?,
+            Commands::Run(args) => {
+                let code = pm.run(args).await?;
+                if code != 0 {
+                    exit(code);
+                }
+            }
+            Commands::Check(args) => pm.check(args).await?,
+            Commands::Outdated(args) => {
+                let code = pm.outdated(args).await?;
+                if code != 0 {
+                    exit(code);
+                }
+            }
             _ => {}
         }
         Ok(())
     }
 
-    async fn load_config(&self) -> Result<Config> {
-        let path = self
-            .config_path
-            .as_deref()
-            .map(ToOwned::to_owned)
-            .unwrap_or_else(|| PROJECT_DIRS.config_dir().join("config.toml"));
-        config::from_path(path)
+    async fn config(&self, cmd: &ConfigCommands, path: &Path) -> Result<()> {
+        match cmd {
+            ConfigCommands::Validate => self.validate_config(path),
+            ConfigCommands::Init(args) => self.init_config(path, args.force),
+            ConfigCommands::Edit => self.edit_config(path).await,
+            ConfigCommands::Path => {
+                println!("{}", path.display());
+                Ok(())
+            }
+            ConfigCommands::Show(args) if args.resolved => {
+                self.print_config(&config::from_path(path)?)
+            }
+            ConfigCommands::Show(_) => {
+                let mut raw = config::raw_from_path(path)?;
+                // 与resolved一样不打印token的值
+                if let Some(token) = raw.github.as_mut().and_then(|g| g.token.as_mut()) {
+                    if let Ok(t @ GithubToken::Value(_)) = token.parse() {
+                        *token = t.to_string();
+                    }
+                }
+                self.print_config(&raw)
+            }
+        }
     }
 
-    fn init_log(&self) -> Result<()> {
-        let verbose = self.verbose;
-        if verbose > 4 {
-            return Err(anyhow!("invalid arg: 4 < {} number of verbose", verbose));
+    /// 以toml或json打印配置
+    fn print_config(&self, config: &impl Serialize) -> Result<()> {
+        match self.output {
+            OutputFormat::Text => print!("{}", toml::to_string(&toml::Value::try_from(config)?)?),
+            OutputFormat::Json => print_json(config)?,
         }
-        let level: log::LevelFilter = unsafe { std::mem::transmute((verbose + 1) as usize) };
-        env_logger::builder()
-            .filter_level(log::LevelFilter::Error)
-            .filter_module(module_path!(), level)
-            .init();
         Ok(())
     }
-}
-
-#[derive(Debug, Subcommand)]
-enum Commands {
-    List,
-    Update,
-    Install,
-    Uninstall(UninstallArgs),
-    Clean,
-}
-
-#[derive(Debug, Args)]
-pub struct UninstallArgs {
-    #[clap(short, long)]
-    names: Option<Vec<String>>,
 
-    #[clap(short, long)]
-    all: bool,
-}
+    /// 使用`$VISUAL`或`$EDITOR`编辑配置，保存后验证并报告问题。在终端中可以重新编辑
+    async fn edit_config(&self, path: &Path) -> Result<()> {
+        if !path.exists() {
+            self.bootstrap_config(path).await?;
+        }
+        let editor = env::var("VISUAL")
+            .or_else(|_| env::var("EDITOR"))
+            .unwrap_or_else(|_| "vi".to_owned());
+        let args = shell_words::split(&editor)?;
+        let (program, args) = args.split_first().ok_or_else(|| anyhow!("empty editor"))?;
+        loop {
+            let status = tokio::process::Command::new(program)
+                .args(args)
+                .arg(path)
+                .status()
+                .await
+                .with_context(|| format!("failed to run the editor `{}`", editor))?;
+            if !status.success() {
+                bail!("the editor `{}` exited with {}", editor, status);
+            }
+            let problems = config::validate::validate(path);
+            if problems.is_empty() {
+                status!("{} is valid", path.display());
+                return Ok(());
+            }
+            problems.iter().for_each(|p| eprintln!("{}", p));
+            let retry = !self.non_interactive && io::stdin().is_terminal();
+            if !(retry && confirm("edit again?").await?) {
+                bail!("found {} problems in config", problems.len());
+            }
+        }
+    }
 
-#[derive(Debug, Clone)]
-pub struct PackageManager {
-    bin_pkgs: Vec<BinaryPackage>,
-}
+    fn validate_config(&self, path: &Path) -> Result<()> {
+        let problems = config::validate::validate(path);
+        match self.output {
+            OutputFormat::Text if problems.is_empty() => {
+                println!("{} is valid", path.display())
+            }
+            OutputFormat::Text => problems.iter().for_each(|p| println!("{}", p)),
+            OutputFormat::Json => print_json(&problems)?,
+        }
+        if !problems.is_empty() {
+            bail!("found {} problems in config", problems.len());
+        }
+        Ok(())
+    }
 
-impl PackageManager {
-    pub async fn new(config: Config) -> Result<Self> {
-        let project_dirs = PROJECT_DIRS.clone();
-        let base_dirs = BaseDirs::new().ok_or_else(|| anyhow!("no base dirs"))?;
-
-        let client = build_client()?;
-        let mapper =
-            build_mapper(project_dirs.data_dir().join(&format!("{}.db", CRATE_NAME))).await?;
-
-        let build_pkg = |bin| {
-            let (data_dir, cache_dir, executable_dir) = (
-                project_dirs.data_dir().to_owned(),
-                project_dirs.cache_dir().to_owned(),
-                base_dirs.executable_dir().map(ToOwned::to_owned),
+    /// 写入带注释的初始配置到path，已存在时需要force才覆盖
+    fn init_config(&self, path: &Path, force: bool) -> Result<()> {
+        if path.exists() && !force {
+            bail!(
+                "{} already exists. overwrite it by `--force`",
+                path.display()
             );
-            let client = client.clone();
-            let mapper = mapper.clone();
-            async move {
-                BinaryPackageBuilder::default()
-                    .bin(bin)
-                    .data_dir(data_dir.to_owned())
-                    .link_path(executable_dir.ok_or_else(|| anyhow!("no exe dir"))?)
-                    .cache_dir(cache_dir.to_owned())
-                    .client(client)
-                    .mapper(mapper)
-                    .build()
-                    .await
-            }
-        };
+        }
+        if !matches!(Format::from_path(path), None | Some(Format::Toml)) {
+            bail!("the starter config is toml but got {}", path.display());
+        }
+        if self.dry_run {
+            status!(
+                "[dry-run] would write a starter config to {}",
+                path.display()
+            );
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, config::STARTER)?;
+        println!("wrote a starter config to {}", path.display());
+        Ok(())
+    }
 
-        // build packages
-        let bin_pkgs = try_join_all(
-            config
-                .bins()
-                .iter()
-                .map(Clone::clone)
-                .map(build_pkg)
-                .map(tokio::spawn),
+    /// 配置文件不存在时在终端中提示创建初始配置，否则提示使用`config init`
+    async fn bootstrap_config(&self, path: &Path) -> Result<()> {
+        let interactive = !self.non_interactive
+            && !self.dry_run
+            && io::stdin().is_terminal()
+            && io::stdout().is_terminal();
+        let prompt = format!(
+            "not found config {}. create a starter config?",
+            path.display()
+        );
+        if interactive && confirm(&prompt).await? {
+            return self.init_config(path, false);
+        }
+        let cmd = match &self.config_path {
+            Some(path) => format!("{} -f \"{}\" config init", CRATE_NAME, path.display()),
+            None => format!("{} config init", CRATE_NAME),
+        };
+        bail!(
+            "not found config {}. create one by `{}`",
+            path.display(),
+            cmd
         )
-        .await?
-        .into_iter()
-        .collect::<Result<Vec<BinaryPackage>>>()?;
-
-        trace!("got {} bin packages", bin_pkgs.len());
+    }
 
-        // uninstall unused bins
-        join_all(
-            unused_bins(&mapper, config.bins())
-                .await?
-                .into_iter()
-                .map(build_pkg)
-                .map(|f| async move {
-                    let pkg: BinaryPackage = f.await?;
-                    info!("uninstalling unused binary {}", pkg.bin().bin().name());
-                    pkg.uninstall()
-                        .await
-                        .map(|_| pkg.bin().bin().name().to_owned())
-                        .map_err(|e| {
-                            anyhow!(
-                                "failed to uninstall unused bin {}: {}",
-                                pkg.bin().bin().name(),
-                                e
-                            )
-                        })
-                })
-                .map(tokio::spawn),
-        )
-        .await
-        .iter()
-        .filter_map(|r| r.as_ref().ok())
-        .map(|r| r.as_deref())
-        .for_each(|r: Result<&str, _>| match r {
-            Ok(name) => debug!("uninstalled bin {} of unused", name),
-            Err(e) => warn!("{}", e),
+    /// 打印shell的初始化脚本，检查更新时使用当前的配置文件
+    fn init(&self, args: &InitArgs, dirs: &Dirs) -> Result<()> {
+        if args.add_path {
+            self.add_user_path(&dirs.bin)?;
+        }
+        let check = args.check_updates.map(|hours| UpdateCheck {
+            cmd: match &self.config_path {
+                Some(path) => format!("{} -f \"{}\"", CRATE_NAME, path.display()),
+                None => CRATE_NAME.to_owned(),
+            },
+            stamp: dirs.cache.join("update-check"),
+            interval_mins: hours * 60,
         });
-
-        Ok(Self { bin_pkgs })
+        let snippet = completion::init_snippet(
+            args.shell,
+            &dirs.bin,
+            &dirs.data.join("completions"),
+            check.as_ref(),
+        )?;
+        print!("{}", snippet);
+        Ok(())
     }
 
-    pub async fn uninstall(&self, args: &UninstallArgs) -> Result<()> {
-        if let Some(names) = &args.names {
-            let jobs = names
-                .iter()
-                .flat_map(|name| {
-                    self.bin_pkgs
-                        .iter()
-                        .find(|pkg| pkg.bin().bin().name() == name)
-                })
-                .map(|pkg| {
-                    let pkg = pkg.clone();
-                    async move { pkg.uninstall().await }
-                })
-                .map(tokio::spawn)
-                .collect::<Vec<_>>() as Vec<JoinHandle<Result<()>>>;
-            for job in try_join_all(jobs).await? {
-                if let Err(e) = job {
-                    warn!("failed to uninstall: {}", e);
-                }
-            }
+    /// 在windows注册表中用户的PATH末尾添加dir，已存在时忽略。保留其中未展开的`%VAR%`
+    ///
+    /// 修改后广播环境变量的变更，之后打开的终端会使用新的PATH
+    fn add_user_path(&self, dir: &Path) -> Result<()> {
+        const SCRIPT: &str = r#"$dir = $env:BINARIES_PATH_DIR
+$path = (Get-Item 'HKCU:\Environment').GetValue('Path', '', 'DoNotExpandEnvironmentNames')
+if (($path -split ';') -contains $dir) { return }
+$path = (@($path.TrimEnd(';'), $dir) | Where-Object { $_ }) -join ';'
+Set-ItemProperty 'HKCU:\Environment' Path $path -Type ExpandString
+[Environment]::SetEnvironmentVariable('BINARIES_PATH_DIR', $null, 'User')
+'added'"#;
+        if !cfg!(windows) {
+            bail!("--add-path only supports windows. add the bin dir to PATH by the snippet");
+        }
+        if self.dry_run {
+            status!("[dry-run] would add {} to the user PATH", dir.display());
             return Ok(());
         }
-
-        let mut fails = 0;
-        for res in try_join_all(
-            self.bin_pkgs
-                .iter()
-                .map(Clone::clone)
-                .map(|pkg| async move {
-                    let name = pkg.bin().bin().name();
-
-                    pkg.uninstall().await.map(|_| name.to_owned())
-                })
-                .map(tokio::spawn),
-        )
-        .await?
-        {
-            match res {
-                Ok(name) => info!("uninstalled {}", name),
-                Err(e) => {
-                    fails += 1;
-                    error!("{}", e)
-                }
-            }
+        let out = std::process::Command::new("powershell")
+            .args(["-NoProfile", "-NonInteractive", "-Command", SCRIPT])
+            .env("BINARIES_PATH_DIR", dir)
+            .output()
+            .context("failed to run powershell")?;
+        if !out.status.success() {
+            bail!(
+                "failed to add {} to the user PATH: {}",
+                dir.display(),
+                String::from_utf8_lossy(&out.stderr).trim()
+            );
         }
-        if fails == 0 {
-            Ok(())
+        if String::from_utf8_lossy(&out.stdout).trim() == "added" {
+            status!(
+                "added {} to the user PATH. it takes effect in new terminals",
+                dir.display()
+            );
         } else {
-            bail!("uninstall has failed {} tasks", fails)
+            status!("{} is already in the user PATH", dir.display());
         }
+        Ok(())
     }
 
-    pub async fn list(&self) -> Result<()> {
-        let find_vers = |pkg: BinaryPackage| async move {
-            let old_ver = if pkg.has_installed().await {
-                let mut infos = pkg
-                    .mapper()
-                    .select_list_by_name(pkg.bin().bin().name())
-                    .await?;
-                infos.sort_by(|a, b| b.create_time().cmp(a.create_time()));
-                infos.first().map(|info| info.version().to_owned())
-            } else {
-                info!("installed bin {} is skipped", pkg.bin().bin().name());
-                None
-            };
-            let latest_ver = pkg.bin().latest_ver().await?;
-            Ok::<_, Error>((pkg, old_ver, latest_ver))
+    /// 打印配置中的命令并记录其digest，之后这个配置的命令不再被禁用
+    async fn trust(&self, args: &TrustArgs, config_path: &Path, dirs: &Dirs) -> Result<()> {
+        let path = args.path.as_deref().unwrap_or(config_path).canonicalize()?;
+        let config = config::from_path(&path)?;
+        let digest = match config.hooks_digest() {
+            Some(digest) => digest,
+            None => {
+                status!("no hooks in {}", path.display());
+                return Ok(());
+            }
         };
-
-        let jobs = self
-            .bin_pkgs
-            .iter()
-            .map(Clone::clone)
-            .map(find_vers)
-            .map(tokio::spawn)
-            .collect::<Vec<_>>()
-            as Vec<JoinHandle<Result<(BinaryPackage, Option<String>, String)>>>;
-
-        for job in join_all(jobs).await {
-            match job? {
-                Ok((bin, old, latest)) => {
-                    let name = bin.bin().bin().name();
-                    if let Some(old) = old {
-                        if old < latest {
-                            println!("updateable {}: {} => {}", name, old, latest);
-                        } else {
-                            println!("installed {}: {}", name, old);
-                        }
-                    } else {
-                        println!("installable {}: {}", name, latest);
-                    }
-                }
-                Err(e) => {
-                    eprintln!("faild to check job: {}", e)
+        for file in config.files().iter().skip(1) {
+            println!("include: {}", file.display());
+        }
+        if let Some(token @ (GithubToken::Command(_) | GithubToken::File(_))) =
+            config.github_token()
+        {
+            println!("github token: {}", token);
+        }
+        for bin in config.bins() {
+            for event in HookEvent::ALL {
+                for hook in bin.hooks_on(event) {
+                    println!("{} {}: {}", bin.name(), event, hook.command());
                 }
             }
+            if let Some(cmd) = bin.fallback() {
+                println!("{} fallback: {}", bin.name(), cmd);
+            }
+            if let Some(cmd) = bin.version_command() {
+                println!("{} version-command: {}", bin.name(), cmd);
+            }
+            if let Source::Urls(urls) = bin.source() {
+                println!("{} urls: {}", bin.name(), urls);
+            }
+            if let Some(url) = bin.asset_url() {
+                println!("{} asset-url: {}", bin.name(), url);
+            }
+            for (name, val) in bin.headers() {
+                println!("{} header {}: {}", bin.name(), name, val);
+            }
+            if let Some(auth) = bin.basic_auth() {
+                println!("{} basic-auth: {}", bin.name(), auth);
+            }
+        }
+        let interactive = !self.non_interactive && io::stdin().is_terminal();
+        if interactive && !confirm(&format!("trust the hooks of {}?", path.display())).await? {
+            bail!("not trusted {}", path.display());
+        }
+        if self.dry_run {
+            println!("[dry-run] would trust the hooks of {}", path.display());
+            return Ok(());
         }
+        let mapper = build_mapper(dirs.data.join(format!("{}.db", CRATE_NAME)), false).await?;
+        mapper
+            .insert_trusted_config(&path.display().to_string(), digest)
+            .await?;
+        status!("trusted the hooks of {}", path.display());
         Ok(())
     }
 
-    pub async fn install(&self) -> Result<()> {
-        let task = |pkg: BinaryPackage| async move {
-            if !pkg.has_installed().await {
-                pkg.install().await
-            } else {
-                info!("installed bin {} is skipped", pkg.bin().bin().name());
-                Ok::<_, Error>(())
+    /// 未指定配置文件时，在配置目录中按[Format::EXTENSIONS]的顺序查找第一个存在的config文件
+    fn config_path(&self) -> PathBuf {
+        self.config_path
+            .as_deref()
+            .map(ToOwned::to_owned)
+            .unwrap_or_else(|| {
+                let dir = PROJECT_DIRS.config_dir();
+                Format::EXTENSIONS
+                    .iter()
+                    .map(|ext| dir.join("config").with_extension(ext))
+                    .find(|p| p.is_file())
+                    .unwrap_or_else(|| dir.join("config.toml"))
+            })
+    }
+
+    /// 获取使用的dirs，优先使用命令行参数，其次是配置，否则使用平台默认的dirs
+    fn dirs(&self, config: &Config) -> Result<Dirs> {
+        let data = match self
+            .data_dir
+            .as_ref()
+            .or_else(|| config.data_dir().as_ref())
+        {
+            Some(dir) => dir.to_owned(),
+            None => PROJECT_DIRS.data_dir().to_owned(),
+        };
+        let cache = match self
+            .cache_dir
+            .as_ref()
+            .or_else(|| config.cache_dir().as_ref())
+        {
+            Some(dir) => dir.to_owned(),
+            None => PROJECT_DIRS.cache_dir().to_owned(),
+        };
+        let bin = match self.bin_dir.as_ref().or_else(|| config.bin_dir().as_ref()) {
+            Some(dir) => dir.to_owned(),
+            None => {
+                let base_dirs = BaseDirs::new().ok_or_else(|| anyhow!("no base dirs"))?;
+                executable_dir(&base_dirs).ok_or_else(|| anyhow!("no exe dir"))?
             }
         };
+        let dirs = Dirs { data, cache, bin };
+        debug!("using dirs: {:?}", dirs);
+        Ok(dirs)
+    }
 
-        let jobs = self
-            .bin_pkgs
+    fn init_log(&self) -> Result<()> {
+        let verbose = self.verbose;
+        let level = match verbose {
+            0 => LevelFilter::Error,
+            1 => LevelFilter::Warn,
+            2 => LevelFilter::Info,
+            3 => LevelFilter::Debug,
+            4 => LevelFilter::Trace,
+            _ => bail!("invalid arg: 4 < {} number of verbose", verbose),
+        };
+        QUIET.store(self.quiet, Ordering::Relaxed);
+        let style = match self.color {
+            ColorChoice::Auto if env::var_os("NO_COLOR").is_some() => WriteStyle::Never,
+            ColorChoice::Auto => WriteStyle::Auto,
+            ColorChoice::Always => WriteStyle::Always,
+            ColorChoice::Never => WriteStyle::Never,
+        };
+        let mut builder = env_logger::Builder::new();
+        builder.filter_level(LevelFilter::Error).parse_default_env();
+        if verbose > 0 {
+            builder.filter_module(module_path!(), level);
+        }
+        let filters = self
+            .log
             .iter()
-            .map(Clone::clone)
-            .map(task)
-            .map(tokio::spawn)
-            .collect::<Vec<_>>() as Vec<JoinHandle<Result<()>>>;
-        debug!("waiting for install {} jobs", jobs.len());
+            .map(|v| log_directive(v))
+            .collect::<Vec<_>>();
+        let console = builder
+            .parse_filters(&filters.join(","))
+            .write_style(style)
+            .build();
+        let file = env_logger::filter::Builder::new()
+            .filter_level(LevelFilter::Warn)
+            .filter_module(module_path!(), LevelFilter::Trace)
+            .build();
+        log::set_max_level(console.filter());
+        log::set_boxed_logger(Box::new(Logger { console, file }))?;
+        Ok(())
+    }
 
-        let mut fails = 0;
-        for job in join_all(jobs).await {
-            if let Err(e) = job? {
-                error!("failed to install: {}", e);
-                fails += 1;
-            }
+    /// 打开配置的日志文件记录本次运行的所有trace日志，失败时仅警告
+    fn init_log_file(&self, config: &Config, dirs: &Dirs) {
+        let max_size = *config.max_log_size();
+        if max_size == 0 {
+            return;
         }
-        if fails > 0 {
-            bail!("install has {} failed tasks", fails);
+        let path = config
+            .log_file()
+            .clone()
+            .unwrap_or_else(|| dirs.cache.join(format!("{}.log", CRATE_NAME)));
+        match RotatingFile::open(&path, max_size, LOG_FILE_KEEP) {
+            Ok(file) => {
+                if LOG_FILE.set(Mutex::new(file)).is_ok() {
+                    log::set_max_level(LevelFilter::Trace);
+                    debug!(
+                        "running {:?} with logs in {}",
+                        env::args().collect::<Vec<_>>(),
+                        path.display()
+                    );
+                }
+            }
+            Err(e) => warn!("failed to open the log file {}: {}", path.display(), e),
         }
-        Ok(())
     }
 }
 
-async fn unused_bins(mapper: &Mapper, bins: &[Binary]) -> Result<Vec<Binary>> {
-    let unused = mapper
-        .select_all()
-        .await?
-        .into_iter()
-        .filter(|info| !bins.iter().any(|bin| bin.name() == info.name()))
-        .map(|info| {
-            BinaryBuilder::default()
-                .name(info.name())
-                .source(&serde_json::from_str::<Source>(info.source())?)?
-                .build()
-                .map_err(Into::into)
-        })
-        .collect::<Result<Vec<_>>>()?;
-    debug!(
-        "found {} bins of unused and {} bins of used",
-        unused.len(),
-        bins.len()
-    );
-    Ok(unused)
+/// 将`--log`的过滤规则转换为env_logger的格式。模块默认相对本crate，以`::`开头时为其它crate，
+/// 只有level时设置本crate的level
+fn log_directive(filter: &str) -> String {
+    let module = filter.split('=').next().unwrap_or_default();
+    if !filter.contains('=') && module.parse::<LevelFilter>().is_ok() {
+        format!("{}={}", CRATE_NAME, filter)
+    } else if let Some(filter) = filter.strip_prefix("::") {
+        filter.to_owned()
+    } else if module == CRATE_NAME || module.starts_with(&format!("{}::", CRATE_NAME)) {
+        filter.to_owned()
+    } else {
+        format!("{}::{}", CRATE_NAME, filter)
+    }
 }
 
-fn build_client() -> Result<Client> {
-    let mut headers = HeaderMap::new();
-    headers.insert(
-        header::ACCEPT,
-        header::HeaderValue::from_static("application/vnd.github.v3+json"),
-    );
-    let name = "Authorization";
-    if let Ok(val) = std::env::var(name) {
-        info!("loaded token {} for github rate limit", name);
-        headers.insert(name, header::HeaderValue::from_str(&val)?);
+/// 按verbose在终端输出日志，同时将trace日志写入[LOG_FILE]
+struct Logger {
+    console: env_logger::Logger,
+    file: env_logger::filter::Filter,
+}
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.console.enabled(metadata) || (LOG_FILE.get().is_some() && self.file.enabled(metadata))
     }
-    headers.insert(header::USER_AGENT, header::HeaderValue::from_static("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/100.0.4896.88 Safari/537.36"));
 
-    ClientBuilder::new()
-        .default_headers(headers)
-        .build()
-        .map_err(Into::into)
+    fn log(&self, record: &Record) {
+        if self.console.matches(record) {
+            self.console.log(record);
+        }
+        if let Some(file) = LOG_FILE.get().filter(|_| self.file.matches(record)) {
+            let _ = writeln!(
+                file.lock(),
+                "{} {:<5} {}: {}",
+                Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+                record.level(),
+                record.target(),
+                record.args()
+            );
+        }
+    }
+
+    fn flush(&self) {
+        self.console.flush();
+        if let Some(file) = LOG_FILE.get() {
+            let _ = file.lock().flush();
+        }
+    }
 }
 
-async fn build_mapper(p: impl AsRef<Path>) -> Result<Mapper> {
-    let p = p.as_ref();
+#[derive(Debug, Subcommand)]
+enum Commands {
+    List(ListArgs),
+    /// update installed binaries to the latest versions
+    Update(UpdateArgs),
+    /// print the binaries with newer versions. exit with 1 if any, 0 if all are up to date
+    Outdated(OutdatedArgs),
+    /// check for newer versions and write a status file for shell prompts, e.g. in cron
+    Check(CheckArgs),
+    Install(InstallArgs),
+    Uninstall(UninstallArgs),
+    /// evict the least recently used downloads over max-cache-size or remove all
+    Clean(CleanArgs),
+    /// pin a binary to a version that will not be updated
+    Pin(PinArgs),
+    /// release a pinned binary
+    Unpin(UnpinArgs),
+    /// never install a version of a binary like a known broken release
+    Skip(SkipArgs),
+    /// skip a binary in install, update and sync without removing it from config
+    Disable(DisableArgs),
+    /// enable a binary disabled by `disable`
+    Enable(EnableArgs),
+    /// forget the saved asset of a binary to pick again in the next install or update
+    Repick(RepickArgs),
+    /// re-install a previous installed version of a binary
+    Rollback(RollbackArgs),
+    /// switch a binary to an installed version without downloading
+    Use(UseArgs),
+    /// show the details of a binary
+    Info(InfoArgs),
+    /// execute a binary from cache without installing it
+    Run(RunArgs),
+    /// install, update and uninstall binaries to match the config exactly
+    Sync(SyncArgs),
+    /// uninstall installed binaries that are no longer in the config
+    Prune(PruneArgs),
+    /// print the installed state as json to stdout
+    Export,
+    /// re-install binaries from an exported state file
+    Import(ImportArgs),
+    /// pack installed binaries with their records into a `.tar.zst` to restore on offline machines
+    Bundle(BundleArgs),
+    /// print a CycloneDX sbom of the installed binaries with their sources and digests
+    Sbom,
+    /// diagnose the environment, db, links and cache
+    Doctor(DoctorArgs),
+    /// check the installed files of binaries against the ones recorded at install time
+    Verify(VerifyArgs),
+    /// show the installed version history of binaries
+    History(HistoryArgs),
+    /// print the shell completion script of this cli
+    Completions(CompletionsArgs),
+    /// print a snippet to eval in the shell rc that sets up PATH and completions
+    Init(InitArgs),
+    /// approve the hooks of a config outside the config dir. changed hooks need approving again
+    Trust(TrustArgs),
+    /// manage the config file
+    #[clap(subcommand)]
+    Config(ConfigCommands),
+}
 
-    let url = format!("sqlite:{}", p.display());
-    let mut opts = SqlitePoolOptions::new();
+#[derive(Debug, Subcommand)]
+enum ConfigCommands {
+    /// check the config and its includes and report all problems at once
+    Validate,
+    /// write a commented starter config to the config path and print where it lives
+    Init(ConfigInitArgs),
+    /// open the config in `$VISUAL` or `$EDITOR` and validate it on save
+    Edit,
+    /// print the path of the active config
+    Path,
+    /// print the config merged with includes
+    Show(ConfigShowArgs),
+}
 
-    if afs::metadata(p).await.is_err() {
-        if let Some(p) = p.parent() {
-            if afs::metadata(p).await.is_err() {
-                trace!("creating all dirs for sqlite db {}", p.display());
-                create_dir_all(p).await?;
-            }
-        }
-        trace!("creating db file: {}", p.display());
-        afs::File::create(p).await?;
+#[derive(Debug, Args)]
+pub struct ConfigShowArgs {
+    /// apply the defaults and the global values inherited by bins
+    #[clap(long)]
+    resolved: bool,
+}
 
-        let init_sql = include_str!("../schema.sql");
+#[derive(Debug, Args)]
+pub struct ConfigInitArgs {
+    /// overwrite the existing config
+    #[clap(long)]
+    force: bool,
+}
 
-        opts = opts.after_connect(move |con| {
-            Box::pin(async move {
-                trace!("executing sql for init sqlite: {}", init_sql);
-                let mut rows = con.execute_many(init_sql);
-                while let Some(row) = rows.next().await {
-                    trace!("get row: {:?}", row?);
-                }
-                Ok(())
-            })
-        });
-    }
-    debug!("connecting sqlite db for {}", url);
-    let pool = opts.connect(&url).await?;
+#[derive(Debug, Args)]
+pub struct CompletionsArgs {
+    #[clap(arg_enum, required_unless_present = "bins")]
+    shell: Option<Shell>,
 
-    Ok(Mapper { pool })
+    /// print the names of configured binaries for the dynamic completion
+    #[clap(long, hide = true)]
+    bins: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct TrustArgs {
+    /// the config to trust. use the current config if not set
+    path: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct InitArgs {
+    #[clap(arg_enum)]
+    shell: Shell,
+
+    /// check for updates in background at most once per HOURS and show them on shell startup
+    #[clap(
+        long,
+        value_name = "HOURS",
+        min_values = 0,
+        default_missing_value = "24"
+    )]
+    check_updates: Option<u64>,
+
+    /// add the bin dir to the user PATH in the registry on windows for new terminals
+    #[clap(long)]
+    add_path: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ListArgs {
+    /// show the install stats of binaries
+    #[clap(long)]
+    verbose: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct HistoryArgs {
+    /// the name of binary. show all binaries in db if not set
+    name: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct VerifyArgs {
+    /// the name of binary. verify all installed binaries if not set
+    name: Option<String>,
+
+    /// re-extract the drifted binaries from cache
+    #[clap(long)]
+    repair: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct DoctorArgs {
+    /// fix the problems that can be fixed automatically
+    #[clap(long)]
+    fix: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ImportArgs {
+    /// the state file created by `export`
+    path: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct BundleArgs {
+    /// the `.tar.zst` file to write, or to restore by `--install`
+    path: PathBuf,
+
+    /// the names of binaries to pack. pack all installed binaries if not set
+    #[clap(conflicts_with = "install")]
+    names: Vec<String>,
+
+    /// restore the binaries in the bundle without downloading
+    #[clap(long)]
+    install: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct CleanArgs {
+    /// remove all the cached downloads even if under max-cache-size
+    #[clap(long)]
+    all: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct PruneArgs {
+    /// uninstall without confirmation
+    #[clap(short, long)]
+    yes: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct SyncArgs {
+    /// apply the plan without confirmation
+    #[clap(short, long)]
+    yes: bool,
+
+    #[clap(flatten)]
+    fail: FailArgs,
+
+    #[clap(flatten)]
+    tags: TagArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct TagArgs {
+    /// only the binaries with any of the tags. separated by `,`
+    #[clap(long, value_delimiter = ',')]
+    only_tags: Vec<String>,
+
+    /// skip the binaries with any of the tags. separated by `,`
+    #[clap(long, value_delimiter = ',')]
+    skip_tags: Vec<String>,
+}
+
+impl TagArgs {
+    fn is_match(&self, bin: &Binary) -> bool {
+        let has_any = |tags: &[String]| tags.iter().any(|t| bin.tags().contains(t));
+        (self.only_tags.is_empty() || has_any(&self.only_tags)) && !has_any(&self.skip_tags)
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct PinArgs {
+    /// the name of binary
+    name: String,
+
+    /// the version to pin. use the installed version if not set
+    version: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct UnpinArgs {
+    /// the name of binary
+    name: String,
+}
+
+#[derive(Debug, Args)]
+pub struct SkipArgs {
+    /// the name of binary
+    name: String,
+
+    /// the version to skip
+    version: String,
+
+    /// stop skipping the version
+    #[clap(long)]
+    remove: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct DisableArgs {
+    /// the name of binary
+    name: String,
+}
+
+#[derive(Debug, Args)]
+pub struct EnableArgs {
+    /// the name of binary
+    name: String,
+}
+
+#[derive(Debug, Args)]
+pub struct RepickArgs {
+    /// the name of binary
+    name: String,
+}
+
+#[derive(Debug, Args)]
+pub struct InfoArgs {
+    /// the name of binary
+    name: String,
+}
+
+#[derive(Debug, Args)]
+pub struct RunArgs {
+    /// the binary to run like `name` or `name@version`
+    target: String,
+
+    /// the args passed to the binary
+    #[clap(last = true)]
+    args: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct RollbackArgs {
+    /// the name of binary
+    name: String,
+
+    /// the installed version to roll back to. use the previous version if not set
+    #[clap(long)]
+    to: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct UpdateArgs {
+    /// write the resolved versions of installed binaries to the lock file
+    #[clap(long)]
+    write_lock: bool,
+
+    /// do not print the changelogs of the updated versions
+    #[clap(long)]
+    no_changelog: bool,
+
+    #[clap(flatten)]
+    fail: FailArgs,
+
+    #[clap(flatten)]
+    tags: TagArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct OutdatedArgs {
+    #[clap(flatten)]
+    tags: TagArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct CheckArgs {
+    /// write the check result as json to the path
+    #[clap(long, value_name = "PATH")]
+    write_status: Option<PathBuf>,
+
+    #[clap(flatten)]
+    tags: TagArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct UseArgs {
+    /// the name of binary
+    name: String,
+
+    /// the installed version to switch to
+    version: String,
+}
+
+#[derive(Debug, Args)]
+pub struct InstallArgs {
+    /// reinstall the installed binaries and replace the existing links into the data dir
+    #[clap(long)]
+    force: bool,
+
+    /// back up the existing unmanaged files at link paths and take them over
+    #[clap(long)]
+    adopt: bool,
+
+    /// install the versions whose asset digests differ from the ones pinned on their first
+    /// installs, and pin the new digests
+    #[clap(long)]
+    accept_new_digest: bool,
+
+    /// install the managed binaries even if the os provided ones are reused by the `system`
+    /// option
+    #[clap(long)]
+    prefer_managed: bool,
+
+    /// install the exact versions and assets recorded in the lock file
+    #[clap(long)]
+    locked: bool,
+
+    #[clap(flatten)]
+    fail: FailArgs,
+
+    #[clap(flatten)]
+    tags: TagArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct FailArgs {
+    /// continue the other packages when some fail. default mode
+    #[clap(long, conflicts_with = "fail-fast")]
+    keep_going: bool,
+
+    /// cancel and roll back the other running packages when any fails
+    #[clap(long)]
+    fail_fast: bool,
+}
+
+impl FailArgs {
+    /// 没有`--keep-going`时使用`--fail-fast`
+    fn fail_fast(&self) -> bool {
+        self.fail_fast && !self.keep_going
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct UninstallArgs {
+    #[clap(short, long)]
+    names: Option<Vec<String>>,
+
+    #[clap(short, long)]
+    all: bool,
+}
+
+/// 安装使用的dirs
+#[derive(Debug, Clone)]
+pub struct Dirs {
+    /// the dir of installed files and db
+    data: PathBuf,
+    cache: PathBuf,
+    /// the dir to link executables
+    bin: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub struct PackageManager {
+    manager: Manager,
+    /// the lock file next to the config
+    lock_path: PathBuf,
+    output: OutputFormat,
+}
+
+impl PackageManager {
+    /// 构建manager，未信任的配置中的命令与下载设置会被禁用
+    pub async fn new(
+        builder: &mut ManagerBuilder,
+        config_path: &Path,
+        output: OutputFormat,
+    ) -> Result<Self> {
+        let manager = builder.build().await?;
+        if manager.hooks_disabled() {
+            status!(
+                "disabled the hooks and download settings of the untrusted config {}. review and trust them by `{} trust {}`",
+                config_path.display(),
+                CRATE_NAME,
+                config_path.display()
+            );
+        }
+        Ok(Self {
+            manager,
+            lock_path: config::lock::lock_path(config_path),
+            output,
+        })
+    }
+
+    pub async fn uninstall(&self, args: &UninstallArgs) -> Result<()> {
+        if let Some(names) = &args.names {
+            let jobs = names
+                .iter()
+                .flat_map(|name| {
+                    self.manager
+                        .pkgs()
+                        .iter()
+                        .find(|pkg| pkg.bin().bin().name() == name)
+                })
+                .map(|pkg| {
+                    let pkg = pkg.clone();
+                    async move { pkg.uninstall().await }
+                })
+                .map(tokio::spawn)
+                .collect::<Vec<_>>() as Vec<JoinHandle<Result<()>>>;
+            for job in try_join_all(jobs).await? {
+                if let Err(e) = job {
+                    warn!("failed to uninstall: {}", e);
+                }
+            }
+            return Ok(());
+        }
+
+        let mut fails = 0;
+        for res in try_join_all(
+            self.manager
+                .pkgs()
+                .iter()
+                .map(Clone::clone)
+                .map(|pkg| async move {
+                    let name = pkg.bin().bin().name();
+
+                    pkg.uninstall().await.map(|_| name.to_owned())
+                })
+                .map(tokio::spawn),
+        )
+        .await?
+        {
+            match res {
+                Ok(name) => info!("uninstalled {}", name),
+                Err(e) => {
+                    fails += 1;
+                    error!("{}", e)
+                }
+            }
+        }
+        if fails == 0 {
+            Ok(())
+        } else {
+            bail!("uninstall has failed {} tasks", fails)
+        }
+    }
+
+    pub async fn list(&self, args: &ListArgs) -> Result<()> {
+        let find_vers = |pkg: BinaryPackage| async move {
+            let name = pkg.bin().bin().name().to_owned();
+            let res = async {
+                let old_ver = if pkg.has_installed().await? {
+                    pkg.mapper()
+                        .select_current_by_name(&name)
+                        .await?
+                        .map(|info| info.version().to_owned())
+                } else {
+                    info!("installed bin {} is skipped", name);
+                    None
+                };
+                let pinned = pkg
+                    .mapper()
+                    .select_pinned_by_name(&name)
+                    .await?
+                    .map(|p| p.version().to_owned());
+                let disabled = pkg.is_disabled().await;
+                let external = match old_ver {
+                    Some(_) => None,
+                    None => pkg.external().await?,
+                };
+                let foreign = match (&old_ver, &external) {
+                    (None, None) => pkg.foreign_version().await,
+                    _ => None,
+                };
+                let latest_ver = pkg.latest_ver().await?;
+                Ok::<_, Error>((old_ver, latest_ver, pinned, disabled, external, foreign))
+            }
+            .await;
+            (name, res)
+        };
+
+        let jobs = self
+            .manager
+            .pkgs()
+            .iter()
+            .map(Clone::clone)
+            .map(find_vers)
+            .map(tokio::spawn)
+            .collect::<Vec<_>>();
+
+        let mut items = vec![];
+        for job in join_all(jobs).await {
+            let (name, res) = job?;
+            items.push(match res {
+                Ok((old, latest, pinned, disabled, external, foreign)) => ListItem {
+                    status: match (&old, &external) {
+                        (Some(old), _) if old < &latest => "updateable",
+                        (Some(_), _) => "installed",
+                        (None, Some(_)) => "external",
+                        (None, None) => "installable",
+                    },
+                    name,
+                    installed: old.or_else(|| external.map(|e| e.version().to_owned())),
+                    latest: Some(latest),
+                    pinned,
+                    disabled,
+                    foreign: foreign.map(|(path, version)| ForeignItem {
+                        path: path.display().to_string(),
+                        version,
+                    }),
+                    error: None,
+                    stats: None,
+                },
+                Err(e) => ListItem {
+                    name,
+                    status: "error",
+                    installed: None,
+                    latest: None,
+                    pinned: None,
+                    disabled: false,
+                    foreign: None,
+                    error: Some(e.to_string()),
+                    stats: None,
+                },
+            });
+        }
+        if args.verbose {
+            for item in &mut items {
+                let stats = self
+                    .manager
+                    .mapper()
+                    .select_stats_by_name(&item.name, STATS_LIMIT)
+                    .await?;
+                item.stats = Some(StatSummary::from_stats(&stats));
+            }
+        }
+
+        if self.output == OutputFormat::Json {
+            return print_json(&items);
+        }
+        for item in items {
+            let mut pin = item
+                .pinned
+                .map(|v| format!(" (pinned {})", v))
+                .unwrap_or_default();
+            if item.disabled {
+                pin.push_str(" (disabled)");
+            }
+            if let Some(foreign) = &item.foreign {
+                pin.push_str(&format!(" (found {} at {})", foreign.version, foreign.path));
+            }
+            match (item.installed, item.latest, item.error) {
+                (_, _, Some(e)) => eprintln!("faild to check job of {}: {}", item.name, e),
+                (Some(old), Some(latest), _) if item.status == "updateable" => {
+                    println!("updateable {}: {} => {}{}", item.name, old, latest, pin)
+                }
+                (Some(old), _, _) if item.status == "external" => {
+                    println!("external {}: {}{}", item.name, old, pin)
+                }
+                (Some(old), _, _) => println!("installed {}: {}{}", item.name, old, pin),
+                (None, latest, _) => println!(
+                    "installable {}: {}{}",
+                    item.name,
+                    latest.unwrap_or_default(),
+                    pin
+                ),
+            }
+            if let Some(stats) = &item.stats {
+                stats.print();
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn pin(&self, args: &PinArgs) -> Result<()> {
+        let pkg = self.find_pkg(&args.name)?;
+        let ver = match &args.version {
+            Some(ver) => ver.to_owned(),
+            None => pkg
+                .mapper()
+                .select_current_by_name(&args.name)
+                .await?
+                .map(|info| info.version().to_owned())
+                .ok_or_else(|| {
+                    anyhow!("{} is not installed. specify a version to pin", args.name)
+                })?,
+        };
+        let info = PinnedInfoBuilder::default()
+            .name(&args.name)
+            .version(&ver)
+            .build()?;
+        if self.manager.dry_run() {
+            status!("[dry-run] would pin {} to {}", args.name, ver);
+            return Ok(());
+        }
+        debug!("inserting pinned info: {:?}", info);
+        pkg.mapper().insert_pinned(&info).await?;
+        status!("pinned {} to {}", args.name, ver);
+        Ok(())
+    }
+
+    pub async fn unpin(&self, args: &UnpinArgs) -> Result<()> {
+        let pkg = self.find_pkg(&args.name)?;
+        if self.manager.dry_run() {
+            status!("[dry-run] would unpin {}", args.name);
+            return Ok(());
+        }
+        if pkg.mapper().delete_pinned_by_name(&args.name).await? == 0 {
+            warn!("{} is not pinned", args.name);
+        } else {
+            status!("unpinned {}", args.name);
+        }
+        Ok(())
+    }
+
+    /// 记录或移除跳过的版本，check与update会使用未被跳过的最新版本
+    pub async fn skip(&self, args: &SkipArgs) -> Result<()> {
+        let pkg = self.find_pkg(&args.name)?;
+        let (name, ver) = (&args.name, &args.version);
+        if args.remove {
+            if self.manager.dry_run() {
+                status!("[dry-run] would stop skipping {} {}", name, ver);
+                return Ok(());
+            }
+            if pkg.mapper().delete_skipped(name, ver).await? == 0 {
+                warn!("{} {} is not skipped", name, ver);
+            } else {
+                status!("stopped skipping {} {}", name, ver);
+            }
+            return Ok(());
+        }
+
+        if self.manager.dry_run() {
+            status!("[dry-run] would skip {} {}", name, ver);
+            return Ok(());
+        }
+        pkg.mapper().insert_skipped(name, ver).await?;
+        status!("skipped {} {}", name, ver);
+        if let Some(info) = pkg.mapper().select_current_by_name(name).await? {
+            if is_skipped(std::slice::from_ref(ver), info.version()) {
+                warn!(
+                    "the skipped version {} of {} is installed. run `rollback` to replace it",
+                    ver, name
+                );
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn disable(&self, args: &DisableArgs) -> Result<()> {
+        let pkg = self.find_pkg(&args.name)?;
+        if self.manager.dry_run() {
+            status!("[dry-run] would disable {}", args.name);
+            return Ok(());
+        }
+        pkg.mapper().insert_disabled(&args.name).await?;
+        status!("disabled {}", args.name);
+        Ok(())
+    }
+
+    pub async fn repick(&self, args: &RepickArgs) -> Result<()> {
+        let pkg = self.find_pkg(&args.name)?;
+        if self.manager.dry_run() {
+            status!("[dry-run] would forget the saved asset of {}", args.name);
+            return Ok(());
+        }
+        if pkg
+            .mapper()
+            .delete_asset_pattern_by_name(&args.name)
+            .await?
+            == 0
+        {
+            warn!("{} has no saved asset", args.name);
+        } else {
+            status!("forgot the saved asset of {}", args.name);
+        }
+        Ok(())
+    }
+
+    pub async fn enable(&self, args: &EnableArgs) -> Result<()> {
+        let pkg = self.find_pkg(&args.name)?;
+        if self.manager.dry_run() {
+            status!("[dry-run] would enable {}", args.name);
+            return Ok(());
+        }
+        if pkg.mapper().delete_disabled_by_name(&args.name).await? == 0 {
+            warn!("{} is not disabled by command", args.name);
+        } else {
+            status!("enabled {}", args.name);
+        }
+        if !pkg.bin().bin().enabled() {
+            warn!(
+                "{} is still disabled by `enabled = false` in config",
+                args.name
+            );
+        }
+        Ok(())
+    }
+
+    pub async fn rollback(&self, args: &RollbackArgs) -> Result<()> {
+        let pkg = self.find_pkg(&args.name)?;
+        let ver = pkg.rollback(args.to.as_deref()).await?;
+        status!("rolled back {} to {}", args.name, ver);
+        Ok(())
+    }
+
+    pub async fn use_version(&self, args: &UseArgs) -> Result<()> {
+        let pkg = self.find_pkg(&args.name)?;
+        pkg.switch(&args.version).await?;
+        status!("switched {} to {}", args.name, args.version);
+        Ok(())
+    }
+
+    pub async fn history(&self, args: &HistoryArgs) -> Result<()> {
+        let names = match &args.name {
+            Some(name) => vec![name.to_owned()],
+            None => self
+                .manager
+                .mapper()
+                .select_all()
+                .await?
+                .into_iter()
+                .map(|info| info.name().to_owned())
+                .collect::<IndexSet<_>>()
+                .into_iter()
+                .collect(),
+        };
+
+        let mut histories = IndexMap::new();
+        for name in names {
+            let infos = self.manager.mapper().select_history_by_name(&name).await?;
+            if infos.is_empty() && args.name.is_some() {
+                bail!("not found history of {}", name);
+            }
+            histories.insert(name, HistoryItem::from_infos(&infos));
+        }
+
+        match self.output {
+            OutputFormat::Text => {
+                for (name, items) in &histories {
+                    println!("{}:", name);
+                    items.iter().for_each(HistoryItem::print);
+                }
+            }
+            OutputFormat::Json => print_json(&histories)?,
+        }
+        Ok(())
+    }
+
+    pub async fn info(&self, args: &InfoArgs) -> Result<()> {
+        let pkg = self.find_pkg(&args.name)?;
+        let bin = pkg.bin().bin();
+
+        let pinned = pkg
+            .mapper()
+            .select_pinned_by_name(bin.name())
+            .await?
+            .map(|p| p.version().to_owned());
+
+        let history =
+            HistoryItem::from_infos(&pkg.mapper().select_history_by_name(bin.name()).await?);
+        let stats = pkg
+            .mapper()
+            .select_stats_by_name(bin.name(), STATS_LIMIT)
+            .await?;
+
+        let mut links = vec![];
+        for path in pkg.link_paths() {
+            let target = afs::read_link(path).await.ok();
+            let exists = target.is_some() || afs::metadata(path).await.is_ok();
+            links.push(LinkItem {
+                path: path.to_owned(),
+                target,
+                exists,
+            });
+        }
+
+        let (data_dir, cache_dir) = (pkg.data_dir().to_owned(), pkg.cache_dir().to_owned());
+        let (data_size, cache_size) =
+            tokio::task::spawn_blocking(move || (dir_size(data_dir), dir_size(cache_dir))).await?;
+
+        let (latest, latest_error, changelog) = match pkg.latest_ver().await {
+            Ok(latest) => {
+                let changelog = match pkg.bin().changelog(&latest).await {
+                    Ok(log) => log.map(|log| log.trim().to_owned()),
+                    Err(e) => {
+                        warn!("failed to get changelog of {}: {}", latest, e);
+                        None
+                    }
+                };
+                (Some(latest), None, changelog)
+            }
+            Err(e) => (None, Some(e.to_string()), None),
+        };
+
+        let out = InfoOutput {
+            name: bin.name().to_owned(),
+            source: bin.source().to_string(),
+            version: bin.version().clone(),
+            pick_regex: bin.pick_regex().as_ref().map(|s| s.trim().to_owned()),
+            ignore_regex: bin.ignore_regex().as_ref().map(|s| s.trim().to_owned()),
+            bin_glob: bin.bin_glob().as_ref().map(|s| s.trim().to_owned()),
+            asset_url: bin.asset_url().clone(),
+            asset_id: *bin.asset_id(),
+            exe_type: bin.exe_type().clone(),
+            hook: bin.hook().clone(),
+            hooks: bin.hooks().clone(),
+            completion: bin.completion().clone(),
+            pinned,
+            asset_pattern: pkg.mapper().select_asset_pattern(bin.name()).await?,
+            history,
+            stats: StatSummary::from_stats(&stats),
+            installs: stats.iter().map(Into::into).collect(),
+            links,
+            data_dir: pkg.data_dir().to_owned(),
+            data_size,
+            cache_dir: pkg.cache_dir().to_owned(),
+            cache_size,
+            latest,
+            latest_error,
+            changelog,
+        };
+        if self.output == OutputFormat::Json {
+            return print_json(&out);
+        }
+        out.print();
+        Ok(())
+    }
+
+    /// 同步已安装的bins与配置一致
+    ///
+    /// * 安装未安装的bin
+    /// * 更新当前版本与目标版本不一致的bin，pinned的bin不会超过pin的版本
+    /// * 卸载db中存在但配置中不存在的bin
+    pub async fn sync(&self, args: &SyncArgs) -> Result<()> {
+        let plan_pkg = |pkg: BinaryPackage| async move {
+            let name = pkg.bin().bin().name().to_owned();
+            let res = async {
+                if pkg.is_disabled().await {
+                    info!("disabled bin {} is skipped", name);
+                    return Ok(None);
+                }
+                let target = pkg.target_ver().await?;
+                if !pkg.has_installed().await? {
+                    return Ok(Some(SyncAction::Install(pkg.clone(), target)));
+                }
+                let cur = pkg
+                    .mapper()
+                    .select_current_by_name(&name)
+                    .await?
+                    .map(|info| info.version().to_owned())
+                    .unwrap_or_default();
+                Ok::<_, Error>(if cur != target {
+                    Some(SyncAction::Update(pkg.clone(), cur, target))
+                } else {
+                    None
+                })
+            }
+            .await;
+            (name, res)
+        };
+
+        let mut summary = Summary::default();
+        let mut actions = vec![];
+        for job in join_all(
+            self.tagged_pkgs(&args.tags)
+                .map(Clone::clone)
+                .map(plan_pkg)
+                .map(tokio::spawn),
+        )
+        .await
+        {
+            let (name, res) = job?;
+            match res {
+                Ok(Some(action)) => actions.push(action),
+                Ok(None) => summary.push(name, Done::Skipped),
+                Err(e) => {
+                    error!("failed to check {}: {}", name, e);
+                    summary.failed.push((name, e));
+                }
+            }
+        }
+        // the bins skipped by tags are still in the config and not unused
+        let bins = self
+            .manager
+            .pkgs()
+            .iter()
+            .map(|pkg| pkg.bin().bin().clone())
+            .collect::<Vec<_>>();
+        for bin in unused_bins(self.manager.mapper(), &bins).await? {
+            actions.push(SyncAction::Uninstall(self.manager.build_pkg(bin).await?));
+        }
+
+        if actions.is_empty() {
+            status!("everything is up to date");
+        } else {
+            status!("sync plan:");
+            for action in &actions {
+                status!("  {}", action);
+            }
+            if !args.yes && !self.manager.dry_run() && !confirm("proceed?").await? {
+                status!("sync aborted");
+                return Ok(());
+            }
+        }
+
+        // fail-fast cancels the other running actions by the child token
+        let cancel = self.manager.cancel().child_token();
+        let mut jobs = actions
+            .into_iter()
+            .map(|action| {
+                let cancel = cancel.clone();
+                async move {
+                    let (pkg, res) = match action {
+                        SyncAction::Install(pkg, _) => {
+                            let pkg = pkg.with_cancel(cancel);
+                            let res = pkg.install().await.map(Done::Installed);
+                            (pkg, res)
+                        }
+                        SyncAction::Update(pkg, cur, _) => {
+                            let pkg = pkg.with_cancel(cancel);
+                            let res = pkg.update().await.map(|ver| Done::Updated(cur, ver));
+                            (pkg, res)
+                        }
+                        SyncAction::Uninstall(pkg) => {
+                            let res = pkg.uninstall().await.map(|_| Done::Uninstalled);
+                            (pkg, res)
+                        }
+                    };
+                    (
+                        pkg.bin().bin().name().to_owned(),
+                        res,
+                        pkg.downloaded_bytes(),
+                    )
+                }
+            })
+            .map(tokio::spawn)
+            .collect::<FuturesUnordered<_>>();
+        while let Some(job) = jobs.next().await {
+            let (name, res, downloaded) = job?;
+            summary.downloaded += downloaded;
+            match res {
+                Ok(done) => summary.push(name, done),
+                Err(e) => {
+                    error!("failed to sync {}: {}", name, e);
+                    if args.fail.fail_fast() && !cancel.is_cancelled() {
+                        warn!("cancelling {} remaining sync jobs", jobs.len());
+                        cancel.cancel();
+                    }
+                    summary.failed.push((name, e));
+                }
+            }
+        }
+        self.print_summary(&summary, "sync")?;
+        self.manager.evict_cache().await;
+
+        if !summary.failed.is_empty() {
+            return Err(summary.error("sync"));
+        }
+        Ok(())
+    }
+
+    /// 清理缓存中最久未使用的下载文件直到不超过max cache size，未配置或使用`--all`时清理所有
+    pub async fn clean(&self, args: &CleanArgs) -> Result<()> {
+        let limit = match self.manager.config().max_cache_size() {
+            Some(limit) if !args.all => *limit,
+            _ => return self.clean_all().await,
+        };
+        let evicted = evict_cache(
+            self.manager.mapper(),
+            self.manager.cache_dir(),
+            limit,
+            self.manager.dry_run(),
+        )
+        .await?;
+        if self.output == OutputFormat::Json {
+            return print_json(&evicted);
+        }
+        if evicted.is_empty() {
+            status!("the cache is under {}", human_size(limit));
+            return Ok(());
+        }
+        let action = if self.manager.dry_run() {
+            "[dry-run] would evict"
+        } else {
+            "evicted"
+        };
+        for file in &evicted {
+            println!(
+                "{} {} ({}, last used {})",
+                action,
+                file.path.display(),
+                human_size(file.size),
+                file.accessed.format("%Y-%m-%d")
+            );
+        }
+        println!(
+            "freed {}",
+            human_size(evicted.iter().map(|file| file.size).sum())
+        );
+        Ok(())
+    }
+
+    /// 删除cache dir中所有bins的下载目录，包括不在配置中的bins
+    async fn clean_all(&self) -> Result<()> {
+        let mut dirs = match afs::read_dir(self.manager.cache_dir()).await {
+            Ok(dirs) => dirs,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        while let Some(dir) = dirs.next_entry().await? {
+            if !dir.file_type().await?.is_dir() {
+                continue;
+            }
+            if self.manager.dry_run() {
+                status!("[dry-run] would remove cache dir {}", dir.path().display());
+            } else {
+                info!("removing cache dir {}", dir.path().display());
+                afs::remove_dir_all(dir.path()).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 卸载db中存在但配置中不存在的bins
+    pub async fn prune(&self, args: &PruneArgs) -> Result<()> {
+        let bins = self
+            .manager
+            .pkgs()
+            .iter()
+            .map(|pkg| pkg.bin().bin().clone())
+            .collect::<Vec<_>>();
+        let unused = unused_bins(self.manager.mapper(), &bins).await?;
+        if unused.is_empty() {
+            status!("no unused binaries");
+            return Ok(());
+        }
+
+        status!("unused binaries:");
+        for bin in &unused {
+            status!("  {} ({})", bin.name(), bin.source());
+        }
+        if !args.yes && !self.manager.dry_run() && !confirm("uninstall them?").await? {
+            status!("prune aborted");
+            return Ok(());
+        }
+
+        let jobs = unused
+            .into_iter()
+            .map(|bin| {
+                let name = bin.name().to_owned();
+                let f = self.manager.build_pkg(bin);
+                async move {
+                    let res = async {
+                        let pkg = f.await?;
+                        info!("uninstalling unused binary {}", name);
+                        pkg.uninstall().await
+                    }
+                    .await;
+                    (name, res)
+                }
+            })
+            .map(tokio::spawn)
+            .collect::<Vec<_>>();
+        let mut summary = Summary::default();
+        for job in join_all(jobs).await {
+            let (name, res) = job?;
+            match res {
+                Ok(_) => summary.push(name, Done::Uninstalled),
+                Err(e) => {
+                    error!("failed to uninstall unused bin {}: {}", name, e);
+                    summary.failed.push((name, e));
+                }
+            }
+        }
+        self.print_summary(&summary, "prune")?;
+
+        if !summary.failed.is_empty() {
+            return Err(summary.error("prune"));
+        }
+        Ok(())
+    }
+
+    /// 导出db中所有的版本记录与pinned版本
+    pub async fn export(&self) -> Result<()> {
+        let state = State {
+            infos: self.manager.mapper().select_all().await?,
+            pinned: self.manager.mapper().select_all_pinned().await?,
+        };
+        debug!(
+            "exporting {} infos and {} pinned",
+            state.infos.len(),
+            state.pinned.len()
+        );
+        print_json(&state)
+    }
+
+    /// 从导出的状态中恢复pinned版本，重新安装每个bin当前使用的版本并恢复版本历史
+    ///
+    /// 优先使用配置中的bin，否则使用记录的source构建
+    pub async fn import(&self, args: &ImportArgs) -> Result<()> {
+        let state: State = serde_json::from_str(&afs::read_to_string(&args.path).await?)?;
+        let mut summary = Summary::default();
+
+        for info in &state.pinned {
+            if self.manager.dry_run() {
+                status!("[dry-run] would pin {} to {}", info.name(), info.version());
+            } else {
+                self.manager.mapper().insert_pinned(info).await?;
+            }
+        }
+
+        let mut histories = IndexMap::<String, Vec<UpdatedInfo>>::new();
+        for mut info in state.infos {
+            // the states exported by old versions have json sources
+            info.set_source(info.parse_source()?.to_string());
+            histories
+                .entry(info.name().to_owned())
+                .or_default()
+                .push(info);
+        }
+
+        let mut jobs = vec![];
+        for (name, mut infos) in histories {
+            infos.sort_by(|a, b| {
+                b.updated_time()
+                    .cmp(a.updated_time())
+                    .then_with(|| b.id().cmp(a.id()))
+            });
+            let pkg = match self.find_pkg(&name) {
+                Ok(pkg) => pkg.clone(),
+                Err(_) => self.manager.build_pkg(bin_of(&infos[0])?).await?,
+            };
+            let dry_run = self.manager.dry_run();
+            jobs.push(tokio::spawn(async move {
+                let res = async {
+                    if pkg.has_installed().await? {
+                        info!("installed bin {} is skipped", name);
+                        return Ok(Done::Skipped);
+                    }
+                    let cur = &infos[0];
+                    pkg.install_url(cur.version(), &cur.url().parse()?).await?;
+                    let done = Done::Installed(cur.version().to_owned());
+                    if dry_run {
+                        return Ok(done);
+                    }
+                    let installed = pkg.mapper().select_list_by_name(&name).await?;
+                    for info in &infos[1..] {
+                        if !installed.iter().any(|i| i.version() == info.version()) {
+                            trace!("restoring history info: {:?}", info);
+                            pkg.mapper().insert(info).await?;
+                        }
+                    }
+                    Ok::<_, Error>(done)
+                }
+                .await;
+                (name, res, pkg.downloaded_bytes())
+            }));
+        }
+
+        for job in join_all(jobs).await {
+            let (name, res, downloaded) = job?;
+            summary.downloaded += downloaded;
+            match res {
+                Ok(done) => summary.push(name, done),
+                Err(e) => {
+                    error!("failed to import {}: {}", name, e);
+                    summary.failed.push((name, e));
+                }
+            }
+        }
+        self.print_summary(&summary, "import")?;
+
+        if !summary.failed.is_empty() {
+            return Err(summary.error("import"));
+        }
+        Ok(())
+    }
+
+    /// 打印所有已安装bins的CycloneDX sbom，包括安装时记录的来源与digest
+    ///
+    /// 记录来源之前安装的bins只有db中的版本与url
+    pub async fn sbom(&self) -> Result<()> {
+        let names = self
+            .manager
+            .mapper()
+            .select_all()
+            .await?
+            .into_iter()
+            .map(|info| info.name().to_owned())
+            .collect::<IndexSet<_>>();
+        let mut components = vec![];
+        for name in names {
+            let info = match self.manager.mapper().select_current_by_name(&name).await? {
+                Some(info) => info,
+                None => continue,
+            };
+            let pkg = match self.find_pkg(&name) {
+                Ok(pkg) => pkg.clone(),
+                Err(_) => self.manager.build_pkg(bin_of(&info)?).await?,
+            };
+            let provenance = pkg
+                .provenance()
+                .await?
+                .filter(|p| p.version() == info.version());
+            if provenance.is_none() {
+                warn!(
+                    "not found the provenance of {} {}. re-install it to record the digest",
+                    name,
+                    info.version()
+                );
+            }
+            components.push(sbom_component(&info, provenance.as_ref())?);
+        }
+        print_json(&json!({
+            "bomFormat": "CycloneDX",
+            "specVersion": "1.5",
+            "version": 1,
+            "metadata": {
+                "timestamp": Utc::now().to_rfc3339(),
+                "tools": [{ "name": CRATE_NAME, "version": env!("CARGO_PKG_VERSION") }],
+            },
+            "components": components,
+        }))
+    }
+
+    /// 打包bins已安装的版本目录与db中的记录到`.tar.zst`文件，`--install`时恢复
+    pub async fn bundle(&self, args: &BundleArgs) -> Result<()> {
+        if args.install {
+            return self.restore_bundle(&args.path).await;
+        }
+        let names = if args.names.is_empty() {
+            self.manager
+                .mapper()
+                .select_all()
+                .await?
+                .into_iter()
+                .map(|info| info.name().to_owned())
+                .collect::<IndexSet<_>>()
+                .into_iter()
+                .collect()
+        } else {
+            args.names.clone()
+        };
+
+        let mut bins = vec![];
+        let mut dirs = vec![];
+        for name in names {
+            let infos = self.manager.mapper().select_history_by_name(&name).await?;
+            if infos.is_empty() {
+                bail!("not found installed {}", name);
+            }
+            let pkg = match self.find_pkg(&name) {
+                Ok(pkg) => pkg.clone(),
+                Err(_) => self.manager.build_pkg(bin_of(&infos[0])?).await?,
+            };
+            let mut files = vec![];
+            for info in &infos {
+                let dir = pkg.version_dir(info.version());
+                if afs::metadata(&dir).await.is_ok_and(|d| d.is_dir()) {
+                    files.extend(
+                        self.manager
+                            .mapper()
+                            .select_files(&name, info.version())
+                            .await?,
+                    );
+                    dirs.push((bundle_dir(&name, info.version()), dir));
+                } else if info == &infos[0] {
+                    bail!(
+                        "not found the current version dir {} of {}",
+                        dir.display(),
+                        name
+                    );
+                }
+            }
+            let links = pkg
+                .link_paths()
+                .iter()
+                .filter(|path| path.symlink_metadata().is_ok())
+                .filter_map(|path| path.file_name()?.to_str())
+                .map(|link| link.trim_end_matches(EXE_SUFFIX).to_owned())
+                .collect();
+            bins.push(BundledBin {
+                pinned: self.manager.mapper().select_pinned_by_name(&name).await?,
+                name,
+                infos,
+                files,
+                links,
+            });
+        }
+
+        if self.manager.dry_run() {
+            for bin in &bins {
+                status!(
+                    "[dry-run] would pack {} {} to {}",
+                    bin.name,
+                    bin.infos[0].version(),
+                    args.path.display()
+                );
+            }
+            return Ok(());
+        }
+        let manifest = serde_json::to_vec_pretty(&Bundle { bins })?;
+        let count = dirs.len();
+        let path = args.path.clone();
+        tokio::task::spawn_blocking(move || {
+            let file = std::fs::File::create(&path)
+                .with_context(|| format!("failed to create {}", path.display()))?;
+            let mut tar = tar::Builder::new(zstd::Encoder::new(file, 0)?);
+            // 版本目录中的链接保持为链接
+            tar.follow_symlinks(false);
+            let mut header = tar::Header::new_gnu();
+            header.set_size(manifest.len() as u64);
+            header.set_mode(0o644);
+            header.set_mtime(Utc::now().timestamp() as u64);
+            header.set_cksum();
+            tar.append_data(&mut header, BUNDLE_MANIFEST, manifest.as_slice())?;
+            for (name, dir) in dirs {
+                tar.append_dir_all(name, dir)?;
+            }
+            tar.into_inner()?.finish()?;
+            Ok::<_, Error>(())
+        })
+        .await??;
+        status!("packed {} versions to {}", count, args.path.display());
+        Ok(())
+    }
+
+    /// 恢复bundle中未安装的bins，不需要网络
+    ///
+    /// 解压到data dir中的临时目录后将版本目录移动到各个bin的data dir，写入db记录并切换到当前版本
+    async fn restore_bundle(&self, path: &Path) -> Result<()> {
+        let bundle = {
+            let path = path.to_owned();
+            tokio::task::spawn_blocking(move || read_bundle(&path)).await??
+        };
+        let tmp = if self.manager.dry_run() {
+            None
+        } else {
+            create_dir_all(self.manager.data_dir()).await?;
+            let tmp = tempfile::Builder::new()
+                .prefix(".bundle")
+                .tempdir_in(self.manager.data_dir())?;
+            let (from, to) = (path.to_owned(), tmp.path().to_owned());
+            tokio::task::spawn_blocking(move || {
+                let file = std::fs::File::open(&from)?;
+                tar::Archive::new(zstd::Decoder::new(file)?).unpack(&to)?;
+                Ok::<_, Error>(())
+            })
+            .await??;
+            Some(tmp)
+        };
+
+        let mut summary = Summary::default();
+        for bin in bundle.bins {
+            let name = bin.name.clone();
+            if self.manager.cancel().is_cancelled() {
+                summary.failed.push((name, Cancelled.into()));
+                continue;
+            }
+            let dir = tmp.as_ref().map(|t| t.path());
+            match self.restore_bin(bin, dir).await {
+                Ok(done) => summary.push(name, done),
+                Err(e) => {
+                    error!("failed to restore {}: {}", name, e);
+                    summary.failed.push((name, e));
+                }
+            }
+        }
+        self.print_summary(&summary, "restore")?;
+
+        if !summary.failed.is_empty() {
+            return Err(summary.error("restore"));
+        }
+        Ok(())
+    }
+
+    /// 从解压的bundle目录dir中恢复bin，已有db记录的bin被跳过。dir为none时只打印
+    async fn restore_bin(&self, bin: BundledBin, dir: Option<&Path>) -> Result<Done> {
+        let name = &bin.name;
+        let is_invalid = |s: &str| s.is_empty() || s == ".." || s.contains(['/', '\\']);
+        if is_invalid(name) || bin.infos.iter().any(|info| is_invalid(info.version())) {
+            bail!("invalid name or version of {} in the bundle", name);
+        }
+        let cur = bin
+            .infos
+            .first()
+            .ok_or_else(|| anyhow!("not found the current version of {} in the bundle", name))?;
+        if !self
+            .manager
+            .mapper()
+            .select_list_by_name(name)
+            .await?
+            .is_empty()
+        {
+            info!("recorded bin {} is skipped", name);
+            return Ok(Done::Skipped);
+        }
+        let pkg = match self.find_pkg(name) {
+            Ok(pkg) => pkg.clone(),
+            Err(_) => {
+                // 没有配置时使用打包时的链接名
+                let alias = if bin.links == [name.to_owned()] {
+                    vec![]
+                } else {
+                    bin.links.clone()
+                };
+                let bin = BinaryBuilder::default()
+                    .name(name)
+                    .source(&cur.parse_source()?)?
+                    .alias(alias)
+                    .build()?;
+                self.manager.build_pkg(bin).await?
+            }
+        };
+        let done = Done::Installed(cur.version().to_owned());
+        let dir = match dir {
+            Some(dir) => dir,
+            None => {
+                status!("[dry-run] would restore {} {}", name, cur.version());
+                return Ok(done);
+            }
+        };
+
+        let res = async {
+            // 旧的版本在前，保持db中id的顺序
+            for info in bin.infos.iter().rev() {
+                let from = dir.join(bundle_dir(name, info.version()));
+                let to = pkg.version_dir(info.version());
+                if afs::metadata(&from).await.is_ok() && afs::symlink_metadata(&to).await.is_err() {
+                    trace!("moving {} to {}", from.display(), to.display());
+                    afs::rename(&from, &to).await?;
+                }
+                self.manager.mapper().insert(info).await?;
+                let files = bin
+                    .files
+                    .iter()
+                    .filter(|f| f.version() == info.version())
+                    .cloned()
+                    .collect::<Vec<_>>();
+                if !files.is_empty() {
+                    self.manager
+                        .mapper()
+                        .replace_files(name, info.version(), &files)
+                        .await?;
+                }
+            }
+            if let Some(pinned) = &bin.pinned {
+                self.manager.mapper().insert_pinned(pinned).await?;
+            }
+            pkg.switch(cur.version()).await
+        }
+        .await;
+        if let Err(e) = res {
+            // 不留下没有链接的记录，移动的版本目录由uninstall清理
+            for clear in [
+                self.manager.mapper().delete_by_name(name).await.map(drop),
+                self.manager
+                    .mapper()
+                    .delete_files_by_name(name)
+                    .await
+                    .map(drop),
+                self.manager
+                    .mapper()
+                    .delete_pinned_by_name(name)
+                    .await
+                    .map(drop),
+            ] {
+                if let Err(e) = clear {
+                    warn!("failed to clear the records of {}: {}", name, e);
+                }
+            }
+            return Err(e);
+        }
+        Ok(done)
+    }
+
+    /// 检查环境，db，链接与缓存的问题并打印修复方法，如果fix则自动修复可修复的问题
+    pub async fn doctor(&self, args: &DoctorArgs) -> Result<()> {
+        let mut report = DoctorReport::default();
+
+        // exe dir
+        let exe_dir = self.manager.bin_dir().clone();
+        if afs::metadata(&exe_dir).await.is_ok_and(|d| d.is_dir()) {
+            report.ok(format!("exe dir {} exists", exe_dir.display()));
+        } else {
+            report.fixable(
+                format!("exe dir {} does not exist", exe_dir.display()),
+                DoctorFix::CreateDir(exe_dir.clone()),
+            );
+        }
+        if is_on_path(&exe_dir) {
+            report.ok(format!("exe dir {} is on PATH", exe_dir.display()));
+        } else {
+            report.manual(
+                format!("exe dir {} is not on PATH", exe_dir.display()),
+                format!(
+                    "add `export PATH=\"{}:$PATH\"` to your shell rc",
+                    exe_dir.display()
+                ),
+            );
+        }
+
+        // db schema
+        for (table, expected) in EXPECTED_SCHEMA {
+            let cols = self.manager.mapper().select_columns(table).await?;
+            let missing = expected
+                .iter()
+                .filter(|c| !cols.iter().any(|col| col == *c))
+                .collect::<Vec<_>>();
+            if missing.is_empty() {
+                report.ok(format!("db table {} is up to date", table));
+            } else {
+                report.manual(
+                    format!("db table {} is missing columns {:?}", table, missing),
+                    format!(
+                        "back up with `{} export`, remove the db in {} and `{} import` it",
+                        CRATE_NAME,
+                        self.manager.data_dir().display(),
+                        CRATE_NAME
+                    ),
+                );
+            }
+        }
+
+        // links
+        for pkg in self.manager.pkgs() {
+            for path in pkg
+                .link_paths()
+                .iter()
+                .cloned()
+                .chain(once(pkg.current_dir()))
+            {
+                if afs::symlink_metadata(&path).await.is_ok() && afs::metadata(&path).await.is_err()
+                {
+                    report.fixable(
+                        format!("dangling link {}", path.display()),
+                        DoctorFix::RemoveFile(path),
+                    );
+                }
+            }
+        }
+        // the dangling links into data dir of the bins not in config
+        if let Ok(mut entries) = afs::read_dir(self.manager.bin_dir()).await {
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                if self
+                    .manager
+                    .pkgs()
+                    .iter()
+                    .any(|p| p.link_paths().contains(&path))
+                {
+                    continue;
+                }
+                let into_data = afs::read_link(&path)
+                    .await
+                    .is_ok_and(|target| target.starts_with(self.manager.data_dir()));
+                if into_data && afs::metadata(&path).await.is_err() {
+                    report.fixable(
+                        format!("dangling link {}", path.display()),
+                        DoctorFix::RemoveFile(path),
+                    );
+                }
+            }
+        }
+
+        // the executables on PATH not installed by this
+        for pkg in self.manager.pkgs() {
+            let name = pkg.bin().bin().name();
+            if pkg.has_installed().await? || matches!(pkg.external().await, Ok(Some(_))) {
+                continue;
+            }
+            if let Some((path, ver)) = pkg.foreign_version().await {
+                report.manual(
+                    format!(
+                        "found {} {} at {} not installed by this",
+                        name,
+                        ver,
+                        path.display()
+                    ),
+                    format!(
+                        "set `system = true` for {} to reuse it if the version matches, or \
+                         `{} install` the managed one",
+                        name, CRATE_NAME
+                    ),
+                );
+            }
+        }
+
+        // db records and data dirs
+        let data_dir = self.manager.data_dir();
+        let infos = self.manager.mapper().select_all().await?;
+        for info in &infos {
+            let name_dir = data_dir.join(info.name());
+            let ver_dir = name_dir.join(info.version());
+            // the data dir without versions installed by old versions
+            let legacy = afs::metadata(&name_dir).await.is_ok()
+                && afs::symlink_metadata(name_dir.join(CURRENT_LINK_NAME))
+                    .await
+                    .is_err();
+            if !legacy && afs::metadata(&ver_dir).await.is_err() {
+                report.fixable(
+                    format!(
+                        "db record {} {} has no files in {}",
+                        info.name(),
+                        info.version(),
+                        ver_dir.display()
+                    ),
+                    DoctorFix::DeleteInfo(*info.id(), info.name().to_owned()),
+                );
+            }
+        }
+        if let Ok(mut dirs) = afs::read_dir(data_dir).await {
+            while let Some(entry) = dirs.next_entry().await? {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if !entry.file_type().await?.is_dir() || name == "completions" {
+                    continue;
+                }
+                if !infos.iter().any(|info| *info.name() == name) {
+                    report.fixable(
+                        format!("data dir {} has no db record", entry.path().display()),
+                        DoctorFix::RemoveDir(entry.path()),
+                    );
+                }
+            }
+        }
+
+        // cache
+        if let Ok(mut dirs) = afs::read_dir(self.manager.cache_dir()).await {
+            while let Some(dir) = dirs.next_entry().await? {
+                if !dir.file_type().await?.is_dir() {
+                    continue;
+                }
+                let mut files = afs::read_dir(dir.path()).await?;
+                while let Some(file) = files.next_entry().await? {
+                    let path = file.path();
+                    if !file.file_type().await?.is_file()
+                        || path.extension().is_some_and(|ext| ext == "md5")
+                    {
+                        continue;
+                    }
+                    let md5_path = PathBuf::from(format!("{}.md5", path.display()));
+                    let consistent = match afs::read_to_string(&md5_path).await {
+                        Ok(digest) => {
+                            let path = path.clone();
+                            tokio::task::spawn_blocking(move || md5_file(path)).await??
+                                == digest.trim()
+                        }
+                        Err(_) => false,
+                    };
+                    if !consistent {
+                        report.fixable(
+                            format!("cache {} has no consistent md5 digest", path.display()),
+                            DoctorFix::RemoveFile(path),
+                        );
+                    }
+                }
+            }
+        }
+
+        // github token
+        if let (Some(from), Some(token)) = (self.manager.token_from(), self.manager.token()) {
+            let url = Url::parse("https://api.github.com/rate_limit")?;
+            let req = self.manager.client().get(url.clone());
+            match token.authorize(req, &url).send().await {
+                Ok(resp) if resp.status() == StatusCode::UNAUTHORIZED => report.manual(
+                    "the github token is invalid".to_owned(),
+                    format!("renew the token in {}", from),
+                ),
+                Ok(resp) if resp.status().is_success() => {
+                    report.ok(format!("the github token from {} is valid", from))
+                }
+                Ok(resp) => report.manual(
+                    format!("failed to check the github token: {}", resp.status()),
+                    format!("check the token in {}", from),
+                ),
+                Err(e) => report.manual(
+                    format!("failed to check the github token: {}", e),
+                    "check your network".to_owned(),
+                ),
+            }
+        } else {
+            report.manual(
+                "no github token is set. api requests are rate limited".to_owned(),
+                "set `github.token` in config like `command:gh auth token`".to_owned(),
+            );
+        }
+
+        report.print();
+        if args.fix {
+            for fix in report.fixes() {
+                if self.manager.dry_run() {
+                    status!("[dry-run] would {}", fix);
+                    continue;
+                }
+                match self.apply_fix(fix).await {
+                    Ok(_) => status!("fixed: {}", fix),
+                    Err(e) => eprintln!("failed to {}: {}", fix, e),
+                }
+            }
+        } else if report.fixes().next().is_some() {
+            status!(
+                "run `{} doctor --fix` to fix {} problems automatically",
+                CRATE_NAME,
+                report.fixes().count()
+            );
+        }
+        Ok(())
+    }
+
+    async fn apply_fix(&self, fix: &DoctorFix) -> Result<()> {
+        match fix {
+            DoctorFix::CreateDir(p) => afs::create_dir_all(p).await?,
+            DoctorFix::RemoveFile(p) => afs::remove_file(p).await?,
+            DoctorFix::RemoveDir(p) => afs::remove_dir_all(p).await?,
+            DoctorFix::DeleteInfo(id, _) => {
+                self.manager.mapper().delete_by_id(*id).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 运行binary并返回exit code
+    pub async fn run(&self, args: &RunArgs) -> Result<i32> {
+        let (name, ver) = match args.target.split_once('@') {
+            Some((name, ver)) => (name, Some(ver)),
+            None => (args.target.as_str(), None),
+        };
+        let status = self.find_pkg(name)?.run(ver, &args.args).await?;
+        debug!("{} exited with {}", args.target, status);
+        Ok(status.code().unwrap_or(1))
+    }
+
+    /// 按照输出格式打印summary
+    fn print_summary(&self, summary: &Summary, action: &str) -> Result<()> {
+        match self.output {
+            OutputFormat::Text if QUIET.load(Ordering::Relaxed) => Ok(()),
+            OutputFormat::Text => {
+                summary.print(action);
+                Ok(())
+            }
+            OutputFormat::Json => print_json(&summary.to_json(action)),
+        }
+    }
+
+    fn find_pkg(&self, name: &str) -> Result<&BinaryPackage> {
+        self.manager
+            .pkgs()
+            .iter()
+            .find(|pkg| pkg.bin().bin().name() == name)
+            .ok_or_else(|| anyhow!("not found binary {} in config", name))
+    }
+
+    /// 获取tags匹配的packages，不匹配的不会被处理
+    fn tagged_pkgs<'a>(&'a self, tags: &'a TagArgs) -> impl Iterator<Item = &'a BinaryPackage> {
+        self.manager.pkgs().iter().filter(move |pkg| {
+            let is_match = tags.is_match(pkg.bin().bin());
+            if !is_match {
+                debug!("skipped {} by tags", pkg.bin().bin().name());
+            }
+            is_match
+        })
+    }
+
+    /// 安装未安装的bins，cancel取消时运行中的安装会被回滚。有bin安装失败时返回错误
+    ///
+    /// fail-fast时一个失败会取消其它运行中的安装并等待它们回滚
+    pub async fn install(&self, args: &InstallArgs) -> Result<()> {
+        let lock = if args.locked {
+            Some(config::lock::from_path(&self.lock_path).map_err(|e| {
+                anyhow!(
+                    "failed to load lock file {}: {}",
+                    self.lock_path.display(),
+                    e
+                )
+            })?)
+        } else {
+            None
+        };
+        let opts = InstallOptions {
+            force: args.force,
+            adopt: args.adopt,
+            accept_new_digest: args.accept_new_digest,
+            prefer_managed: args.prefer_managed,
+            fail_fast: args.fail.fail_fast(),
+            lock,
+        };
+        let outcomes = self
+            .manager
+            .install_pkgs(self.tagged_pkgs(&args.tags).cloned().collect(), &opts)
+            .await?;
+
+        let summary = Summary::from_outcomes(outcomes, "install");
+        self.print_summary(&summary, "install")?;
+        if !summary.failed.is_empty() {
+            return Err(summary.error("install"));
+        }
+        Ok(())
+    }
+
+    /// 更新所有可更新的bins，pinned或配置了version的bin会被跳过
+    ///
+    /// 发布时间不足`min-release-age`的latest版本不会更新，在check中显示为pending
+    ///
+    /// cancel取消时运行中的更新会被回滚
+    pub async fn update(&self, args: &UpdateArgs) -> Result<()> {
+        let outcomes = self
+            .manager
+            .update_pkgs(
+                self.tagged_pkgs(&args.tags).cloned().collect(),
+                args.fail.fail_fast(),
+            )
+            .await?;
+
+        if !args.no_changelog && self.output == OutputFormat::Text {
+            let jobs = outcomes.iter().filter_map(|outcome| match &outcome.status {
+                Status::Updated(_, ver) => Some(async move {
+                    let pkg = self.find_pkg(&outcome.name)?;
+                    let log = pkg.bin().changelog(ver).await.unwrap_or_else(|e| {
+                        warn!("failed to get changelog of {} {}: {}", outcome.name, ver, e);
+                        None
+                    });
+                    Ok::<_, Error>((&outcome.name, ver, log))
+                }),
+                _ => None,
+            });
+            for (name, ver, log) in try_join_all(jobs).await? {
+                if let Some(log) = log.filter(|log| !log.trim().is_empty()) {
+                    println!("changelog of {} {}:\n{}\n", name, ver, log.trim_end());
+                }
+            }
+        }
+
+        let summary = Summary::from_outcomes(outcomes, "update");
+        self.print_summary(&summary, "update")?;
+        if args.write_lock {
+            self.write_lock().await?;
+        }
+        if !summary.failed.is_empty() {
+            return Err(summary.error("update"));
+        }
+        Ok(())
+    }
+
+    /// 打印有更新版本的bins并返回exit code：都是最新时为0，有更新时为1，检查失败时为2
+    pub async fn outdated(&self, args: &OutdatedArgs) -> Result<i32> {
+        let status = self.check_outdated(&args.tags).await?;
+        self.print_outdated(&status.outdated)?;
+        Ok(if !status.failed.is_empty() {
+            2
+        } else if !status.outdated.is_empty() {
+            1
+        } else {
+            0
+        })
+    }
+
+    /// 检查有更新版本的bins，可选写入status文件供shell prompt读取而不需要访问网络
+    pub async fn check(&self, args: &CheckArgs) -> Result<()> {
+        let status = self.check_outdated(&args.tags).await?;
+        if !QUIET.load(Ordering::Relaxed) {
+            self.print_outdated(&status.outdated)?;
+        }
+        if let Some(path) = &args.write_status {
+            if self.manager.dry_run() {
+                status!("[dry-run] would write the status to {}", path.display());
+            } else {
+                write_status(path, &status).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 并发检查bins的更新版本，检查失败的bins记录在[CheckStatus::failed]中
+    async fn check_outdated(&self, tags: &TagArgs) -> Result<CheckStatus> {
+        let mut status = CheckStatus {
+            checked_at: Local::now(),
+            outdated: vec![],
+            failed: vec![],
+        };
+        for (name, res) in self
+            .manager
+            .check_pkgs(self.tagged_pkgs(tags).cloned().collect())
+            .await?
+        {
+            match res {
+                Ok(BinStatus {
+                    installed: Some(current),
+                    latest: Some(latest),
+                    released,
+                    pending,
+                    ..
+                }) => status.outdated.push(OutdatedItem {
+                    name,
+                    current,
+                    latest,
+                    released,
+                    pending,
+                }),
+                Ok(_) => debug!("{} is up to date", name),
+                Err(e) => {
+                    error!("failed to check the newer version of {}: {}", name, e);
+                    status.failed.push(name);
+                }
+            }
+        }
+        status.outdated.sort_by(|a, b| a.name.cmp(&b.name));
+        status.failed.sort();
+        Ok(status)
+    }
+
+    fn print_outdated(&self, items: &[OutdatedItem]) -> Result<()> {
+        match self.output {
+            OutputFormat::Text => items.iter().for_each(OutdatedItem::print),
+            OutputFormat::Json => print_json(&items)?,
+        }
+        Ok(())
+    }
+
+    /// 检查已安装bins的文件是否与安装时记录的一致，可选从cache中修复
+    pub async fn verify(&self, args: &VerifyArgs) -> Result<()> {
+        let pkgs = match &args.name {
+            Some(name) => vec![self.find_pkg(name)?],
+            None => {
+                let mut pkgs = vec![];
+                for pkg in self.manager.pkgs() {
+                    let name = pkg.bin().bin().name();
+                    if pkg.mapper().select_current_by_name(name).await?.is_some() {
+                        pkgs.push(pkg);
+                    } else {
+                        debug!("skipped verifying {} not installed", name);
+                    }
+                }
+                pkgs
+            }
+        };
+
+        let (mut items, mut failed) = (vec![], 0);
+        for pkg in pkgs {
+            let name = pkg.bin().bin().name();
+            let drift = match pkg.verify().await {
+                Ok(drift) => drift,
+                Err(e) if args.name.is_some() => return Err(e),
+                Err(e) => {
+                    error!("failed to verify {}: {}", name, e);
+                    failed += 1;
+                    continue;
+                }
+            };
+            let repaired = if args.repair && !drift.is_empty() {
+                match pkg.repair().await {
+                    Ok(_) => true,
+                    Err(e) => {
+                        error!("failed to repair {}: {}", name, e);
+                        false
+                    }
+                }
+            } else {
+                false
+            };
+            items.push(VerifyItem { drift, repaired });
+        }
+
+        match self.output {
+            OutputFormat::Text => items.iter().for_each(VerifyItem::print),
+            OutputFormat::Json => print_json(&items)?,
+        }
+        let drifted = items
+            .iter()
+            .filter(|item| !item.drift.is_empty() && !item.repaired)
+            .count();
+        if drifted != 0 || failed != 0 {
+            bail!(
+                "verify found {} drifted and {} failed binaries",
+                drifted,
+                failed
+            );
+        }
+        Ok(())
+    }
+
+    /// 将所有已安装bins的当前版本，url与下载文件的digest写入lock文件
+    async fn write_lock(&self) -> Result<()> {
+        if self.manager.dry_run() {
+            println!(
+                "[dry-run] would write lock file {}",
+                self.lock_path.display()
+            );
+            return Ok(());
+        }
+        let mut lock = LockFile::default();
+        for pkg in self.manager.pkgs() {
+            let name = pkg.bin().bin().name();
+            let info = match pkg.mapper().select_current_by_name(name).await? {
+                Some(info) => info,
+                None => {
+                    warn!("skipped {} not installed for lock file", name);
+                    continue;
+                }
+            };
+            let digest = pkg.digest(&info.url().parse()?).await?;
+            lock.insert(
+                name,
+                LockedBinaryBuilder::default()
+                    .version(info.version())
+                    .url(info.url())
+                    .digest(digest)
+                    .build()?,
+            );
+        }
+        lock.write_to(&self.lock_path)?;
+        info!("wrote lock file {}", self.lock_path.display());
+        Ok(())
+    }
+}
+
+/// sync时对一个package的操作
+#[derive(Debug)]
+enum SyncAction {
+    Install(BinaryPackage, String),
+    Update(BinaryPackage, String, String),
+    Uninstall(BinaryPackage),
+}
+
+impl fmt::Display for SyncAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncAction::Install(pkg, ver) => {
+                write!(f, "install {} {}", pkg.bin().bin().name(), ver)
+            }
+            SyncAction::Update(pkg, cur, ver) => {
+                write!(f, "update {} {} => {}", pkg.bin().bin().name(), cur, ver)
+            }
+            SyncAction::Uninstall(pkg) => write!(f, "uninstall {}", pkg.bin().bin().name()),
+        }
+    }
+}
+
+/// 打印shell的补全脚本，或者`--bins`时打印配置中的bin名称用于动态补全
+fn completions(args: &CompletionsArgs, config_path: &Path) -> Result<()> {
+    if args.bins {
+        for bin in config::from_path(config_path)?.bins() {
+            println!("{}", bin.name());
+        }
+        return Ok(());
+    }
+    if let Some(shell) = args.shell {
+        print!("{}", completion::generate(&Opt::command(), shell));
+    }
+    Ok(())
+}
+
+/// 收到SIGINT或SIGTERM时取消cancel，运行中的操作会回滚并打印summary。再次收到时立即退出
+fn cancel_on_signal(cancel: CancellationToken) {
+    tokio::spawn(async move {
+        if let Err(e) = shutdown_signal().await {
+            warn!("failed to listen for signals: {}", e);
+            return;
+        }
+        eprintln!("cancelling the running jobs. press ctrl-c again to exit immediately");
+        cancel.cancel();
+        if shutdown_signal().await.is_ok() {
+            exit(130);
+        }
+    });
+}
+
+async fn shutdown_signal() -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut term = signal(SignalKind::terminate())?;
+        tokio::select! {
+            res = tokio::signal::ctrl_c() => res,
+            _ = term.recv() => Ok(()),
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
+}
+
+/// 在终端提示用户确认，默认为否
+async fn confirm(prompt: &str) -> Result<bool> {
+    let prompt = prompt.to_owned();
+    tokio::task::spawn_blocking(move || {
+        print!("{} [y/N] ", prompt);
+        io::stdout().flush()?;
+        let mut line = String::new();
+        io::stdin().read_line(&mut line)?;
+        Ok::<_, Error>(matches!(line.trim().to_lowercase().as_str(), "y" | "yes"))
+    })
+    .await?
+}
+
+/// 一个package任务完成的操作
+#[derive(Debug)]
+enum Done {
+    /// installed the version
+    Installed(String),
+    /// updated from the old version to the new
+    Updated(String, String),
+    Uninstalled,
+    Skipped,
+}
+
+/// 多个package任务的结果汇总
+#[derive(Debug)]
+struct Summary {
+    /// the names and versions of the installed bins
+    installed: Vec<(String, String)>,
+    /// the names, old and new versions of the updated bins
+    updated: Vec<(String, String, String)>,
+    uninstalled: Vec<String>,
+    failed: Vec<(String, Error)>,
+    skipped: Vec<String>,
+    /// the bytes downloaded by all tasks
+    downloaded: u64,
+    started: Instant,
+}
+
+impl Default for Summary {
+    fn default() -> Self {
+        Self {
+            installed: vec![],
+            updated: vec![],
+            uninstalled: vec![],
+            failed: vec![],
+            skipped: vec![],
+            downloaded: 0,
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Summary {
+    /// 汇总[Manager]的结果，失败的会被打印
+    fn from_outcomes(outcomes: Vec<Outcome>, action: &str) -> Self {
+        let mut summary = Self::default();
+        for outcome in outcomes {
+            summary.downloaded += outcome.downloaded;
+            let done = match outcome.status {
+                Status::Installed(ver) => Done::Installed(ver),
+                Status::Updated(old, new) => Done::Updated(old, new),
+                Status::Skipped => Done::Skipped,
+                Status::Failed(e) => {
+                    error!("failed to {} {}: {}", action, outcome.name, e);
+                    summary.failed.push((outcome.name, e));
+                    continue;
+                }
+            };
+            summary.push(outcome.name, done);
+        }
+        summary
+    }
+
+    fn push(&mut self, name: String, done: Done) {
+        match done {
+            Done::Installed(ver) => self.installed.push((name, ver)),
+            Done::Updated(old, new) => self.updated.push((name, old, new)),
+            Done::Uninstalled => self.uninstalled.push(name),
+            Done::Skipped => self.skipped.push(name),
+        }
+    }
+
+    /// 成功完成的bins，不包括跳过的
+    fn succeeded(&self) -> Vec<&str> {
+        self.installed
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .chain(self.updated.iter().map(|(name, _, _)| name.as_str()))
+            .chain(self.uninstalled.iter().map(String::as_str))
+            .collect()
+    }
+
+    fn print(&self, action: &str) {
+        let mut counts = [
+            (self.installed.len(), "installed"),
+            (self.updated.len(), "updated"),
+            (self.uninstalled.len(), "uninstalled"),
+        ]
+        .into_iter()
+        .filter(|(n, _)| *n > 0)
+        .map(|(n, done)| format!("{} {}", n, done))
+        .collect::<Vec<_>>();
+        counts.push(format!("{} failed", self.failed.len()));
+        counts.push(format!("{} skipped", self.skipped.len()));
+        println!(
+            "{} summary: {} in {:.1}s, downloaded {}",
+            action,
+            counts.join(", "),
+            self.started.elapsed().as_secs_f64(),
+            human_size(self.downloaded)
+        );
+        if !self.installed.is_empty() {
+            let installed = self
+                .installed
+                .iter()
+                .map(|(name, ver)| format!("{} {}", name, ver))
+                .collect::<Vec<_>>();
+            println!("  installed: {}", installed.join(", "));
+        }
+        if !self.updated.is_empty() {
+            let updated = self
+                .updated
+                .iter()
+                .map(|(name, old, new)| format!("{} {} → {}", name, old, new))
+                .collect::<Vec<_>>();
+            println!("  updated: {}", updated.join(", "));
+        }
+        if !self.uninstalled.is_empty() {
+            println!("  uninstalled: {}", self.uninstalled.join(", "));
+        }
+        if !self.skipped.is_empty() {
+            println!("  skipped: {}", self.skipped.join(", "));
+        }
+        let (limited, failed) = self
+            .failed
+            .iter()
+            .partition::<Vec<_>, _>(|(_, e)| rate_limited(e).is_some());
+        if !failed.is_empty() {
+            println!("  failed:");
+            for (name, e) in failed {
+                println!("    {}: {}", name, e);
+            }
+        }
+        if let Some(err) = self.rate_limited() {
+            let names = limited
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>();
+            println!("  rate limited: {}", names.join(", "));
+            println!("  {}", err);
+        }
+    }
+
+    /// 所有rate limit错误中最晚重置的一个
+    fn rate_limited(&self) -> Option<RateLimited> {
+        self.failed
+            .iter()
+            .filter_map(|(_, e)| rate_limited(e))
+            .max_by_key(|limited| limited.reset)
+    }
+
+    /// 汇总失败任务的错误，rate limit只报告一次
+    fn error(&self, action: &str) -> Error {
+        let n = self.failed.len();
+        match self.rate_limited() {
+            Some(limited) => anyhow!("{} has {} failed tasks: {}", action, n, limited),
+            None => anyhow!("{} has {} failed tasks", action, n),
+        }
+    }
+
+    fn to_json(&self, action: &str) -> serde_json::Value {
+        json!({
+            "action": action,
+            "succeeded": self.succeeded(),
+            "installed": self
+                .installed
+                .iter()
+                .map(|(name, ver)| json!({ "name": name, "version": ver }))
+                .collect::<Vec<_>>(),
+            "updated": self
+                .updated
+                .iter()
+                .map(|(name, old, new)| json!({ "name": name, "from": old, "to": new }))
+                .collect::<Vec<_>>(),
+            "uninstalled": self.uninstalled,
+            "failed": self
+                .failed
+                .iter()
+                .map(|(name, e)| json!({ "name": name, "error": e.to_string() }))
+                .collect::<Vec<_>>(),
+            "skipped": self.skipped,
+            "rate_limited": self.rate_limited().map(|limited| limited.to_string()),
+            "downloaded_bytes": self.downloaded,
+            "elapsed_ms": self.started.elapsed().as_millis() as u64,
+        })
+    }
+}
+
+fn rate_limited(e: &Error) -> Option<RateLimited> {
+    e.chain()
+        .find_map(|e| e.downcast_ref::<RateLimited>())
+        .copied()
+}
+
+/// export导出的已安装状态
+#[derive(Debug, Serialize, Deserialize)]
+struct State {
+    infos: Vec<UpdatedInfo>,
+    pinned: Vec<PinnedInfo>,
+}
+
+/// sbom中bin的component，没有provenance时只有db中记录的来源
+fn sbom_component(
+    info: &UpdatedInfo,
+    provenance: Option<&Provenance>,
+) -> Result<serde_json::Value> {
+    let source = info.parse_source()?;
+    let mut refs = vec![json!({ "type": "distribution", "url": info.url() })];
+    if let Some(repo) = source.repo_url() {
+        refs.push(json!({ "type": "vcs", "url": repo }));
+    }
+    let mut properties = vec![json!({ "name": "binaries:source", "value": source.to_string() })];
+    let mut hashes = vec![];
+    if let Some(p) = provenance {
+        properties.push(json!({
+            "name": "binaries:installed-time",
+            "value": p.installed_time().to_rfc3339(),
+        }));
+        if let Some(digest) = p.source_digest() {
+            properties.push(json!({ "name": "binaries:source-digest", "value": digest }));
+        }
+        if let Some(("sha256", hex)) = p.digest().split_once(':') {
+            hashes.push(json!({ "alg": "SHA-256", "content": hex }));
+        }
+    }
+    Ok(json!({
+        "type": "application",
+        "bom-ref": format!("{}@{}", info.name(), info.version()),
+        "name": info.name(),
+        "version": info.version(),
+        "hashes": hashes,
+        "externalReferences": refs,
+        "properties": properties,
+    }))
+}
+
+/// bundle中描述打包的bins的文件
+static BUNDLE_MANIFEST: &str = "manifest.json";
+
+/// bundle的manifest
+#[derive(Debug, Serialize, Deserialize)]
+struct Bundle {
+    bins: Vec<BundledBin>,
+}
+
+/// bundle中一个bin的db记录与链接
+#[derive(Debug, Serialize, Deserialize)]
+struct BundledBin {
+    name: String,
+    /// the version history with the current first
+    infos: Vec<UpdatedInfo>,
+    pinned: Option<PinnedInfo>,
+    /// the recorded files of the packed versions
+    files: Vec<InstalledFile>,
+    /// the names linked in the exe dir
+    links: Vec<String>,
+}
+
+/// bin的版本目录在bundle中的路径
+fn bundle_dir(name: &str, ver: &str) -> String {
+    format!("data/{}/{}", name, ver)
+}
+
+/// 只读取bundle中的manifest，不解压版本目录
+fn read_bundle(path: &Path) -> Result<Bundle> {
+    let file =
+        std::fs::File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let mut archive = tar::Archive::new(zstd::Decoder::new(file)?);
+    for entry in archive.entries()? {
+        let entry = entry?;
+        if entry.path()? == Path::new(BUNDLE_MANIFEST) {
+            return serde_json::from_reader(entry)
+                .with_context(|| format!("invalid {} in {}", BUNDLE_MANIFEST, path.display()));
+        }
+    }
+    bail!("not found {} in {}", BUNDLE_MANIFEST, path.display())
+}
+
+/// doctor检查的db表与必须存在的列
+static EXPECTED_SCHEMA: [(&str, &[&str]); 9] = [
+    (
+        "updated_info",
+        &[
+            "id",
+            "name",
+            "version",
+            "source",
+            "url",
+            "create_time",
+            "updated_time",
+            "rollback_from",
+        ],
+    ),
+    ("pinned_info", &["name", "version", "create_time"]),
+    (
+        "install_stat",
+        &[
+            "id",
+            "name",
+            "version",
+            "action",
+            "asset",
+            "duration_ms",
+            "downloaded_bytes",
+            "success",
+            "error",
+            "create_time",
+        ],
+    ),
+    (
+        "installed_file",
+        &["id", "name", "version", "path", "digest"],
+    ),
+    ("disabled_info", &["name", "create_time"]),
+    ("asset_pattern", &["name", "pattern", "create_time"]),
+    (
+        "link_conflict",
+        &["path", "name", "action", "backup", "create_time"],
+    ),
+    ("installed_artifact", &["path", "name", "create_time"]),
+    ("cache_access", &["path", "name", "access_time"]),
+];
+
+/// doctor可以自动修复的操作
+#[derive(Debug)]
+enum DoctorFix {
+    CreateDir(PathBuf),
+    RemoveFile(PathBuf),
+    RemoveDir(PathBuf),
+    /// the id and name of a updated info
+    DeleteInfo(u32, String),
+}
+
+impl fmt::Display for DoctorFix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DoctorFix::CreateDir(p) => write!(f, "create dir {}", p.display()),
+            DoctorFix::RemoveFile(p) => write!(f, "remove file {}", p.display()),
+            DoctorFix::RemoveDir(p) => write!(f, "remove dir {}", p.display()),
+            DoctorFix::DeleteInfo(id, name) => write!(f, "delete db record {} of {}", id, name),
+        }
+    }
+}
+
+/// doctor检查的结果
+#[derive(Debug, Default)]
+struct DoctorReport {
+    oks: Vec<String>,
+    /// the problem and its manual fix hint
+    manuals: Vec<(String, String)>,
+    fixables: Vec<(String, DoctorFix)>,
+}
+
+impl DoctorReport {
+    fn ok(&mut self, msg: String) {
+        self.oks.push(msg);
+    }
+
+    fn manual(&mut self, problem: String, hint: String) {
+        self.manuals.push((problem, hint));
+    }
+
+    fn fixable(&mut self, problem: String, fix: DoctorFix) {
+        self.fixables.push((problem, fix));
+    }
+
+    fn fixes(&self) -> impl Iterator<Item = &DoctorFix> {
+        self.fixables.iter().map(|(_, fix)| fix)
+    }
+
+    fn print(&self) {
+        for msg in &self.oks {
+            println!("[ok] {}", msg);
+        }
+        for (problem, hint) in &self.manuals {
+            println!("[warn] {}\n    fix: {}", problem, hint);
+        }
+        for (problem, fix) in &self.fixables {
+            println!("[warn] {}\n    fix: {} (auto)", problem, fix);
+        }
+        println!(
+            "doctor summary: {} ok, {} problems",
+            self.oks.len(),
+            self.manuals.len() + self.fixables.len()
+        );
+    }
+}
+
+/// 何时为日志着色
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ArgEnum)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+/// 命令结果在stdout上的输出格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ArgEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+fn print_json(val: &impl Serialize) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(val)?);
+    Ok(())
+}
+
+/// outdated中一个有更新版本的bin
+#[derive(Debug, Serialize)]
+struct OutdatedItem {
+    name: String,
+    current: String,
+    latest: String,
+    released: Option<DateTime<Utc>>,
+    /// released less than the min-release-age ago and skipped by update
+    pending: bool,
+}
+
+impl OutdatedItem {
+    fn print(&self) {
+        let pending = if self.pending { " pending" } else { "" };
+        match &self.released {
+            Some(released) => println!(
+                "{} {} → {} ({}{})",
+                self.name,
+                self.current,
+                self.latest,
+                released.with_timezone(&Local).format("%Y-%m-%d"),
+                pending
+            ),
+            None => println!("{} {} → {}", self.name, self.current, self.latest),
+        }
+    }
+}
+
+/// check写入status文件的结果
+#[derive(Debug, Serialize)]
+struct CheckStatus {
+    checked_at: DateTime<Local>,
+    outdated: Vec<OutdatedItem>,
+    /// the names of the binaries failed to check
+    failed: Vec<String>,
+}
+
+/// 使用临时文件替换的方式写入status，避免读取时看到不完整的文件
+async fn write_status(path: &Path, status: &CheckStatus) -> Result<()> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        afs::create_dir_all(parent).await?;
+    }
+    let tmp = PathBuf::from(format!("{}.tmp", path.display()));
+    afs::write(&tmp, serde_json::to_string_pretty(status)?).await?;
+    afs::rename(&tmp, path).await?;
+    debug!("wrote the check status to {}", path.display());
+    Ok(())
+}
+
+/// verify中一个bin的检查结果
+#[derive(Debug, Serialize)]
+struct VerifyItem {
+    #[serde(flatten)]
+    drift: Drift,
+    repaired: bool,
+}
+
+impl VerifyItem {
+    fn print(&self) {
+        let drift = &self.drift;
+        if drift.is_empty() {
+            println!("{} {}: ok", drift.name(), drift.version());
+            return;
+        }
+        println!(
+            "{} {}: {}",
+            drift.name(),
+            drift.version(),
+            if self.repaired { "repaired" } else { "drifted" }
+        );
+        for (kind, paths) in [
+            ("modified", drift.modified()),
+            ("missing", drift.missing()),
+            ("extra", drift.extra()),
+        ] {
+            for path in paths {
+                println!("  {}: {}", kind, path);
+            }
+        }
+    }
+}
+
+/// list中一个bin的状态
+#[derive(Debug, Serialize)]
+struct ListItem {
+    name: String,
+    /// one of installed, updateable, external, installable and error
+    status: &'static str,
+    installed: Option<String>,
+    latest: Option<String>,
+    pinned: Option<String>,
+    disabled: bool,
+    /// the executable on PATH not installed by this
+    #[serde(skip_serializing_if = "Option::is_none")]
+    foreign: Option<ForeignItem>,
+    error: Option<String>,
+    /// only with `list --verbose`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stats: Option<StatSummary>,
+}
+
+/// PATH中非本程序安装的同名可执行文件
+#[derive(Debug, Serialize)]
+struct ForeignItem {
+    path: String,
+    version: String,
+}
+
+/// info与list中显示的最近安装结果数
+const STATS_LIMIT: u32 = 20;
+
+/// 一个bin最近安装结果的统计
+#[derive(Debug, Serialize)]
+struct StatSummary {
+    total: usize,
+    failed: usize,
+    avg_duration_ms: i64,
+    downloaded_bytes: i64,
+}
+
+impl StatSummary {
+    fn from_stats(stats: &[InstallStat]) -> Self {
+        let total = stats.len();
+        Self {
+            total,
+            failed: stats.iter().filter(|s| !s.success()).count(),
+            avg_duration_ms: stats.iter().map(|s| s.duration_ms()).sum::<i64>()
+                / total.max(1) as i64,
+            downloaded_bytes: stats.iter().map(|s| s.downloaded_bytes()).sum(),
+        }
+    }
+
+    fn print(&self) {
+        println!(
+            "  installs: {}, failed: {}, avg time: {:.1}s, downloaded: {}",
+            self.total,
+            self.failed,
+            self.avg_duration_ms as f64 / 1000.0,
+            human_size(self.downloaded_bytes as u64)
+        );
+    }
+}
+
+/// 一次安装操作的结果
+#[derive(Debug, Serialize)]
+struct StatItem {
+    action: String,
+    version: String,
+    asset: String,
+    time: String,
+    duration_ms: i64,
+    downloaded_bytes: i64,
+    success: bool,
+    error: Option<String>,
+}
+
+impl From<&InstallStat> for StatItem {
+    fn from(stat: &InstallStat) -> Self {
+        Self {
+            action: stat.action().to_owned(),
+            version: stat.version().to_owned(),
+            asset: stat.asset().to_owned(),
+            time: stat.create_time().format("%Y-%m-%d %H:%M:%S").to_string(),
+            duration_ms: *stat.duration_ms(),
+            downloaded_bytes: *stat.downloaded_bytes(),
+            success: *stat.success(),
+            error: stat.error().clone(),
+        }
+    }
+}
+
+impl StatItem {
+    fn print(&self) {
+        println!(
+            "  {} {} {} {} {:.1}s {}{}",
+            self.time,
+            self.action,
+            self.version,
+            self.asset,
+            self.duration_ms as f64 / 1000.0,
+            human_size(self.downloaded_bytes as u64),
+            match &self.error {
+                Some(e) => format!(" (failed: {})", e),
+                None => String::new(),
+            }
+        );
+    }
+}
+
+/// 一个已安装的版本记录
+#[derive(Debug, Serialize)]
+struct HistoryItem {
+    version: String,
+    updated_time: String,
+    url: String,
+    rollback_from: Option<String>,
+    current: bool,
+}
+
+impl HistoryItem {
+    /// infos需按最近更新的顺序排列，第一个为当前使用的版本
+    fn from_infos(infos: &[UpdatedInfo]) -> Vec<Self> {
+        infos
+            .iter()
+            .enumerate()
+            .map(|(i, info)| HistoryItem {
+                version: info.version().to_owned(),
+                updated_time: info.updated_time().format("%Y-%m-%d %H:%M:%S").to_string(),
+                url: info.url().to_owned(),
+                rollback_from: info.rollback_from().clone(),
+                current: i == 0,
+            })
+            .collect()
+    }
+
+    fn print(&self) {
+        println!(
+            "  {} {} {}{}{}",
+            self.version,
+            self.updated_time,
+            self.url,
+            self.rollback_from
+                .as_ref()
+                .map(|v| format!(" (rolled back from {})", v))
+                .unwrap_or_default(),
+            if self.current { " (current)" } else { "" }
+        );
+    }
+}
+
+/// exe dir中的一个链接
+#[derive(Debug, Serialize)]
+struct LinkItem {
+    path: PathBuf,
+    target: Option<PathBuf>,
+    exists: bool,
+}
+
+/// info命令的输出
+#[derive(Debug, Serialize)]
+struct InfoOutput {
+    name: String,
+    source: String,
+    version: Option<String>,
+    pick_regex: Option<String>,
+    ignore_regex: Option<String>,
+    bin_glob: Option<String>,
+    asset_url: Option<String>,
+    asset_id: Option<i64>,
+    exe_type: Option<ExeType>,
+    hook: Option<HookAction>,
+    hooks: Vec<Hook>,
+    completion: Option<Completion>,
+    pinned: Option<String>,
+    /// the saved pattern of the picked asset
+    asset_pattern: Option<String>,
+    history: Vec<HistoryItem>,
+    stats: StatSummary,
+    installs: Vec<StatItem>,
+    links: Vec<LinkItem>,
+    data_dir: PathBuf,
+    data_size: u64,
+    cache_dir: PathBuf,
+    cache_size: u64,
+    latest: Option<String>,
+    latest_error: Option<String>,
+    changelog: Option<String>,
+}
+
+impl InfoOutput {
+    fn print(&self) {
+        println!("name: {}", self.name);
+        println!("source: {}", self.source);
+        if let Some(ver) = &self.version {
+            println!("version: {}", ver);
+        }
+        if let Some(re) = &self.pick_regex {
+            println!("pick regex: {}", re);
+        }
+        if let Some(re) = &self.ignore_regex {
+            println!("ignore regex: {}", re);
+        }
+        if let Some(glob) = &self.bin_glob {
+            println!("bin glob: {}", glob);
+        }
+        if let Some(url) = &self.asset_url {
+            println!("asset url: {}", url);
+        }
+        if let Some(id) = self.asset_id {
+            println!("asset id: {}", id);
+        }
+        if let Some(ty) = &self.exe_type {
+            println!("exe type: {:?}", ty);
+        }
+        if let Some(hook) = &self.hook {
+            println!("hook: {:?}", hook);
+        }
+        for hook in &self.hooks {
+            let on = hook
+                .on()
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>();
+            println!("hook on {}: {}", on.join(","), hook.command());
+        }
+        if let Some(completion) = &self.completion {
+            println!("completion: {:?}", completion);
+        }
+        if let Some(pinned) = &self.pinned {
+            println!("pinned: {}", pinned);
+        }
+        if let Some(pattern) = &self.asset_pattern {
+            println!("asset pattern: {}", pattern);
+        }
+
+        println!("history:");
+        if self.history.is_empty() {
+            println!("  not installed");
+        }
+        self.history.iter().for_each(HistoryItem::print);
+
+        println!("installs:");
+        if !self.installs.is_empty() {
+            self.stats.print();
+        }
+        self.installs.iter().for_each(StatItem::print);
+
+        for link in &self.links {
+            match (&link.target, link.exists) {
+                (Some(target), _) => {
+                    println!("link: {} -> {}", link.path.display(), target.display())
+                }
+                (None, true) => println!("link: {}", link.path.display()),
+                (None, false) => println!("link: {} (not found)", link.path.display()),
+            }
+        }
+        println!(
+            "data dir: {} ({})",
+            self.data_dir.display(),
+            human_size(self.data_size)
+        );
+        println!(
+            "cache dir: {} ({})",
+            self.cache_dir.display(),
+            human_size(self.cache_size)
+        );
+
+        match (&self.latest, &self.latest_error) {
+            (Some(latest), _) => {
+                println!("latest: {}", latest);
+                if let Some(log) = &self.changelog {
+                    println!("changelog:\n{}", log);
+                }
+            }
+            (None, Some(e)) => println!("latest: unavailable ({})", e),
+            (None, None) => {}
+        }
+    }
+}
+
+async fn unused_bins(mapper: &Mapper, bins: &[Binary]) -> Result<Vec<Binary>> {
+    let unused = mapper
+        .select_all()
+        .await?
+        .into_iter()
+        .filter(|info| !bins.iter().any(|bin| bin.name() == info.name()))
+        .map(|info| bin_of(&info))
+        .collect::<Result<Vec<_>>>()?;
+    debug!(
+        "found {} bins of unused and {} bins of used",
+        unused.len(),
+        bins.len()
+    );
+    Ok(unused)
+}
+
+/// 在dir中获取进程间的advisory文件锁，避免多个实例同时修改data, cache dir与db
+///
+/// 返回的文件被drop时释放锁。如果wait则阻塞等待其它实例释放锁，否则立即失败
+async fn lock_instance(dir: &Path, wait: bool) -> Result<std::fs::File> {
+    create_dir_all(dir).await?;
+    let path = dir.join(format!("{}.pid.lock", CRATE_NAME));
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)?;
+    match file.try_lock_exclusive() {
+        Ok(_) => {}
+        Err(e) if e.kind() == fs2::lock_contended_error().kind() => {
+            if !wait {
+                bail!(
+                    "another {} instance is running with the lock {}. use --wait to wait for it",
+                    CRATE_NAME,
+                    path.display()
+                );
+            }
+            info!("waiting for another instance to release {}", path.display());
+            let file = file.try_clone()?;
+            tokio::task::spawn_blocking(move || file.lock_exclusive()).await??;
+        }
+        Err(e) => bail!("failed to lock {}: {}", path.display(), e),
+    }
+    trace!("locked {}", path.display());
+    Ok(file)
+}
+
+/// 使用info中记录的name与source构建bin
+fn bin_of(info: &UpdatedInfo) -> Result<Binary> {
+    BinaryBuilder::default()
+        .name(info.name())
+        .source(&info.parse_source()?)?
+        .build()
+        .map_err(Into::into)
+}
+
+/// 获取链接可执行文件的目录
+///
+/// windows上没有[BaseDirs::executable_dir]，使用项目local data目录下的scripts目录
+fn executable_dir(base_dirs: &BaseDirs) -> Option<PathBuf> {
+    base_dirs
+        .executable_dir()
+        .map(ToOwned::to_owned)
+        .or_else(|| {
+            if cfg!(windows) {
+                Some(PROJECT_DIRS.data_local_dir().join("scripts"))
+            } else {
+                None
+            }
+        })
 }
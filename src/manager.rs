@@ -0,0 +1,1054 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Error, Result};
+use chrono::{DateTime, Local, Utc};
+use derive_builder::Builder;
+use futures_util::{
+    future::{join_all, try_join_all},
+    stream::FuturesUnordered,
+    StreamExt,
+};
+use getset::{CopyGetters, Getters};
+use log::{debug, info, trace, warn};
+use reqwest::{
+    header::{self, HeaderMap},
+    Client, ClientBuilder, Url,
+};
+use serde::Serialize;
+use sqlx::{sqlite::SqlitePoolOptions, Executor, SqlitePool};
+use tokio::{fs as afs, sync::mpsc::UnboundedSender};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    config::{lock::LockFile, Binary, Config},
+    migration,
+    package::{redirect_policy, BinaryPackage, BinaryPackageBuilder, InstallObserver},
+    source::{github::ApiToken, ApiCache, SourceRegistry},
+    updated_info::Mapper,
+    util::human_size,
+    CRATE_NAME,
+};
+
+/// 作为库使用时的入口，管理config中所有bins的安装与更新
+///
+/// ```no_run
+/// # async fn f(config: binaries::config::Config) -> anyhow::Result<()> {
+/// let manager = binaries::Manager::builder()
+///     .config(config)
+///     .data_dir("/tmp/binaries")
+///     .cache_dir("/tmp/binaries/cache")
+///     .bin_dir("/tmp/binaries/bin")
+///     .build()
+///     .await?;
+/// for outcome in manager.install(&[]).await? {
+///     println!("{}: {:?}", outcome.name, outcome.status);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Builder, Getters, CopyGetters)]
+#[builder(build_fn(name = "pre_build"), derive(Debug))]
+pub struct Manager {
+    #[getset(get = "pub")]
+    config: Config,
+    /// the dir of installed files and db
+    #[builder(setter(into))]
+    #[getset(get = "pub")]
+    data_dir: PathBuf,
+    #[builder(setter(into))]
+    #[getset(get = "pub")]
+    cache_dir: PathBuf,
+    /// the dir to link executables
+    #[builder(setter(into))]
+    #[getset(get = "pub")]
+    bin_dir: PathBuf,
+    /// the http client without the github token. build one from config if none
+    #[builder(default, setter(strip_option))]
+    client: Option<Client>,
+    /// only print what would be done without touching disk and db
+    #[builder(default)]
+    #[getset(get_copy = "pub")]
+    dry_run: bool,
+    /// prompt to choose one of multiple candidate assets
+    #[builder(default)]
+    #[getset(get_copy = "pub")]
+    interactive: bool,
+    /// never escalate the privileged operations by sudo or doas
+    #[builder(default)]
+    #[getset(get_copy = "pub")]
+    no_sudo: bool,
+    /// the factories to create sources. register third-party sources on it
+    #[builder(default)]
+    sources: SourceRegistry,
+    /// the callbacks of the install progress of every bin
+    #[builder(default, setter(strip_option))]
+    observer: Option<Arc<dyn InstallObserver>>,
+    /// abort the running installs and updates and roll back if cancelled
+    #[builder(default)]
+    #[getset(get = "pub")]
+    cancel: CancellationToken,
+    /// the receiver of progress events. no events are sent if none
+    #[builder(default, setter(strip_option))]
+    events: Option<UnboundedSender<Event>>,
+    /// the dir of configs trusted without `trust` like the default config dir
+    #[builder(default, setter(into, strip_option))]
+    trusted_dir: Option<PathBuf>,
+    /// the commands of the untrusted config are disabled
+    #[builder(setter(skip))]
+    #[getset(get_copy = "pub")]
+    hooks_disabled: bool,
+    /// where the github token is loaded from
+    #[builder(setter(skip))]
+    #[getset(get = "pub")]
+    token_from: Option<String>,
+    /// the github token sent only to the github api
+    #[builder(setter(skip))]
+    #[getset(get = "pub")]
+    token: Option<ApiToken>,
+    #[builder(setter(skip))]
+    mapper: Option<Mapper>,
+    #[builder(setter(skip))]
+    pkg_builder: BinaryPackageBuilder,
+    #[builder(setter(skip))]
+    pkgs: Vec<BinaryPackage>,
+}
+
+impl ManagerBuilder {
+    /// 连接db并构建config中所有bins的packages
+    ///
+    /// 在运行任何命令前检查配置是否被信任，未信任的配置中的命令会被禁用
+    pub async fn build(&mut self) -> Result<Manager> {
+        let mut manager = self.pre_build()?;
+        let mapper = build_mapper(
+            manager.data_dir.join(format!("{}.db", CRATE_NAME)),
+            manager.dry_run,
+        )
+        .await?;
+        if !is_hooks_trusted(&mapper, &manager.config, manager.trusted_dir.as_deref()).await? {
+            warn!("disabled the commands and download settings of the untrusted config");
+            manager.config.disable_hooks();
+            manager.hooks_disabled = true;
+        }
+
+        let token = github_token(&manager.config).await?;
+        if let Some((_, from)) = &token {
+            info!("loaded github token from {}", from);
+        }
+        let client = match manager.client.clone() {
+            Some(client) => client,
+            None => build_client(&manager.config)?,
+        };
+        let api_token = token
+            .as_ref()
+            .map(|(val, _)| ApiToken::new(val))
+            .transpose()?;
+
+        let mut builder = BinaryPackageBuilder::default();
+        builder
+            .data_dir(manager.data_dir.clone())
+            .exe_dir(manager.bin_dir.clone())
+            .cache_dir(manager.cache_dir.clone())
+            .completion_dir(manager.data_dir.join("completions"))
+            .client(client.clone())
+            .mapper(mapper.clone())
+            .dry_run(manager.dry_run)
+            .interactive(manager.interactive)
+            .no_sudo(manager.no_sudo)
+            .cancel(manager.cancel.clone())
+            .sources(manager.sources.clone())
+            .api_cache(ApiCache::default())
+            .mirrors(manager.config.mirrors().clone())
+            .arch_aliases(manager.config.archs().clone())
+            .history_limit(*manager.config.history_limit());
+        if let Some(token) = &api_token {
+            builder.token(token.clone());
+        }
+        if let Some(observer) = &manager.observer {
+            builder.observer(observer.clone());
+        }
+        manager.client = Some(client);
+        manager.mapper = Some(mapper);
+        manager.token_from = token.map(|(_, from)| from);
+        manager.token = api_token;
+        manager.pkg_builder = builder;
+        manager.pkgs = try_join_all(
+            manager
+                .config
+                .bins()
+                .iter()
+                .cloned()
+                .map(|bin| manager.build_pkg(bin))
+                .map(tokio::spawn),
+        )
+        .await?
+        .into_iter()
+        .collect::<Result<_>>()?;
+        trace!("built {} bin packages", manager.pkgs.len());
+        Ok(manager)
+    }
+}
+
+/// [Manager]执行的操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    Install,
+    Update,
+    Check,
+}
+
+/// [Manager]操作一个bin时发送的进度事件
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event {
+    Started {
+        name: String,
+        action: Action,
+    },
+    Finished {
+        name: String,
+        action: Action,
+        version: Option<String>,
+    },
+    Skipped {
+        name: String,
+        action: Action,
+    },
+    Failed {
+        name: String,
+        action: Action,
+        error: String,
+    },
+}
+
+/// 一个bin的操作结果
+#[derive(Debug)]
+pub struct Outcome {
+    pub name: String,
+    pub status: Status,
+    /// the bytes downloaded by the operation
+    pub downloaded: u64,
+}
+
+#[derive(Debug)]
+pub enum Status {
+    /// installed the version
+    Installed(String),
+    /// updated from the old version to the new
+    Updated(String, String),
+    /// skipped as installed, disabled, pinned, pending or not updateable
+    Skipped,
+    Failed(Error),
+}
+
+/// [Manager::install_pkgs]的选项
+#[derive(Debug, Clone, Default)]
+pub struct InstallOptions {
+    /// reinstall the installed bins and replace the existing links
+    pub force: bool,
+    /// back up the existing unmanaged files at the link paths and take them over
+    pub adopt: bool,
+    /// reinstall the versions whose asset digests differ from the ones pinned on their first installs
+    pub accept_new_digest: bool,
+    /// install the managed binaries even if the os provided ones are reused by the `system` option
+    pub prefer_managed: bool,
+    /// cancel and roll back the other running installs when any fails
+    pub fail_fast: bool,
+    /// install the locked versions. the bins not in the lock file fail
+    pub lock: Option<LockFile>,
+}
+
+/// 一个bin的安装状态
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BinStatus {
+    pub name: String,
+    /// the current installed version
+    pub installed: Option<String>,
+    /// the newer version from the source to update. only set by [Manager::check]
+    pub latest: Option<String>,
+    /// the release date of the latest version if the source supports
+    pub released: Option<DateTime<Utc>>,
+    /// the latest version is released less than the min-release-age ago and skipped by update
+    pub pending: bool,
+    pub pinned: Option<String>,
+    pub disabled: bool,
+}
+
+impl BinStatus {
+    /// 是否已安装且有更新的版本
+    pub fn is_updateable(&self) -> bool {
+        self.installed.is_some() && self.latest.is_some()
+    }
+}
+
+impl Manager {
+    pub fn builder() -> ManagerBuilder {
+        ManagerBuilder::default()
+    }
+
+    /// 配置中的所有bins
+    pub fn bins(&self) -> impl Iterator<Item = &Binary> {
+        self.pkgs.iter().map(|pkg| pkg.bin().bin())
+    }
+
+    /// 配置中所有bins的packages
+    pub fn pkgs(&self) -> &[BinaryPackage] {
+        &self.pkgs
+    }
+
+    /// 所有packages共享的client
+    pub fn client(&self) -> &Client {
+        self.client.as_ref().expect("the client is set by build")
+    }
+
+    pub fn mapper(&self) -> &Mapper {
+        self.mapper
+            .as_ref()
+            .expect("the mapper is connected by build")
+    }
+
+    /// 使用共享的dirs, client, mapper构建bin的package，用于不在配置中的bin
+    pub fn build_pkg(
+        &self,
+        bin: Binary,
+    ) -> impl Future<Output = Result<BinaryPackage>> + Send + 'static {
+        let mut builder = self.pkg_builder.clone();
+        async move { builder.bin(bin).build().await }
+    }
+
+    /// 安装names中未安装的bins，names为空时安装所有bins
+    pub async fn install(&self, names: &[String]) -> Result<Vec<Outcome>> {
+        self.install_pkgs(self.find_pkgs(names)?, &InstallOptions::default())
+            .await
+    }
+
+    /// 使用opts安装pkgs中未安装的bins，被禁用的与非force时已安装或在系统中存在的bins会被跳过
+    pub async fn install_pkgs(
+        &self,
+        pkgs: Vec<BinaryPackage>,
+        opts: &InstallOptions,
+    ) -> Result<Vec<Outcome>> {
+        let pkgs = pkgs
+            .into_iter()
+            .map(|pkg| {
+                pkg.with_force(opts.force)
+                    .with_adopt(opts.adopt)
+                    .with_accept_new_digest(opts.accept_new_digest)
+                    .with_prefer_managed(opts.prefer_managed)
+            })
+            .collect();
+        self.run_all(Action::Install, pkgs, opts.fail_fast, |pkg| {
+            let locked = opts
+                .lock
+                .as_ref()
+                .map(|lock| lock.get(pkg.bin().bin().name()).cloned());
+            async move {
+                let name = pkg.bin().bin().name();
+                if !pkg.force() && pkg.has_installed().await? {
+                    info!("installed bin {} is skipped", name);
+                    Ok(Status::Skipped)
+                } else if pkg.is_disabled().await {
+                    info!("disabled bin {} is skipped", name);
+                    Ok(Status::Skipped)
+                } else if !pkg.prefer_managed() && matches!(pkg.external().await, Ok(Some(_))) {
+                    info!("external bin {} is skipped", name);
+                    Ok(Status::Skipped)
+                } else {
+                    match &locked {
+                        Some(Some(locked)) => pkg
+                            .install_locked(locked)
+                            .await
+                            .map(|_| Status::Installed(locked.version().to_owned())),
+                        Some(None) => Err(anyhow!("not found {} in lock file", name)),
+                        None => pkg.install().await.map(Status::Installed),
+                    }
+                }
+            }
+        })
+        .await
+    }
+
+    /// 更新names中可更新的bins，names为空时更新所有bins
+    pub async fn update(&self, names: &[String]) -> Result<Vec<Outcome>> {
+        self.update_pkgs(self.find_pkgs(names)?, false).await
+    }
+
+    /// 更新pkgs中可更新的bins，pinned或配置了version的bin会被跳过
+    ///
+    /// 发布时间不足`min-release-age`的latest版本不会更新。fail_fast时一个失败会取消其它运行中的更新
+    pub async fn update_pkgs(
+        &self,
+        pkgs: Vec<BinaryPackage>,
+        fail_fast: bool,
+    ) -> Result<Vec<Outcome>> {
+        self.run_all(Action::Update, pkgs, fail_fast, |pkg| async move {
+            let name = pkg.bin().bin().name();
+            let (cur, latest) = match pkg.newer_version().await? {
+                Some(vers) => vers,
+                None => {
+                    debug!("skipped update for {}", name);
+                    return Ok(Status::Skipped);
+                }
+            };
+            if pkg.bin().bin().min_release_age().is_some() {
+                let released = pkg.bin().release_date(&latest).await?;
+                if released.is_none() {
+                    warn!("not found the release date of {} {}", name, latest);
+                }
+                if pkg.is_pending(released.as_ref()) {
+                    info!(
+                        "skipped update for {} {} released less than min-release-age ago",
+                        name, latest
+                    );
+                    return Ok(Status::Skipped);
+                }
+            }
+            pkg.update().await.map(|ver| Status::Updated(cur, ver))
+        })
+        .await
+    }
+
+    /// 从source中检查names中bins的更新版本，names为空时检查所有bins
+    pub async fn check(&self, names: &[String]) -> Result<Vec<(String, Result<BinStatus>)>> {
+        self.check_pkgs(self.find_pkgs(names)?).await
+    }
+
+    /// 从source中检查pkgs的更新版本，不可更新的bins没有latest
+    pub async fn check_pkgs(
+        &self,
+        pkgs: Vec<BinaryPackage>,
+    ) -> Result<Vec<(String, Result<BinStatus>)>> {
+        let jobs = pkgs.into_iter().map(|pkg| {
+            let events = self.events.clone();
+            tokio::spawn(async move {
+                let name = pkg.bin().bin().name().to_owned();
+                send(
+                    &events,
+                    Event::Started {
+                        name: name.clone(),
+                        action: Action::Check,
+                    },
+                );
+                let res = async {
+                    let mut status = bin_status(&pkg).await?;
+                    if let Some((cur, latest)) = pkg.newer_version().await? {
+                        let released = pkg.bin().release_date(&latest).await.unwrap_or_else(|e| {
+                            warn!(
+                                "failed to get the release date of {} {}: {}",
+                                name, latest, e
+                            );
+                            None
+                        });
+                        status.pending = pkg.is_pending(released.as_ref());
+                        status.installed = Some(cur);
+                        status.latest = Some(latest);
+                        status.released = released;
+                    }
+                    Ok::<_, Error>(status)
+                }
+                .await;
+                send(
+                    &events,
+                    match &res {
+                        Ok(status) => Event::Finished {
+                            name: name.clone(),
+                            action: Action::Check,
+                            version: status.latest.clone(),
+                        },
+                        Err(e) => Event::Failed {
+                            name: name.clone(),
+                            action: Action::Check,
+                            error: e.to_string(),
+                        },
+                    },
+                );
+                (name, res)
+            })
+        });
+        join_all(jobs)
+            .await
+            .into_iter()
+            .map(|job| job.map_err(Into::into))
+            .collect()
+    }
+
+    /// 列出所有bins在db中的安装状态，不会访问source
+    pub async fn list(&self) -> Result<Vec<BinStatus>> {
+        try_join_all(self.pkgs.iter().map(bin_status)).await
+    }
+
+    /// 在pkgs上并发执行f，cancel取消时运行中的任务会被回滚
+    ///
+    /// fail_fast时一个失败会取消其它运行中的任务并等待它们回滚
+    async fn run_all<F, Fut>(
+        &self,
+        action: Action,
+        pkgs: Vec<BinaryPackage>,
+        fail_fast: bool,
+        f: F,
+    ) -> Result<Vec<Outcome>>
+    where
+        F: Fn(BinaryPackage) -> Fut,
+        Fut: Future<Output = Result<Status>> + Send + 'static,
+    {
+        let cancel = self.cancel.child_token();
+        let mut jobs = pkgs
+            .into_iter()
+            .map(|pkg| {
+                let pkg = pkg.with_cancel(cancel.clone());
+                let events = self.events.clone();
+                let name = pkg.bin().bin().name().to_owned();
+                let fut = f(pkg.clone());
+                tokio::spawn(async move {
+                    send(
+                        &events,
+                        Event::Started {
+                            name: name.clone(),
+                            action,
+                        },
+                    );
+                    let status = fut.await.unwrap_or_else(Status::Failed);
+                    send(
+                        &events,
+                        match &status {
+                            Status::Installed(ver) | Status::Updated(_, ver) => Event::Finished {
+                                name: name.clone(),
+                                action,
+                                version: Some(ver.clone()),
+                            },
+                            Status::Skipped => Event::Skipped {
+                                name: name.clone(),
+                                action,
+                            },
+                            Status::Failed(e) => Event::Failed {
+                                name: name.clone(),
+                                action,
+                                error: e.to_string(),
+                            },
+                        },
+                    );
+                    Outcome {
+                        name,
+                        status,
+                        downloaded: pkg.downloaded_bytes(),
+                    }
+                })
+            })
+            .collect::<FuturesUnordered<_>>();
+        debug!("waiting for {} {:?} jobs", jobs.len(), action);
+
+        let mut outcomes = vec![];
+        while let Some(job) = jobs.next().await {
+            let outcome = job?;
+            if let Status::Failed(e) = &outcome.status {
+                if fail_fast && !cancel.is_cancelled() {
+                    warn!(
+                        "cancelling {} remaining jobs as {} failed: {}",
+                        jobs.len(),
+                        outcome.name,
+                        e
+                    );
+                    cancel.cancel();
+                }
+            }
+            outcomes.push(outcome);
+        }
+        self.evict_cache().await;
+        Ok(outcomes)
+    }
+
+    /// 缓存超过配置的max cache size时清理最久未使用的下载文件，失败时只警告
+    pub async fn evict_cache(&self) {
+        let limit = match self.config.max_cache_size() {
+            Some(limit) if !self.dry_run => *limit,
+            _ => return,
+        };
+        match evict_cache(self.mapper(), &self.cache_dir, limit, false).await {
+            Ok(evicted) => {
+                for file in evicted {
+                    info!(
+                        "evicted cached file {} of {}",
+                        file.path.display(),
+                        human_size(file.size)
+                    );
+                }
+            }
+            Err(e) => warn!("failed to evict the cache: {}", e),
+        }
+    }
+
+    /// 找到names对应的packages，names为空时为所有packages
+    ///
+    /// # Error
+    ///
+    /// * 如果一个name不在配置中
+    fn find_pkgs(&self, names: &[String]) -> Result<Vec<BinaryPackage>> {
+        if names.is_empty() {
+            return Ok(self.pkgs.clone());
+        }
+        names
+            .iter()
+            .map(|name| {
+                self.pkgs
+                    .iter()
+                    .find(|pkg| pkg.bin().bin().name() == name)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("not found binary {} in config", name))
+            })
+            .collect()
+    }
+}
+
+fn send(events: &Option<UnboundedSender<Event>>, event: Event) {
+    if let Some(tx) = events {
+        // the receiver may be dropped by the caller
+        if tx.send(event).is_err() {
+            trace!("dropped an event as the receiver is closed");
+        }
+    }
+}
+
+async fn current_ver(pkg: &BinaryPackage) -> Result<Option<String>> {
+    Ok(pkg
+        .mapper()
+        .select_current_by_name(pkg.bin().bin().name())
+        .await?
+        .map(|info| info.version().to_owned()))
+}
+
+async fn bin_status(pkg: &BinaryPackage) -> Result<BinStatus> {
+    let name = pkg.bin().bin().name().to_owned();
+    let installed = if pkg.has_installed().await? {
+        current_ver(pkg).await?
+    } else {
+        None
+    };
+    let pinned = pkg
+        .mapper()
+        .select_pinned_by_name(&name)
+        .await?
+        .map(|p| p.version().to_owned());
+    Ok(BinStatus {
+        disabled: pkg.is_disabled().await,
+        name,
+        installed,
+        latest: None,
+        released: None,
+        pending: false,
+        pinned,
+    })
+}
+
+/// 配置中的命令是否被信任
+///
+/// 没有命令的，不是从文件加载的与所有文件都在trusted dir中的配置总是信任的，
+/// 其它配置需要`trust`记录了相同的digest
+pub async fn is_hooks_trusted(
+    mapper: &Mapper,
+    config: &Config,
+    trusted_dir: Option<&Path>,
+) -> Result<bool> {
+    let (digest, path) = match (config.hooks_digest(), config.files().first()) {
+        (Some(digest), Some(path)) => (digest, path),
+        _ => return Ok(true),
+    };
+    let in_trusted_dir = trusted_dir
+        .and_then(|dir| dir.canonicalize().ok())
+        .is_some_and(|dir| config.files().iter().all(|p| p.starts_with(&dir)));
+    if in_trusted_dir {
+        return Ok(true);
+    }
+    let trusted = mapper
+        .select_trusted_digest(&path.display().to_string())
+        .await?;
+    Ok(trusted.as_ref() == Some(digest))
+}
+
+/// 获取github token与其来源的描述，优先使用配置中的`github.token`，其次是env `Authorization`
+pub async fn github_token(config: &Config) -> Result<Option<(String, String)>> {
+    if let Some(token) = config.github_token() {
+        let val = token
+            .resolve()
+            .await
+            .map_err(|e| anyhow!("failed to read github token from {}: {}", token, e))?;
+        return Ok(Some((val, token.to_string())));
+    }
+    let name = "Authorization";
+    Ok(std::env::var(name)
+        .ok()
+        .map(|val| (val, format!("env `{}`", name))))
+}
+
+/// 探测空闲连接是否存活的间隔，避免连接池中的连接被中间设备静默断开
+const TCP_KEEPALIVE: Duration = Duration::from_secs(60);
+
+/// 构建所有packages共享的client，clone的client使用同一个连接池
+///
+/// 只限制连接的超时，下载大文件时没有总时长限制。https连接通过ALPN协商http2，
+/// 多个bins对同一host的并发请求可以复用一个连接。github token不作为默认header，
+/// 由[ApiToken]只添加到github api的请求中
+pub fn build_client(config: &Config) -> Result<Client> {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::ACCEPT,
+        header::HeaderValue::from_static("application/vnd.github.v3+json"),
+    );
+    headers.insert(header::USER_AGENT, header::HeaderValue::from_static("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/100.0.4896.88 Safari/537.36"));
+
+    ClientBuilder::new()
+        .default_headers(headers)
+        .connect_timeout(*config.connect_timeout())
+        .pool_max_idle_per_host(*config.pool_max_idle())
+        .pool_idle_timeout(*config.pool_idle_timeout())
+        .tcp_keepalive(TCP_KEEPALIVE)
+        .http2_adaptive_window(true)
+        .http2_keep_alive_interval(TCP_KEEPALIVE)
+        .redirect(redirect_policy())
+        .build()
+        .map_err(Into::into)
+}
+
+/// 被[evict_cache]清理的一个缓存文件
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EvictedCache {
+    pub path: PathBuf,
+    pub size: u64,
+    /// the last time the file was used or the modified time if never recorded
+    pub accessed: DateTime<Local>,
+}
+
+/// 按最近访问时间从旧到新删除cache dir中下载的文件，直到总大小不超过limit
+///
+/// 没有访问记录的文件使用其修改时间。dry run时只返回将删除的文件
+pub async fn evict_cache(
+    mapper: &Mapper,
+    cache_dir: &Path,
+    limit: u64,
+    dry_run: bool,
+) -> Result<Vec<EvictedCache>> {
+    let accesses = mapper
+        .select_cache_accesses()
+        .await?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+    let mut files = vec![];
+    if let Ok(mut dirs) = afs::read_dir(cache_dir).await {
+        while let Some(dir) = dirs.next_entry().await? {
+            if !dir.file_type().await?.is_dir() {
+                continue;
+            }
+            let mut entries = afs::read_dir(dir.path()).await?;
+            while let Some(file) = entries.next_entry().await? {
+                let path = file.path();
+                if !file.file_type().await?.is_file()
+                    || path.extension().is_some_and(|ext| ext == "md5")
+                {
+                    continue;
+                }
+                let meta = file.metadata().await?;
+                let accessed = match accesses.get(&path.display().to_string()) {
+                    Some(time) => *time,
+                    None => meta.modified()?.into(),
+                };
+                files.push(EvictedCache {
+                    path,
+                    size: meta.len(),
+                    accessed,
+                });
+            }
+        }
+    }
+    let mut total = files.iter().map(|f| f.size).sum::<u64>();
+    debug!(
+        "found {} cached files of {} bytes with limit {} in {}",
+        files.len(),
+        total,
+        limit,
+        cache_dir.display()
+    );
+    files.sort_by_key(|f| f.accessed);
+
+    let mut evicted = vec![];
+    for file in files {
+        if total <= limit {
+            break;
+        }
+        total -= file.size;
+        if !dry_run {
+            trace!("evicting cached file {}", file.path.display());
+            afs::remove_file(&file.path).await?;
+            let md5_path = PathBuf::from(format!("{}.md5", file.path.display()));
+            if afs::metadata(&md5_path).await.is_ok() {
+                afs::remove_file(&md5_path).await?;
+            }
+            mapper
+                .delete_cache_access(&file.path.display().to_string())
+                .await?;
+        }
+        evicted.push(file);
+    }
+    Ok(evicted)
+}
+
+/// 连接sqlite db文件p，如果不存在则创建并初始化
+///
+/// dry run时不会创建db文件，而是使用初始化的内存db
+pub async fn build_mapper(p: impl AsRef<Path>, dry_run: bool) -> Result<Mapper> {
+    let p = p.as_ref();
+
+    let exists = afs::metadata(p).await.is_ok();
+    let pool = if dry_run {
+        let pool = memory_pool().await?;
+        if exists {
+            debug!("loading db {} into a memory db for dry run", p.display());
+            copy_db(&pool, p).await?;
+            let (ver, latest) = (
+                migration::current_version(&pool).await?,
+                migration::latest_version(),
+            );
+            if ver < latest {
+                println!(
+                    "[dry-run] would migrate db schema from version {} to {}",
+                    ver, latest
+                );
+            }
+        } else {
+            debug!("not found db {}. use a memory db for dry run", p.display());
+        }
+        pool
+    } else {
+        if !exists {
+            if let Some(p) = p.parent() {
+                if afs::metadata(p).await.is_err() {
+                    trace!("creating all dirs for sqlite db {}", p.display());
+                    afs::create_dir_all(p).await?;
+                }
+            }
+            trace!("creating db file: {}", p.display());
+            afs::File::create(p).await?;
+        }
+        let url = format!("sqlite:{}", p.display());
+        debug!("connecting sqlite db for {}", url);
+        SqlitePoolOptions::new().connect(&url).await?
+    };
+
+    // the memory db in dry run is a copy that is safe to migrate
+    migration::migrate(&pool).await?;
+    Ok(Mapper { pool })
+}
+
+/// 只有一个连接的内存db。每个连接的`sqlite::memory:`都是独立的db，连接不能被回收
+async fn memory_pool() -> Result<SqlitePool> {
+    SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect("sqlite::memory:")
+        .await
+        .map_err(Into::into)
+}
+
+/// 以只读方式打开db文件p，将其表与数据复制到pool的db中
+async fn copy_db(pool: &SqlitePool, p: &Path) -> Result<()> {
+    let mut uri = Url::from_file_path(afs::canonicalize(p).await?)
+        .map_err(|_| anyhow!("invalid db path {}", p.display()))?;
+    uri.set_query(Some("mode=ro"));
+    sqlx::query("ATTACH DATABASE ? AS src")
+        .bind(uri.as_str())
+        .execute(pool)
+        .await?;
+
+    // create the indexes and triggers after the data is copied
+    let objs = sqlx::query_as::<_, (String, String, String)>(
+        "SELECT type, name, sql FROM src.sqlite_master
+        WHERE sql IS NOT NULL AND name NOT LIKE 'sqlite_%'
+        ORDER BY type != 'table', rowid",
+    )
+    .fetch_all(pool)
+    .await?;
+    for (ty, name, sql) in objs {
+        trace!("copying {} {} of db {}", ty, name, p.display());
+        pool.execute(sql.as_str()).await?;
+        if ty == "table" {
+            let name = name.replace('"', "\"\"");
+            pool.execute(
+                format!(r#"INSERT INTO main."{0}" SELECT * FROM src."{0}""#, name).as_str(),
+            )
+            .await?;
+        }
+    }
+
+    pool.execute("DETACH DATABASE src").await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use crate::config::raw::RawConfig;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_evict_cache() -> Result<()> {
+        let dir = tempdir()?;
+        let mapper = build_mapper(dir.path().join("test.db"), false).await?;
+        let cache_dir = dir.path().join("cache");
+        let paths = ["a/1.zip", "a/2.zip", "b/3.zip"].map(|p| cache_dir.join(p));
+        for path in &paths {
+            afs::create_dir_all(path.parent().unwrap()).await?;
+            afs::write(path, [0; 10]).await?;
+        }
+        afs::write(cache_dir.join("a/2.zip.md5"), "digest").await?;
+        // 1.zip has no record and uses the older modified time
+        for path in &paths[1..] {
+            mapper.touch_cache(&path.display().to_string(), "a").await?;
+        }
+
+        let evicted = evict_cache(&mapper, &cache_dir, 25, true).await?;
+        assert_eq!(evicted.len(), 1);
+        assert!(paths[0].is_file());
+
+        let evicted = evict_cache(&mapper, &cache_dir, 15, false).await?;
+        assert_eq!(
+            evicted.into_iter().map(|f| f.path).collect::<Vec<_>>(),
+            &paths[..2]
+        );
+        assert!(!cache_dir.join("a/2.zip.md5").exists());
+        assert!(paths[2].is_file());
+        assert_eq!(mapper.select_cache_accesses().await?.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_list() -> Result<()> {
+        let raw = toml::from_str::<RawConfig>(
+            r#"
+[bins.mgr-a]
+github = "a/mgr-a"
+enabled = false
+"#,
+        )?;
+        let dir = tempdir()?;
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let manager = Manager::builder()
+            .config(Config::try_from(raw)?)
+            .data_dir(dir.path().join("data"))
+            .cache_dir(dir.path().join("cache"))
+            .bin_dir(dir.path().join("bin"))
+            .events(tx)
+            .build()
+            .await?;
+        assert_eq!(
+            manager.list().await?,
+            [BinStatus {
+                name: "mgr-a".to_owned(),
+                installed: None,
+                latest: None,
+                released: None,
+                pending: false,
+                pinned: None,
+                disabled: true,
+            }]
+        );
+
+        let outcomes = manager.install(&[]).await?;
+        assert!(matches!(outcomes[0].status, Status::Skipped));
+        assert_eq!(
+            rx.recv().await,
+            Some(Event::Started {
+                name: "mgr-a".to_owned(),
+                action: Action::Install
+            })
+        );
+        assert!(matches!(rx.recv().await, Some(Event::Skipped { .. })));
+
+        assert!(manager.update(&["none".to_owned()]).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_build_mapper_dry_run() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("legacy.db");
+        let pool = SqlitePoolOptions::new()
+            .connect(&format!("sqlite:{}?mode=rwc", path.display()))
+            .await?;
+        pool.execute(migration::MIGRATIONS[0].1).await?;
+        pool.execute(
+            "INSERT INTO updated_info (name, version, source, url, create_time, updated_time)
+            VALUES ('a', '1.0', 'github:a/a', 'https://a.b/a', '2022-01-01 00:00:00', '2022-01-01 00:00:00')",
+        )
+        .await?;
+        pool.close().await;
+        let data = afs::read(&path).await?;
+
+        let mapper = build_mapper(&path, true).await?;
+        assert_eq!(mapper.select_all().await?.len(), 1);
+        assert_eq!(mapper.select_asset_pattern("a").await?, None);
+        mapper.insert_disabled("a").await?;
+        assert!(mapper.is_disabled("a").await?);
+        assert_eq!(afs::read(&path).await?, data);
+
+        let mapper = build_mapper(&path, false).await?;
+        assert_eq!(mapper.select_all().await?.len(), 1);
+        assert!(!mapper.is_disabled("a").await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_untrusted_config() -> Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("config.toml");
+        afs::write(
+            &path,
+            "github.token = \"command:echo token\"\n[bins.mgr-b]\ngithub = \"a/mgr-b\"\nhook.install = \"echo 1\"",
+        )
+        .await?;
+        let build = |trusted_dir: Option<PathBuf>| {
+            let mut builder = Manager::builder();
+            builder
+                .config(crate::config::from_path(&path).unwrap())
+                .data_dir(dir.path().join("data"))
+                .cache_dir(dir.path().join("cache"))
+                .bin_dir(dir.path().join("bin"));
+            if let Some(trusted_dir) = trusted_dir {
+                builder.trusted_dir(trusted_dir);
+            }
+            async move { builder.build().await }
+        };
+
+        let manager = build(None).await?;
+        assert!(manager.hooks_disabled());
+        assert_eq!(manager.config().github_token(), &None);
+        assert_eq!(manager.token_from(), &None);
+        assert!(manager.config().bins()[0].hook().is_none());
+
+        assert!(!build(Some(dir.path().to_owned())).await?.hooks_disabled());
+
+        let digest = manager.config().hooks_digest().clone();
+        let config = crate::config::from_path(&path)?;
+        manager
+            .mapper()
+            .insert_trusted_config(
+                &path.canonicalize()?.display().to_string(),
+                config.hooks_digest().as_deref().unwrap(),
+            )
+            .await?;
+        let manager = build(None).await?;
+        assert!(!manager.hooks_disabled());
+        assert_eq!(manager.config().hooks_digest(), &digest);
+        assert_eq!(
+            manager.token_from().as_deref(),
+            Some("command `echo token`")
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,366 @@
+use anyhow::{bail, Result};
+use chrono::Local;
+use futures_util::TryStreamExt;
+use log::{debug, info, trace};
+use sqlx::{Executor, Sqlite, SqlitePool};
+
+/// 按版本顺序排列的schema migration，已发布的migration不能修改，只能追加
+///
+/// 版本从1开始，对应其在数组中的索引+1
+pub static MIGRATIONS: &[(&str, &str)] = &[
+    (
+        "create updated_info",
+        "CREATE TABLE IF NOT EXISTS `updated_info` (
+            `id` INTEGER PRIMARY KEY AUTOINCREMENT,
+            `name` VARCHAR(20) NOT NULL,
+            `version` VARCHAR(20) NOT NULL,
+            `source` varchar(1024) NOT NULL,
+            `url` varchar(1024) NOT NULL,
+            `create_time` datetime NOT NULL,
+            `updated_time` datetime NOT NULL,
+            UNIQUE(`name`, `version`)
+        );",
+    ),
+    (
+        "create pinned_info",
+        "CREATE TABLE IF NOT EXISTS `pinned_info` (
+            `name` VARCHAR(20) PRIMARY KEY NOT NULL,
+            `version` VARCHAR(20) NOT NULL,
+            `create_time` datetime NOT NULL
+        );",
+    ),
+    (
+        "add updated_info.rollback_from",
+        "ALTER TABLE `updated_info` ADD COLUMN `rollback_from` VARCHAR(20);",
+    ),
+    (
+        "create installed_file",
+        "CREATE TABLE IF NOT EXISTS `installed_file` (
+            `id` INTEGER PRIMARY KEY AUTOINCREMENT,
+            `name` VARCHAR(20) NOT NULL,
+            `version` VARCHAR(20) NOT NULL,
+            `path` varchar(1024) NOT NULL,
+            `digest` VARCHAR(64) NOT NULL,
+            UNIQUE(`name`, `version`, `path`)
+        );",
+    ),
+    (
+        "create install_stat",
+        "CREATE TABLE IF NOT EXISTS `install_stat` (
+            `id` INTEGER PRIMARY KEY AUTOINCREMENT,
+            `name` VARCHAR(20) NOT NULL,
+            `version` VARCHAR(20) NOT NULL,
+            `action` VARCHAR(20) NOT NULL,
+            `asset` varchar(1024) NOT NULL,
+            `duration_ms` INTEGER NOT NULL,
+            `downloaded_bytes` INTEGER NOT NULL,
+            `success` BOOLEAN NOT NULL,
+            `error` TEXT,
+            `create_time` datetime NOT NULL
+        );",
+    ),
+    (
+        "normalize updated_info.source from json",
+        "UPDATE `updated_info`
+        SET `source` = 'github:' || json_extract(`source`, '$.github.owner') || '/' || json_extract(`source`, '$.github.repo')
+        WHERE json_valid(`source`) AND json_extract(`source`, '$.github.owner') IS NOT NULL;",
+    ),
+    (
+        "create disabled_info",
+        "CREATE TABLE IF NOT EXISTS `disabled_info` (
+            `name` VARCHAR(20) PRIMARY KEY NOT NULL,
+            `create_time` datetime NOT NULL
+        );",
+    ),
+    (
+        "create asset_pattern",
+        "CREATE TABLE IF NOT EXISTS `asset_pattern` (
+            `name` VARCHAR(20) PRIMARY KEY NOT NULL,
+            `pattern` varchar(1024) NOT NULL,
+            `create_time` datetime NOT NULL
+        );",
+    ),
+    (
+        "create link_conflict",
+        "CREATE TABLE IF NOT EXISTS `link_conflict` (
+            `path` varchar(1024) PRIMARY KEY NOT NULL,
+            `name` VARCHAR(20) NOT NULL,
+            `action` VARCHAR(20) NOT NULL,
+            `backup` varchar(1024),
+            `create_time` datetime NOT NULL
+        );",
+    ),
+    (
+        "create installed_artifact",
+        "CREATE TABLE IF NOT EXISTS `installed_artifact` (
+            `path` varchar(1024) PRIMARY KEY NOT NULL,
+            `name` VARCHAR(20) NOT NULL,
+            `create_time` datetime NOT NULL
+        );",
+    ),
+    (
+        "create cache_access",
+        "CREATE TABLE IF NOT EXISTS `cache_access` (
+            `path` varchar(1024) PRIMARY KEY NOT NULL,
+            `name` VARCHAR(20) NOT NULL,
+            `access_time` datetime NOT NULL
+        );",
+    ),
+    (
+        "create asset_digest",
+        "CREATE TABLE IF NOT EXISTS `asset_digest` (
+            `name` VARCHAR(20) NOT NULL,
+            `version` VARCHAR(20) NOT NULL,
+            `digest` VARCHAR(256) NOT NULL,
+            `create_time` datetime NOT NULL,
+            PRIMARY KEY(`name`, `version`)
+        );",
+    ),
+    (
+        "create trusted_config",
+        "CREATE TABLE IF NOT EXISTS `trusted_config` (
+            `path` varchar(1024) PRIMARY KEY NOT NULL,
+            `digest` VARCHAR(64) NOT NULL,
+            `create_time` datetime NOT NULL
+        );",
+    ),
+    (
+        "create external_binary",
+        "CREATE TABLE IF NOT EXISTS `external_binary` (
+            `name` VARCHAR(20) PRIMARY KEY NOT NULL,
+            `path` varchar(1024) NOT NULL,
+            `version` VARCHAR(20) NOT NULL,
+            `provider` VARCHAR(20) NOT NULL,
+            `create_time` datetime NOT NULL
+        );",
+    ),
+    (
+        "create skipped_version",
+        "CREATE TABLE IF NOT EXISTS `skipped_version` (
+            `name` VARCHAR(20) NOT NULL,
+            `version` VARCHAR(20) NOT NULL,
+            `create_time` datetime NOT NULL,
+            PRIMARY KEY(`name`, `version`)
+        );",
+    ),
+];
+
+/// 引入schema_version表之前的最新版本，更早的db只可能是它之前的版本
+const LEGACY_VERSION: u32 = 4;
+
+/// 当前程序支持的最新schema版本
+pub fn latest_version() -> u32 {
+    MIGRATIONS.len() as u32
+}
+
+/// 获取db的schema版本。没有schema_version表的旧db从已存在的表与列中推断
+pub async fn current_version(pool: &SqlitePool) -> Result<u32> {
+    if table_exists(pool, "schema_version").await? {
+        let ver = sqlx::query_scalar::<_, Option<u32>>("select max(version) from schema_version")
+            .fetch_one(pool)
+            .await?;
+        return Ok(ver.unwrap_or(0));
+    }
+
+    // the schema of legacy dbs was created at once from a snapshot of schema.sql
+    let ver = if !table_exists(pool, "updated_info").await? {
+        0
+    } else if !table_exists(pool, "pinned_info").await? {
+        1
+    } else if !column_exists(pool, "updated_info", "rollback_from").await? {
+        2
+    } else if !table_exists(pool, "installed_file").await? {
+        3
+    } else {
+        LEGACY_VERSION
+    };
+    debug!("inferred version {} of the legacy db schema", ver);
+    Ok(ver)
+}
+
+/// 将db升级到[latest_version]，返回升级前的版本
+///
+/// 每个migration与其版本记录在同一个事务中执行
+///
+/// # Error
+///
+/// * 如果db的版本比当前程序支持的更新
+pub async fn migrate(pool: &SqlitePool) -> Result<u32> {
+    let from = current_version(pool).await?;
+    let latest = latest_version();
+    if from > latest {
+        bail!(
+            "the db schema version {} is newer than the supported {}. please upgrade",
+            from,
+            latest
+        );
+    }
+
+    pool.execute(
+        "CREATE TABLE IF NOT EXISTS `schema_version` (
+            `version` INTEGER PRIMARY KEY NOT NULL,
+            `applied_time` datetime NOT NULL
+        );",
+    )
+    .await?;
+    if from != 0
+        && sqlx::query_scalar::<_, u32>("select count(*) from schema_version")
+            .fetch_one(pool)
+            .await?
+            == 0
+    {
+        debug!("recording the inferred version {} of the legacy db", from);
+        insert_version(pool, from).await?;
+    }
+
+    for (i, (desc, sql)) in MIGRATIONS.iter().enumerate().skip(from as usize) {
+        let ver = i as u32 + 1;
+        info!("migrating db schema to version {}: {}", ver, desc);
+        let mut tx = pool.begin().await?;
+        let mut rows = (&mut tx).execute_many(*sql);
+        while let Some(row) = rows.try_next().await? {
+            trace!("get row: {:?}", row);
+        }
+        drop(rows);
+        insert_version(&mut tx, ver).await?;
+        tx.commit().await?;
+    }
+    Ok(from)
+}
+
+async fn insert_version<'e, E>(executor: E, ver: u32) -> Result<()>
+where
+    E: Executor<'e, Database = Sqlite>,
+{
+    sqlx::query("insert into schema_version(version, applied_time) values(?, ?)")
+        .bind(ver)
+        .bind(Local::now())
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+async fn table_exists(pool: &SqlitePool, table: &str) -> Result<bool> {
+    sqlx::query_scalar::<_, u32>(
+        "select count(*) from sqlite_master where type = 'table' and name = ?",
+    )
+    .bind(table)
+    .fetch_one(pool)
+    .await
+    .map(|n| n != 0)
+    .map_err(Into::into)
+}
+
+async fn column_exists(pool: &SqlitePool, table: &str, column: &str) -> Result<bool> {
+    sqlx::query_scalar::<_, u32>("select count(*) from pragma_table_info(?) where name = ?")
+        .bind(table)
+        .bind(column)
+        .fetch_one(pool)
+        .await
+        .map(|n| n != 0)
+        .map_err(Into::into)
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    use crate::updated_info::Mapper;
+
+    use super::*;
+
+    async fn memory_pool() -> Result<SqlitePool> {
+        SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .map_err(Into::into)
+    }
+
+    /// 执行前n个migration的sql但不记录版本，模拟旧版本schema.sql创建的db
+    async fn legacy_pool(n: usize) -> Result<SqlitePool> {
+        let pool = memory_pool().await?;
+        for (_, sql) in &MIGRATIONS[..n] {
+            pool.execute(*sql).await?;
+        }
+        Ok(pool)
+    }
+
+    async fn assert_latest(pool: SqlitePool) -> Result<()> {
+        assert_eq!(current_version(&pool).await?, latest_version());
+        let mapper = Mapper { pool };
+        for (table, cols) in read_schema_tables() {
+            assert_eq!(mapper.select_columns(&table).await?, cols, "{}", table);
+        }
+        Ok(())
+    }
+
+    /// 从schema.sql中读取表与列名
+    fn read_schema_tables() -> Vec<(String, Vec<String>)> {
+        let mut tables: Vec<(String, Vec<String>)> = vec![];
+        for line in include_str!("../schema.sql").lines().map(str::trim) {
+            if let Some(rest) = line.strip_prefix("CREATE TABLE IF NOT EXISTS `") {
+                let name = rest.split('`').next().unwrap().to_owned();
+                tables.push((name, vec![]));
+            } else if let (Some(rest), Some((_, cols))) =
+                (line.strip_prefix('`'), tables.last_mut())
+            {
+                cols.push(rest.split('`').next().unwrap().to_owned());
+            }
+        }
+        tables
+    }
+
+    #[tokio::test]
+    async fn test_migrate_new_db() -> Result<()> {
+        let pool = memory_pool().await?;
+        assert_eq!(current_version(&pool).await?, 0);
+        assert_eq!(migrate(&pool).await?, 0);
+        assert_latest(pool).await
+    }
+
+    #[tokio::test]
+    async fn test_migrate_legacy_dbs() -> Result<()> {
+        for n in 1..=LEGACY_VERSION as usize {
+            let pool = legacy_pool(n).await?;
+            sqlx::query(
+                r#"insert into updated_info(name, version, source, url, create_time, updated_time)
+                values('a', 'v1', '{"github":{"owner":"o","repo":"r"}}', 'https://a.b/a', datetime(), datetime())"#,
+            )
+            .execute(&pool)
+            .await?;
+
+            assert_eq!(current_version(&pool).await?, n as u32);
+            assert_eq!(migrate(&pool).await?, n as u32);
+
+            let mapper = Mapper { pool: pool.clone() };
+            let infos = mapper.select_list_by_name("a").await?;
+            assert_eq!(infos.len(), 1, "lost data after migrating from {}", n);
+            assert_eq!(infos[0].rollback_from(), &None);
+            assert_eq!(infos[0].source(), "github:o/r");
+            assert_latest(pool).await?;
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_migrate_idempotent() -> Result<()> {
+        let pool = memory_pool().await?;
+        migrate(&pool).await?;
+        assert_eq!(migrate(&pool).await?, latest_version());
+        let count = sqlx::query_scalar::<_, u32>("select count(*) from schema_version")
+            .fetch_one(&pool)
+            .await?;
+        assert_eq!(count, latest_version());
+        assert_latest(pool).await
+    }
+
+    #[tokio::test]
+    async fn test_migrate_newer_db() -> Result<()> {
+        let pool = memory_pool().await?;
+        migrate(&pool).await?;
+        insert_version(&pool, latest_version() + 1).await?;
+        assert!(migrate(&pool).await.is_err());
+        Ok(())
+    }
+}
@@ -1,41 +1,79 @@
-use std::fs::File;
+use std::collections::{BTreeMap, HashSet};
+use std::env::consts::EXE_SUFFIX;
+use std::fmt;
+use std::io::SeekFrom;
+use std::ops::Range;
 use std::path::Path;
 use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use anyhow::Error;
-use anyhow::{anyhow, bail, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use async_trait::async_trait;
+use chrono::{DateTime, Local, Utc};
 use derive_builder::Builder;
-use futures_util::StreamExt;
+use futures_util::{Future, StreamExt};
 use getset::Getters;
+use indexmap::IndexMap;
 use log::log_enabled;
 use log::{debug, error, info, trace, warn};
 use md5::{Digest, Md5};
-use reqwest::Client;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use reqwest::{
+    header::{CONTENT_RANGE, LOCATION, RANGE},
+    redirect::Policy,
+    Client, RequestBuilder, Response, StatusCode,
+};
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 use tokio::fs::read_to_string;
 use tokio::fs::remove_file;
-use tokio::{fs as afs, io::AsyncWriteExt};
+use tokio::process::Command;
+use tokio::{
+    fs as afs,
+    io::{AsyncSeekExt, AsyncWriteExt},
+};
+use tokio_util::sync::CancellationToken;
 use url::Url;
 use which::which;
 
+use crate::checksum;
+use crate::config::lock::LockedBinary;
 use crate::config::Binary;
-use crate::config::Source;
-use crate::source::github::GithubBinaryBuilder;
-use crate::source::Visible;
+use crate::config::ExeType;
+use crate::config::{Hook, HookEvent};
+use crate::source::github::ApiToken;
+use crate::source::script::SCRIPT_SOURCE;
+use crate::source::{ApiCache, NoMatchedAsset, SourceContext, SourceRegistry, Visible};
+use crate::CRATE_NAME;
 
+use crate::extract::EXTRACTED_MARK_NAME;
+use crate::updated_info::{
+    ExternalBinary, ExternalBinaryBuilder, InstallStatBuilder, InstalledFileBuilder, LinkConflict,
+    LinkConflictBuilder,
+};
+use crate::util::cmp_version;
+use crate::util::expand_env;
+use crate::util::file_digests;
+use crate::util::is_on_path;
+use crate::util::md5_file;
 use crate::util::platform_values;
-use crate::util::run_cmd;
 use crate::util::Templater;
+use crate::util::{find_version, is_same_version, parse_version};
+use crate::util::{run_cmd_with, CmdOptions, Escalation};
 use crate::{
-    extract::decompress,
+    extract::{decompress, is_extracted, place_script},
     updated_info::{Mapper, UpdatedInfoBuilder},
-    util::find_one_bin_with_glob,
+    util::{find_files_with_globs, find_one_bin_with_glob},
+    zsync::ControlFile,
 };
 
 #[derive(Debug, Clone, Builder, Getters)]
-#[builder(build_fn(name = "pre_build"))]
+#[builder(build_fn(name = "pre_build"), derive(Debug))]
 #[getset(get = "pub")]
 pub struct BinaryPackage {
     #[builder(setter(custom))]
@@ -44,9 +82,71 @@ pub struct BinaryPackage {
     client: Client,
     data_dir: PathBuf,
     cache_dir: PathBuf,
-    link_path: PathBuf,
+    /// the dir to link executables
+    exe_dir: PathBuf,
+    /// the paths of the links in exe dir named by [Binary::link_names]
+    #[builder(setter(skip))]
+    link_paths: Vec<PathBuf>,
+    /// the dir to install completion files of all packages
+    #[builder(default, setter(strip_option))]
+    completion_dir: Option<PathBuf>,
     #[builder(default)]
     templater: Templater,
+    /// only print what would be done without touching disk and db
+    #[builder(default)]
+    dry_run: bool,
+    /// prompt to choose one of multiple candidate assets
+    #[builder(default)]
+    interactive: bool,
+    /// never escalate the privileged operations by sudo or doas
+    #[builder(default)]
+    no_sudo: bool,
+    /// the arch aliases in config
+    #[builder(default)]
+    arch_aliases: IndexMap<String, Vec<String>>,
+    /// the url prefixes of downloads to the mirror prefixes
+    #[builder(default)]
+    mirrors: IndexMap<String, String>,
+    /// the github token added only to the requests of the github api. download the github
+    /// assets by the api if any
+    #[builder(default, setter(strip_option))]
+    token: Option<ApiToken>,
+    /// the factories to create the source of bin
+    #[builder(default)]
+    sources: SourceRegistry,
+    /// the api responses shared by all packages in a run
+    #[builder(default)]
+    api_cache: ApiCache,
+    /// abort the running install or update and roll back if cancelled
+    #[builder(default)]
+    cancel: CancellationToken,
+    /// reinstall the installed bin and replace the existing links into the data dir
+    #[builder(default)]
+    force: bool,
+    /// back up the existing unmanaged files at the link paths and take them over
+    #[builder(default)]
+    adopt: bool,
+    /// reinstall the version whose asset digest differs from the one pinned on its first install
+    #[builder(default)]
+    accept_new_digest: bool,
+    /// install the managed binary even if the os provided one is reused by the `system` option
+    #[builder(default)]
+    prefer_managed: bool,
+    /// the callbacks of the install progress
+    #[builder(default = "Arc::new(NoopObserver)")]
+    observer: Arc<dyn InstallObserver>,
+    /// the max number of history infos kept in db. use [DEFAULT_HISTORY_LIMIT] if none
+    /// and keep all if 0
+    #[builder(default)]
+    history_limit: Option<usize>,
+    /// the bytes downloaded by the running operation for [Self::with_stat]
+    #[builder(setter(skip))]
+    #[getset(skip)]
+    downloaded: Arc<AtomicU64>,
+    /// the version and url of the running operation for [Self::template_values]
+    #[builder(setter(skip))]
+    #[getset(skip)]
+    release: Arc<Mutex<Option<Release>>>,
 }
 
 impl BinaryPackageBuilder {
@@ -83,36 +183,50 @@ impl BinaryPackageBuilder {
             .bin()
             .clone();
 
-        self.link_path = self.link_path.take().map(|p| p.join(bin.name()));
-
-        let visible: Box<dyn Visible> = match bin.source() {
-            Source::Github { owner: _, repo: _ } => Box::new(
-                GithubBinaryBuilder::default()
-                    .client(
-                        self.client
-                            .as_ref()
-                            .ok_or_else(|| anyhow!("no field client"))?
-                            .clone(),
-                    )
-                    .binary(bin)
-                    .build()?,
-            ),
+        let asset_pattern = match &self.mapper {
+            Some(mapper) => mapper.select_asset_pattern(bin.name()).await?,
+            None => None,
         };
+        let visible = self
+            .sources
+            .clone()
+            .unwrap_or_default()
+            .create(SourceContext {
+                bin,
+                client: self
+                    .client
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("no field client"))?
+                    .clone(),
+                interactive: self.interactive.unwrap_or_default(),
+                asset_pattern,
+                arch_aliases: self.arch_aliases.clone().unwrap_or_default(),
+                api_cache: self.api_cache.clone().unwrap_or_default(),
+                token: self.token.clone().flatten(),
+            })?;
         self.bin.replace(Arc::new(visible));
 
         let mut pkg = self.pre_build()?;
 
         pkg.data_dir = pkg.data_dir.join(&format!("{}/", pkg.bin.bin().name()));
         pkg.cache_dir = pkg.cache_dir.join(&format!("{}/", pkg.bin.bin().name()));
+        let exts = match pkg.bin.bin().exe_type() {
+            Some(ExeType::Shim { .. }) if cfg!(windows) => WINDOWS_SHIM_EXTS,
+            _ => &[EXE_SUFFIX],
+        };
+        pkg.link_paths = pkg
+            .bin
+            .bin()
+            .link_names()
+            .into_iter()
+            .flat_map(|name| exts.iter().map(move |ext| format!("{}{}", name, ext)))
+            .map(|name| pkg.exe_dir.join(name))
+            .collect();
 
-        if afs::metadata(&pkg.link_path).await.is_err() {
-            afs::create_dir_all(
-                &pkg.link_path
-                    .parent()
-                    .ok_or_else(|| anyhow!("no parent for {}", pkg.link_path.display()))?,
-            )
-            .await?;
+        if pkg.dry_run {
+            return Ok(pkg);
         }
+        afs::create_dir_all(&pkg.exe_dir).await?;
         afs::create_dir_all(&pkg.data_dir).await?;
         afs::create_dir_all(&pkg.cache_dir).await?;
         Ok(pkg)
@@ -120,81 +234,182 @@ impl BinaryPackageBuilder {
 }
 
 impl BinaryPackage {
-    pub async fn has_installed(&self) -> bool {
-        let name = self.bin.bin().name().to_owned();
-        let whiched = {
-            let name = name.clone();
-            tokio::task::spawn_blocking(move || {
-                which(&name).map_or(false, |p| {
-                    trace!("found executable bin {} in {}", name, p.display());
-                    true
-                })
-            })
+    /// 检查db中是否有安装记录且任一[Self::link_paths]存在，悬空的链接不算已安装
+    ///
+    /// 不使用PATH中同名的可执行文件判断，避免系统安装的同名bin导致跳过安装
+    pub async fn has_installed(&self) -> Result<bool> {
+        if !self.has_record().await? {
+            return Ok(false);
+        }
+        for path in &self.link_paths {
+            if afs::metadata(path).await.is_ok() {
+                trace!("found the managed link {}", path.display());
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// 检查db中是否有bin的安装记录
+    async fn has_record(&self) -> Result<bool> {
+        let name = self.bin.bin().name();
+        let infos = self.mapper.select_list_by_name(name).await?;
+        trace!("found infos by name {}: {:?}", name, infos);
+        Ok(!infos.is_empty())
+    }
+
+    /// 检查bin是否在配置中`enabled = false`或被`disable`命令禁用
+    pub async fn is_disabled(&self) -> bool {
+        let name = self.bin.bin().name();
+        if !self.bin.bin().enabled() {
+            debug!("{} is disabled in config", name);
+            return true;
+        }
+        match self.mapper.is_disabled(name).await {
+            Ok(disabled) => disabled,
+            Err(e) => {
+                warn!("failed to check disabled {}: {}", name, e);
+                false
+            }
+        }
+    }
+
+    pub async fn is_updateable(&self) -> bool {
+        self.newer_version()
             .await
             .unwrap_or_else(|e| {
-                error!("failed spawn blocking `which` task: {}", e);
-                false
+                warn!(
+                    "failed to check the newer version of {}: {}",
+                    self.bin.bin().name(),
+                    e
+                );
+                None
             })
-        };
+            .is_some()
+    }
 
-        whiched
-            && self
-                .mapper
-                .select_list_by_name(&name)
-                .await
-                .map_or(false, |v| {
-                    trace!("found infos by name {}: {:?}", name, v);
-                    !v.is_empty()
-                })
+    /// 获取可更新时的当前版本与latest版本，不可更新时为none
+    ///
+    /// 配置了version，未安装，被禁用或被pin的bin不可更新
+    pub async fn newer_version(&self) -> Result<Option<(String, String)>> {
+        if self.bin.bin().version().is_some()
+            || !self.has_installed().await?
+            || self.is_disabled().await
+        {
+            return Ok(None);
+        }
+
+        let name = self.bin.bin().name();
+        if let Some(pinned) = self.mapper.select_pinned_by_name(name).await? {
+            debug!("skipped update for pinned {} {}", name, pinned.version());
+            return Ok(None);
+        }
+
+        let info = self
+            .mapper
+            .select_current_by_name(name)
+            .await?
+            .ok_or_else(|| anyhow!("not found current info of {}", name))?;
+        let latest = self.latest_ver().await?;
+        let cur = info.version();
+        trace!(
+            "checking current version: {} vs latest version: {}",
+            cur,
+            latest
+        );
+        Ok(cmp_version(&latest, cur)
+            .is_gt()
+            .then(|| (cur.to_owned(), latest)))
     }
 
-    pub async fn is_updateable(&self) -> bool {
-        if self.bin.bin().version().is_some() || !self.has_installed().await {
-            return false;
+    /// 发布时间不足配置的`min-release-age`时为true，未知发布时间时不作为pending
+    pub fn is_pending(&self, released: Option<&DateTime<Utc>>) -> bool {
+        match (self.bin.bin().min_release_age(), released) {
+            (Some(age), Some(released)) => {
+                chrono::Duration::from_std(*age).is_ok_and(|age| Utc::now() - *released < age)
+            }
+            _ => false,
         }
+    }
 
+    /// 获取要安装的版本
+    ///
+    /// 优先使用配置的version，其次是pin的version，否则使用未被skip的latest version
+    pub async fn target_ver(&self) -> Result<String> {
+        if let Some(ver) = self.bin.bin().version() {
+            return Ok(ver.clone());
+        }
         let name = self.bin.bin().name();
-        match self
+        if let Some(pinned) = self.mapper.select_pinned_by_name(name).await? {
+            debug!("use pinned version {} for {}", pinned.version(), name);
+            return Ok(pinned.version().to_owned());
+        }
+        self.latest_ver().await
+    }
+
+    /// 获取不在`binaries skip`记录的版本中的最新版本
+    pub async fn latest_ver(&self) -> Result<String> {
+        let skipped = self
             .mapper
-            .select_list_by_name(name)
-            .await
-            .and_then(|mut infos| {
-                infos.sort_by(|a, b| b.create_time().cmp(a.create_time()));
-                trace!("found {} infos by name {}: {:?}", infos.len(), name, infos);
-                let first = infos
-                    .first()
-                    .cloned()
-                    .ok_or_else(|| anyhow!("not found first in infos: {:?}", infos));
-                debug!("found latest info by name {}: {:?}", name, first);
-                first
-            }) {
-            Ok(info) => self
-                .bin
-                .latest_ver()
-                .await
-                .map(|latest| {
-                    let cur = info.version();
-                    trace!(
-                        "checking current version: {} vs latest version: {}",
-                        cur,
-                        latest
-                    );
-                    &latest > cur
-                })
-                .unwrap_or(false),
-            Err(e) => {
-                warn!("failed to get info by name {}: {}", name, e);
-                false
+            .select_skipped_by_name(self.bin.bin().name())
+            .await?;
+        if skipped.is_empty() {
+            self.bin.latest_ver().await
+        } else {
+            trace!("resolving the latest version skipping {:?}", skipped);
+            self.bin.latest_ver_skipping(&skipped).await
+        }
+    }
+
+    /// 安装[Self::target_ver]并返回安装的版本
+    ///
+    /// 配置了`system`时如果PATH中有os提供的相同版本则记录并使用它而不下载，
+    /// [Self::with_prefer_managed]时总是安装并移除该记录
+    pub async fn install(&self) -> Result<String> {
+        self.check_cancelled()?;
+        let ver = self.target_ver().await?;
+        if *self.bin.bin().system() && !self.prefer_managed {
+            if let Some(external) = self.find_system(&ver).await {
+                info!(
+                    "reusing {} version {} provided by {} for {}",
+                    external.path(),
+                    external.version(),
+                    external.provider(),
+                    external.name()
+                );
+                if !self.dry_run {
+                    self.mapper.insert_external(&external).await?;
+                }
+                return Ok(ver);
             }
         }
+        if self.prefer_managed && !self.dry_run {
+            self.mapper
+                .delete_external_by_name(self.bin.bin().name())
+                .await?;
+        }
+        let url = self.resolve_url(&ver).await?;
+        self.install_with(&ver, &url, None).await?;
+        Ok(ver)
     }
 
-    pub async fn install(&self) -> Result<()> {
-        let ver = match self.bin.bin().version() {
-            Some(ver) => ver.clone(),
-            None => self.bin.latest_ver().await?,
-        };
-        let url = self.bin.get_url(&ver).await?;
+    /// 安装lock文件中锁定的版本与url
+    ///
+    /// # Error
+    ///
+    /// * 如果下载文件的digest与锁定的digest不一致
+    pub async fn install_locked(&self, locked: &LockedBinary) -> Result<()> {
+        let url = locked.url().parse::<Url>()?;
+        self.install_with(locked.version(), &url, Some(locked.digest()))
+            .await
+    }
+
+    /// 安装已知url的版本ver，不会再解析版本与asset
+    pub async fn install_url(&self, ver: &str, url: &Url) -> Result<()> {
+        self.install_with(ver, url, None).await
+    }
+
+    async fn install_with(&self, ver: &str, url: &Url, digest: Option<&str>) -> Result<()> {
         info!(
             "installing {} version {} for {}",
             self.bin.bin().name(),
@@ -202,156 +417,1951 @@ impl BinaryPackage {
             url
         );
 
-        // download
-        let download_path = self.download(&url).await?;
-        let to = &self.data_dir;
-        if !afs::metadata(to).await.map_or(false, |d| d.is_dir()) {
-            bail!("{} is not a dir", to.display());
+        // only replace the links of a recorded install. the foreign files are rejected in link
+        let relink = self.force && self.has_record().await?;
+        self.with_stat("install", ver, url, async {
+            let backup = self.deploy(url, ver, relink, digest).await?;
+
+            // inserto into db
+            let res = match self.run_hooks(HookEvent::Check).await {
+                Ok(_) => self.record(ver, url).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = res {
+                self.revert(backup).await;
+                return Err(e);
+            }
+            self.commit(backup).await;
+            self.save_asset_pattern().await;
+
+            self.record_provenance(ver, url).await;
+            self.run_hooks(HookEvent::Install).await?;
+
+            // after the hook that may change the installed files
+            self.record_files_or_warn(ver).await;
+            Ok(())
+        })
+        .await
+    }
+
+    /// 更新已安装的bin到[Self::target_ver]，重新链接并记录到db
+    pub async fn update(&self) -> Result<String> {
+        self.check_cancelled()?;
+        let ver = self.target_ver().await?;
+        let url = self.resolve_url(&ver).await?;
+        info!(
+            "updating {} to version {} for {}",
+            self.bin.bin().name(),
+            ver,
+            url
+        );
+
+        self.with_stat("update", &ver, &url, async {
+            let backup = self.deploy(&url, &ver, true, None).await?;
+            let res = match self.run_hooks(HookEvent::Check).await {
+                Ok(_) => self.record(&ver, &url).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = res {
+                self.revert(backup).await;
+                return Err(e);
+            }
+            self.commit(backup).await;
+            self.save_asset_pattern().await;
+
+            self.record_provenance(&ver, &url).await;
+            self.run_hooks(HookEvent::Update).await?;
+            // after the hook that may change the installed files
+            self.record_files_or_warn(&ver).await;
+            Ok(())
+        })
+        .await?;
+        Ok(ver)
+    }
+
+    /// 获取版本ver的url，没有匹配当前平台的asset且配置了fallback时为`fallback:<name>`
+    async fn resolve_url(&self, ver: &str) -> Result<Url> {
+        let bin = self.bin.bin();
+        match self.bin.get_url(ver).await {
+            Err(e) if e.chain().any(|e| e.is::<NoMatchedAsset>()) => match bin.fallback() {
+                Some(cmd) => {
+                    warn!(
+                        "building {} {} by the fallback `{}` for {}",
+                        bin.name(),
+                        ver,
+                        cmd,
+                        e
+                    );
+                    format!("{}:{}", FALLBACK_SCHEME, bin.name())
+                        .parse()
+                        .map_err(Into::into)
+                }
+                None => Err(e),
+            },
+            res => res,
+        }
+    }
+
+    /// 执行安装操作fut，并将其结果，耗时与下载的字节数记录到db中
+    async fn with_stat<T>(
+        &self,
+        action: &str,
+        ver: &str,
+        url: &Url,
+        fut: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        self.downloaded.store(0, Ordering::Relaxed);
+        self.set_release(ver, self.release_tag(ver).await, url);
+        let name = self.bin.bin().name();
+        self.observer.on_resolve(name, ver, url);
+        let start = Instant::now();
+        let res = fut.await;
+        match &res {
+            Ok(_) => self.observer.on_done(name, action, ver),
+            Err(e) => self.observer.on_error(name, action, e),
+        }
+        if !self.dry_run {
+            let err = res.as_ref().err().map(ToString::to_string);
+            if let Err(e) = self.record_stat(action, ver, url, start, err).await {
+                warn!(
+                    "failed to record the {} stat of {}: {}",
+                    action,
+                    self.bin.bin().name(),
+                    e
+                );
+            }
         }
+        res
+    }
 
-        // try use custom to extract
-        self.extract(&download_path, to).await?;
+    /// 最近一次安装或更新下载的字节数，使用缓存时为0
+    pub fn downloaded_bytes(&self) -> u64 {
+        self.downloaded.load(Ordering::Relaxed)
+    }
 
-        // link to exe dir
-        self.link(&to).await?;
+    /// 使用token取消之后的安装与更新
+    pub fn with_cancel(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
 
-        // inserto into db
-        let info = UpdatedInfoBuilder::default()
+    /// 强制安装时重新安装已安装的bin并替换其链接
+    pub fn with_force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// 接管链接路径上已存在的非本程序管理的文件，备份在其同级的隐藏文件中
+    pub fn with_adopt(mut self, adopt: bool) -> Self {
+        self.adopt = adopt;
+        self
+    }
+
+    /// 接受与首次安装时记录的digest不同的asset，并替换记录的digest
+    pub fn with_accept_new_digest(mut self, accept: bool) -> Self {
+        self.accept_new_digest = accept;
+        self
+    }
+
+    /// 总是安装本程序管理的bin，不使用`system`找到的os提供的可执行文件
+    pub fn with_prefer_managed(mut self, prefer: bool) -> Self {
+        self.prefer_managed = prefer;
+        self
+    }
+
+    /// 获取代替安装使用的os提供的可执行文件，如果其路径已不存在则删除记录
+    pub async fn external(&self) -> Result<Option<ExternalBinary>> {
+        let name = self.bin.bin().name();
+        let external = match self.mapper.select_external_by_name(name).await? {
+            Some(e) => e,
+            None => return Ok(None),
+        };
+        if afs::metadata(external.path()).await.is_err() {
+            info!(
+                "deleting the external {} of {} that no longer exists",
+                external.path(),
+                name
+            );
+            if !self.dry_run {
+                self.mapper.delete_external_by_name(name).await?;
+            }
+            return Ok(None);
+        }
+        Ok(Some(external))
+    }
+
+    /// 查找PATH中不在exe dir内的同名可执行文件，使用`version_command`与`version_regex`获取其版本
+    ///
+    /// 没有找到或无法解析版本时返回none
+    pub async fn foreign_version(&self) -> Option<(PathBuf, String)> {
+        let bin = self.bin.bin();
+        let path = which(bin.name()).ok()?;
+        if path.starts_with(&self.exe_dir) {
+            trace!("ignore the managed {} on PATH", path.display());
+            return None;
+        }
+        let args = match bin.version_command() {
+            Some(cmd) => shell_words::split(cmd).ok()?,
+            None => vec!["--version".to_owned()],
+        };
+        let out = match Command::new(&path).args(&args).output().await {
+            Ok(out) => out,
+            Err(e) => {
+                debug!("failed to run {} {:?}: {}", path.display(), args, e);
+                return None;
+            }
+        };
+        let parse = |out: &[u8]| {
+            let out = String::from_utf8_lossy(out);
+            match bin.version_regex() {
+                Some(re) => find_version(&out, &regex::Regex::new(re).ok()?),
+                None => parse_version(&out),
+            }
+        };
+        let ver = parse(&out.stdout).or_else(|| parse(&out.stderr))?;
+        Some((path, ver))
+    }
+
+    /// 查找PATH中不在exe dir内且版本与ver相同的可执行文件
+    async fn find_system(&self, ver: &str) -> Option<ExternalBinary> {
+        let name = self.bin.bin().name();
+        let (path, found) = self.foreign_version().await?;
+        if !is_same_version(&found, ver) {
+            debug!(
+                "ignore {} version {} that is not the version {}",
+                path.display(),
+                found,
+                ver
+            );
+            return None;
+        }
+        let provider = system_provider(&path).await;
+        ExternalBinaryBuilder::default()
+            .name(name)
+            .path(path.to_string_lossy())
+            .version(found)
+            .provider(provider)
+            .build()
+            .ok()
+    }
+
+    /// 如果已取消则返回[Cancelled]错误
+    fn check_cancelled(&self) -> Result<()> {
+        if self.cancel.is_cancelled() {
+            return Err(Cancelled.into());
+        }
+        Ok(())
+    }
+
+    /// 设置正在安装的版本ver与url用于渲染之后的模板
+    fn set_release(&self, ver: &str, tag: Option<String>, url: &Url) {
+        *self.release.lock() = Some(Release {
+            version: ver.to_owned(),
+            tag,
+            url: url.clone(),
+        });
+    }
+
+    /// 从source获取版本ver的tag，失败时为none
+    async fn release_tag(&self, ver: &str) -> Option<String> {
+        self.bin.tag(ver).await.unwrap_or_else(|e| {
+            warn!(
+                "failed to get the tag of {} {}: {}",
+                self.bin.bin().name(),
+                ver,
+                e
+            );
+            None
+        })
+    }
+
+    /// 在[platform_values]上添加正在安装的`version`,`tag`,`asset`与`url`，未设置时不存在，source没有tag时没有`tag`
+    fn template_values(&self, val: serde_json::Value) -> Result<serde_json::Value> {
+        let mut val = platform_values(val)?;
+        if let (Some(rel), Some(obj)) = (&*self.release.lock(), val.as_object_mut()) {
+            let asset = rel
+                .url
+                .path_segments()
+                .and_then(|mut seg| seg.next_back())
+                .unwrap_or_default();
+            obj.insert("version".to_owned(), json!(rel.version));
+            if let Some(tag) = &rel.tag {
+                obj.insert("tag".to_owned(), json!(tag));
+            }
+            obj.insert("asset".to_owned(), json!(asset));
+            obj.insert("url".to_owned(), json!(rel.url.as_str()));
+        }
+        Ok(val)
+    }
+
+    async fn record_stat(
+        &self,
+        action: &str,
+        ver: &str,
+        url: &Url,
+        start: Instant,
+        err: Option<String>,
+    ) -> Result<()> {
+        let stat = InstallStatBuilder::default()
             .name(self.bin.bin().name())
-            .source(serde_json::to_string(self.bin.bin().source())?)
-            .url(url)
+            .version(ver)
+            .action(action)
+            .asset(
+                url.path_segments()
+                    .and_then(|mut seg| seg.next_back())
+                    .unwrap_or_else(|| url.as_str()),
+            )
+            .duration_ms(start.elapsed().as_millis() as i64)
+            .downloaded_bytes(self.downloaded.load(Ordering::Relaxed) as i64)
+            .success(err.is_none())
+            .error(err)
+            .build()?;
+        trace!("recording install stat: {:?}", stat);
+        self.mapper.insert_stat(&stat).await?;
+        Ok(())
+    }
+
+    /// 保存选择的asset pattern，之后的安装与更新使用相同的asset且不再提示
+    async fn save_asset_pattern(&self) {
+        let name = self.bin.bin().name();
+        if let Some(pattern) = self.bin.chosen_pattern() {
+            if self.dry_run {
+                println!("[dry-run] would save asset pattern {} of {}", pattern, name);
+                return;
+            }
+            debug!("saving the asset pattern {} of {}", pattern, name);
+            if let Err(e) = self.mapper.insert_asset_pattern(name, &pattern).await {
+                warn!("failed to save the asset pattern of {}: {}", name, e);
+            }
+        }
+    }
+
+    /// 记录安装的版本到db中
+    ///
+    /// 如果之前已安装过相同的版本则标记为当前使用的info
+    async fn record(&self, ver: &str, url: &Url) -> Result<()> {
+        let name = self.bin.bin().name();
+        if self.dry_run {
+            println!("[dry-run] would record {} {} in db", name, ver);
+            return Ok(());
+        }
+        if let Some(info) = self
+            .mapper
+            .select_list_by_name(name)
+            .await?
+            .into_iter()
+            .find(|info| info.version() == ver)
+        {
+            debug!("touching the existing info in db: {:?}", info);
+            self.mapper.touch_by_id(*info.id()).await?;
+            return Ok(());
+        }
+
+        let info = UpdatedInfoBuilder::default()
+            .name(name)
+            .typed_source(self.bin.bin().source())
+            .url(url.as_str())
             .version(ver)
             .build()?;
         debug!("inserting info to db: {:?}", info);
         self.mapper.insert(&info).await?;
 
-        if let Some(hook) = self
-            .bin
-            .bin()
-            .hook()
-            .as_ref()
-            .and_then(|h| h.install().as_deref())
-        {
-            let data = platform_values(json!({
-                "data_dir": self.data_dir.display().to_string(),
-                "name": self.bin.bin().name(),
-            }))?;
-            let cmd = self.templater.render(hook, &data)?;
-            run_cmd(&cmd, &self.data_dir).await?;
+        if let Err(e) = self.trim_history().await {
+            warn!("failed to trim the history of {}: {}", name, e);
+        }
+        Ok(())
+    }
+
+    /// 删除超过[Self::history_limit]的旧info，并移除它们的版本目录与安装的文件记录
+    async fn trim_history(&self) -> Result<()> {
+        let keep = self.history_limit.unwrap_or(DEFAULT_HISTORY_LIMIT);
+        if keep == 0 {
+            return Ok(());
         }
+        let name = self.bin.bin().name();
+        for info in self.mapper.delete_old_by_name(name, keep).await? {
+            let ver = info.version();
+            debug!("trimmed the old history of {} {}", name, ver);
+            self.mapper.delete_files(name, ver).await?;
 
+            let dir = self.version_dir(ver);
+            if afs::metadata(&dir).await.is_ok() {
+                trace!("removing the old version dir {}", dir.display());
+                afs::remove_dir_all(&dir).await?;
+            }
+        }
         Ok(())
     }
 
-    pub async fn uninstall(&self) -> Result<()> {
-        trace!("removing link file {}", self.link_path.display());
-        if let Err(e) = afs::remove_file(&self.link_path).await {
-            info!(
-                "failed to remove a link file {}: {}",
-                self.link_path.display(),
+    /// 记录版本ver目录中所有文件的路径与digest到db中，替换之前的记录
+    async fn record_files(&self, ver: &str) -> Result<()> {
+        let name = self.bin.bin().name();
+        if self.dry_run {
+            println!(
+                "[dry-run] would record installed files of {} {} in db",
+                name, ver
+            );
+            return Ok(());
+        }
+        let files = self
+            .installed_digests(ver)
+            .await?
+            .into_iter()
+            .map(|(path, digest)| {
+                InstalledFileBuilder::default()
+                    .name(name)
+                    .version(ver)
+                    .path(path)
+                    .digest(digest)
+                    .build()
+                    .map_err(Into::into)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        debug!(
+            "recording {} installed files of {} {}",
+            files.len(),
+            name,
+            ver
+        );
+        self.mapper.replace_files(name, ver, &files).await
+    }
+
+    /// 同[Self::record_files]，但失败时仅记录日志，不影响已完成的安装
+    async fn record_files_or_warn(&self, ver: &str) {
+        if let Err(e) = self.record_files(ver).await {
+            warn!(
+                "failed to record installed files of {} {}: {}",
+                self.bin.bin().name(),
+                ver,
+                e
+            );
+        }
+    }
+
+    /// 写入版本ver的来源与下载文件的sha256到版本目录中的[PROVENANCE_NAME]，失败时仅记录日志
+    async fn record_provenance(&self, ver: &str, url: &Url) {
+        // no downloaded asset of the built bin
+        if self.dry_run || url.scheme() == FALLBACK_SCHEME {
+            return;
+        }
+        let res = async {
+            let path = self.cache_path(url)?;
+            let digest = tokio::task::spawn_blocking(move || checksum::digest_file(path, "sha256"))
+                .await??;
+            let bin = self.bin.bin();
+            let provenance = Provenance {
+                name: bin.name().to_owned(),
+                version: ver.to_owned(),
+                source: bin.source().to_string(),
+                repo: bin.source().repo_url(),
+                asset_url: url.to_string(),
+                digest: format!("sha256:{}", digest),
+                source_digest: self.bin.digest_of(url),
+                installed_time: Local::now(),
+                installer: format!("{} {}", CRATE_NAME, env!("CARGO_PKG_VERSION")),
+            };
+            let path = self.version_dir(ver).join(PROVENANCE_NAME);
+            trace!("writing provenance to {}: {:?}", path.display(), provenance);
+            afs::write(path, serde_json::to_vec_pretty(&provenance)?).await?;
+            Ok::<_, Error>(())
+        }
+        .await;
+        if let Err(e) = res {
+            warn!(
+                "failed to record the provenance of {} {}: {}",
+                self.bin.bin().name(),
+                ver,
                 e
             );
         }
+    }
+
+    /// 当前版本的来源，在记录来源之前安装的为none
+    pub async fn provenance(&self) -> Result<Option<Provenance>> {
+        let path = self.current_dir().join(PROVENANCE_NAME);
+        match afs::read(&path).await {
+            Ok(data) => serde_json::from_slice(&data)
+                .map(Some)
+                .with_context(|| format!("invalid provenance {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// 获取版本ver目录中所有文件的相对路径与md5 digest
+    async fn installed_digests(&self, ver: &str) -> Result<Vec<(String, String)>> {
+        let dir = self.version_dir(ver);
+        let digests = tokio::task::spawn_blocking(move || {
+            file_digests(dir, &[EXTRACTED_MARK_NAME, PROVENANCE_NAME])
+        })
+        .await??;
+        Ok(digests
+            .into_iter()
+            .map(|(path, digest)| (path.to_string_lossy().into_owned(), digest))
+            .collect())
+    }
+
+    /// 检查当前版本目录中的文件与安装时记录的是否一致
+    ///
+    /// # Error
+    ///
+    /// * 如果未安装或db中没有安装时记录的文件
+    pub async fn verify(&self) -> Result<Drift> {
+        let name = self.bin.bin().name();
+        let info = self
+            .mapper
+            .select_current_by_name(name)
+            .await?
+            .ok_or_else(|| anyhow!("{} is not installed", name))?;
+        let ver = info.version();
+        let recorded = self.mapper.select_files(name, ver).await?;
+        if recorded.is_empty() {
+            bail!(
+                "not found recorded files of {} {}. reinstall it to verify",
+                name,
+                ver
+            );
+        }
+        let mut actual = self
+            .installed_digests(ver)
+            .await?
+            .into_iter()
+            .collect::<BTreeMap<_, _>>();
+        trace!(
+            "found {} files in version dir of {} {}",
+            actual.len(),
+            name,
+            ver
+        );
+
+        let mut drift = Drift {
+            name: name.to_owned(),
+            version: ver.to_owned(),
+            ..Default::default()
+        };
+        for file in recorded {
+            match actual.remove(file.path()) {
+                Some(digest) if digest != *file.digest() => {
+                    drift.modified.push(file.path().clone())
+                }
+                Some(_) => {}
+                None => drift.missing.push(file.path().clone()),
+            }
+        }
+        drift.extra = actual.into_keys().collect();
+        Ok(drift)
+    }
+
+    /// 从cache中重新解压当前版本并替换版本目录，然后重新记录安装的文件
+    ///
+    /// 如果cache不存在则重新下载
+    pub async fn repair(&self) -> Result<String> {
+        let name = self.bin.bin().name();
+        let info = self
+            .mapper
+            .select_current_by_name(name)
+            .await?
+            .ok_or_else(|| anyhow!("{} is not installed", name))?;
+        let ver = info.version();
+        let url = info.url().parse::<Url>()?;
+        info!("repairing {} version {} from {}", name, ver, url);
+
+        self.with_stat("repair", ver, &url, async {
+            // force to re-extract
+            let mark = self.version_dir(ver).join(EXTRACTED_MARK_NAME);
+            if !self.dry_run && afs::metadata(&mark).await.is_ok() {
+                trace!("removing the extracted mark {}", mark.display());
+                afs::remove_file(&mark).await?;
+            }
+            let backup = self.deploy(&url, ver, true, None).await?;
+            self.commit(backup).await;
+            self.record_files(ver).await
+        })
+        .await?;
+        Ok(ver.to_owned())
+    }
+
+    /// 回滚到之前安装过的版本ver，如果ver为none则回滚到上一个使用的不同版本
+    ///
+    /// 使用db中记录的url重新安装，并在db中标记回滚
+    ///
+    /// # Error
+    ///
+    /// * 如果未安装或未找到可回滚的版本
+    pub async fn rollback(&self, ver: Option<&str>) -> Result<String> {
+        let name = self.bin.bin().name();
+        let mut infos = self.mapper.select_list_by_name(name).await?;
+        infos.sort_by(|a, b| {
+            b.updated_time()
+                .cmp(a.updated_time())
+                .then_with(|| b.id().cmp(a.id()))
+        });
+        trace!("found {} infos by name {}: {:?}", infos.len(), name, infos);
+
+        let cur = infos
+            .first()
+            .ok_or_else(|| anyhow!("{} is not installed", name))?;
+        let target = match ver {
+            Some(ver) => infos
+                .iter()
+                .find(|info| info.version() == ver)
+                .ok_or_else(|| anyhow!("not found installed version {} of {}", ver, name))?,
+            None => infos
+                .iter()
+                .find(|info| info.version() != cur.version())
+                .ok_or_else(|| anyhow!("not found a previous version of {}", name))?,
+        };
+        if target.version() == cur.version() {
+            bail!("{} is already at version {}", name, cur.version());
+        }
+
+        let url = target.url().parse::<Url>()?;
+        info!(
+            "rolling back {} from {} to {} for {}",
+            name,
+            cur.version(),
+            target.version(),
+            url
+        );
+        self.with_stat("rollback", target.version(), &url, async {
+            let backup = self.deploy(&url, target.version(), true, None).await?;
+
+            if self.dry_run {
+                println!(
+                    "[dry-run] would mark {} {} rolled back from {} in db",
+                    name,
+                    target.version(),
+                    cur.version()
+                );
+                return Ok(());
+            }
+            if let Err(e) = self
+                .mapper
+                .update_rollback(*target.id(), cur.version())
+                .await
+            {
+                self.revert(backup).await;
+                return Err(e);
+            }
+            self.commit(backup).await;
+            self.record_provenance(target.version(), &url).await;
+            self.record_files_or_warn(target.version()).await;
+            Ok(())
+        })
+        .await?;
+        Ok(target.version().to_owned())
+    }
+
+    /// 下载并解压版本ver到cache dir中直接执行，不会链接与记录到db
+    ///
+    /// 如果ver为none则使用[Self::target_ver]
+    pub async fn run(&self, ver: Option<&str>, args: &[String]) -> Result<ExitStatus> {
+        let ver = match ver {
+            Some(ver) => ver.to_owned(),
+            None => self.target_ver().await?,
+        };
+        let url = self.bin.get_url(&ver).await?;
+        self.set_release(&ver, self.release_tag(&ver).await, &url);
+        let to = self.cache_dir.join(&ver);
+        if self.dry_run {
+            println!(
+                "[dry-run] would download {} to {}",
+                url,
+                self.cache_dir.display()
+            );
+            println!("[dry-run] would extract to {}", to.display());
+            println!(
+                "[dry-run] would run {} with args: {:?}",
+                self.bin.bin().name(),
+                args
+            );
+            return Ok(ExitStatus::default());
+        }
+        let download_path = self.download(&url).await?;
+
+        afs::create_dir_all(&to).await?;
+        self.extract(&download_path, &to).await?;
+
+        let exe = self.find_exe(&to).await?;
+        info!("running {} with args: {:?}", exe.display(), args);
+        Command::new(&exe)
+            .args(args)
+            .status()
+            .await
+            .map_err(Into::into)
+    }
+
+    /// 切换到已安装的版本ver，仅重新指向current链接与exe链接，不会下载
+    ///
+    /// # Error
+    ///
+    /// * 如果db中没有ver的记录或版本目录不存在
+    pub async fn switch(&self, ver: &str) -> Result<()> {
+        let name = self.bin.bin().name();
+        let info = self
+            .mapper
+            .select_list_by_name(name)
+            .await?
+            .into_iter()
+            .find(|info| info.version() == ver)
+            .ok_or_else(|| anyhow!("not found installed version {} of {}", ver, name))?;
+        let ver_dir = self.version_dir(ver);
+        if !afs::metadata(&ver_dir).await.is_ok_and(|d| d.is_dir()) {
+            bail!(
+                "not found the version dir {}. use rollback to re-install it",
+                ver_dir.display()
+            );
+        }
+
+        if self.dry_run {
+            println!(
+                "[dry-run] would point {} to {}",
+                self.current_dir().display(),
+                ver_dir.display()
+            );
+            for path in &self.link_paths {
+                println!("[dry-run] would relink {}", path.display());
+            }
+            println!("[dry-run] would mark {} {} as current in db", name, ver);
+            return Ok(());
+        }
+
+        info!("switching {} to version {}", name, ver);
+        let mut backup = Backup::default();
+        if let Err(e) = self.activate(ver, true, &mut backup).await {
+            self.revert(backup).await;
+            return Err(e);
+        }
+        if let Err(e) = self.mapper.touch_by_id(*info.id()).await {
+            self.revert(backup).await;
+            return Err(e);
+        }
+        self.commit(backup).await;
+        Ok(())
+    }
+
+    /// data dir中指向当前使用的版本目录的链接
+    pub fn current_dir(&self) -> PathBuf {
+        self.data_dir.join(CURRENT_LINK_NAME)
+    }
+
+    /// 版本ver解压后所在的目录`data_dir/<version>`
+    pub fn version_dir(&self, ver: &str) -> PathBuf {
+        self.data_dir.join(ver)
+    }
+
+    /// 下载url并解压到版本ver的目录中，然后将current指向它并链接到exe dir
+    ///
+    /// 先解压到staging dir中再替换版本目录，旧的版本目录与链接被移动为备份。
+    /// 失败时恢复所有的修改，成功后调用者应在记录到db后[Self::commit]或[Self::revert]返回的备份
+    ///
+    /// 如果relink则替换已存在的链接。如果有digest则检查下载文件的digest是否一致
+    async fn deploy(
+        &self,
+        url: &Url,
+        ver: &str,
+        relink: bool,
+        digest: Option<&str>,
+    ) -> Result<Backup> {
+        if url.scheme() == FALLBACK_SCHEME {
+            return self.deploy_fallback(ver, relink).await;
+        }
+        if self.dry_run {
+            self.dry_deploy(url, ver, relink, digest)?;
+            return Ok(Backup::default());
+        }
+        // download
+        self.check_cancelled()?;
+        let download_path = self.download(url).await?;
+        self.verify_source_digest(url, &download_path).await?;
+        let sha256 = {
+            let path = download_path.clone();
+            tokio::task::spawn_blocking(move || checksum::digest_file(path, "sha256")).await??
+        };
+        self.verify_attestation(url, &download_path, &sha256)
+            .await?;
+        let pin = self.check_pinned_digest(ver, url, &sha256).await?;
+        let sha256 = format!("sha256:{}", sha256);
+        if let Some(expected) = digest {
+            let actual = if expected.starts_with("sha256:") {
+                sha256.clone()
+            } else {
+                warn!(
+                    "verifying the legacy md5 digest {} of {}. rewrite the lock file by `--write-lock`",
+                    expected, url
+                );
+                md5_of(download_path.clone()).await?
+            };
+            if actual != expected {
+                bail!(
+                    "inconsistent digest {} of {} with the locked {}",
+                    actual,
+                    download_path.display(),
+                    expected
+                );
+            }
+        }
+
+        let mut backup = Backup::default();
+        if let Err(e) = self
+            .swap_in(Some(&download_path), ver, relink, &mut backup)
+            .await
+        {
+            self.revert(backup).await;
+            return Err(e);
+        }
+        if pin {
+            let name = self.bin.bin().name();
+            if let Err(e) = self.mapper.insert_asset_digest(name, ver, &sha256).await {
+                warn!("failed to pin digest {} of {} {}: {}", sha256, name, ver, e);
+            }
+        }
+        Ok(backup)
+    }
+
+    /// 使用fallback命令在staging dir中构建版本ver并激活，不会下载
+    async fn deploy_fallback(&self, ver: &str, relink: bool) -> Result<Backup> {
+        if self.dry_run {
+            let cmd = self.render_fallback(ver, &self.version_dir(ver))?;
+            println!(
+                "[dry-run] would build {} {} by: {}",
+                self.bin.bin().name(),
+                ver,
+                cmd
+            );
+            return Ok(Backup::default());
+        }
+        self.check_cancelled()?;
+        let mut backup = Backup::default();
+        if let Err(e) = self.swap_in(None, ver, relink, &mut backup).await {
+            self.revert(backup).await;
+            return Err(e);
+        }
+        Ok(backup)
+    }
+
+    /// 渲染fallback命令，其中可用`name`,`version`与构建的目录`dir`
+    fn render_fallback(&self, ver: &str, dir: &Path) -> Result<String> {
+        let bin = self.bin.bin();
+        let cmd = bin
+            .fallback()
+            .as_ref()
+            .ok_or_else(|| anyhow!("not found fallback of {}", bin.name()))?;
+        let data = platform_values(json!({
+            "name": bin.name(),
+            "version": ver,
+            "dir": dir.display().to_string(),
+        }))?;
+        self.templater.render(cmd, &data)
+    }
+
+    /// 在dir中执行fallback命令，`cargo install`与`go install`的可执行文件被安装到dir中
+    async fn build_fallback(&self, ver: &str, dir: &Path) -> Result<()> {
+        let cmd = self.render_fallback(ver, dir)?;
+        let opts = CmdOptions {
+            env: vec![
+                ("CARGO_INSTALL_ROOT".to_owned(), dir.display().to_string()),
+                ("GOBIN".to_owned(), dir.join("bin").display().to_string()),
+            ],
+            shell: *self.bin.bin().shell(),
+            ..Default::default()
+        };
+        run_cmd_with(&cmd, dir, &opts)
+            .await
+            .with_context(|| format!("failed to run the fallback `{}`", cmd))?;
+        afs::write(dir.join(BUILT_MARK_NAME), cmd).await?;
+        Ok(())
+    }
+
+    /// 检查下载文件与source提供的digest是否一致，只支持sha256
+    ///
+    /// 不一致时删除缓存的文件，避免下次继续使用
+    async fn verify_source_digest(&self, url: &Url, path: &Path) -> Result<()> {
+        let digest = match self.bin.digest_of(url) {
+            Some(digest) => digest,
+            None => return Ok(()),
+        };
+        let (algo, expected) = match digest.split_once(':') {
+            Some((algo, hex)) if checksum::is_supported(algo) => {
+                (algo.to_owned(), hex.to_lowercase())
+            }
+            _ => {
+                warn!("skipped verifying unsupported digest {} of {}", digest, url);
+                return Ok(());
+            }
+        };
+        let actual = {
+            let (path, algo) = (path.to_owned(), algo.clone());
+            tokio::task::spawn_blocking(move || checksum::digest_file(path, &algo)).await??
+        };
+        if actual != expected {
+            let md5_path = path.with_file_name(format!(
+                "{}.md5",
+                path.file_name().unwrap_or_default().to_string_lossy()
+            ));
+            for p in [path, &md5_path] {
+                if let Err(e) = remove_file(p).await {
+                    warn!("failed to remove cache {}: {}", p.display(), e);
+                }
+            }
+            bail!(
+                "integrity error: {} digest {} of {} does not match {} from the source",
+                algo,
+                actual,
+                url,
+                expected
+            );
+        }
+        debug!("verified {} digest {} of {}", algo, actual, path.display());
+        Ok(())
+    }
+
+    /// 检查下载文件的sha256与版本ver首次安装时记录的digest是否一致，返回是否需要记录新的digest
+    ///
+    /// # Error
+    ///
+    /// * 如果digest不一致且没有[Self::with_accept_new_digest]
+    async fn check_pinned_digest(&self, ver: &str, url: &Url, sha256: &str) -> Result<bool> {
+        let name = self.bin.bin().name();
+        let actual = format!("sha256:{}", sha256);
+        let pinned = match self.mapper.select_asset_digest(name, ver).await? {
+            Some(pinned) if pinned == actual => return Ok(false),
+            Some(pinned) => pinned,
+            None => return Ok(true),
+        };
+        if !self.accept_new_digest {
+            bail!(
+                "security warning: the asset {} of {} {} has the digest {} but {} was pinned \
+                on its first install. the release may be re-published or tampered with. \
+                reinstall by `--accept-new-digest` if you trust the new asset",
+                url,
+                name,
+                ver,
+                actual,
+                pinned
+            );
+        }
+        warn!(
+            "accepted the new digest {} of {} {} replacing the pinned {}",
+            actual, name, ver, pinned
+        );
+        Ok(true)
+    }
+
+    /// 开启[Binary::verify_attestation]时验证下载到path的文件的github artifact attestation，
+    /// source不支持attestation时跳过
+    ///
+    /// # Error
+    ///
+    /// * 如果attestation无效
+    /// * 如果没有attestation或source不支持且开启了[Binary::require_attestation]
+    async fn verify_attestation(&self, url: &Url, path: &Path, digest: &str) -> Result<()> {
+        let bin = self.bin.bin();
+        if !*bin.verify_attestation() {
+            return Ok(());
+        }
+        if !self.bin.supports_attestation() {
+            if *bin.require_attestation() {
+                bail!(
+                    "refused the asset {} as the {} source of {} has no attestations",
+                    url,
+                    bin.source().name(),
+                    bin.name()
+                );
+            }
+            warn!(
+                "skipped verifying the attestation of {} unsupported by the {} source",
+                url,
+                bin.source().name()
+            );
+            return Ok(());
+        }
+        match self.bin.attestation(path, digest).await? {
+            Some(workflow) => info!("verified the attestation of {} built by {}", url, workflow),
+            None if *bin.require_attestation() => bail!(
+                "refused the unattested asset {} with sha256 digest {}",
+                url,
+                digest
+            ),
+            None => warn!("not found the attestation of {}", url),
+        }
+        Ok(())
+    }
+
+    /// 解压download path到staging dir中并替换版本ver的目录，然后激活这个版本。
+    /// 没有download path时在staging dir中执行fallback命令构建
+    ///
+    /// 所有的修改都记录在backup中用于恢复
+    async fn swap_in(
+        &self,
+        download_path: Option<&Path>,
+        ver: &str,
+        relink: bool,
+        backup: &mut Backup,
+    ) -> Result<()> {
+        self.check_cancelled()?;
+        let to = self.version_dir(ver);
+        let extracted = match download_path {
+            Some(path) => is_extracted(path, &to).await?,
+            None => false,
+        };
+        if extracted {
+            info!("use the extracted version dir {}", to.display());
+        } else {
+            let staging = hidden_sibling(&to, "staging")?;
+            if afs::metadata(&staging).await.is_ok() {
+                warn!("removing the stale staging dir {}", staging.display());
+                afs::remove_dir_all(&staging).await?;
+            }
+            afs::create_dir_all(&staging).await?;
+            // try use custom to extract
+            let res = match download_path {
+                Some(path) => match self.extract(path, &staging).await {
+                    Ok(_) => self.build_in(&staging).await,
+                    Err(e) => Err(e),
+                },
+                None => self.build_fallback(ver, &staging).await,
+            };
+            if let Err(e) = res {
+                if let Err(e) = afs::remove_dir_all(&staging).await {
+                    warn!("failed to remove staging dir {}: {}", staging.display(), e);
+                }
+                return Err(e);
+            }
+
+            let old = hidden_sibling(&to, "backup")?;
+            if afs::metadata(&old).await.is_ok() {
+                afs::remove_dir_all(&old).await?;
+            }
+            if afs::metadata(&to).await.is_ok() {
+                debug!(
+                    "moving the old version dir {} to {}",
+                    to.display(),
+                    old.display()
+                );
+                afs::rename(&to, &old).await?;
+                backup.version_dir = Some(old);
+            }
+            backup.staged = Some(to.clone());
+            debug!(
+                "moving staging dir {} to {}",
+                staging.display(),
+                to.display()
+            );
+            afs::rename(&staging, &to).await?;
+        }
+
+        self.activate(ver, relink, backup).await
+    }
+
+    /// 将current链接指向版本ver的目录，然后链接其中的可执行文件到exe dir
+    ///
+    /// 如果relink则替换已存在的链接
+    async fn activate(&self, ver: &str, relink: bool, backup: &mut Backup) -> Result<()> {
+        backup.old_current = self.point_current(Path::new(ver)).await?;
+        backup.switched = true;
+
+        for path in &self.link_paths {
+            if relink && afs::symlink_metadata(path).await.is_ok() {
+                let old = hidden_sibling(path, "backup")?;
+                debug!(
+                    "moving the old link {} to {}",
+                    path.display(),
+                    old.display()
+                );
+                self.rename_in_exe_dir(path, &old).await?;
+                backup.links.push((path.to_owned(), old));
+            }
+        }
+        // link to exe dir
+        self.check_cancelled()?;
+        self.link(ver, backup).await?;
+
+        self.install_completions(ver).await
+    }
+
+    /// 使用临时链接替换的方式将current链接指向target，返回之前的指向
+    async fn point_current(&self, target: &Path) -> Result<Option<PathBuf>> {
+        let current = self.current_dir();
+        let old = afs::read_link(&current).await.ok();
+        let tmp = hidden_sibling(&current, "tmp")?;
+        if afs::symlink_metadata(&tmp).await.is_ok() {
+            remove_dir_link(&tmp).await?;
+        }
+        trace!("pointing {} to {}", current.display(), target.display());
+        symlink_dir(target, &tmp).await?;
+        // rename can not replace a existing dir on windows
+        if cfg!(windows) && old.is_some() {
+            remove_dir_link(&current).await?;
+        }
+        afs::rename(&tmp, &current).await?;
+        Ok(old)
+    }
+
+    /// 移除[Self::deploy]成功后的备份
+    async fn commit(&self, backup: Backup) {
+        if let Some(old) = backup.version_dir {
+            trace!("removing the old version dir {}", old.display());
+            if let Err(e) = afs::remove_dir_all(&old).await {
+                warn!(
+                    "failed to remove the old version dir {}: {}",
+                    old.display(),
+                    e
+                );
+            }
+        }
+        for (_, old) in backup.links {
+            trace!("removing the old link {}", old.display());
+            if let Err(e) = self.remove_in_exe_dir(&old).await {
+                warn!("failed to remove the old link {}: {}", old.display(), e);
+            }
+        }
+        let linked = backup
+            .linked
+            .iter()
+            .map(|p| p.to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        if let Err(e) = self
+            .mapper
+            .insert_artifacts(self.bin.bin().name(), &linked)
+            .await
+        {
+            warn!(
+                "failed to record the links of {}: {}",
+                self.bin.bin().name(),
+                e
+            );
+        }
+        for conflict in backup.conflicts {
+            if let Err(e) = self.mapper.insert_link_conflict(&conflict).await {
+                warn!(
+                    "failed to record the link conflict {}: {}",
+                    conflict.path(),
+                    e
+                );
+            }
+        }
+    }
+
+    /// 撤销[Self::deploy]的修改并从备份中恢复版本目录，current与链接
+    async fn revert(&self, backup: Backup) {
+        warn!("reverting the deployment of {}", self.bin.bin().name());
+        for path in backup.linked {
+            if let Err(e) = self.remove_in_exe_dir(&path).await {
+                error!("failed to remove link {}: {}", path.display(), e);
+            }
+        }
+        for (path, old) in backup.links.into_iter().chain(backup.adopted) {
+            if let Err(e) = self.rename_in_exe_dir(&old, &path).await {
+                error!(
+                    "failed to restore link {} from {}: {}",
+                    path.display(),
+                    old.display(),
+                    e
+                );
+            }
+        }
+        if backup.switched {
+            let res = match &backup.old_current {
+                Some(old) => self.point_current(old).await.map(|_| ()),
+                None => remove_dir_link(&self.current_dir()).await,
+            };
+            if let Err(e) = res {
+                error!(
+                    "failed to restore current link {}: {}",
+                    self.current_dir().display(),
+                    e
+                );
+            }
+        }
+        if let Some(dir) = backup.staged {
+            if let Err(e) = afs::remove_dir_all(&dir).await {
+                error!("failed to remove version dir {}: {}", dir.display(), e);
+            }
+            if let Some(old) = backup.version_dir {
+                if let Err(e) = afs::rename(&old, &dir).await {
+                    error!(
+                        "failed to restore version dir {} from {}: {}",
+                        dir.display(),
+                        old.display(),
+                        e
+                    );
+                }
+            }
+        }
+    }
+
+    /// 打印[Self::deploy]将要执行的操作
+    fn dry_deploy(&self, url: &Url, ver: &str, relink: bool, digest: Option<&str>) -> Result<()> {
+        let cache_path = self.cache_path(url)?;
+        println!(
+            "[dry-run] would download {} to {}",
+            url,
+            cache_path.display()
+        );
+        if let Some(digest) = self.bin.digest_of(url) {
+            println!("[dry-run] would verify digest {}", digest);
+        }
+        if let Some(digest) = digest {
+            println!("[dry-run] would verify locked digest {}", digest);
+        }
+        let ver_dir = self.version_dir(ver);
+        match self.extract_cmd(&cache_path, &ver_dir)? {
+            Some(cmd) => println!("[dry-run] would extract with hook: {}", cmd),
+            None => println!("[dry-run] would extract to {}", ver_dir.display()),
+        }
+        for hook in self.bin.bin().hooks_on(HookEvent::Build) {
+            let cmd = self.render_hook(hook.command(), &ver_dir)?;
+            println!("[dry-run] would run build hook: {}", cmd);
+        }
+        println!(
+            "[dry-run] would point {} to {}",
+            self.current_dir().display(),
+            ver_dir.display()
+        );
+        if relink {
+            for path in &self.link_paths {
+                println!("[dry-run] would remove the old link {}", path.display());
+            }
+        }
+        let mode = match self.bin.bin().exe_type() {
+            Some(ExeType::Shim { .. }) => "shim",
+            Some(ExeType::Symlink) => "symlink",
+            Some(ExeType::Link) => "hard link",
+            Some(ExeType::Copy) => "copy",
+            None => "link",
+        };
+        for path in &self.link_paths {
+            println!(
+                "[dry-run] would {} {} from {}",
+                mode,
+                path.display(),
+                self.current_dir().display()
+            );
+        }
+        if let (Some(_), Some(dir)) = (self.bin.bin().completion(), &self.completion_dir) {
+            println!("[dry-run] would install completions to {}", dir.display());
+        }
+        Ok(())
+    }
+
+    pub async fn uninstall(&self) -> Result<()> {
+        if self.dry_run {
+            let name = self.bin.bin().name();
+            for path in &self.link_paths {
+                println!("[dry-run] would remove link {}", path.display());
+            }
+            for path in self.mapper.select_artifacts_by_name(name).await? {
+                if !self
+                    .link_paths
+                    .iter()
+                    .any(|p| p.as_os_str() == path.as_str())
+                {
+                    println!("[dry-run] would remove {}", path);
+                }
+            }
+            println!(
+                "[dry-run] would remove data dir {}",
+                self.data_dir.display()
+            );
+            println!(
+                "[dry-run] would delete {} infos of {} from db",
+                self.mapper.select_list_by_name(name).await?.len(),
+                name
+            );
+        } else {
+            self.remove_installed().await;
+        }
+
+        self.run_hooks(HookEvent::Uninstall).await
+    }
+
+    /// 移除链接，data dir与db中的记录，失败时仅记录日志
+    async fn remove_installed(&self) {
+        for path in &self.link_paths {
+            trace!("removing link file {}", path.display());
+            if let Err(e) = self.remove_in_exe_dir(path).await {
+                info!("failed to remove a link file {}: {}", path.display(), e);
+            }
+        }
+        // the links of the removed alias are not in link paths
+        match self.links_into_data_dir().await {
+            Ok(paths) => {
+                for path in paths {
+                    debug!("removing the stale link {}", path.display());
+                    if let Err(e) = self.remove_in_exe_dir(&path).await {
+                        info!("failed to remove a link file {}: {}", path.display(), e);
+                    }
+                }
+            }
+            Err(e) => info!("failed to find links in {}: {}", self.exe_dir.display(), e),
+        }
+        self.remove_artifacts().await;
+        // after removing the links at the paths of the adopted files
+        self.restore_adopted().await;
+
+        trace!("removing data dir {}", self.data_dir.display());
+        if let Err(e) = afs::remove_dir_all(&self.data_dir).await {
+            info!(
+                "failed to remove data dir {}: {}",
+                self.data_dir.display(),
+                e
+            );
+        }
+
+        let name = self.bin.bin().name();
+        if let Err(e) = self.mapper.delete_files_by_name(name).await {
+            info!("failed to delete installed files of {}: {}", name, e);
+        }
+        if let Err(e) = self.mapper.delete_asset_pattern_by_name(name).await {
+            info!("failed to delete the asset pattern of {}: {}", name, e);
+        }
+        if let Err(e) = self.mapper.delete_external_by_name(name).await {
+            info!("failed to delete the external binary of {}: {}", name, e);
+        }
+        trace!("deleting installed infos of {} from db", name);
+        match self.mapper.delete_by_name(name).await {
+            Ok(rows) => {
+                if rows != 0 {
+                    trace!("deleted {} infos of {}", rows, name);
+                } else {
+                    warn!("no info of {} removed", name);
+                }
+            }
+            Err(e) => {
+                info!("failed to delete info of {}: {}", name, e);
+            }
+        }
+    }
+
+    /// 在当前版本目录中按顺序执行event上所有渲染后的hook cmd，dry run时仅打印
+    ///
+    /// hook中的`data_dir`为[Self::current_dir]，如果不存在则使用data dir，uninstall后使用其上级目录。
+    /// 一个hook失败时不再执行之后的hook
+    async fn run_hooks(&self, event: HookEvent) -> Result<()> {
+        let hooks = self.bin.bin().hooks_on(event);
+        if hooks.is_empty() {
+            return Ok(());
+        }
+        let current = self.current_dir();
+        let mut work_dir = self.data_dir.parent().unwrap_or(&self.data_dir);
+        for dir in [&current, &self.data_dir] {
+            if afs::metadata(dir).await.is_ok() {
+                work_dir = dir;
+                break;
+            }
+        }
+        for hook in &hooks {
+            self.run_hook(event, hook, work_dir).await?;
+        }
+        Ok(())
+    }
+
+    /// 在work dir中执行渲染后的hook，hook配置的work dir相对于work dir
+    async fn run_hook(&self, event: HookEvent, hook: &Hook, work_dir: &Path) -> Result<()> {
+        let cmd = self.render_hook(hook.command(), work_dir)?;
+        let dir = match hook.work_dir() {
+            Some(dir) => work_dir.join(dir),
+            None => work_dir.to_owned(),
+        };
+        if self.dry_run {
+            println!(
+                "[dry-run] would run {} hook in {}: {}",
+                event,
+                dir.display(),
+                cmd
+            );
+            return Ok(());
+        }
+        debug!(
+            "running {} hook of {}: {}",
+            event,
+            self.bin.bin().name(),
+            cmd
+        );
+        let opts = CmdOptions {
+            shebang: hook.shebang().clone(),
+            user: hook.user().clone(),
+            env: hook
+                .env()
+                .iter()
+                .map(|(k, v)| (k.to_owned(), v.to_owned()))
+                .collect(),
+            timeout: hook.timeout().map(Duration::from_secs),
+            shell: hook.shell().unwrap_or(*self.bin.bin().shell()),
+            escalation: self.escalation(),
+        };
+        run_cmd_with(&cmd, &dir, &opts)
+            .await
+            .with_context(|| format!("failed to run {} hook `{}`", event, cmd))
+    }
+
+    /// 渲染hook cmd，其中的`data_dir`为work dir
+    fn render_hook(&self, hook: &str, work_dir: &Path) -> Result<String> {
+        let data = self.template_values(json!({
+            "data_dir": work_dir.display().to_string(),
+            "name": self.bin.bin().name(),
+        }))?;
+        self.templater.render(hook, &data)
+    }
+
+    /// 在解压的dir中按顺序执行build hooks。如tag tarball这样只解压出一个目录时在这个目录中执行
+    async fn build_in(&self, dir: &Path) -> Result<()> {
+        let hooks = self.bin.bin().hooks_on(HookEvent::Build);
+        if hooks.is_empty() {
+            return Ok(());
+        }
+        let mut entries = afs::read_dir(dir).await?;
+        let (first, second) = (entries.next_entry().await?, entries.next_entry().await?);
+        let work_dir = match (first, second) {
+            (Some(entry), None) if entry.file_type().await?.is_dir() => entry.path(),
+            _ => dir.to_owned(),
+        };
+        for hook in &hooks {
+            self.run_hook(HookEvent::Build, hook, &work_dir).await?;
+        }
+        Ok(())
+    }
+
+    /// 获取url下载文件的sha256 digest如`sha256:<hex>`，如果未缓存则先下载
+    pub async fn digest(&self, url: &Url) -> Result<String> {
+        let path = self.download(url).await?;
+        let digest =
+            tokio::task::spawn_blocking(move || checksum::digest_file(path, "sha256")).await??;
+        Ok(format!("sha256:{}", digest))
+    }
+
+    pub async fn clean_cache(&self) -> Result<()> {
+        let cache_dir = &self.cache_dir;
+        if self.dry_run {
+            println!("[dry-run] would remove cache dir {}", cache_dir.display());
+            return Ok(());
+        }
+        trace!("removing cache dir {}", cache_dir.display());
+        if let Err(e) = afs::remove_dir_all(&cache_dir).await {
+            info!("failed to remove cache dir {}: {}", cache_dir.display(), e);
+        }
+        Ok(())
+    }
+
+    /// 使用bin glob在目录base中找到一个可执行文件
+    ///
+    /// 如果未配置bin glob则使用默认的`**/*{name}*`
+    async fn find_exe(&self, base: impl AsRef<Path>) -> Result<PathBuf> {
+        let base = base.as_ref().to_path_buf();
+        let glob_pat = self
+            .bin
+            .bin()
+            .bin_glob()
+            .as_ref()
+            .map(|glob| {
+                let data = self.template_values(json!({
+                    "name": self.bin.bin().name(),
+                }))?;
+                self.templater.render(glob, &data).map(|pat| {
+                    let s = pat.trim().to_owned();
+                    debug!("use bin glob pattern {} in directory {}", s, base.display());
+                    s
+                })
+            })
+            .unwrap_or_else(|| {
+                // the script is placed as the file of its name. so is the built bin
+                if self.bin.bin().source().name() == SCRIPT_SOURCE
+                    || base.join(BUILT_MARK_NAME).exists()
+                {
+                    return Ok(format!("**/{}", self.bin.bin().name()));
+                }
+                let pat = format!("**/*{}*", self.bin.bin().name());
+                warn!(
+                    "use default glob pattern {} in directory {}",
+                    pat,
+                    base.display()
+                );
+                Ok(pat)
+            })?;
+        // keep the extension of executable file on windows
+        let glob_pat = if !glob_pat.ends_with(EXE_SUFFIX) {
+            glob_pat + EXE_SUFFIX
+        } else {
+            glob_pat
+        };
+        tokio::task::spawn_blocking(move || find_one_bin_with_glob(base, &glob_pat)).await?
+    }
+
+    /// 链接版本ver中的可执行文件到exe dir
+    ///
+    /// 链接的源路径位于[Self::current_dir]中，切换版本时不需要改变路径
+    async fn link(&self, ver: &str, backup: &mut Backup) -> Result<()> {
+        for dst in &self.link_paths {
+            if afs::symlink_metadata(dst).await.is_ok() {
+                self.take_over(dst, backup).await?;
+            }
+        }
+
+        let ver_dir = self.version_dir(ver);
+        let exe = self.find_exe(&ver_dir).await?;
+        if cfg!(target_os = "macos") {
+            self.prepare_macos_exe(&exe).await;
+        }
+        let src = self.current_dir().join(exe.strip_prefix(&ver_dir)?);
+        for dst in &self.link_paths {
+            self.link_one(&src, dst).await?;
+            backup.linked.push(dst.to_owned());
+            self.observer.on_link(self.bin.bin().name(), &src, dst);
+        }
+        self.warn_not_on_path();
+        self.warn_shadowed().await;
+        Ok(())
+    }
+
+    /// 移除执行文件exe的[QUARANTINE_XATTR]避免被Gatekeeper拦截，开启[Binary::codesign]时验证其签名。
+    /// 失败时仅警告
+    async fn prepare_macos_exe(&self, exe: &Path) {
+        let bin = self.bin.bin();
+        if !*bin.keep_quarantine() {
+            let res = Command::new("xattr")
+                .arg("-d")
+                .arg(QUARANTINE_XATTR)
+                .arg(exe)
+                .output()
+                .await;
+            match res {
+                Ok(out) if out.status.success() => {
+                    info!("removed {} of {}", QUARANTINE_XATTR, exe.display())
+                }
+                Ok(out) => {
+                    let err = String::from_utf8_lossy(&out.stderr);
+                    // 没有下载时添加的属性
+                    if !err.contains("No such xattr") {
+                        warn!(
+                            "failed to remove {} of {}: {}",
+                            QUARANTINE_XATTR,
+                            exe.display(),
+                            err.trim()
+                        );
+                    }
+                }
+                Err(e) => warn!("failed to run xattr for {}: {}", exe.display(), e),
+            }
+        }
+        if *bin.codesign() {
+            self.verify_codesign(exe).await;
+        }
+    }
+
+    /// 使用`codesign`验证exe的签名，未签名或ad-hoc签名时警告
+    async fn verify_codesign(&self, exe: &Path) {
+        let verified = Command::new("codesign")
+            .args(["--verify", "--strict"])
+            .arg(exe)
+            .output()
+            .await;
+        match verified {
+            Ok(out) if out.status.success() => {}
+            Ok(out) => {
+                warn!(
+                    "failed to verify the signature of {}: {}",
+                    exe.display(),
+                    String::from_utf8_lossy(&out.stderr).trim()
+                );
+                return;
+            }
+            Err(e) => {
+                warn!("failed to run codesign for {}: {}", exe.display(), e);
+                return;
+            }
+        }
+        // `codesign -dv`的信息在stderr中
+        let details = match Command::new("codesign").arg("-dv").arg(exe).output().await {
+            Ok(out) => String::from_utf8_lossy(&out.stderr).into_owned(),
+            Err(e) => {
+                warn!("failed to run codesign for {}: {}", exe.display(), e);
+                return;
+            }
+        };
+        if details.contains("Signature=adhoc") {
+            warn!(
+                "{} of {} is ad-hoc signed without a developer identity",
+                exe.display(),
+                self.bin.bin().name()
+            );
+        } else if let Some(authority) = details.lines().find_map(|l| l.strip_prefix("Authority=")) {
+            info!(
+                "verified the signature of {} by {}",
+                exe.display(),
+                authority
+            );
+        }
+    }
+
+    /// PATH中找到的同名可执行文件不是链接时提示冲突
+    async fn warn_shadowed(&self) {
+        let paths = self.link_paths.clone();
+        let shadows = tokio::task::spawn_blocking(move || {
+            paths
+                .into_iter()
+                .filter_map(|dst| {
+                    let found = which(dst.file_name()?).ok()?;
+                    let same = found == dst
+                        || std::fs::canonicalize(&found).ok() == std::fs::canonicalize(&dst).ok();
+                    (!same).then_some((found, dst))
+                })
+                .collect::<Vec<_>>()
+        })
+        .await
+        .unwrap_or_else(|e| {
+            error!("failed spawn blocking `which` task: {}", e);
+            vec![]
+        });
+        for (found, dst) in shadows {
+            warn!(
+                "found a foreign executable {} in PATH that shadows the linked {} of {}",
+                found.display(),
+                dst.display(),
+                self.bin.bin().name()
+            );
+        }
+    }
+
+    /// 删除db中记录的在data dir外创建的文件，如links与completions
+    async fn remove_artifacts(&self) {
+        let name = self.bin.bin().name();
+        let paths = match self.mapper.select_artifacts_by_name(name).await {
+            Ok(paths) => paths,
+            Err(e) => {
+                info!("failed to get artifacts of {}: {}", name, e);
+                return;
+            }
+        };
+        for path in paths.iter().map(Path::new) {
+            if afs::symlink_metadata(path).await.is_err() {
+                continue;
+            }
+            trace!("removing artifact {}", path.display());
+            if let Err(e) = self.remove_in_exe_dir(path).await {
+                info!("failed to remove artifact {}: {}", path.display(), e);
+            }
+        }
+        if let Err(e) = self.mapper.delete_artifacts_by_name(name).await {
+            info!("failed to delete artifacts of {}: {}", name, e);
+        }
+    }
+
+    /// 恢复adopt时备份的文件并删除db中的link conflicts
+    async fn restore_adopted(&self) {
+        let name = self.bin.bin().name();
+        let conflicts = match self.mapper.select_link_conflicts_by_name(name).await {
+            Ok(conflicts) => conflicts,
+            Err(e) => {
+                info!("failed to get link conflicts of {}: {}", name, e);
+                return;
+            }
+        };
+        for conflict in conflicts {
+            let (path, backup) = match conflict.backup() {
+                Some(backup) => (Path::new(conflict.path()), Path::new(backup)),
+                None => continue,
+            };
+            debug!(
+                "restoring the adopted file {} from {}",
+                path.display(),
+                backup.display()
+            );
+            if let Err(e) = self.rename_in_exe_dir(backup, path).await {
+                warn!(
+                    "failed to restore the adopted file {} from {}: {}",
+                    path.display(),
+                    backup.display(),
+                    e
+                );
+            }
+        }
+        if let Err(e) = self.mapper.delete_link_conflicts_by_name(name).await {
+            info!("failed to delete link conflicts of {}: {}", name, e);
+        }
+    }
+
+    /// 处理链接路径dst上已存在的文件，在[Self::commit]时记录到db中
+    ///
+    /// * 替换指向data dir的悬空链接，如手动删除了data dir
+    /// * force时替换指向data dir的链接，成功后删除旧的链接
+    /// * adopt时将其它文件移到隐藏的备份中，卸载时恢复
+    ///
+    /// # Error
+    ///
+    /// * 如果没有对应的force或adopt
+    /// * 如果adopt的备份已存在
+    async fn take_over(&self, dst: &Path, backup: &mut Backup) -> Result<()> {
+        let data_dir = self.data_dir.parent().unwrap_or(&self.data_dir);
+        let owned = afs::read_link(dst)
+            .await
+            .is_ok_and(|target| target.starts_with(data_dir));
+        if owned && afs::metadata(dst).await.is_err() {
+            debug!("replacing the dangling link {}", dst.display());
+            let old = hidden_sibling(dst, "backup")?;
+            self.rename_in_exe_dir(dst, &old).await?;
+            backup.links.push((dst.to_owned(), old));
+            return Ok(());
+        }
+        let mut conflict = LinkConflictBuilder::default();
+        conflict
+            .path(dst.to_string_lossy())
+            .name(self.bin.bin().name());
+        if owned && self.force {
+            let old = hidden_sibling(dst, "backup")?;
+            warn!("replacing the existing link {}", dst.display());
+            self.rename_in_exe_dir(dst, &old).await?;
+            backup.links.push((dst.to_owned(), old));
+            backup.conflicts.push(conflict.action("replace").build()?);
+        } else if !owned && self.adopt {
+            let to = hidden_sibling(dst, "adopted")?;
+            if afs::symlink_metadata(&to).await.is_ok() {
+                bail!(
+                    "found the existing backup {} for adopting {}",
+                    to.display(),
+                    dst.display()
+                );
+            }
+            warn!(
+                "adopting the existing file {} and backing it up to {}",
+                dst.display(),
+                to.display()
+            );
+            self.rename_in_exe_dir(dst, &to).await?;
+            backup.adopted.push((dst.to_owned(), to.clone()));
+            backup.conflicts.push(
+                conflict
+                    .action("adopt")
+                    .backup(Some(to.to_string_lossy().into_owned()))
+                    .build()?,
+            );
+        } else if owned {
+            bail!(
+                "found the existing link {} for linking. use `--force` to replace it",
+                dst.display()
+            );
+        } else {
+            bail!(
+                "found the existing file {} for linking. use `--adopt` to back it up and take it over",
+                dst.display()
+            );
+        }
+        Ok(())
+    }
+
+    /// 链接的目录不在PATH中时提示添加到PATH的命令，每个目录只提示一次
+    fn warn_not_on_path(&self) {
+        static WARNED: Lazy<Mutex<HashSet<PathBuf>>> = Lazy::new(Default::default);
+        for dir in self.link_paths.iter().filter_map(|p| p.parent()) {
+            if is_on_path(dir) || !WARNED.lock().insert(dir.to_owned()) {
+                continue;
+            }
+            let export = if cfg!(windows) {
+                format!("$env:PATH = \"{};\" + $env:PATH", dir.display())
+            } else {
+                format!("export PATH=\"{}:$PATH\"", dir.display())
+            };
+            warn!(
+                "linked {} into {} which is not on PATH. add `{}` to your shell rc or use `{} init`",
+                self.bin.bin().name(),
+                dir.display(),
+                export,
+                crate::CRATE_NAME
+            );
+        }
+    }
 
-        trace!("removing data dir {}", self.data_dir.display());
-        if let Err(e) = afs::remove_dir_all(&self.data_dir).await {
-            info!(
-                "failed to remove data dir {}: {}",
-                self.data_dir.display(),
-                e
-            );
+    /// 找到exe dir中指向data dir的symlinks
+    async fn links_into_data_dir(&self) -> Result<Vec<PathBuf>> {
+        let mut paths = vec![];
+        let mut entries = afs::read_dir(&self.exe_dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if let Ok(target) = afs::read_link(entry.path()).await {
+                if target.starts_with(&self.data_dir) {
+                    paths.push(entry.path());
+                }
+            }
         }
+        Ok(paths)
+    }
 
-        let name = self.bin.bin().name();
-        trace!("deleting installed infos of {} from db", name);
-        match self.mapper.delete_by_name(name).await {
-            Ok(rows) => {
-                if rows != 0 {
-                    trace!("deleted {} infos of {}", rows, name);
-                } else {
-                    warn!("no info of {} removed", name);
+    async fn link_one(&self, src: &Path, dst: &Path) -> Result<()> {
+        match self.bin.bin().exe_type() {
+            Some(ExeType::Shim { template }) => {
+                let data = self.template_values(json!({
+                    "name": self.bin.bin().name(),
+                    "data_dir": self.current_dir().display().to_string(),
+                    "exe_path": src.display().to_string(),
+                }))?;
+                let is_ps1 = dst
+                    .extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("ps1"));
+                let template = if is_ps1 { PS1_SHIM_TEMPLATE } else { template };
+                let content = self.templater.render(template, &data)?;
+                info!("writing shim {} for {}", dst.display(), src.display());
+                let res = write_executable(dst, &content).await;
+                if !self.need_privilege(&res) {
+                    return res;
                 }
+                // write to the data dir first and then copy into the exe dir as root
+                let tmp = self.data_dir.join(format!(
+                    ".{}.shim",
+                    dst.file_name().unwrap_or_default().to_string_lossy()
+                ));
+                write_executable(&tmp, &content).await?;
+                let res = self.run_privileged(res, &["cp", "-p"], &[&tmp, dst]).await;
+                if let Err(e) = afs::remove_file(&tmp).await {
+                    warn!("failed to remove the shim {}: {}", tmp.display(), e);
+                }
+                res
             }
-            Err(e) => {
-                info!("failed to delete info of {}: {}", name, e);
+            Some(ExeType::Symlink) => {
+                let res = symlink(src, dst).await;
+                self.run_privileged(res, &["ln", "-s"], &[src, dst]).await
+            }
+            Some(ExeType::Link) => {
+                let res = hard_link(src, dst).await;
+                self.run_privileged(res, &["ln"], &[src, dst]).await
+            }
+            Some(ExeType::Copy) => {
+                info!("copying {} to {}", src.display(), dst.display());
+                let res = afs::copy(src, dst).await.map(|_| ()).map_err(Into::into);
+                self.run_privileged(res, &["cp"], &[src, dst]).await
+            }
+            None => {
+                let res = link_exe(src, dst).await;
+                self.run_privileged(res, &["ln", "-s"], &[src, dst]).await
             }
         }
+    }
 
-        if let Some(hook) = self
-            .bin
-            .bin()
-            .hook()
-            .as_ref()
-            .and_then(|h| h.uninstall().as_deref())
-        {
-            let data = platform_values(json!({
-                "data_dir": self.data_dir.display().to_string(),
-                "name": self.bin.bin().name(),
-            }))?;
-            let cmd = self.templater.render(hook, &data)?;
-            run_cmd(&cmd, &self.data_dir).await?;
+    /// 在exe dir中移除文件path，权限不足时使用[Self::run_privileged]
+    async fn remove_in_exe_dir(&self, path: &Path) -> Result<()> {
+        let res = afs::remove_file(path).await.map_err(Into::into);
+        self.run_privileged(res, &["rm", "-f"], &[path]).await
+    }
+
+    /// 在exe dir中重命名文件from为to，权限不足时使用[Self::run_privileged]
+    async fn rename_in_exe_dir(&self, from: &Path, to: &Path) -> Result<()> {
+        let res = afs::rename(from, to).await.map_err(Into::into);
+        self.run_privileged(res, &["mv"], &[from, to]).await
+    }
+
+    /// 是否需要以root重新执行失败的操作res
+    ///
+    /// 仅在unix上privileged的bin因为权限不足而失败时需要
+    fn need_privilege(&self, res: &Result<()>) -> bool {
+        cfg!(unix)
+            && *self.bin.bin().privileged()
+            && matches!(
+                res.as_ref()
+                    .map_err(|e| e.downcast_ref::<std::io::Error>().map(|e| e.kind())),
+                Err(Some(std::io::ErrorKind::PermissionDenied))
+            )
+    }
+
+    /// 如果操作res需要权限则使用sudo或doas以root执行等价的命令`cmd paths..`
+    async fn run_privileged(&self, res: Result<()>, cmd: &[&str], paths: &[&Path]) -> Result<()> {
+        if !self.need_privilege(&res) {
+            return res;
         }
-        Ok(())
+        if self.no_sudo {
+            return res
+                .context("permission denied in the bin dir and sudo is disabled by --no-sudo");
+        }
+        let cmd = shell_words::join(
+            cmd.iter()
+                .map(|s| s.to_string())
+                .chain(paths.iter().map(|p| p.display().to_string())),
+        );
+        let opts = CmdOptions {
+            user: Some("root".to_owned()),
+            escalation: self.escalation(),
+            ..Default::default()
+        };
+        run_cmd_with(&cmd, &self.exe_dir, &opts)
+            .await
+            .with_context(|| format!("failed to run `{}` as root", cmd))
     }
 
-    pub async fn clean_cache(&self) -> Result<()> {
-        let cache_dir = &self.cache_dir;
-        trace!("removing cache dir {}", cache_dir.display());
-        if let Err(e) = afs::remove_dir_all(&cache_dir).await {
-            info!("failed to remove cache dir {}: {}", cache_dir.display(), e);
+    /// 以其它用户执行命令的程序，`--no-sudo`时为none
+    fn escalation(&self) -> Option<Escalation> {
+        if self.no_sudo {
+            None
+        } else {
+            Escalation::find()
         }
-        Ok(())
     }
 
-    async fn link<P>(&self, to: P) -> Result<()>
-    where
-        P: AsRef<Path>,
-    {
-        let dst = &self.link_path;
-        if afs::metadata(dst).await.is_ok() {
-            bail!("found the existing file {} for linking", dst.display());
-        }
-
-        let src = {
-            let base = to.as_ref().to_path_buf();
-            let glob_pat = self
-                .bin
-                .bin()
-                .bin_glob()
-                .as_ref()
-                .map(|glob| {
-                    let data = platform_values(json!({
-                        "name": self.bin.bin().name(),
-                    }))?;
-                    self.templater.render(glob, &data).map(|pat| {
-                        let s = pat.trim().to_owned();
-                        debug!("use bin glob pattern {} in directory {}", s, base.display());
-                        s
-                    })
-                })
-                .unwrap_or_else(|| {
-                    let pat = format!("**/*{}*", self.bin.bin().name());
-                    warn!(
-                        "use default glob pattern {} in directory {}",
-                        pat,
-                        base.display()
-                    );
-                    Ok(pat)
-                })?;
-            tokio::task::spawn_blocking(move || find_one_bin_with_glob(base, &glob_pat)).await??
+    /// 复制版本ver目录中匹配completion globs的文件到completion dir中，并刷新加载completions的snippet
+    ///
+    /// * fpath文件复制到`completion_dir/zsh`
+    /// * source文件复制到`completion_dir/source`
+    /// * snippet为`completion_dir/init.zsh`，用户需要在shell rc中source它
+    ///
+    /// 复制的文件记录为artifacts，卸载时删除
+    async fn install_completions(&self, ver: &str) -> Result<()> {
+        let (completion, completion_dir) = match (self.bin.bin().completion(), &self.completion_dir)
+        {
+            (Some(c), Some(d)) => (c, d),
+            _ => return Ok(()),
         };
+        let mut copied = vec![];
 
-        if let Ok(d) = afs::metadata(&dst).await {
-            error!(
-                "found a existing path {} for linking. is link: {}",
-                dst.display(),
-                d.is_symlink()
-            );
-            bail!("a existing path {} for linking", dst.display());
+        for (globs, ty) in [(completion.fpath(), "zsh"), (completion.source(), "source")] {
+            let globs = match globs {
+                Some(globs) if !globs.is_empty() => globs.clone(),
+                _ => continue,
+            };
+            let to_dir = completion_dir.join(ty);
+            afs::create_dir_all(&to_dir).await?;
+
+            let base = self.version_dir(ver);
+            let paths = {
+                let base = base.clone();
+                tokio::task::spawn_blocking(move || find_files_with_globs(base, &globs)).await??
+            };
+            if paths.is_empty() {
+                warn!(
+                    "not found {} completion files for {} in {}",
+                    ty,
+                    self.bin.bin().name(),
+                    base.display()
+                );
+            }
+            for path in paths {
+                let to = to_dir.join(
+                    path.file_name()
+                        .ok_or_else(|| anyhow!("no filename for {}", path.display()))?,
+                );
+                debug!("copying completion {} to {}", path.display(), to.display());
+                afs::copy(&path, &to).await?;
+                copied.push(to.to_string_lossy().into_owned());
+            }
         }
+        self.mapper
+            .insert_artifacts(self.bin.bin().name(), &copied)
+            .await?;
 
-        info!("sym linking {} to {}", src.display(), dst.display());
-        tokio::fs::symlink(src, dst).await?;
+        let snippet_path = completion_dir.join("init.zsh");
+        let snippet = format!(
+            r#"# generated by {name}. add `source "{path}"` to your .zshrc
+fpath=("{dir}/zsh" $fpath)
+for f in "{dir}/source"/*(N); do
+    source "$f"
+done
+"#,
+            name = crate::CRATE_NAME,
+            path = snippet_path.display(),
+            dir = completion_dir.display(),
+        );
+        trace!("writing completion snippet to {}", snippet_path.display());
+        afs::write(&snippet_path, snippet).await?;
+        info!(
+            "installed completions of {}. add `source \"{}\"` to your shell rc",
+            self.bin.bin().name(),
+            snippet_path.display()
+        );
         Ok(())
     }
 
@@ -360,7 +2370,7 @@ impl BinaryPackage {
     /// 如果配置了extract hook，则使用自定义的cmd解压，在from级目录上可解压在`bin.{name,filename}`目录。
     /// 否则使用通用解压
     ///
-    /// 如果之前已存在对应的目录，则不会解压直接返回认为是缓存
+    /// 如果之前已解压过相同digest的文件到to中，则不会解压直接返回认为是缓存
     ///
     /// # Error
     ///
@@ -371,40 +2381,56 @@ impl BinaryPackage {
     where
         P: AsRef<Path>,
     {
-        let cmd = if let Some(hook) = self
+        let cmd = self.extract_cmd(from.as_ref(), to.as_ref())?;
+        self.observer
+            .on_extract(self.bin.bin().name(), from.as_ref(), to.as_ref());
+        let bin = self.bin.bin();
+        if cmd.is_none() && bin.source().name() == SCRIPT_SOURCE {
+            return place_script(
+                from.as_ref(),
+                to.as_ref(),
+                bin.name(),
+                bin.shebang().as_deref(),
+            )
+            .await;
+        }
+        decompress(from, to, cmd.as_deref()).await
+    }
+
+    /// 渲染配置的第一个extract hook，未配置时为none
+    fn extract_cmd(&self, from: &Path, to: &Path) -> Result<Option<String>> {
+        match self
             .bin
             .bin()
-            .hook()
-            .as_ref()
-            .and_then(|h| h.extract().as_deref())
+            .hooks_on(HookEvent::Extract)
+            .first()
+            .map(|h| h.command())
         {
-            let data = platform_values(json!({
-                "from": from.as_ref().display().to_string(),
-                "to": to.as_ref().display().to_string(),
-                "name": self.bin.bin().name(),
-            }))?;
-            Some(self.templater.render(hook, &data)?)
-        } else {
-            None
-        };
-
-        decompress(from, to, cmd.as_deref()).await
+            Some(hook) => {
+                let data = self.template_values(json!({
+                    "from": from.display().to_string(),
+                    "to": to.display().to_string(),
+                    "name": self.bin.bin().name(),
+                }))?;
+                self.templater.render(hook, &data).map(Some)
+            }
+            None => Ok(None),
+        }
     }
 
     /// 下载url对应文件到缓存path
     ///
-    /// 如果之前有下载过相同的文件且md5相同则使用缓存文件，否则重新下载
+    /// url匹配mirrors时先从mirror下载，失败后回退到原url。如果之前有下载过相同的文件且md5相同则使用缓存文件，否则重新下载
     async fn download(&self, url: &Url) -> Result<PathBuf> {
-        let filename = url
-            .path_segments()
-            .and_then(|seg| seg.last())
-            .map(ToString::to_string)
+        let cache_path = self.cache_path(url)?;
+        let filename = cache_path
+            .file_name()
+            .and_then(|s| s.to_str())
             .ok_or_else(|| anyhow!("not found filename for {}", url))?;
 
         let cache_dir = &self.cache_dir;
         afs::create_dir_all(&cache_dir).await?;
 
-        let cache_path = cache_dir.join(&filename);
         let md5_path = cache_dir.join(&format!("{}.md5", filename));
 
         // check digest
@@ -414,12 +2440,7 @@ impl BinaryPackage {
                     let (md5_digest, cache_path) =
                         (read_to_string(&md5_path).await?, cache_path.clone());
                     tokio::task::spawn_blocking(move || {
-                        let mut hasher = Md5::new();
-                        std::io::copy(&mut File::open(&cache_path)?, &mut hasher)?;
-                        let digest: String = hasher
-                            .finalize()
-                            .iter()
-                            .fold(String::new(), |a, e| a + &e.to_string());
+                        let digest = md5_file(&cache_path)?;
                         trace!(
                             "found new digest {} and old {} for {}",
                             digest,
@@ -433,6 +2454,7 @@ impl BinaryPackage {
 
                 if is_identical {
                     info!("use cached file {}", cache_path.display());
+                    self.touch_cache(&cache_path).await;
                     return Ok(cache_path);
                 } else {
                     warn!(
@@ -453,8 +2475,131 @@ impl BinaryPackage {
             }
         }
 
+        if *self.bin.bin().delta() {
+            match self.fetch_delta(url, &cache_path, &md5_path).await {
+                Ok(true) => {
+                    self.touch_cache(&cache_path).await;
+                    return Ok(cache_path);
+                }
+                Ok(false) => {}
+                Err(e) if e.is::<Cancelled>() => return Err(e),
+                Err(e) => warn!(
+                    "failed to download the delta of {}: {}. falling back to the full download",
+                    url, e
+                ),
+            }
+        }
+
         debug!("downloading {} for {}", filename, url);
-        let resp = self.client.get(url.as_ref()).send().await?;
+        if let Some(mirror) = self.mirror_url(url)? {
+            // 不向mirror发送bin的headers与auth
+            let req = self.client.get(mirror.as_ref());
+            match self.fetch(req, &mirror, &cache_path, &md5_path).await {
+                Ok(()) => {
+                    self.touch_cache(&cache_path).await;
+                    return Ok(cache_path);
+                }
+                Err(e) if e.is::<Cancelled>() => return Err(e),
+                Err(e) => warn!(
+                    "failed to download {} from mirror {}: {}. falling back to {}",
+                    filename, mirror, e, url
+                ),
+            }
+        }
+        self.fetch(self.request(url)?, url, &cache_path, &md5_path)
+            .await?;
+        self.touch_cache(&cache_path).await;
+        Ok(cache_path)
+    }
+
+    /// 记录缓存文件的访问时间，超过max cache size时按最近访问时间清理
+    async fn touch_cache(&self, path: &Path) {
+        let name = self.bin.bin().name();
+        if let Err(e) = self
+            .mapper
+            .touch_cache(&path.display().to_string(), name)
+            .await
+        {
+            warn!("failed to record the access of {}: {}", path.display(), e);
+        }
+    }
+
+    /// 下载url的请求，添加source需要的headers与bin配置的headers与basic auth，
+    /// 其中的`${VAR}`在下载时才展开。只有github api的请求带上token
+    fn request(&self, url: &Url) -> Result<RequestBuilder> {
+        let mut req = self
+            .client
+            .get(url.as_ref())
+            .headers(self.bin.download_headers(url));
+        if let Some(token) = &self.token {
+            req = token.authorize(req, url);
+        }
+        let bin = self.bin.bin();
+        for (name, val) in bin.headers() {
+            let val = expand_env(val).with_context(|| format!("invalid header {}", name))?;
+            req = req.header(name.as_str(), val);
+        }
+        if let Some(auth) = bin.basic_auth() {
+            let auth = expand_env(auth).context("invalid basic auth")?;
+            let (user, pass) = auth.split_once(':').unwrap_or((&auth, ""));
+            req = req.basic_auth(user, Some(pass));
+        }
+        Ok(req)
+    }
+
+    /// 发送url的请求req。client在跨域的重定向时停止，如github的asset重定向到cdn，
+    /// 不带token与bin的headers与auth跟随，避免泄露给其它host
+    async fn send(&self, req: RequestBuilder, url: &Url) -> Result<Response> {
+        let (client, req) = req.build_split();
+        let req = req?;
+        // range不是认证信息，跟随重定向时保留
+        let range = req.headers().get(RANGE).cloned();
+        let mut resp = client.execute(req).await?;
+        for _ in 0..MAX_REDIRECTS {
+            if !resp.status().is_redirection() {
+                return Ok(resp);
+            }
+            let location = resp
+                .headers()
+                .get(LOCATION)
+                .and_then(|v| v.to_str().ok())
+                .ok_or_else(|| anyhow!("no location in the redirect {}", resp.url()))?;
+            let next = resp.url().join(location)?;
+            debug!(
+                "following the cross-origin redirect from {} to {} without auth",
+                resp.url().origin().ascii_serialization(),
+                next.origin().ascii_serialization()
+            );
+            let mut req = self.client.get(next);
+            if let Some(range) = &range {
+                req = req.header(RANGE, range.clone());
+            }
+            resp = req.send().await?;
+        }
+        bail!("too many redirects for {}", url)
+    }
+
+    /// 发送url的请求req，下载到cache path并写入md5 digest
+    async fn fetch(
+        &self,
+        req: RequestBuilder,
+        url: &Url,
+        cache_path: &Path,
+        md5_path: &Path,
+    ) -> Result<()> {
+        let read_timeout = *self.bin.bin().read_timeout();
+        // 连接池中没有可复用的连接时包括连接与tls握手的时间
+        let start = Instant::now();
+        let resp = tokio::time::timeout(read_timeout, self.send(req, url))
+            .await
+            .map_err(|_| anyhow!("no response in {:?} for {}", read_timeout, url))??
+            .error_for_status()?;
+        debug!(
+            "got the {:?} response in {:?} for {}",
+            resp.version(),
+            start.elapsed(),
+            url
+        );
 
         if log_enabled!(log::Level::Trace) {
             let content_type = resp
@@ -471,14 +2616,38 @@ impl BinaryPackage {
         }
 
         // create a new or truncate old
-        let mut file = afs::File::create(&cache_path).await?;
+        let mut file = afs::File::create(cache_path).await?;
+        let total = resp.content_length();
         let mut stream = resp.bytes_stream();
 
         trace!("downloading to {} for url: {}", cache_path.display(), url);
         let mut hasher = Md5::new();
-        while let Some(chunk) = stream.next().await {
-            let chunk = chunk?;
+        let mut downloaded = 0;
+        loop {
+            // 只限制两块数据间的等待时间，慢速下载大文件不会超时
+            let chunk = tokio::select! {
+                chunk = tokio::time::timeout(read_timeout, stream.next()) => chunk
+                    .map_err(|_| anyhow!("no data in {:?} downloading {}", read_timeout, url))?,
+                _ = self.cancel.cancelled() => None,
+            };
+            let chunk = match chunk {
+                Some(chunk) => chunk?,
+                None if self.cancel.is_cancelled() => {
+                    drop(file);
+                    warn!("removing the partial download {}", cache_path.display());
+                    if let Err(e) = remove_file(cache_path).await {
+                        warn!("failed to remove {}: {}", cache_path.display(), e);
+                    }
+                    return Err(Cancelled.into());
+                }
+                None => break,
+            };
             file.write_all(&chunk).await?;
+            self.downloaded
+                .fetch_add(chunk.len() as u64, Ordering::Relaxed);
+            downloaded += chunk.len() as u64;
+            self.observer
+                .on_download_progress(self.bin.bin().name(), downloaded, total);
             hasher.update(chunk);
         }
         let digest = hasher
@@ -492,17 +2661,465 @@ impl BinaryPackage {
             md5_path.display(),
             cache_path.display()
         );
-        afs::write(&md5_path, digest).await?;
+        afs::write(md5_path, digest).await?;
+        Ok(())
+    }
 
-        Ok(cache_path)
+    /// 使用url旁的`.zsync`文件，以缓存中当前安装版本的文件为seed只下载变化的块到cache path
+    ///
+    /// 没有zsync文件或缓存的旧文件时返回false，由调用者完整下载
+    async fn fetch_delta(&self, url: &Url, cache_path: &Path, md5_path: &Path) -> Result<bool> {
+        let name = self.bin.bin().name();
+        // github api的asset url以fragment表示文件名，没有对应的zsync url
+        if url.fragment().is_some() {
+            return Ok(false);
+        }
+        let seed_path = match self.mapper.select_current_by_name(name).await? {
+            Some(info) => self.cache_path(&info.url().parse()?)?,
+            None => return Ok(false),
+        };
+        if seed_path == cache_path || afs::metadata(&seed_path).await.is_err() {
+            debug!(
+                "not found the cached previous asset {} of {} for the delta",
+                seed_path.display(),
+                name
+            );
+            return Ok(false);
+        }
+
+        let read_timeout = *self.bin.bin().read_timeout();
+        let get = |url: Url, range: Option<Range<u64>>| async move {
+            let mut req = self.request(&url)?;
+            if let Some(r) = &range {
+                req = req.header(RANGE, format!("bytes={}-{}", r.start, r.end - 1));
+            }
+            let resp = tokio::time::timeout(read_timeout, self.send(req, &url))
+                .await
+                .map_err(|_| anyhow!("no response in {:?} for {}", read_timeout, url))??;
+            if resp.status() == StatusCode::NOT_FOUND {
+                return Ok(None);
+            }
+            let resp = resp.error_for_status()?;
+            if range.is_some() && resp.status() != StatusCode::PARTIAL_CONTENT {
+                bail!("range requests are not supported by {}", resp.url());
+            }
+            // `Content-Range: bytes 0-9/100`中的文件长度
+            let total = resp
+                .headers()
+                .get(CONTENT_RANGE)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.rsplit('/').next())
+                .and_then(|total| total.parse::<u64>().ok());
+            Ok(Some((resp, total)))
+        };
+
+        let mut zsync_url = url.clone();
+        zsync_url.set_path(&format!("{}.zsync", url.path()));
+        let control = match get(zsync_url.clone(), None).await? {
+            Some((resp, _)) => {
+                let data = tokio::time::timeout(read_timeout, resp.bytes())
+                    .await
+                    .map_err(|_| {
+                        anyhow!("no data in {:?} downloading {}", read_timeout, zsync_url)
+                    })??;
+                ControlFile::parse(&data)
+                    .with_context(|| format!("invalid zsync file {}", zsync_url))?
+            }
+            None => {
+                debug!("not found the zsync file {}", zsync_url);
+                return Ok(false);
+            }
+        };
+        let seed = std::fs::File::open(&seed_path)?;
+        let (control, found) = tokio::task::spawn_blocking(move || -> Result<_> {
+            let found = control.find_blocks(seed)?;
+            Ok((control, found))
+        })
+        .await??;
+
+        let ranges = control.missing_ranges(&found);
+        let total = ranges.iter().map(|r| r.end - r.start).sum::<u64>();
+        info!(
+            "reusing {} of {} bytes from {} for {}",
+            control.length() - total,
+            control.length(),
+            seed_path.display(),
+            url
+        );
+        // 在cache path中组装，失败时移除不完整的文件
+        let assembled = async {
+            let (seed_path, out_path) = (seed_path.clone(), cache_path.to_owned());
+            let control = tokio::task::spawn_blocking(move || -> Result<_> {
+                let out = std::fs::File::create(out_path)?;
+                out.set_len(control.length())?;
+                control.copy_found(std::fs::File::open(seed_path)?, &found, &out)?;
+                Ok(control)
+            })
+            .await??;
+
+            let mut file = afs::OpenOptions::new().write(true).open(cache_path).await?;
+            let mut downloaded = 0;
+            for range in ranges {
+                self.check_cancelled()?;
+                let (resp, len) = get(url.clone(), Some(range.clone()))
+                    .await?
+                    .ok_or_else(|| anyhow!("not found {}", url))?;
+                if let Some(len) = len.filter(|len| *len != control.length()) {
+                    bail!(
+                        "the length {} of {} is inconsistent with {} in the zsync file",
+                        len,
+                        url,
+                        control.length()
+                    );
+                }
+                file.seek(SeekFrom::Start(range.start)).await?;
+                let (expected, mut received) = (range.end - range.start, 0);
+                let mut stream = resp.bytes_stream();
+                loop {
+                    let chunk = tokio::select! {
+                        chunk = tokio::time::timeout(read_timeout, stream.next()) => chunk
+                            .map_err(|_| anyhow!("no data in {:?} downloading {}", read_timeout, url))?,
+                        _ = self.cancel.cancelled() => return Err(Cancelled.into()),
+                    };
+                    let chunk = match chunk {
+                        Some(chunk) => chunk?,
+                        None => break,
+                    };
+                    received += chunk.len() as u64;
+                    if received > expected {
+                        bail!("got more than {} bytes for the range {:?} of {}", expected, range, url);
+                    }
+                    file.write_all(&chunk).await?;
+                    self.downloaded
+                        .fetch_add(chunk.len() as u64, Ordering::Relaxed);
+                    downloaded += chunk.len() as u64;
+                    self.observer
+                        .on_download_progress(name, downloaded, Some(total));
+                }
+                if received != expected {
+                    bail!("got {} bytes for the range {:?} of {}", received, range, url);
+                }
+            }
+            file.flush().await?;
+            drop(file);
+
+            let file = std::fs::File::open(cache_path)?;
+            tokio::task::spawn_blocking(move || control.verify(std::io::BufReader::new(file)))
+                .await?
+        }
+        .await;
+        if let Err(e) = assembled {
+            debug!("removing the partial delta {}", cache_path.display());
+            if let Err(e) = remove_file(cache_path).await {
+                warn!("failed to remove {}: {}", cache_path.display(), e);
+            }
+            return Err(e);
+        }
+
+        let digest = md5_of(cache_path.to_owned()).await?;
+        afs::write(md5_path, digest).await?;
+        Ok(true)
+    }
+
+    /// 使用[Self::mirrors]中第一个匹配的前缀替换url，没有匹配时为none
+    fn mirror_url(&self, url: &Url) -> Result<Option<Url>> {
+        self.mirrors
+            .iter()
+            .find(|(prefix, _)| url.as_str().starts_with(prefix.as_str()))
+            .map(|(prefix, mirror)| {
+                let mirrored = format!("{}{}", mirror, &url.as_str()[prefix.len()..]);
+                mirrored
+                    .parse()
+                    .with_context(|| format!("invalid mirror url {}", mirrored))
+            })
+            .transpose()
+    }
+
+    /// url下载到cache dir中的文件路径
+    ///
+    /// 如github artifact `.../artifacts/{id}/zip`这样以格式结尾的url使用`{id}.zip`，
+    /// 有fragment的github api asset url使用fragment中的文件名
+    fn cache_path(&self, url: &Url) -> Result<PathBuf> {
+        if let Some(name) = url.fragment().and_then(|f| Path::new(f).file_name()) {
+            return Ok(self.cache_dir.join(name));
+        }
+        let segs = url
+            .path_segments()
+            .map(|seg| seg.collect::<Vec<_>>())
+            .unwrap_or_default();
+        let filename = match segs[..] {
+            [.., id, "zip"] if !id.is_empty() => format!("{}.zip", id),
+            [.., filename] if !filename.is_empty() => filename.to_owned(),
+            _ => bail!("not found filename for {}", url),
+        };
+        Ok(self.cache_dir.join(filename))
+    }
+}
+
+/// 观察[BinaryPackage]安装，更新等操作进度的回调，默认不做任何事
+///
+/// 回调在操作的task中同步调用，不应阻塞
+pub trait InstallObserver: std::fmt::Debug + Send + Sync {
+    /// 解析到将要安装的版本ver与url
+    fn on_resolve(&self, _name: &str, _ver: &str, _url: &Url) {}
+
+    /// 已下载downloaded字节，total为响应的content length
+    fn on_download_progress(&self, _name: &str, _downloaded: u64, _total: Option<u64>) {}
+
+    /// 开始解压from到to
+    fn on_extract(&self, _name: &str, _from: &Path, _to: &Path) {}
+
+    /// 链接了src到exe dir中的dst
+    fn on_link(&self, _name: &str, _src: &Path, _dst: &Path) {}
+
+    /// 操作action如`install`成功完成
+    fn on_done(&self, _name: &str, _action: &str, _ver: &str) {}
+
+    /// 操作action失败
+    fn on_error(&self, _name: &str, _action: &str, _err: &Error) {}
+}
+
+/// 操作被[CancellationToken]取消，可通过[anyhow::Error::downcast_ref]识别
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// 正在安装的版本，用于渲染模板
+#[derive(Debug, Clone)]
+struct Release {
+    version: String,
+    /// the git tag if the source has one
+    tag: Option<String>,
+    url: Url,
+}
+
+/// 不做任何事的[InstallObserver]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopObserver;
+
+impl InstallObserver for NoopObserver {}
+
+/// data dir中指向当前使用版本目录的链接名
+pub static CURRENT_LINK_NAME: &str = "current";
+
+/// 每个bin默认在db中保留的历史版本数
+pub static DEFAULT_HISTORY_LIMIT: usize = 10;
+
+/// 版本目录中记录[Provenance]的文件，不属于安装的文件
+pub static PROVENANCE_NAME: &str = ".binaries-provenance.json";
+
+/// 安装的版本的来源与下载的asset的digest
+#[derive(Debug, Clone, Serialize, Deserialize, Getters)]
+#[serde(rename_all = "kebab-case")]
+#[getset(get = "pub")]
+pub struct Provenance {
+    name: String,
+    version: String,
+    /// the source like `github:owner/repo`
+    source: String,
+    /// the repo url of github sources
+    repo: Option<String>,
+    asset_url: String,
+    /// the digest of the downloaded asset like `sha256:...`
+    digest: String,
+    /// the digest published by the source if any
+    source_digest: Option<String>,
+    installed_time: DateTime<Local>,
+    /// the tool and its version that installed it
+    installer: String,
+}
+
+/// 一次下载最多跟随的重定向数
+pub const MAX_REDIRECTS: usize = 10;
+
+/// 只跟随同源的重定向，跨域时停止并返回重定向的响应，由[BinaryPackage]在下载时
+/// 不带认证地跟随。api host上的重定向保留token
+pub fn redirect_policy() -> Policy {
+    Policy::custom(|attempt| {
+        let cross_origin = attempt
+            .previous()
+            .last()
+            .is_some_and(|prev| prev.origin() != attempt.url().origin());
+        if attempt.previous().len() > MAX_REDIRECTS {
+            let msg = format!("too many redirects to {}", attempt.url());
+            attempt.error(msg)
+        } else if cross_origin {
+            attempt.stop()
+        } else {
+            attempt.follow()
+        }
+    })
+}
+
+/// [BinaryPackage::verify]发现的与安装时不一致的文件，路径相对版本目录
+#[derive(Debug, Default, Clone, Getters, Serialize)]
+#[getset(get = "pub")]
+pub struct Drift {
+    name: String,
+    version: String,
+    modified: Vec<String>,
+    missing: Vec<String>,
+    extra: Vec<String>,
+}
+
+impl Drift {
+    pub fn is_empty(&self) -> bool {
+        self.modified.is_empty() && self.missing.is_empty() && self.extra.is_empty()
+    }
+}
+
+/// [BinaryPackage::deploy]替换前的版本目录，current与链接，用于失败时恢复
+#[derive(Debug, Default)]
+struct Backup {
+    /// the version dir replaced by the staging dir
+    staged: Option<PathBuf>,
+    /// the moved old version dir
+    version_dir: Option<PathBuf>,
+    /// whether the current link is switched
+    switched: bool,
+    /// the old target of the current link
+    old_current: Option<PathBuf>,
+    /// the moved old links and where they are moved to
+    links: Vec<(PathBuf, PathBuf)>,
+    /// the created links
+    linked: Vec<PathBuf>,
+    /// the adopted files and their backups
+    adopted: Vec<(PathBuf, PathBuf)>,
+    /// the existing files taken over by links recorded in commit
+    conflicts: Vec<LinkConflict>,
+}
+
+/// path同级目录中以suffix结尾的隐藏路径，如`a/b`为`a/.b.staging`
+fn hidden_sibling(path: &Path, suffix: &str) -> Result<PathBuf> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("no filename for {}", path.display()))?;
+    let parent = path
+        .parent()
+        .ok_or_else(|| anyhow!("no parent for {}", path.display()))?;
+    Ok(parent.join(format!(".{}.{}", name.to_string_lossy(), suffix)))
+}
+
+async fn md5_of(path: PathBuf) -> Result<String> {
+    tokio::task::spawn_blocking(move || md5_file(path)).await?
+}
+
+/// 使用平台默认的方式链接可执行文件src到dst
+///
+/// windows上创建symlink需要管理员权限，优先使用硬链接，失败时复制文件
+async fn link_exe(src: &Path, dst: &Path) -> Result<()> {
+    if cfg!(windows) {
+        if let Err(e) = hard_link(src, dst).await {
+            debug!(
+                "failed to hard link {} to {}: {}. try to copy",
+                src.display(),
+                dst.display(),
+                e
+            );
+            afs::copy(src, dst).await?;
+        }
+        Ok(())
+    } else {
+        symlink(src, dst).await
+    }
+}
+
+#[cfg(unix)]
+async fn symlink(src: &Path, dst: &Path) -> Result<()> {
+    info!("sym linking {} to {}", src.display(), dst.display());
+    afs::symlink(src, dst).await.map_err(Into::into)
+}
+
+#[cfg(windows)]
+async fn symlink(src: &Path, dst: &Path) -> Result<()> {
+    info!("sym linking {} to {}", src.display(), dst.display());
+    afs::symlink_file(src, dst).await.map_err(Into::into)
+}
+
+#[cfg(unix)]
+async fn symlink_dir(src: &Path, dst: &Path) -> Result<()> {
+    afs::symlink(src, dst).await.map_err(Into::into)
+}
+
+#[cfg(windows)]
+async fn symlink_dir(src: &Path, dst: &Path) -> Result<()> {
+    afs::symlink_dir(src, dst).await.map_err(Into::into)
+}
+
+/// 移除一个指向目录的链接，windows上目录链接需要使用remove dir
+async fn remove_dir_link(path: &Path) -> Result<()> {
+    if cfg!(windows) {
+        afs::remove_dir(path).await?;
+    } else {
+        afs::remove_file(path).await?;
+    }
+    Ok(())
+}
+
+/// 写入内容content到path并设置为可执行
+/// macos上下载的文件带有的属性，Gatekeeper会拦截执行
+/// 使用包管理器查询path所属的provider，都不是时为`path`
+async fn system_provider(path: &Path) -> String {
+    let real = afs::canonicalize(path)
+        .await
+        .unwrap_or_else(|_| path.to_path_buf());
+    if real.components().any(|c| c.as_os_str() == "Cellar") {
+        return "brew".to_owned();
     }
+    for (provider, program, arg) in [
+        ("apt", "dpkg", "-S"),
+        ("pacman", "pacman", "-Qo"),
+        ("rpm", "rpm", "-qf"),
+    ] {
+        let owned = Command::new(program)
+            .arg(arg)
+            .arg(&real)
+            .output()
+            .await
+            .is_ok_and(|out| out.status.success());
+        if owned {
+            return provider.to_owned();
+        }
+    }
+    "path".to_owned()
+}
+
+/// 没有匹配的asset时使用fallback命令构建的url scheme
+const FALLBACK_SCHEME: &str = "fallback";
+
+/// 记录构建的fallback命令的文件名，位于构建的目录中
+const BUILT_MARK_NAME: &str = ".binaries-built";
+
+const QUARANTINE_XATTR: &str = "com.apple.quarantine";
+
+/// windows上shim的扩展名。cmd可以在任意shell中执行，powershell中优先使用不经过cmd的`.ps1`
+const WINDOWS_SHIM_EXTS: &[&str] = &[".cmd", ".ps1"];
+
+/// `.ps1` shim的模板，单引号中的路径不展开变量
+const PS1_SHIM_TEMPLATE: &str = "& '{{{exe_path}}}' @args\r\nexit $LASTEXITCODE\r\n";
+
+async fn write_executable(path: &Path, content: &str) -> Result<()> {
+    afs::write(path, content).await?;
+    #[cfg(unix)]
+    crate::util::set_executable(path)?;
+    Ok(())
+}
+
+async fn hard_link(src: &Path, dst: &Path) -> Result<()> {
+    info!("hard linking {} to {}", src.display(), dst.display());
+    afs::hard_link(src, dst).await.map_err(Into::into)
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{
-        env, fs::Permissions, iter::once, os::unix::prelude::PermissionsExt, thread, time::Duration,
-    };
+    use std::{env, iter::once, thread, time::Duration};
 
     use futures_util::TryStreamExt;
     use once_cell::sync::Lazy;
@@ -518,7 +3135,9 @@ mod tests {
         runtime::Runtime,
     };
 
-    use crate::config::{Binary, BinaryBuilder, HookActionBuilder};
+    use crate::config::{
+        lock::LockedBinaryBuilder, Binary, BinaryBuilder, Hook, HookActionBuilder,
+    };
 
     use super::*;
 
@@ -533,9 +3152,11 @@ mod tests {
         thread::spawn(|| {
             let pool = TOKIO_RT
                 .block_on(async {
+                    // 每个连接的`sqlite::memory:`都是独立的db，使用文件使所有连接共享schema
+                    let url = format!("sqlite://{}?mode=rwc", TEMP.path().join("db").display());
                     let pool = SqlitePoolOptions::new()
                         .max_connections(4)
-                        .connect("sqlite::memory:")
+                        .connect(&url)
                         .await?;
                     let sql =
                         read_to_string("schema.sql").await? + &read_to_string("data.sql").await?;
@@ -593,11 +3214,6 @@ mod tests {
     fn create_pkg(bin: Binary) -> Result<BinaryPackage> {
         let client = BIN_CLIENT.clone();
 
-        let new_path = env::join_paths(
-            env::split_paths(&env::var("PATH").unwrap()).chain(once(EXE_DIR.clone())),
-        )?;
-        env::set_var("PATH", &new_path);
-
         let f = || {
             let data_dir = DATA_DIR.to_owned();
             let exe_dir = EXE_DIR.to_owned();
@@ -607,7 +3223,7 @@ mod tests {
                 BinaryPackageBuilder::default()
                     .bin(bin)
                     .data_dir(data_dir)
-                    .link_path(exe_dir)
+                    .exe_dir(exe_dir)
                     .cache_dir(cache_dir)
                     .client(client)
                     .mapper(mapper)
@@ -619,13 +3235,390 @@ mod tests {
         Ok(bin_pkg)
     }
 
+    /// 固定版本`1.0.0`的source
+    #[derive(Debug)]
+    struct Fixed(Binary);
+
+    #[async_trait]
+    impl Visible for Fixed {
+        async fn latest_ver(&self) -> Result<String> {
+            Ok("1.0.0".to_owned())
+        }
+
+        async fn get_url(&self, ver: &str) -> Result<Url> {
+            format!("https://a.b/{}/{}", self.0.source(), ver)
+                .parse()
+                .map_err(Into::into)
+        }
+
+        fn bin(&self) -> &Binary {
+            &self.0
+        }
+    }
+
+    #[tokio::test]
+    async fn test_observer() -> Result<()> {
+        #[derive(Debug, Default)]
+        struct Recorder(parking_lot::Mutex<Vec<String>>);
+
+        impl InstallObserver for Recorder {
+            fn on_resolve(&self, name: &str, ver: &str, url: &Url) {
+                self.0
+                    .lock()
+                    .push(format!("resolve {} {} {}", name, ver, url));
+            }
+
+            fn on_done(&self, name: &str, action: &str, ver: &str) {
+                self.0.lock().push(format!("{} {} {}", action, name, ver));
+            }
+        }
+
+        let bin = BinaryBuilder::default()
+            .name("observed")
+            .source("fixed:a")?
+            .build()?;
+        let mut sources = SourceRegistry::default();
+        sources.register("fixed", |ctx| {
+            Ok(Box::new(Fixed(ctx.bin)) as Box<dyn Visible>)
+        });
+        let recorder = Arc::new(Recorder::default());
+        let pkg = BinaryPackageBuilder::default()
+            .data_dir(DATA_DIR.to_owned())
+            .exe_dir(EXE_DIR.to_owned())
+            .cache_dir(CACHE_DIR.to_owned())
+            .client(BIN_CLIENT.clone())
+            .mapper(MAPPER.clone())
+            .dry_run(true)
+            .sources(sources)
+            .observer(recorder.clone())
+            .bin(bin)
+            .build()
+            .await?;
+        pkg.install().await?;
+        assert_eq!(
+            *recorder.0.lock(),
+            [
+                "resolve observed 1.0.0 https://a.b/fixed:a/1.0.0",
+                "install observed 1.0.0"
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cancel() -> Result<()> {
+        let bin = BinaryBuilder::default()
+            .name("cancelled")
+            .source("fixed:a")?
+            .build()?;
+        let mut sources = SourceRegistry::default();
+        sources.register("fixed", |ctx| {
+            Ok(Box::new(Fixed(ctx.bin)) as Box<dyn Visible>)
+        });
+        let cancel = CancellationToken::new();
+        let pkg = BinaryPackageBuilder::default()
+            .data_dir(DATA_DIR.to_owned())
+            .exe_dir(EXE_DIR.to_owned())
+            .cache_dir(CACHE_DIR.to_owned())
+            .client(BIN_CLIENT.clone())
+            .mapper(MAPPER.clone())
+            .sources(sources)
+            .bin(bin)
+            .build()
+            .await?
+            .with_cancel(cancel.clone());
+        cancel.cancel();
+        let e = pkg.install().await.unwrap_err();
+        assert_eq!(e.downcast_ref::<Cancelled>(), Some(&Cancelled));
+        assert!(afs::metadata(pkg.version_dir("1.0.0")).await.is_err());
+        assert!(pkg
+            .mapper()
+            .select_current_by_name("cancelled")
+            .await?
+            .is_none());
+        afs::remove_dir_all(pkg.data_dir()).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fallback() -> Result<()> {
+        /// 没有任何asset的source
+        #[derive(Debug)]
+        struct NoAsset(Binary);
+
+        #[async_trait]
+        impl Visible for NoAsset {
+            async fn latest_ver(&self) -> Result<String> {
+                Ok("1.0.0".to_owned())
+            }
+
+            async fn get_url(&self, _ver: &str) -> Result<Url> {
+                Err(NoMatchedAsset("for test".to_owned()).into())
+            }
+
+            fn bin(&self) -> &Binary {
+                &self.0
+            }
+        }
+
+        let bin = BinaryBuilder::default()
+            .name("fallback")
+            .source("noasset:a")?
+            .fallback(r#"sh -c 'mkdir -p $GOBIN && echo {{version}} > $GOBIN/{{name}}'"#)
+            .build()?;
+        let mut sources = SourceRegistry::default();
+        sources.register("noasset", |ctx| {
+            Ok(Box::new(NoAsset(ctx.bin)) as Box<dyn Visible>)
+        });
+        let pkg = BinaryPackageBuilder::default()
+            .data_dir(DATA_DIR.to_owned())
+            .exe_dir(EXE_DIR.to_owned())
+            .cache_dir(CACHE_DIR.to_owned())
+            .client(BIN_CLIENT.clone())
+            .mapper(MAPPER.clone())
+            .sources(sources)
+            .bin(bin)
+            .build()
+            .await?;
+        assert_eq!(pkg.install().await?, "1.0.0");
+        let link = &pkg.link_paths()[0];
+        assert_eq!(afs::read_to_string(link).await?.trim(), "1.0.0");
+        let info = pkg.mapper().select_current_by_name("fallback").await?;
+        assert_eq!(
+            info.map(|info| info.url().to_owned()).as_deref(),
+            Some("fallback:fallback")
+        );
+
+        pkg.uninstall().await?;
+        assert!(afs::symlink_metadata(link).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_has_installed() -> Result<()> {
+        let bin = BinaryBuilder::default()
+            .name("has_installed")
+            .source("github:a/has_installed")?
+            .build()?;
+        let pkg = BinaryPackageBuilder::default()
+            .data_dir(DATA_DIR.to_owned())
+            .exe_dir(EXE_DIR.to_owned())
+            .cache_dir(CACHE_DIR.to_owned())
+            .client(BIN_CLIENT.clone())
+            .mapper(MAPPER.clone())
+            .bin(bin)
+            .build()
+            .await?;
+        assert!(!pkg.has_installed().await?);
+
+        let url = "https://a.b/has_installed".parse::<Url>()?;
+        pkg.record("1.0.0", &url).await?;
+        // a same named executable in PATH is not the managed link
+        assert!(!pkg.has_installed().await?);
+
+        let link = &pkg.link_paths()[0];
+        afs::create_dir_all(pkg.exe_dir()).await?;
+        afs::write(link, "").await?;
+        assert!(pkg.has_installed().await?);
+
+        afs::remove_file(link).await?;
+        pkg.mapper().delete_by_name("has_installed").await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_newer_version() -> Result<()> {
+        #[derive(Debug)]
+        struct Latest(Binary);
+
+        #[async_trait]
+        impl Visible for Latest {
+            async fn latest_ver(&self) -> Result<String> {
+                Ok("v0.10.0".to_owned())
+            }
+
+            async fn get_url(&self, ver: &str) -> Result<Url> {
+                format!("https://a.b/{}", ver).parse().map_err(Into::into)
+            }
+
+            fn bin(&self) -> &Binary {
+                &self.0
+            }
+        }
+
+        let bin = BinaryBuilder::default()
+            .name("newer_version")
+            .source("latest:a")?
+            .build()?;
+        let mut sources = SourceRegistry::default();
+        sources.register("latest", |ctx| {
+            Ok(Box::new(Latest(ctx.bin)) as Box<dyn Visible>)
+        });
+        let pkg = BinaryPackageBuilder::default()
+            .data_dir(DATA_DIR.to_owned())
+            .exe_dir(EXE_DIR.to_owned())
+            .cache_dir(CACHE_DIR.to_owned())
+            .client(BIN_CLIENT.clone())
+            .mapper(MAPPER.clone())
+            .sources(sources)
+            .bin(bin)
+            .build()
+            .await?;
+        let link = &pkg.link_paths()[0];
+        afs::create_dir_all(pkg.exe_dir()).await?;
+        afs::write(link, "").await?;
+
+        let url = "https://a.b/newer_version".parse::<Url>()?;
+        pkg.record("v0.9.0", &url).await?;
+        // v0.10.0 is newer than v0.9.0 though it is less as a string
+        assert_eq!(
+            pkg.newer_version().await?,
+            Some(("v0.9.0".to_owned(), "v0.10.0".to_owned()))
+        );
+        pkg.record("v0.10.0", &url).await?;
+        assert_eq!(pkg.newer_version().await?, None);
+
+        afs::remove_file(link).await?;
+        pkg.mapper().delete_by_name("newer_version").await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_take_over() -> Result<()> {
+        let bin = BinaryBuilder::default()
+            .name("take_over")
+            .source("github:a/take_over")?
+            .build()?;
+        let pkg = BinaryPackageBuilder::default()
+            .data_dir(DATA_DIR.to_owned())
+            .exe_dir(EXE_DIR.to_owned())
+            .cache_dir(CACHE_DIR.to_owned())
+            .client(BIN_CLIENT.clone())
+            .mapper(MAPPER.clone())
+            .bin(bin)
+            .build()
+            .await?;
+        let dst = pkg.link_paths()[0].clone();
+        afs::create_dir_all(pkg.exe_dir()).await?;
+
+        // unmanaged file
+        afs::write(&dst, "foreign").await?;
+        assert!(pkg.take_over(&dst, &mut Backup::default()).await.is_err());
+        let pkg = pkg.with_adopt(true);
+        let mut backup = Backup::default();
+        pkg.take_over(&dst, &mut backup).await?;
+        assert!(afs::symlink_metadata(&dst).await.is_err());
+        let adopted = hidden_sibling(&dst, "adopted")?;
+        assert_eq!(afs::read_to_string(&adopted).await?, "foreign");
+        assert_eq!(backup.conflicts[0].action(), "adopt");
+        pkg.revert(backup).await;
+        assert_eq!(afs::read_to_string(&dst).await?, "foreign");
+        afs::remove_file(&dst).await?;
+
+        // the dangling link into data dir
+        let target = DATA_DIR.join("take_over").join("a");
+        symlink(&target, &dst).await?;
+        let mut backup = Backup::default();
+        pkg.take_over(&dst, &mut backup).await?;
+        assert!(afs::symlink_metadata(&dst).await.is_err());
+        assert!(backup.conflicts.is_empty());
+        pkg.revert(backup).await;
+
+        // the link into data dir
+        afs::create_dir_all(target.parent().unwrap()).await?;
+        afs::write(&target, "").await?;
+        assert!(pkg.take_over(&dst, &mut Backup::default()).await.is_err());
+        let pkg = pkg.with_force(true);
+        let mut backup = Backup::default();
+        pkg.take_over(&dst, &mut backup).await?;
+        assert!(afs::symlink_metadata(&dst).await.is_err());
+        assert_eq!(backup.conflicts[0].action(), "replace");
+        pkg.commit(backup).await;
+        assert!(afs::symlink_metadata(hidden_sibling(&dst, "backup")?)
+            .await
+            .is_err());
+        assert_eq!(
+            pkg.mapper()
+                .select_link_conflicts_by_name("take_over")
+                .await?
+                .len(),
+            1
+        );
+        pkg.mapper()
+            .delete_link_conflicts_by_name("take_over")
+            .await?;
+        afs::remove_dir_all(target.parent().unwrap()).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_remove_artifacts() -> Result<()> {
+        let bin = BinaryBuilder::default()
+            .name("artifacts")
+            .source("github:a/artifacts")?
+            .build()?;
+        let pkg = BinaryPackageBuilder::default()
+            .data_dir(DATA_DIR.to_owned())
+            .exe_dir(EXE_DIR.to_owned())
+            .cache_dir(CACHE_DIR.to_owned())
+            .client(BIN_CLIENT.clone())
+            .mapper(MAPPER.clone())
+            .bin(bin)
+            .build()
+            .await?;
+        let completion = TEMP.path().join("completions").join("_artifacts");
+        afs::create_dir_all(completion.parent().unwrap()).await?;
+        afs::write(&completion, "").await?;
+        pkg.mapper()
+            .insert_artifacts("artifacts", &[completion.to_string_lossy().into_owned()])
+            .await?;
+
+        pkg.remove_installed().await;
+        assert!(afs::metadata(&completion).await.is_err());
+        assert!(pkg
+            .mapper()
+            .select_artifacts_by_name("artifacts")
+            .await?
+            .is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_custom_source() -> Result<()> {
+        let bin = BinaryBuilder::default()
+            .name("fixed")
+            .source("fixed:a")?
+            .build()?;
+        let mut builder = BinaryPackageBuilder::default();
+        builder
+            .data_dir(DATA_DIR.to_owned())
+            .exe_dir(EXE_DIR.to_owned())
+            .cache_dir(CACHE_DIR.to_owned())
+            .client(BIN_CLIENT.clone())
+            .mapper(MAPPER.clone())
+            .dry_run(true);
+        assert!(builder.clone().bin(bin.clone()).build().await.is_err());
+
+        let mut sources = SourceRegistry::default();
+        sources.register("fixed", |ctx| {
+            Ok(Box::new(Fixed(ctx.bin)) as Box<dyn Visible>)
+        });
+        let pkg = builder.sources(sources).bin(bin).build().await?;
+        assert_eq!(pkg.bin().latest_ver().await?, "1.0.0");
+        assert_eq!(
+            pkg.bin().get_url("1.0.0").await?.as_str(),
+            "https://a.b/fixed:a/1.0.0"
+        );
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_install() -> Result<()> {
         let test_fn = |config| async move {
             let ver = "v12.1.2";
-            // clear env path
-            env::set_var("PATH", &env::join_paths(once(EXE_DIR.clone()))?);
             let pkg = create_pkg(config)?;
+            // only find in the exe dir
+            let which = |name| which::which_in(name, Some(&*EXE_DIR), TEMP.path());
 
             assert!(which(pkg.bin.bin().name()).is_err());
 
@@ -738,12 +3731,384 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_cache_path() -> Result<()> {
+        for (url, filename) in [
+            (
+                "https://github.com/a/b/releases/download/v1.0/b-linux.tar.gz",
+                "b-linux.tar.gz",
+            ),
+            (
+                "https://api.github.com/repos/a/b/actions/artifacts/12/zip",
+                "12.zip",
+            ),
+            (
+                "https://api.github.com/repos/a/b/releases/assets/34#b-linux.tar.gz",
+                "b-linux.tar.gz",
+            ),
+        ] {
+            assert_eq!(
+                PKG.cache_path(&url.parse()?)?,
+                PKG.cache_dir().join(filename)
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_mirror_url() -> Result<()> {
+        let mut pkg = PKG.clone();
+        pkg.mirrors = [
+            ("https://github.com/a/", "https://m1.com/"),
+            ("https://github.com/", "https://m2.com/https://github.com/"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_owned(), v.to_owned()))
+        .collect();
+        for (url, mirrored) in [
+            (
+                "https://github.com/a/b/c.zip",
+                Some("https://m1.com/b/c.zip"),
+            ),
+            (
+                "https://github.com/c/d/e.zip",
+                Some("https://m2.com/https://github.com/c/d/e.zip"),
+            ),
+            ("https://api.github.com/repos/a/b", None),
+        ] {
+            assert_eq!(
+                pkg.mirror_url(&url.parse()?)?.as_ref().map(Url::as_str),
+                mirrored
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_request() -> Result<()> {
+        // cargo sets `CARGO_PKG_NAME=binaries` for tests
+        let bin = BinaryBuilder::default()
+            .source("github:a/b")?
+            .headers(
+                [("private-token", "${CARGO_PKG_NAME}")]
+                    .into_iter()
+                    .map(|(k, v)| (k.to_owned(), v.to_owned()))
+                    .collect::<IndexMap<_, _>>(),
+            )
+            .basic_auth("me:${CARGO_PKG_NAME}")
+            .build()?;
+        let pkg = create_pkg(bin)?;
+        let req = pkg.request(&"https://a.com/b.zip".parse()?)?.build()?;
+        assert_eq!(req.headers()["private-token"], "binaries");
+        // base64 of `me:binaries`
+        assert_eq!(req.headers()["authorization"], "Basic bWU6YmluYXJpZXM=");
+
+        let req = PKG.request(&"https://a.com/b.zip".parse()?)?.build()?;
+        assert!(req.headers().get("authorization").is_none());
+        Ok(())
+    }
+
+    /// 在本地端口上响应http请求：`/redirect`重定向到`location`，其它path返回请求的authorization
+    async fn serve_redirect(location: Option<String>) -> Result<String> {
+        use tokio::{
+            io::{AsyncReadExt, AsyncWriteExt},
+            net::TcpListener,
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = format!("http://{}", listener.local_addr()?);
+        let base = addr.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = vec![0; 4096];
+                let n = stream.read(&mut buf).await.unwrap_or(0);
+                let req = String::from_utf8_lossy(&buf[..n]).into_owned();
+                let resp = if req.starts_with("GET /redirect ") {
+                    let loc = location.clone().unwrap_or_else(|| format!("{}/echo", base));
+                    format!(
+                        "HTTP/1.1 302 Found\r\nlocation: {}\r\ncontent-length: 0\r\n\r\n",
+                        loc
+                    )
+                } else {
+                    let auth = req
+                        .lines()
+                        .filter_map(|l| l.split_once(": "))
+                        .find_map(|(k, v)| k.eq_ignore_ascii_case("authorization").then_some(v))
+                        .unwrap_or("none")
+                        .to_owned();
+                    format!(
+                        "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{}",
+                        auth.len(),
+                        auth
+                    )
+                };
+                let _ = stream.write_all(resp.as_bytes()).await;
+            }
+        });
+        Ok(addr)
+    }
+
+    #[tokio::test]
+    async fn test_send_redirect() -> Result<()> {
+        let bin = BinaryBuilder::default()
+            .source("github:a/b")?
+            .basic_auth("me:abc")
+            .build()?;
+        let mut pkg = create_pkg(bin)?;
+        pkg.client = ClientBuilder::new().redirect(redirect_policy()).build()?;
+
+        // 同源的重定向保留auth
+        let url = format!("{}/redirect", serve_redirect(None).await?).parse()?;
+        let resp = pkg.send(pkg.request(&url)?, &url).await?;
+        assert_eq!(resp.text().await?, "Basic bWU6YWJj");
+
+        // 跨域的重定向不带auth
+        let cdn = serve_redirect(None).await?;
+        let url = format!(
+            "{}/redirect",
+            serve_redirect(Some(format!("{}/echo", cdn))).await?
+        )
+        .parse()?;
+        let resp = pkg.send(pkg.request(&url)?, &url).await?;
+        assert_eq!(resp.url().as_str(), format!("{}/echo", cdn));
+        assert_eq!(resp.text().await?, "none");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_request_token() -> Result<()> {
+        let addr = serve_redirect(None).await?;
+        let bin = BinaryBuilder::default()
+            .name("foo")
+            .source(format!("urls:{}={}/echo", env::consts::OS, addr).as_str())?
+            .build()?;
+        let pkg = BinaryPackageBuilder::default()
+            .data_dir(DATA_DIR.to_owned())
+            .exe_dir(EXE_DIR.to_owned())
+            .cache_dir(CACHE_DIR.to_owned())
+            .client(ClientBuilder::new().redirect(redirect_policy()).build()?)
+            .mapper(MAPPER.clone())
+            .token(ApiToken::new("abc")?)
+            .bin(bin)
+            .build()
+            .await?;
+
+        // urls source的host收不到token
+        let url = pkg.bin().get_url("1.0").await?;
+        let resp = pkg.send(pkg.request(&url)?, &url).await?;
+        assert_eq!(resp.text().await?, "none");
+
+        let req = pkg
+            .request(&"https://api.github.com/repos/a/b/releases/assets/1".parse()?)?
+            .build()?;
+        assert_eq!(req.headers()["authorization"], "Bearer abc");
+        let req = pkg
+            .request(&"https://github.com/a/b/releases/download/v1/b.zip".parse()?)?
+            .build()?;
+        assert!(req.headers().get("authorization").is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_install_locked() -> Result<()> {
+        let addr = serve_redirect(None).await?;
+        let bin = BinaryBuilder::default()
+            .name("locked")
+            .source(format!("urls:{}={}/echo", env::consts::OS, addr).as_str())?
+            .build()?;
+        let dir = tempdir()?;
+        let pkg = BinaryPackageBuilder::default()
+            .data_dir(dir.path().join("data"))
+            .exe_dir(dir.path().join("exe"))
+            .cache_dir(dir.path().join("cache"))
+            .client(BIN_CLIENT.clone())
+            .mapper(MAPPER.clone())
+            .bin(bin)
+            .build()
+            .await?;
+
+        // the server responds `none` without the authorization
+        let url = format!("{}/echo", addr);
+        let digest = "sha256:140bedbf9c3f6d56a9846d2ba7088798683f4da0c248231336e6a05679e4fdfe";
+        assert_eq!(pkg.digest(&url.parse()?).await?, digest);
+
+        let locked = LockedBinaryBuilder::default()
+            .version("1.0")
+            .url(url)
+            .digest(format!("sha256:{}", "0".repeat(64)))
+            .build()?;
+        let e = pkg.install_locked(&locked).await.unwrap_err();
+        assert!(e.to_string().contains("inconsistent digest"), "{}", e);
+        assert!(!pkg.has_record().await?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_hidden_sibling() -> Result<()> {
+        assert_eq!(
+            hidden_sibling(Path::new("/a/b/"), "staging")?,
+            Path::new("/a/.b.staging")
+        );
+        assert_eq!(
+            hidden_sibling(Path::new("/a/b"), "backup")?,
+            Path::new("/a/.b.backup")
+        );
+        assert!(hidden_sibling(Path::new("/"), "backup").is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_alias_links() -> Result<()> {
+        let bin = BinaryBuilder::default()
+            .name("bat")
+            .source("github:sharkdp/bat")?
+            .alias(vec!["batcat".to_owned(), "bat2".to_owned()])
+            .build()?;
+        let pkg = create_pkg(bin)?;
+        let names = pkg
+            .link_paths()
+            .iter()
+            .map(|p| p.file_name().unwrap().to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["batcat", "bat2"]);
+
+        assert!(pkg.data_dir().starts_with(&*DATA_DIR));
+
+        let ver = "v0.1.0";
+        create_dir_all(pkg.version_dir(ver)).await?;
+        write(pkg.version_dir(ver).join("bat"), "#!/bin/sh\necho bat").await?;
+        let mut backup = Backup::default();
+        pkg.activate(ver, false, &mut backup).await?;
+        assert_eq!(&backup.linked, pkg.link_paths());
+        for path in pkg.link_paths() {
+            assert!(afs::read_link(path).await?.starts_with(pkg.data_dir()));
+        }
+
+        // a link of a removed alias
+        let stale = EXE_DIR.join("bat_old");
+        symlink(&pkg.current_dir().join("bat"), &stale).await?;
+        pkg.remove_installed().await;
+        for path in pkg.link_paths().iter().chain(once(&stale)) {
+            assert!(afs::symlink_metadata(path).await.is_err());
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_build_in() -> Result<()> {
+        let bin = BinaryBuilder::default()
+            .name("tagged")
+            .source("github-tag:a/tagged")?
+            .hook(
+                HookActionBuilder::default()
+                    .build_hook("sh -c 'echo {{name}} > built'")
+                    .build()?,
+            )
+            .build()?;
+        let pkg = create_pkg(bin)?;
+        assert!(pkg.data_dir().starts_with(&*DATA_DIR));
+
+        // like the tarball of a tag with a top dir
+        let dir = pkg.version_dir("v1.0.0");
+        create_dir_all(dir.join("tagged-1.0.0")).await?;
+        pkg.build_in(&dir).await?;
+        let built = read_to_string(dir.join("tagged-1.0.0/built")).await?;
+        assert_eq!(built.trim(), "tagged");
+
+        write(dir.join("README"), "").await?;
+        pkg.build_in(&dir).await?;
+        assert!(afs::metadata(dir.join("built")).await.is_ok());
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_run_hooks() -> Result<()> {
+        let bin = BinaryBuilder::default()
+            .name("hooked")
+            .source("github:a/hooked")?
+            .hook(
+                HookActionBuilder::default()
+                    .update("sh -c 'echo action >> events'")
+                    .build()?,
+            )
+            .hooks(vec![
+                Hook::new(
+                    "sh -c 'echo {{name}} >> events'",
+                    vec![HookEvent::Install, HookEvent::Update],
+                ),
+                Hook::new("sh -c 'exit 1'", vec![HookEvent::Check]),
+            ])
+            .build()?;
+        let pkg = create_pkg(bin)?;
+        assert!(pkg.data_dir().starts_with(&*DATA_DIR));
+        create_dir_all(pkg.data_dir()).await?;
+
+        pkg.run_hooks(HookEvent::Install).await?;
+        pkg.run_hooks(HookEvent::Update).await?;
+        pkg.run_hooks(HookEvent::Uninstall).await?;
+        let events = read_to_string(pkg.data_dir().join("events")).await?;
+        assert_eq!(
+            events.lines().collect::<Vec<_>>(),
+            ["hooked", "action", "hooked"]
+        );
+
+        assert!(pkg.run_hooks(HookEvent::Check).await.is_err());
+        afs::remove_dir_all(pkg.data_dir()).await?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_template_values() -> Result<()> {
+        let bin = BinaryBuilder::default()
+            .name("tv")
+            .source("github:a/tv")?
+            .build()?;
+        let pkg = create_pkg(bin)?;
+        let val = pkg.template_values(json!({"name": "tv"}))?;
+        assert!(val.get("version").is_none());
+
+        let url = "https://github.com/a/tv/releases/download/v1.2.0/tv-linux.tar.gz".parse()?;
+        pkg.set_release("1.2.0", Some("v1.2.0".to_owned()), &url);
+        let val = pkg.template_values(json!({"name": "tv"}))?;
+        assert_eq!(val["version"], "1.2.0");
+        assert_eq!(val["tag"], "v1.2.0");
+        assert_eq!(val["asset"], "tv-linux.tar.gz");
+        assert_eq!(val["url"], url.as_str());
+        assert_eq!(
+            pkg.templater
+                .render("{{name}}-{{trim_prefix tag \"v\"}}", &val)?,
+            "tv-1.2.0"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_ps1_shim() -> Result<()> {
+        let content = Templater::default().render(
+            PS1_SHIM_TEMPLATE,
+            &json!({"exe_path": r"C:\Users\a b\foo&bar.exe"}),
+        )?;
+        assert_eq!(
+            content,
+            "& 'C:\\Users\\a b\\foo&bar.exe' @args\r\nexit $LASTEXITCODE\r\n"
+        );
+        Ok(())
+    }
+
     #[tokio::test]
+    #[cfg(unix)]
     async fn test_exe_path() -> Result<()> {
-        let bin_name = "bin_exe";
-        assert!(which(bin_name).is_err());
+        use std::{fs::Permissions, os::unix::prelude::PermissionsExt};
 
+        let bin_name = "bin_exe";
         let exe_path = TEMP.path().join("exe");
+        let path = env::var("PATH")?;
+        let mut paths = env::split_paths(&path).collect::<Vec<_>>();
+        assert!(which::which_in(bin_name, Some(env::join_paths(&paths)?), TEMP.path()).is_err());
+
         create_dir_all(&exe_path).await?;
 
         let exe_file = exe_path.join(bin_name);
@@ -754,13 +4119,12 @@ echo 'hello'"#;
 
         afs::set_permissions(&exe_file, Permissions::from_mode(0o770)).await?;
 
-        let path = env::var("PATH")?;
-        let mut paths = env::split_paths(&path).collect::<Vec<_>>();
         paths.push(exe_path);
         let new_path = env::join_paths(paths)?;
-        env::set_var("PATH", &new_path);
-
-        assert_eq!(which(bin_name), Ok(exe_file));
+        assert_eq!(
+            which::which_in(bin_name, Some(new_path), TEMP.path()),
+            Ok(exe_file)
+        );
         Ok(())
     }
 }
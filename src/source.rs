@@ -1,20 +1,418 @@
-use anyhow::Result;
-use async_trait::async_trait;
+use std::{collections::HashMap, fmt, future::Future, path::Path, sync::Arc};
 
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use indexmap::IndexMap;
+use log::trace;
+use parking_lot::Mutex;
+use reqwest::{header::HeaderMap, Client};
+use tokio::sync::OnceCell;
 use url::Url;
 
-use crate::config::Binary;
+use crate::config::{Binary, VersionFrom};
+use crate::util::{find_version, parse_version};
+
+use self::github::{ApiToken, GithubBinaryBuilder};
+use self::script::{ScriptBinaryBuilder, SCRIPT_SOURCE};
+use self::urls::UrlsBinaryBuilder;
 
 pub mod github;
+pub mod script;
+pub mod urls;
 
 #[async_trait]
 pub trait Visible: std::fmt::Debug + Send + Sync {
     async fn latest_ver(&self) -> Result<String>;
 
+    /// 获取不在skipped中的最新版本。默认只能获取latest，被跳过时返回错误
+    async fn latest_ver_skipping(&self, skipped: &[String]) -> Result<String> {
+        let latest = self.latest_ver().await?;
+        if is_skipped(skipped, &latest) {
+            bail!(
+                "the latest version {} is skipped and the older versions are unavailable by the source",
+                latest
+            );
+        }
+        Ok(latest)
+    }
+
     async fn get_url(&self, ver: &str) -> Result<Url>;
 
+    /// 获取版本ver的change log，如果source不支持则为none
+    async fn changelog(&self, _ver: &str) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    /// 获取版本ver的发布时间，如果source不支持则为none
+    async fn release_date(&self, _ver: &str) -> Result<Option<DateTime<Utc>>> {
+        Ok(None)
+    }
+
+    /// 获取版本ver对应的git tag如`v1.2.3`，如果source不支持则为none
+    async fn tag(&self, _ver: &str) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    /// 获取[Self::get_url]返回的url由source提供的digest如`sha256:...`，没有时为none
+    fn digest_of(&self, _url: &Url) -> Option<String> {
+        None
+    }
+
+    /// source是否支持[Self::attestation]
+    fn supports_attestation(&self) -> bool {
+        false
+    }
+
+    /// 获取下载到path的sha256为digest的asset的attestation并验证，返回签名的workflow。
+    /// 没有attestation时为none
+    async fn attestation(&self, _path: &Path, _digest: &str) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    /// 下载[Self::get_url]返回的url需要的额外headers
+    fn download_headers(&self, _url: &Url) -> HeaderMap {
+        HeaderMap::new()
+    }
+
+    /// 获取选择的asset pattern，用于保存到db中在之后的安装与更新时使用
+    fn chosen_pattern(&self) -> Option<String> {
+        None
+    }
+
     fn bin(&self) -> &Binary;
     // async fn get_latest_url(&self) -> Result<Url> {
     //     self.get_url(&self.latest_ver().await?).await
     // }
 }
+
+/// ver是否在skipped中，忽略`v`前缀
+pub fn is_skipped(skipped: &[String], ver: &str) -> bool {
+    fn normalize(s: &str) -> &str {
+        s.trim().trim_start_matches(['v', 'V'])
+    }
+    skipped.iter().any(|s| normalize(s) == normalize(ver))
+}
+
+/// 没有匹配当前平台的asset，可通过[anyhow::Error::downcast_ref]识别
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NoMatchedAsset(pub String);
+
+impl fmt::Display for NoMatchedAsset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "not found asset {}", self.0)
+    }
+}
+
+impl std::error::Error for NoMatchedAsset {}
+
+/// 创建[Visible]时可用的参数
+#[derive(Debug, Clone)]
+pub struct SourceContext {
+    pub bin: Binary,
+    pub client: Client,
+    /// prompt to choose one of multiple candidate assets
+    pub interactive: bool,
+    /// the asset pattern saved in db
+    pub asset_pattern: Option<String>,
+    /// the arch aliases in config
+    pub arch_aliases: IndexMap<String, Vec<String>>,
+    /// the api responses shared by all sources in a run
+    pub api_cache: ApiCache,
+    /// the github token sent only to the github api
+    pub token: Option<ApiToken>,
+}
+
+/// 一次运行中按url缓存的api响应，多个bins使用同一个repo时只请求一次
+///
+/// 同时请求相同url时只有第一个会真正请求，其它的等待其结果。失败的请求不会缓存
+#[derive(Debug, Clone, Default)]
+pub struct ApiCache {
+    entries: Arc<Mutex<HashMap<Url, Arc<OnceCell<serde_json::Value>>>>>,
+}
+
+impl ApiCache {
+    /// 获取url缓存的响应，不存在时使用fetch请求并缓存
+    pub async fn get_or_fetch<F, Fut>(&self, url: &Url, fetch: F) -> Result<serde_json::Value>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<serde_json::Value>>,
+    {
+        let cell = self.entries.lock().entry(url.clone()).or_default().clone();
+        if cell.initialized() {
+            trace!("using the cached response of {}", url);
+        }
+        cell.get_or_try_init(fetch).await.cloned()
+    }
+}
+
+/// 使用[SourceContext]创建一个source
+pub type SourceFactory = Arc<dyn Fn(SourceContext) -> Result<Box<dyn Visible>> + Send + Sync>;
+
+/// source名称到[SourceFactory]的注册表，默认注册了github,script与urls的sources
+///
+/// 第三方的source注册后可在配置中使用`from = "<name>:<value>"`
+#[derive(Clone)]
+pub struct SourceRegistry {
+    factories: HashMap<String, SourceFactory>,
+}
+
+impl Default for SourceRegistry {
+    fn default() -> Self {
+        let mut registry = Self {
+            factories: HashMap::new(),
+        };
+        for name in ["github", "github-tag", "github-actions"] {
+            registry.register(name, |ctx| {
+                let bin = GithubBinaryBuilder::default()
+                    .client(ctx.client)
+                    .interactive(ctx.interactive)
+                    .asset_pattern(ctx.asset_pattern)
+                    .arch_aliases(ctx.arch_aliases)
+                    .api_cache(ctx.api_cache)
+                    .token(ctx.token)
+                    .binary(ctx.bin)
+                    .build()?;
+                Ok(Box::new(bin) as Box<dyn Visible>)
+            });
+        }
+        registry.register(SCRIPT_SOURCE, |ctx| {
+            let bin = ScriptBinaryBuilder::default()
+                .client(ctx.client)
+                .api_cache(ctx.api_cache)
+                .binary(ctx.bin)
+                .build()?;
+            Ok(Box::new(bin) as Box<dyn Visible>)
+        });
+        registry.register("urls", |ctx| {
+            let bin = UrlsBinaryBuilder::default()
+                .client(ctx.client)
+                .arch_aliases(ctx.arch_aliases)
+                .binary(ctx.bin)
+                .build()?;
+            Ok(Box::new(bin) as Box<dyn Visible>)
+        });
+        registry
+    }
+}
+
+impl fmt::Debug for SourceRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.factories.keys()).finish()
+    }
+}
+
+impl SourceRegistry {
+    /// 注册名称为name的source，替换已存在的同名source
+    pub fn register<F>(&mut self, name: impl Into<String>, factory: F) -> &mut Self
+    where
+        F: Fn(SourceContext) -> Result<Box<dyn Visible>> + Send + Sync + 'static,
+    {
+        self.factories.insert(name.into(), Arc::new(factory));
+        self
+    }
+
+    /// 使用bin的[crate::config::Source::name]对应的factory创建source。
+    /// 配置了`version-from`时最新版本从其中获取
+    ///
+    /// # Error
+    ///
+    /// * 如果source未注册
+    pub fn create(&self, ctx: SourceContext) -> Result<Box<dyn Visible>> {
+        let name = ctx.bin.source().name();
+        let factory = self.factories.get(name).ok_or_else(|| {
+            anyhow!(
+                "unsupported source `{}` of {}. it is not registered",
+                name,
+                ctx.bin.name()
+            )
+        })?;
+        let client = ctx.client.clone();
+        let source = factory(ctx)?;
+        Ok(match source.bin().version_from().clone() {
+            Some(from) => Box::new(VersionFromSource {
+                inner: source,
+                client,
+                from,
+            }),
+            None => source,
+        })
+    }
+}
+
+/// 从[VersionFrom]获取最新版本，其它的由inner提供
+#[derive(Debug)]
+struct VersionFromSource {
+    inner: Box<dyn Visible>,
+    client: Client,
+    from: VersionFrom,
+}
+
+#[async_trait]
+impl Visible for VersionFromSource {
+    async fn latest_ver(&self) -> Result<String> {
+        fetch_version_from(&self.client, &self.from).await
+    }
+
+    async fn get_url(&self, ver: &str) -> Result<Url> {
+        self.inner.get_url(ver).await
+    }
+
+    async fn changelog(&self, ver: &str) -> Result<Option<String>> {
+        self.inner.changelog(ver).await
+    }
+
+    async fn release_date(&self, ver: &str) -> Result<Option<DateTime<Utc>>> {
+        self.inner.release_date(ver).await
+    }
+
+    async fn tag(&self, ver: &str) -> Result<Option<String>> {
+        self.inner.tag(ver).await
+    }
+
+    fn digest_of(&self, url: &Url) -> Option<String> {
+        self.inner.digest_of(url)
+    }
+
+    fn supports_attestation(&self) -> bool {
+        self.inner.supports_attestation()
+    }
+
+    async fn attestation(&self, path: &Path, digest: &str) -> Result<Option<String>> {
+        self.inner.attestation(path, digest).await
+    }
+
+    fn download_headers(&self, url: &Url) -> HeaderMap {
+        self.inner.download_headers(url)
+    }
+
+    fn chosen_pattern(&self) -> Option<String> {
+        self.inner.chosen_pattern()
+    }
+
+    fn bin(&self) -> &Binary {
+        self.inner.bin()
+    }
+}
+
+/// 请求from的url并在响应中查找版本
+async fn fetch_version_from(client: &Client, from: &VersionFrom) -> Result<String> {
+    trace!("fetching the version from {}", from.url());
+    let text = client
+        .get(from.url())
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    find_version_from(&text, from)?
+        .ok_or_else(|| anyhow!("not found the version in {}", from.url()))
+}
+
+/// 在响应text中查找版本
+///
+/// 有json pointer时先取json中的值，再使用regex查找。没有regex时pointer的值即为版本
+fn find_version_from(text: &str, from: &VersionFrom) -> Result<Option<String>> {
+    let text = match from.pointer() {
+        Some(pointer) => {
+            let json = serde_json::from_str::<serde_json::Value>(text)?;
+            let val = match json.pointer(pointer) {
+                Some(serde_json::Value::String(s)) => s.trim().to_owned(),
+                Some(serde_json::Value::Number(n)) => n.to_string(),
+                Some(val) => bail!("the value {} at {} is not a version", val, pointer),
+                None => return Ok(None),
+            };
+            if from.regex().is_none() {
+                return Ok(Some(val).filter(|v| !v.is_empty()));
+            }
+            val
+        }
+        None => text.to_owned(),
+    };
+    Ok(match from.regex() {
+        Some(re) => find_version(&text, &regex::Regex::new(re)?),
+        None => parse_version(&text),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use anyhow::bail;
+    use futures_util::future::join_all;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_find_version_from() -> Result<()> {
+        let from = |v: serde_json::Value| serde_json::from_value::<VersionFrom>(v);
+        let html = "<a href=\"foo-1.2.3.tgz\">foo 1.2.3</a> 1.2";
+        let url = "https://a.b/latest";
+        assert_eq!(
+            find_version_from(html, &from(json!({ "url": url }))?)?.as_deref(),
+            Some("1.2.3")
+        );
+        assert_eq!(
+            find_version_from(
+                html,
+                &from(json!({ "url": url, "regex": r"foo (\d+\.\d+)" }))?
+            )?
+            .as_deref(),
+            Some("1.2")
+        );
+
+        let json = r#"{"stable": {"version": "v2.0.1"}, "build": 42}"#;
+        let pointer = |p: &str| from(json!({ "url": url, "pointer": p }));
+        assert_eq!(
+            find_version_from(json, &pointer("/stable/version")?)?.as_deref(),
+            Some("v2.0.1")
+        );
+        assert_eq!(
+            find_version_from(json, &pointer("/build")?)?.as_deref(),
+            Some("42")
+        );
+        assert_eq!(find_version_from(json, &pointer("/none")?)?, None);
+        assert!(find_version_from(json, &pointer("/stable")?).is_err());
+        assert_eq!(
+            find_version_from(
+                json,
+                &from(json!({ "url": url, "pointer": "/stable/version", "regex": r"\d+\.\d+" }))?
+            )?
+            .as_deref(),
+            Some("2.0")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_api_cache() -> Result<()> {
+        let cache = ApiCache::default();
+        let url = "https://a.b/repos/a/b/releases/latest".parse::<Url>()?;
+        let count = AtomicUsize::new(0);
+        let fetch = || async {
+            count.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            Ok(json!({"tag_name": "v1"}))
+        };
+        let vals = join_all((0..3).map(|_| cache.get_or_fetch(&url, fetch))).await;
+        for val in vals {
+            assert_eq!(val?, json!({"tag_name": "v1"}));
+        }
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        // the failed fetch is not cached
+        let other = url.join("v2")?;
+        assert!(cache
+            .get_or_fetch(&other, || async { bail!("failed") })
+            .await
+            .is_err());
+        assert_eq!(
+            cache.get_or_fetch(&other, fetch).await?,
+            json!({"tag_name": "v1"})
+        );
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+}
@@ -1,23 +1,97 @@
-use std::env::consts::OS;
+use std::{
+    collections::HashMap,
+    env::consts::{ARCH, OS},
+    fmt,
+    io::{self, Write},
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use anyhow::{anyhow, bail, Result};
 use async_trait::async_trait;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Local, TimeZone, Utc};
 use derive_builder::Builder;
 use getset::Getters;
+use indexmap::IndexMap;
 use log::{debug, log_enabled, trace, warn};
-use regex::Regex;
-use reqwest::Client;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use regex::{NoExpand, Regex};
+use reqwest::{
+    header::{HeaderMap, HeaderValue, ACCEPT, AUTHORIZATION, LOCATION},
+    Client, RequestBuilder, StatusCode,
+};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::json;
+use tokio::process::Command;
 use url::Url;
+use which::which;
 
 use crate::{
+    checksum::find_digest,
     config::{Binary, Source},
     util::{get_archs, get_target_env, platform_values, Templater},
 };
 
-use super::Visible;
+use super::{is_skipped, ApiCache, NoMatchedAsset, Visible};
+
+/// 距离rate limit重置不超过这个时间时等待后重试，否则直接失败
+const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(60);
+
+/// 按版本前缀查找release时每页的数量
+const RELEASES_PER_PAGE: u32 = 100;
+
+/// 按版本前缀查找release时最多请求的页数
+const MAX_RELEASE_PAGES: u32 = 10;
+
+/// github api的rate limit错误，可通过[anyhow::Error::downcast_ref]识别
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimited {
+    /// the time when the limit resets. none if github did not tell
+    pub reset: Option<DateTime<Utc>>,
+}
+
+impl RateLimited {
+    /// 从响应中识别rate limit：429或剩余次数为0的403，以及secondary rate limit的retry-after
+    pub fn from_response(
+        status: StatusCode,
+        headers: &HeaderMap,
+        now: DateTime<Utc>,
+    ) -> Option<Self> {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.trim().parse::<i64>().ok())
+        };
+        let retry_after = header("retry-after");
+        let limited = status == StatusCode::TOO_MANY_REQUESTS
+            || (status == StatusCode::FORBIDDEN
+                && (header("x-ratelimit-remaining") == Some(0) || retry_after.is_some()));
+        if !limited {
+            return None;
+        }
+        let reset = retry_after
+            .map(|secs| now + chrono::Duration::seconds(secs))
+            .or_else(|| {
+                header("x-ratelimit-reset").and_then(|secs| Utc.timestamp_opt(secs, 0).single())
+            });
+        Some(Self { reset })
+    }
+}
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "github api rate limited")?;
+        if let Some(reset) = self.reset {
+            write!(f, " until {}", reset.with_timezone(&Local).format("%H:%M"))?;
+        }
+        write!(f, ". set `github.token` in config for a higher rate limit")
+    }
+}
+
+impl std::error::Error for RateLimited {}
 
 /// [Rate limiting](https://docs.github.com/en/rest/overview/resources-in-the-rest-api#rate-limiting)
 ///
@@ -35,6 +109,38 @@ pub struct GithubBinary {
 
     #[builder(default)]
     templater: Templater,
+
+    /// the digests of the picked assets by the download url
+    #[builder(setter(skip))]
+    #[getset(skip)]
+    digests: Arc<Mutex<HashMap<Url, String>>>,
+
+    /// prompt to choose one of multiple candidate assets
+    #[builder(default)]
+    interactive: bool,
+
+    /// the regex of the asset chosen before
+    #[builder(default)]
+    asset_pattern: Option<String>,
+
+    /// the arch aliases in config for [get_archs]
+    #[builder(default)]
+    arch_aliases: IndexMap<String, Vec<String>>,
+
+    /// the pattern of the asset picked without the saved pattern
+    #[builder(setter(skip))]
+    #[getset(skip)]
+    chosen: Arc<Mutex<Option<String>>>,
+
+    /// the api responses shared with other bins of the same repo
+    #[builder(default)]
+    #[getset(skip)]
+    api_cache: ApiCache,
+
+    /// the token sent only to the api. download assets by the api for private repos if any
+    #[builder(default)]
+    #[getset(skip)]
+    token: Option<ApiToken>,
 }
 
 impl GithubBinaryBuilder {
@@ -42,10 +148,13 @@ impl GithubBinaryBuilder {
         let url = self
             .binary
             .as_ref()
-            .map(|bin| match bin.source() {
-                Source::Github { owner, repo } => {
-                    format!("https://api.github.com/repos/{}/{}/", owner, repo)
+            .and_then(|bin| match bin.source() {
+                Source::Github { owner, repo }
+                | Source::GithubTag { owner, repo }
+                | Source::GithubActions { owner, repo, .. } => {
+                    Some(format!("https://api.github.com/repos/{}/{}/", owner, repo))
                 }
+                Source::Urls(_) | Source::Custom { .. } => None,
             })
             .ok_or_else(|| anyhow!("not a github binary"))
             .and_then(|s| s.parse::<Url>().map_err(Into::into))?;
@@ -58,17 +167,212 @@ impl GithubBinaryBuilder {
 #[async_trait]
 impl Visible for GithubBinary {
     async fn latest_ver(&self) -> Result<String> {
+        match self.binary.source() {
+            Source::GithubTag { .. } => return self.fetch_latest_tag().await.map(|tag| tag.name),
+            Source::GithubActions {
+                workflow, branch, ..
+            } => {
+                return self
+                    .fetch_latest_run(workflow, branch.as_deref())
+                    .await
+                    .map(|run| run.id.to_string())
+            }
+            Source::Github { .. } => {}
+            Source::Urls(_) => bail!("unsupported source urls by github"),
+            Source::Custom { name, .. } => bail!("unsupported source {} by github", name),
+        }
         self.fetch_latest_release()
             .await
             .map(|rel| rel.version().to_owned())
     }
 
+    /// 分页列出releases查找不在skipped中的最新正式版本，github tag在第一页tags中查找
+    async fn latest_ver_skipping(&self, skipped: &[String]) -> Result<String> {
+        match self.binary.source() {
+            Source::Github { .. } => {}
+            Source::GithubTag { .. } => {
+                let tags = self.fetch_tags(1, RELEASES_PER_PAGE).await?;
+                let tags = tags
+                    .into_iter()
+                    .filter(|tag| !is_skipped(skipped, &tag.name))
+                    .collect();
+                return latest_tag(tags)
+                    .map(|tag| tag.name)
+                    .ok_or_else(|| anyhow!("not found any version tag not skipped"));
+            }
+            _ => {
+                let latest = self.latest_ver().await?;
+                if is_skipped(skipped, &latest) {
+                    bail!("the latest version {} is skipped", latest);
+                }
+                return Ok(latest);
+            }
+        }
+
+        let latest = self.latest_ver().await?;
+        if !is_skipped(skipped, &latest) {
+            return Ok(latest);
+        }
+        for page in 1..=MAX_RELEASE_PAGES {
+            let rels = self.fetch_releases(page, RELEASES_PER_PAGE).await?;
+            if let Some(rel) = latest_unskipped_release(&rels, skipped) {
+                debug!(
+                    "found the release {} not skipped in page {}",
+                    rel.tag_name(),
+                    page
+                );
+                return Ok(rel.version().to_owned());
+            }
+            if rels.len() < RELEASES_PER_PAGE as usize {
+                break;
+            }
+        }
+        bail!("not found any release not skipped in {:?}", skipped)
+    }
+
     async fn get_url(&self, ver: &str) -> Result<Url> {
-        let release = self.fetch_release_by_tag_name(ver).await?;
-        self.pick_asset(&release)?
-            .browser_download_url
-            .parse()
-            .map_err(Into::into)
+        if let Some(url) = self.binary.asset_url() {
+            let mut data = json!({
+                "name": self.bin().name(),
+                "version": ver,
+            });
+            // 只在模板使用tag时请求release
+            if url.contains("tag") {
+                if let Some(tag) = self.tag(ver).await? {
+                    data["tag"] = json!(tag);
+                }
+            }
+            let data = platform_values(data)?;
+            let url = self.templater.render(url, &data)?;
+            debug!("use the asset url {} without picking for {}", url, ver);
+            return url.trim().parse().map_err(Into::into);
+        }
+        if let Some(id) = self.binary.asset_id() {
+            let asset = self.fetch_asset_by_id(*id).await?;
+            debug!(
+                "use the asset {} by id {} without picking",
+                asset.name(),
+                id
+            );
+            return self.asset_url(&asset);
+        }
+
+        if let Source::GithubTag { owner, repo } = self.binary.source() {
+            let url = format!(
+                "https://github.com/{}/{}/archive/{}.tar.gz",
+                owner, repo, ver
+            );
+            debug!("use the tarball {} of tag or branch {}", url, ver);
+            return url.parse().map_err(Into::into);
+        }
+
+        if let Source::GithubActions { .. } = self.binary.source() {
+            let id = ver
+                .parse::<i64>()
+                .map_err(|e| anyhow!("invalid workflow run id `{}`: {}", ver, e))?;
+            let artifacts = self.fetch_artifacts(id).await?;
+            return self
+                .pick_artifact(&artifacts)?
+                .archive_download_url
+                .parse()
+                .map_err(Into::into);
+        }
+
+        let release = self.fetch_release_by_prefix(ver).await?;
+        let asset = self.resolve_asset(&release).await?;
+        let url = self.asset_url(asset)?;
+        if asset.digest.is_none() {
+            self.find_checksum(&release, asset, &url).await;
+        }
+        Ok(url)
+    }
+
+    async fn changelog(&self, ver: &str) -> Result<Option<String>> {
+        if let Source::GithubTag { .. } | Source::GithubActions { .. } = self.binary.source() {
+            return Ok(None);
+        }
+        self.fetch_release_by_prefix(ver)
+            .await
+            .map(|rel| Some(rel.body).filter(|s| !s.trim().is_empty()))
+    }
+
+    async fn release_date(&self, ver: &str) -> Result<Option<DateTime<Utc>>> {
+        if let Source::GithubTag { .. } | Source::GithubActions { .. } = self.binary.source() {
+            return Ok(None);
+        }
+        self.fetch_release_by_prefix(ver)
+            .await
+            .map(|rel| Some(rel.published_at))
+    }
+
+    async fn tag(&self, ver: &str) -> Result<Option<String>> {
+        match self.binary.source() {
+            Source::GithubTag { .. } => Ok(Some(ver.to_owned())),
+            Source::Github { .. } => self
+                .fetch_release_by_prefix(ver)
+                .await
+                .map(|rel| Some(rel.tag_name)),
+            _ => Ok(None),
+        }
+    }
+
+    fn digest_of(&self, url: &Url) -> Option<String> {
+        self.digests.lock().get(url).cloned()
+    }
+
+    /// api的asset url需要`Accept: application/octet-stream`才返回文件而不是json
+    fn download_headers(&self, url: &Url) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let is_asset_api = self
+            .base_url
+            .join("releases/assets/")
+            .is_ok_and(|base| url.as_str().starts_with(base.as_str()));
+        if is_asset_api {
+            headers.insert(ACCEPT, HeaderValue::from_static("application/octet-stream"));
+        }
+        headers
+    }
+
+    fn supports_attestation(&self) -> bool {
+        true
+    }
+
+    /// [List attestations](https://docs.github.com/en/rest/repos/repos#list-attestations)
+    /// 并使用`gh attestation verify`验证
+    async fn attestation(&self, path: &Path, digest: &str) -> Result<Option<String>> {
+        let repo = self
+            .binary
+            .source()
+            .repo_url()
+            .ok_or_else(|| anyhow!("not found repo of {}", self.binary.name()))?;
+        let url = self
+            .base_url
+            .join(&format!("attestations/sha256:{}", digest))?;
+        let resp = self
+            .get(&url)
+            .timeout(*self.binary.read_timeout())
+            .send()
+            .await?;
+        if resp.status() == StatusCode::NOT_FOUND {
+            trace!("not found attestations for {}", url);
+            return Ok(None);
+        }
+        let attestations = resp
+            .json::<ResponseResult>()
+            .await?
+            .to::<Attestations>()?
+            .attestations;
+        if attestations.is_empty() {
+            return Ok(None);
+        }
+        let repo = repo.trim_start_matches("https://github.com/");
+        verify_attestations(path, &attestations, repo)
+            .await
+            .map(Some)
+    }
+
+    fn chosen_pattern(&self) -> Option<String> {
+        self.chosen.lock().clone()
     }
 
     fn bin(&self) -> &Binary {
@@ -93,29 +397,85 @@ impl GithubBinary {
     ///
     /// * 如果未找到任何asset
     fn pick_asset<'a>(&self, rel: &'a Release) -> Result<&'a Asset> {
-        let pick_re_fn = |hook| {
-            let data = platform_values(json!({
-                "name": self.bin().name(),
-                "repo": match self.binary.source() {
-                    Source::Github { owner: _, repo } => repo.to_owned(),
-                },
-            }))?;
-            let re = self
-                .templater
-                .render(hook, &data)
-                .map(|s| s.trim().to_owned())?;
-            if re.is_empty() {
-                bail!("empty template");
+        let assets = self.pick_assets(rel)?;
+        if assets.len() > 1 && log_enabled!(log::Level::Warn) {
+            warn!(
+                "found {} assets, pick `{}` asset for top of downloads: {}",
+                assets.len(),
+                assets[0].name(),
+                assets
+                    .iter()
+                    .enumerate()
+                    .map(|(i, a)| (i + 1).to_string()
+                        + ":"
+                        + a.name()
+                        + ","
+                        + &a.download_count().to_string())
+                    .collect::<Vec<_>>()
+                    .join(". ")
+            );
+        }
+        Ok(assets[0])
+    }
+
+    /// 选择release中的asset
+    ///
+    /// 未配置pick regex时优先使用之前保存的[Self::asset_pattern]，它不再匹配时被忽略。
+    /// 找到多个asset且[Self::interactive]时提示用户选择。未使用保存的pattern时，选择的
+    /// asset pattern记录在[Visible::chosen_pattern]中，避免之后的版本在gnu与musl等之间切换
+    async fn resolve_asset<'a>(&self, rel: &'a Release) -> Result<&'a Asset> {
+        if let (None, Some(pattern)) = (self.binary.pick_regex(), &self.asset_pattern) {
+            let re = Regex::new(pattern)?;
+            let assets = self.unignored(rel.assets().iter())?;
+            match assets.into_iter().find(|a| re.is_match(a.name())) {
+                Some(asset) => {
+                    debug!(
+                        "use the asset {} by saved pattern {}",
+                        asset.name(),
+                        pattern
+                    );
+                    return Ok(asset);
+                }
+                None => warn!(
+                    "ignored the saved asset pattern {} matching nothing in {}",
+                    pattern,
+                    rel.tag_name()
+                ),
+            }
+        }
+        let asset = if self.interactive {
+            let assets = self.pick_assets(rel)?;
+            if assets.len() > 1 {
+                prompt_asset(self.binary.name(), rel, &assets).await?
+            } else {
+                assets[0]
             }
+        } else {
+            self.pick_asset(rel)?
+        };
+        if self.binary.pick_regex().is_none() {
+            self.chosen.lock().replace(name_pattern(asset.name()));
+        }
+        Ok(asset)
+    }
+
+    /// 使用pick regex或通用的选择算法从未被忽略的assets中找到候选，按下载数从高到低排列
+    ///
+    /// # Error
+    ///
+    /// * 如果未找到任何asset
+    fn pick_assets<'a>(&self, rel: &'a Release) -> Result<Vec<&'a Asset>> {
+        let candidates = self.unignored(rel.assets().iter())?;
+        let pick_re_fn = |hook| {
+            let re = self.render_pick_regex(hook)?;
             debug!(
                 "filtering {} assets by pick regex: {}",
-                rel.assets().len(),
+                candidates.len(),
                 re
             );
-            let re = Regex::new(&re)?;
-            let assets = rel
-                .assets()
+            let assets = candidates
                 .iter()
+                .copied()
                 .filter(|a| re.is_match(a.name()))
                 .collect::<Vec<_>>();
 
@@ -142,74 +502,370 @@ impl GithubBinary {
             .as_deref()
             .map(pick_re_fn)
             .unwrap_or_else(|| {
-                let conditions = [
-                    // version like:   "tag_name": "0.6.8", "name": "0.6.8 Release",
+                // version like:   "tag_name": "0.6.8", "name": "0.6.8 Release",
+                let platform = Platform::current(
                     vec![
                         self.binary().name().to_owned(),
                         rel.tag_name.to_owned(),
                         rel.name.to_owned(),
                     ],
-                    vec![OS.to_owned()],
-                    get_archs(),
-                    vec![get_target_env().to_owned()],
-                ];
-                pick_by_name(rel.assets().iter(), &conditions).map(|v| v.collect::<Vec<_>>())
+                    &self.arch_aliases,
+                );
+                pick_by_name(candidates.iter().copied(), &platform)
             })?;
         if assets.is_empty() {
-            bail!("empty assets by regex or name");
+            return Err(NoMatchedAsset("by regex or name".to_owned()).into());
         }
 
         if assets.len() == 1 {
             trace!("picked asset: {:?}", assets[0]);
-            return Ok(assets[0]);
+        } else {
+            trace!("sorting {} assets by download count", assets.len());
+            assets.sort_by(|a, b| b.download_count().cmp(a.download_count()));
+        }
+        Ok(assets)
+    }
+
+    /// 获取asset的下载url，并记录api返回的digest用于[Visible::digest_of]
+    ///
+    /// 有token时使用api的asset url，private repo的browser download url没有认证会404。
+    /// api url的fragment为asset的文件名，用于缓存文件的命名
+    fn asset_url(&self, asset: &Asset) -> Result<Url> {
+        let url = self.download_url(asset)?;
+        if let Some(digest) = &asset.digest {
+            trace!("found digest {} of asset {}", digest, asset.name());
+            self.digests.lock().insert(url.clone(), digest.to_owned());
         }
+        Ok(url)
+    }
 
-        trace!("sorting {} assets by download count", assets.len());
-        assets.sort_by(|a, b| b.download_count().cmp(a.download_count()));
+    fn download_url(&self, asset: &Asset) -> Result<Url> {
+        if self.token.is_some() {
+            let mut url = self
+                .base_url
+                .join(&format!("releases/assets/{}", asset.id))?;
+            url.set_fragment(Some(&asset.name));
+            Ok(url)
+        } else {
+            asset.browser_download_url.parse().map_err(Into::into)
+        }
+    }
 
-        if log_enabled!(log::Level::Warn) {
-            warn!(
-                "found {} assets, pick `{}` asset for top of downloads: {}",
-                assets.len(),
-                assets[0].name(),
-                assets
-                    .iter()
-                    .enumerate()
-                    .map(|(i, a)| (i + 1).to_string()
-                        + ":"
-                        + a.name()
-                        + ","
-                        + &a.download_count().to_string())
-                    .collect::<Vec<_>>()
-                    .join(". ")
+    /// 在release的checksums文件中查找没有api digest的asset的digest，记录到url上。
+    /// 失败时不影响安装
+    async fn find_checksum(&self, rel: &Release, asset: &Asset, url: &Url) {
+        for (checksums, single) in checksum_assets(rel.assets(), asset.name()) {
+            let content = match self.fetch_checksums(checksums).await {
+                Ok(content) => content,
+                Err(e) => {
+                    warn!("failed to fetch checksums {}: {}", checksums.name(), e);
+                    continue;
+                }
+            };
+            if let Some(digest) = find_digest(&content, asset.name(), single) {
+                debug!(
+                    "found digest {} of asset {} in {}",
+                    digest,
+                    asset.name(),
+                    checksums.name()
+                );
+                self.digests.lock().insert(url.clone(), digest);
+                return;
+            }
+            trace!(
+                "not found {} in checksums {}",
+                asset.name(),
+                checksums.name()
             );
         }
+    }
 
-        Ok(assets[0])
+    /// 请求url，只在请求api时带上token
+    fn get(&self, url: &Url) -> RequestBuilder {
+        let req = self.client.get(url.clone());
+        match &self.token {
+            Some(token) => token.authorize(req, url),
+            None => req,
+        }
+    }
+
+    /// 下载checksums文件asset的内容
+    async fn fetch_checksums(&self, asset: &Asset) -> Result<String> {
+        let url = self.download_url(asset)?;
+        let resp = self
+            .get(&url)
+            .headers(self.download_headers(&url))
+            .send()
+            .await?;
+        // 有token的client不跟随跨域的重定向，cdn上的文件不需要认证
+        let location = resp
+            .headers()
+            .get(LOCATION)
+            .filter(|_| resp.status().is_redirection())
+            .map(|loc| loc.to_str().map(|loc| resp.url().join(loc)))
+            .transpose()?
+            .transpose()?;
+        let resp = match location {
+            Some(location) => reqwest::get(location).await?,
+            None => resp,
+        };
+        resp.error_for_status()?.text().await.map_err(Into::into)
+    }
+
+    /// 过滤掉匹配[ignore_regex][Binary::ignore_regex]的项
+    fn unignored<'a, T, I>(&self, iter: I) -> Result<Vec<&'a T>>
+    where
+        T: Named + 'a,
+        I: Iterator<Item = &'a T>,
+    {
+        let re = match self.binary.ignore_regex() {
+            Some(hook) => self.render_pick_regex(hook)?,
+            None => return Ok(iter.collect()),
+        };
+        Ok(iter
+            .filter(|a| {
+                let ignored = re.is_match(a.name());
+                if ignored {
+                    debug!("ignored {} by regex {}", a.name(), re);
+                }
+                !ignored
+            })
+            .collect())
+    }
+
+    /// 渲染pick regex模板，其中可用`name`与`repo`
+    fn render_pick_regex(&self, hook: &str) -> Result<Regex> {
+        let data = platform_values(json!({
+            "name": self.bin().name(),
+            "repo": match self.binary.source() {
+                Source::Github { repo, .. }
+                | Source::GithubTag { repo, .. }
+                | Source::GithubActions { repo, .. } => repo.to_owned(),
+                Source::Urls(_) => self.bin().name().to_owned(),
+                Source::Custom { value, .. } => value.to_owned(),
+            },
+        }))?;
+        let re = self
+            .templater
+            .render(hook, &data)
+            .map(|s| s.trim().to_owned())?;
+        if re.is_empty() {
+            bail!("empty template");
+        }
+        Regex::new(&re).map_err(Into::into)
+    }
+
+    /// 从workflow run的artifacts中选择一个未过期的artifact
+    ///
+    /// 只有一个artifact时直接使用。否则使用pick regex或bin name，os与archs过滤，
+    /// 找到多个时使用第一个
+    ///
+    /// # Error
+    ///
+    /// * 如果未找到任何artifact
+    fn pick_artifact<'a>(&self, artifacts: &'a [Artifact]) -> Result<&'a Artifact> {
+        let artifacts = self.unignored(artifacts.iter().filter(|a| !a.expired))?;
+        let picked = match (&artifacts[..], self.binary.pick_regex()) {
+            ([], _) => bail!("not found any unexpired artifact"),
+            ([artifact], None) => vec![*artifact],
+            (_, Some(hook)) => {
+                let re = self.render_pick_regex(hook)?;
+                debug!("filtering {} artifacts by regex {}", artifacts.len(), re);
+                artifacts
+                    .into_iter()
+                    .filter(|a| re.is_match(&a.name))
+                    .collect()
+            }
+            (_, None) => {
+                let platform =
+                    Platform::current(vec![self.binary.name().to_owned()], &self.arch_aliases);
+                pick_by_name(artifacts.into_iter(), &platform)?
+            }
+        };
+        match picked[..] {
+            [] => Err(NoMatchedAsset("in artifacts by regex or name".to_owned()).into()),
+            [artifact] => Ok(artifact),
+            [artifact, ..] => {
+                warn!(
+                    "found {} artifacts, pick the first `{}`",
+                    picked.len(),
+                    artifact.name
+                );
+                Ok(artifact)
+            }
+        }
     }
 
     async fn fetch_latest_release(&self) -> Result<Release> {
         let url = self.base_url.join("releases/latest")?;
-        self.client
-            .get(url)
-            .send()
-            .await?
-            .json::<ResponseResult>()
-            .await?
-            .to()
+        self.fetch(url).await
+    }
+
+    /// 请求github api并解析响应，相同url的响应在[ApiCache]中共享
+    async fn fetch<T: DeserializeOwned>(&self, url: Url) -> Result<T> {
+        let val = self
+            .api_cache
+            .get_or_fetch(&url, || self.fetch_uncached(&url))
+            .await?;
+        serde_json::from_value(val).map_err(Into::into)
+    }
+
+    /// 请求github api。遇到rate limit时如果很快重置则等待后重试一次
+    ///
+    /// # Error
+    ///
+    /// * 如果rate limit未能在[MAX_RATE_LIMIT_WAIT]内重置，返回[RateLimited]
+    async fn fetch_uncached(&self, url: &Url) -> Result<serde_json::Value> {
+        let mut retried = false;
+        loop {
+            let start = Instant::now();
+            let resp = self
+                .get(url)
+                .timeout(*self.binary.read_timeout())
+                .send()
+                .await?;
+            trace!(
+                "got the {:?} response in {:?} for {}",
+                resp.version(),
+                start.elapsed(),
+                url
+            );
+            let now = Utc::now();
+            let limited = match RateLimited::from_response(resp.status(), resp.headers(), now) {
+                Some(limited) => limited,
+                None => return resp.json::<ResponseResult>().await?.to(),
+            };
+            let wait = limited
+                .reset
+                .and_then(|reset| (reset - now).to_std().ok())
+                .filter(|wait| *wait <= MAX_RATE_LIMIT_WAIT);
+            match wait {
+                Some(wait) if !retried => {
+                    warn!(
+                        "{} for {}, retrying in {}s",
+                        limited,
+                        url,
+                        wait.as_secs() + 1
+                    );
+                    tokio::time::sleep(wait + Duration::from_secs(1)).await;
+                    retried = true;
+                }
+                _ => return Err(limited.into()),
+            }
+        }
     }
 
     /// [Get a release by tag name](https://docs.github.com/en/rest/reference/releases#get-a-release-by-tag-name)
     async fn fetch_release_by_tag_name(&self, tag: &str) -> Result<Release> {
         let url = self.base_url.join(&format!("releases/tags/{}", tag))?;
         trace!("fetching release with tag name `{}` for url: {}", tag, url);
-        self.client
-            .get(url)
-            .send()
-            .await?
-            .json::<ResponseResult>()
+        self.fetch(url).await
+    }
+
+    /// [List releases](https://docs.github.com/en/rest/releases/releases#list-releases)
+    ///
+    /// page从1开始，按创建时间从新到旧排列
+    pub async fn fetch_releases(&self, page: u32, per_page: u32) -> Result<Vec<Release>> {
+        let mut url = self.base_url.join("releases")?;
+        url.query_pairs_mut()
+            .append_pair("page", &page.to_string())
+            .append_pair("per_page", &per_page.to_string());
+        trace!("fetching releases page {} for url: {}", page, url);
+        self.fetch(url).await
+    }
+
+    /// 查找版本ver的release。先按tag name精确查找，未找到时分页列出releases按
+    /// [match_release]查找，如`1.10.0`匹配`v1.10.0`，`1.10`匹配最新的`v1.10.x`
+    ///
+    /// # Error
+    ///
+    /// * 如果在前[MAX_RELEASE_PAGES]页中都未找到
+    pub async fn fetch_release_by_prefix(&self, ver: &str) -> Result<Release> {
+        match self.fetch_release_by_tag_name(ver).await {
+            Ok(rel) => return Ok(rel),
+            Err(e) if e.is::<RateLimited>() => return Err(e),
+            Err(e) => debug!("not found release by tag name `{}`: {}", ver, e),
+        }
+
+        for page in 1..=MAX_RELEASE_PAGES {
+            let rels = self.fetch_releases(page, RELEASES_PER_PAGE).await?;
+            if let Some(rel) = match_release(&rels, ver) {
+                debug!(
+                    "found release {} for version {} in page {}",
+                    rel.tag_name(),
+                    ver,
+                    page
+                );
+                return Ok(rel.clone());
+            }
+            if rels.len() < RELEASES_PER_PAGE as usize {
+                break;
+            }
+        }
+        bail!("not found release for version `{}`", ver)
+    }
+
+    /// [List repository tags](https://docs.github.com/en/rest/repos/repos#list-repository-tags)
+    pub async fn fetch_tags(&self, page: u32, per_page: u32) -> Result<Vec<Tag>> {
+        let mut url = self.base_url.join("tags")?;
+        url.query_pairs_mut()
+            .append_pair("page", &page.to_string())
+            .append_pair("per_page", &per_page.to_string());
+        trace!("fetching tags page {} for url: {}", page, url);
+        self.fetch(url).await
+    }
+
+    /// 获取第一页tags中版本号最大的tag。github按名称而不是版本排序tags
+    ///
+    /// # Error
+    ///
+    /// * 如果没有任何包含版本号的tag
+    async fn fetch_latest_tag(&self) -> Result<Tag> {
+        let tags = self.fetch_tags(1, RELEASES_PER_PAGE).await?;
+        trace!("found {} tags", tags.len());
+        latest_tag(tags).ok_or_else(|| anyhow!("not found any version tag"))
+    }
+
+    /// [List workflow runs for a workflow](https://docs.github.com/en/rest/actions/workflow-runs#list-workflow-runs-for-a-workflow)
+    ///
+    /// 获取workflow在branch上最新的成功运行
+    async fn fetch_latest_run(&self, workflow: &str, branch: Option<&str>) -> Result<WorkflowRun> {
+        let mut url = self
+            .base_url
+            .join(&format!("actions/workflows/{}/runs", workflow))?;
+        url.query_pairs_mut()
+            .append_pair("status", "success")
+            .append_pair("per_page", "1");
+        if let Some(branch) = branch {
+            url.query_pairs_mut().append_pair("branch", branch);
+        }
+        trace!(
+            "fetching the latest run of workflow {} for url: {}",
+            workflow,
+            url
+        );
+        self.fetch::<WorkflowRuns>(url)
             .await?
-            .to()
+            .workflow_runs
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("not found any successful run of workflow {}", workflow))
+    }
+
+    /// [List workflow run artifacts](https://docs.github.com/en/rest/actions/artifacts#list-workflow-run-artifacts)
+    async fn fetch_artifacts(&self, run_id: i64) -> Result<Vec<Artifact>> {
+        let url = self
+            .base_url
+            .join(&format!("actions/runs/{}/artifacts", run_id))?;
+        trace!("fetching artifacts of run {} for url: {}", run_id, url);
+        self.fetch::<Artifacts>(url).await.map(|a| a.artifacts)
+    }
+
+    /// [Get a release asset](https://docs.github.com/en/rest/releases/assets#get-a-release-asset)
+    async fn fetch_asset_by_id(&self, id: i64) -> Result<Asset> {
+        let url = self.base_url.join(&format!("releases/assets/{}", id))?;
+        trace!("fetching asset with id `{}` for url: {}", id, url);
+        self.fetch(url).await
     }
 }
 
@@ -302,69 +958,600 @@ impl Release {
     }
 }
 
-fn pick_by_name<'a, I>(
-    iter: I,
-    conditions: &[Vec<String>],
-) -> Result<impl Iterator<Item = &'a Asset> + Clone>
-where
-    I: Iterator<Item = &'a Asset> + Clone,
-{
-    trace!("picking by name with conditions: {:?}", conditions);
-    fn get_regex(conditions: &[Vec<String>]) -> String {
-        let mut s = conditions
-            .iter()
-            .map(|w| w.join("|"))
-            .collect::<Vec<_>>()
-            .join("|");
-
-        s.insert(0, '(');
-        s += ").*";
+/// 在按从新到旧排列的rels中查找第一个不在skipped中的正式版本，忽略draft与prerelease
+fn latest_unskipped_release<'a>(rels: &'a [Release], skipped: &[String]) -> Option<&'a Release> {
+    rels.iter().find(|rel| {
+        !rel.draft
+            && !rel.prerelease
+            && !is_skipped(skipped, &rel.tag_name)
+            && !is_skipped(skipped, rel.version())
+    })
+}
 
-        let mut re = String::new();
-        for _ in 0..conditions.len() {
-            re.push_str(&s);
-        }
-        re
+/// 在按从新到旧排列的rels中查找版本ver，忽略draft与`v`前缀
+///
+/// 优先匹配相同的版本，否则匹配以ver为前缀的最新版本，其中正式版本优先于prerelease
+fn match_release<'a>(rels: &'a [Release], ver: &str) -> Option<&'a Release> {
+    fn normalize(s: &str) -> &str {
+        s.trim().trim_start_matches(['v', 'V'])
     }
+    let ver = normalize(ver);
+    if ver.is_empty() {
+        return None;
+    }
+    let rels = rels.iter().filter(|rel| !rel.draft);
+    rels.clone()
+        .find(|rel| normalize(&rel.tag_name) == ver || normalize(rel.version()) == ver)
+        .or_else(|| {
+            let is_prefix = |rel: &&Release| {
+                matches!(
+                    normalize(&rel.tag_name).strip_prefix(ver),
+                    Some(rest) if rest.starts_with(['.', '-', '+'])
+                )
+            };
+            let mut matched = rels.filter(is_prefix);
+            matched
+                .clone()
+                .find(|rel| !rel.prerelease)
+                .or_else(|| matched.next())
+        })
+}
 
-    for step in (0..=conditions.len()).rev() {
-        for i in (0..conditions.len()).step_by(step) {
-            if i >= step {
-                continue;
-            }
-            let re = get_regex(&conditions[i..step]);
-
-            let re = regex::Regex::new(&re)?;
-            if log_enabled!(log::Level::Trace) {
-                let names = iter
-                    .clone()
-                    .map(|a| a.name().to_owned())
-                    .collect::<Vec<_>>();
-                trace!(
-                    "picking {} assets by regex `{}`: {:?}",
-                    names.len(),
-                    re,
-                    names.join(",")
-                );
-            }
-            let iter = iter.clone().filter(move |a| re.is_match(a.name()));
-            let res = iter.clone().collect::<Vec<_>>();
-            if !res.is_empty() {
-                if log_enabled!(log::Level::Trace) {
-                    trace!(
-                        "found {} assets: {}",
-                        res.len(),
-                        res.iter()
-                            .map(|a| a.name().to_owned())
-                            .collect::<Vec<_>>()
-                            .join(",")
-                    );
-                }
-                return Ok(iter);
-            }
-        }
+/// 同一时间只有一个bin提示选择asset
+static PROMPT_LOCK: Lazy<tokio::sync::Mutex<()>> = Lazy::new(Default::default);
+
+/// 在终端中提示用户从assets中选择一个，直接回车或输入结束时选择第一个
+async fn prompt_asset<'a>(name: &str, rel: &Release, assets: &[&'a Asset]) -> Result<&'a Asset> {
+    let _guard = PROMPT_LOCK.lock().await;
+    let mut prompt = format!(
+        "found {} assets of {} {}:\n",
+        assets.len(),
+        name,
+        rel.tag_name()
+    );
+    for (i, a) in assets.iter().enumerate() {
+        prompt += &format!(
+            "  {}: {} ({} downloads)\n",
+            i + 1,
+            a.name(),
+            a.download_count()
+        );
     }
-    bail!("not found asset by conditions {:?}", conditions)
+    prompt += &format!("choose an asset [1-{}] (default 1): ", assets.len());
+
+    let len = assets.len();
+    let i = tokio::task::spawn_blocking(move || loop {
+        eprint!("{}", prompt);
+        io::stderr().flush()?;
+        let mut line = String::new();
+        if io::stdin().read_line(&mut line)? == 0 {
+            return Ok::<_, anyhow::Error>(0);
+        }
+        match line.trim() {
+            "" => return Ok(0),
+            s => match s.parse::<usize>() {
+                Ok(i) if (1..=len).contains(&i) => return Ok(i - 1),
+                _ => eprintln!("invalid choice `{}`", s),
+            },
+        }
+    })
+    .await??;
+    debug!("chose the asset {} of {}", assets[i].name(), name);
+    Ok(assets[i])
+}
+
+/// 将asset name转换为匹配其它版本同名asset的regex，名称中的数字可以是任意数字
+fn name_pattern(name: &str) -> String {
+    static DIGITS: Lazy<Regex> = Lazy::new(|| Regex::new(r"\d+").unwrap());
+    let escaped = regex::escape(name);
+    format!("^{}$", DIGITS.replace_all(&escaped, NoExpand(r"\d+")))
+}
+
+/// 按tag名称中`-`前的数字比较版本，找到最大的版本，忽略不含数字的tag
+///
+/// 相同的数字时没有`-rc1`这样后缀的版本更大
+fn latest_tag(tags: Vec<Tag>) -> Option<Tag> {
+    fn version_key(name: &str) -> (Vec<u64>, bool) {
+        let (ver, pre) = match name.split_once('-') {
+            Some((ver, _)) => (ver, true),
+            None => (name, false),
+        };
+        let nums = ver
+            .split(|c: char| !c.is_ascii_digit())
+            .filter_map(|s| s.parse().ok())
+            .collect();
+        (nums, !pre)
+    }
+    tags.into_iter()
+        .map(|tag| (version_key(&tag.name), tag))
+        .filter(|((nums, _), _)| !nums.is_empty())
+        .max_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(_, tag)| tag)
+}
+
+/// 可以按名称选择的下载项
+pub(super) trait Named {
+    fn name(&self) -> &str;
+}
+
+impl Named for Asset {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Named for Artifact {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// 已知的os名称及其在asset名称中的别名
+const OS_ALIASES: &[(&str, &[&str])] = &[
+    ("linux", &["linux"]),
+    ("macos", &["macos", "darwin", "apple", "osx", "mac"]),
+    ("windows", &["windows", "win", "win32", "win64"]),
+    ("freebsd", &["freebsd"]),
+    ("netbsd", &["netbsd"]),
+    ("openbsd", &["openbsd"]),
+    ("android", &["android"]),
+    ("illumos", &["illumos", "solaris"]),
+];
+
+/// 已知的arch在asset名称中的写法，用于识别其它arch的asset
+const KNOWN_ARCHS: &[&str] = &[
+    "386",
+    "i386",
+    "686",
+    "i686",
+    "x86_64",
+    "amd64",
+    "x64",
+    "arm",
+    "armv5",
+    "armv6",
+    "armv7",
+    "armv8",
+    "arm32v7",
+    "armhf",
+    "arm64",
+    "aarch64",
+    "mips",
+    "mipsle",
+    "mips64",
+    "mips64le",
+    "ppc64",
+    "ppc64le",
+    "powerpc64le",
+    "s390x",
+    "riscv64",
+    "riscv64gc",
+    "loong64",
+];
+
+/// macos上包含多个arch的universal binary在asset名称中的写法
+const UNIVERSAL_ARCHS: &[&str] = &["universal", "universal2"];
+
+/// aarch64的macos上可以通过rosetta运行的x86_64在asset名称中的写法
+const ROSETTA_ARCHS: &[&str] = &["x86_64", "amd64", "x64", "intel"];
+
+const TARGET_ENVS: &[&str] = &["gnu", "musl", "msvc"];
+
+/// 安装包格式，不能直接解压使用
+const PACKAGE_EXTS: &[&str] = &[".deb", ".rpm", ".msi", ".pkg", ".dmg", ".apk", ".appimage"];
+
+/// 校验与签名文件
+const CHECKSUM_EXTS: &[&str] = &[
+    ".sha256",
+    ".sha256sum",
+    ".sha512",
+    ".sha512sum",
+    ".md5",
+    ".sig",
+    ".asc",
+    ".pem",
+    ".minisig",
+    ".sbom",
+    ".intoto.jsonl",
+];
+
+/// 只包含一个asset的digest的文件如`<asset>.sha256`
+const DIGEST_EXTS: &[&str] = &[".sha256", ".sha256sum", ".sha512", ".sha512sum", ".md5"];
+
+/// 超过这个大小的文件不作为checksums下载
+const MAX_CHECKSUMS_SIZE: i64 = 1 << 20;
+
+/// release中可能包含name的digest的checksums文件，与是否为只属于name的单独文件
+///
+/// 单独的`<name>.sha256`在前，其次是名称包含checksum，sums或算法的清单。签名与其它asset的
+/// 单独文件被忽略
+fn checksum_assets<'a>(assets: &'a [Asset], name: &str) -> Vec<(&'a Asset, bool)> {
+    let mut found = assets
+        .iter()
+        .filter(|a| a.size <= MAX_CHECKSUMS_SIZE && a.name != name)
+        .filter_map(|a| {
+            let lower = a.name.to_lowercase();
+            if let Some(ext) = DIGEST_EXTS.iter().find(|e| lower.ends_with(*e)) {
+                let stem = a.name.get(..a.name.len().checked_sub(ext.len())?)?;
+                // 其它asset的单独文件如`b.zip.sha256`
+                let other = assets.iter().any(|b| b.name != name && b.name == stem);
+                return (!other).then_some((a, stem == name));
+            }
+            let is_signature = CHECKSUM_EXTS.iter().any(|e| lower.ends_with(e));
+            let is_manifest = ["checksum", "sums", "sha256", "sha512"]
+                .iter()
+                .any(|k| lower.contains(k));
+            (is_manifest && !is_signature).then_some((a, false))
+        })
+        .collect::<Vec<_>>();
+    found.sort_by_key(|(_, single)| !single);
+    found
+}
+
+/// github api的地址，token只发送到这个origin
+static API_URL: Lazy<Url> =
+    Lazy::new(|| Url::parse("https://api.github.com/").expect("invalid github api url"));
+
+/// url是否在github api的origin上，包括private repo的asset api
+pub fn is_api_url(url: &Url) -> bool {
+    url.origin() == API_URL.origin()
+}
+
+/// github api的token。不作为client的默认header，只添加到请求github api的请求中，
+/// 避免发送给urls source，asset url模板与cdn等其它host
+#[derive(Debug, Clone)]
+pub struct ApiToken(HeaderValue);
+
+impl ApiToken {
+    /// 没有scheme的token使用`Bearer`，已有的如`token xxx`保持不变
+    pub fn new(token: &str) -> Result<Self> {
+        let token = token.trim();
+        let mut val = if token.contains(char::is_whitespace) {
+            HeaderValue::from_str(token)?
+        } else {
+            HeaderValue::from_str(&format!("Bearer {}", token))?
+        };
+        val.set_sensitive(true);
+        Ok(Self(val))
+    }
+
+    /// url在github api上时给req添加token
+    pub fn authorize(&self, req: RequestBuilder, url: &Url) -> RequestBuilder {
+        if is_api_url(url) {
+            req.header(AUTHORIZATION, self.0.clone())
+        } else {
+            trace!("not sending the github token to {}", url);
+            req
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct Attestations {
+    attestations: Vec<Attestation>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Attestation {
+    bundle: serde_json::Value,
+}
+
+/// 使用`gh attestation verify`验证path的sigstore bundles，返回签名的workflow
+/// `https://github.com/<repo>/<workflow path>@<ref>`
+///
+/// gh验证bundle的签名，到sigstore根证书的证书链，透明日志与签名的workflow属于repo
+async fn verify_attestations(
+    path: &Path,
+    attestations: &[Attestation],
+    repo: &str,
+) -> Result<String> {
+    let gh = which("gh").map_err(|_| {
+        anyhow!("not found `gh` to verify the attestation. install the github cli or disable verify-attestation")
+    })?;
+    let mut bundles = tempfile::Builder::new().suffix(".jsonl").tempfile()?;
+    for attestation in attestations {
+        writeln!(bundles, "{}", attestation.bundle)?;
+    }
+    bundles.flush()?;
+
+    debug!(
+        "verifying {} attestations of {} by gh",
+        attestations.len(),
+        path.display()
+    );
+    let out = Command::new(gh)
+        .args(["attestation", "verify"])
+        .arg(path)
+        .arg("--repo")
+        .arg(repo)
+        .arg("--bundle")
+        .arg(bundles.path())
+        .args(["--format", "json"])
+        .output()
+        .await?;
+    if !out.status.success() {
+        bail!(
+            "invalid attestations of {}: {}",
+            repo,
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+    }
+    signer_workflow(&out.stdout)
+}
+
+/// 从`gh attestation verify --format json`的输出中获取证书的SAN，即签名的workflow
+fn signer_workflow(out: &[u8]) -> Result<String> {
+    let results = serde_json::from_slice::<serde_json::Value>(out)?;
+    results[0]["verificationResult"]["signature"]["certificate"]["subjectAlternativeName"]
+        .as_str()
+        .map(ToOwned::to_owned)
+        .ok_or_else(|| anyhow!("not found the signer workflow in the output of gh"))
+}
+
+const ARCHIVE_EXTS: &[&str] = &[
+    ".tar.gz", ".tgz", ".tar.xz", ".txz", ".tar.bz2", ".tbz", ".tar.zst", ".zip", ".7z", ".gz",
+    ".xz", ".bz2",
+];
+
+/// 对asset名称打分时使用的当前平台信息
+#[derive(Debug, Clone)]
+pub(super) struct Platform {
+    /// bin name, tag等任一出现在名称中即可
+    names: Vec<String>,
+    os: String,
+    archs: Vec<String>,
+    /// 可以通过模拟运行的其它arch，得分低于原生与universal的arch
+    emulated_archs: Vec<String>,
+    target_env: String,
+}
+
+impl Platform {
+    pub(super) fn current(
+        names: Vec<String>,
+        arch_aliases: &IndexMap<String, Vec<String>>,
+    ) -> Self {
+        let emulated_archs = if OS == "macos" && ARCH == "aarch64" {
+            ROSETTA_ARCHS.iter().map(|s| s.to_string()).collect()
+        } else {
+            vec![]
+        };
+        Self {
+            names,
+            os: OS.to_owned(),
+            archs: get_archs(arch_aliases),
+            emulated_archs,
+            target_env: get_target_env().to_owned(),
+        }
+    }
+
+    /// 名称中只有模拟运行的arch
+    fn is_emulated(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        let has = |archs: &[String]| archs.iter().any(|a| contains_word(&name, a));
+        has(&self.emulated_archs) && !has(&self.archs)
+    }
+
+    fn os_aliases(&self) -> &[&str] {
+        OS_ALIASES
+            .iter()
+            .find(|(os, _)| *os == self.os)
+            .map(|(_, aliases)| *aliases)
+            .unwrap_or_default()
+    }
+
+    /// 计算名称的得分，包含其它os或arch时返回None
+    fn score(&self, name: &str) -> Option<Score> {
+        let name = name.to_lowercase();
+        let host_os = self.os_aliases();
+        let other_os = OS_ALIASES
+            .iter()
+            .flat_map(|(_, aliases)| aliases.iter())
+            .filter(|a| !host_os.contains(a))
+            .find(|a| contains_word(&name, a));
+        if let Some(os) = other_os {
+            trace!("skipped asset {} for other os {}", name, os);
+            return None;
+        }
+        let other_arch = KNOWN_ARCHS
+            .iter()
+            .filter(|a| {
+                !self
+                    .archs
+                    .iter()
+                    .chain(&self.emulated_archs)
+                    .any(|h| h == *a)
+            })
+            .find(|a| contains_word(&name, a));
+        if let Some(arch) = other_arch {
+            trace!("skipped asset {} for other arch {}", name, arch);
+            return None;
+        }
+
+        let mut score = Score::default();
+        if host_os.iter().any(|a| contains_word(&name, a)) {
+            score.os = 100;
+        }
+        if self.archs.iter().any(|a| contains_word(&name, a)) {
+            score.arch = 50;
+        } else if self.os == "macos" && UNIVERSAL_ARCHS.iter().any(|a| contains_word(&name, a)) {
+            score.arch = 40;
+        } else if self.emulated_archs.iter().any(|a| contains_word(&name, a)) {
+            score.arch = 10;
+        }
+        if self
+            .names
+            .iter()
+            .filter(|n| !n.is_empty())
+            .any(|n| name.contains(&n.to_lowercase()))
+        {
+            score.name = 20;
+        }
+        if contains_prefix(&name, &self.target_env) {
+            score.env = 10;
+        } else if TARGET_ENVS.iter().any(|e| contains_prefix(&name, e)) {
+            score.env = -5;
+        }
+        if CHECKSUM_EXTS.iter().any(|e| name.ends_with(e)) || name.contains("checksum") {
+            score.format = -1000;
+        } else if PACKAGE_EXTS.iter().any(|e| name.ends_with(e)) {
+            score.format = -30;
+        } else if ARCHIVE_EXTS.iter().any(|e| name.ends_with(e)) {
+            score.format = 5;
+        }
+        Some(score)
+    }
+}
+
+/// asset名称各项的得分
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Score {
+    os: i32,
+    arch: i32,
+    env: i32,
+    format: i32,
+    name: i32,
+}
+
+impl Score {
+    fn total(&self) -> i32 {
+        self.os + self.arch + self.env + self.format + self.name
+    }
+
+    /// 名称中至少出现了os, arch或bin name之一
+    fn matched(&self) -> bool {
+        self.os + self.arch + self.name > 0
+    }
+}
+
+impl fmt::Display for Score {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (os={}, arch={}, env={}, format={}, name={})",
+            self.total(),
+            self.os,
+            self.arch,
+            self.env,
+            self.format,
+            self.name
+        )
+    }
+}
+
+/// word在s中出现且前后不是字母或数字
+fn contains_word(s: &str, word: &str) -> bool {
+    s.match_indices(word).any(|(i, _)| {
+        !ends_with_alnum(&s[..i])
+            && !s[i + word.len()..].starts_with(|c: char| c.is_ascii_alphanumeric())
+    })
+}
+
+fn ends_with_alnum(s: &str) -> bool {
+    s.ends_with(|c: char| c.is_ascii_alphanumeric())
+}
+
+/// s中有一个以prefix开头的词，如`gnueabihf`中的`gnu`
+fn contains_prefix(s: &str, prefix: &str) -> bool {
+    s.match_indices(prefix)
+        .any(|(i, _)| !ends_with_alnum(&s[..i]))
+}
+
+/// 按名称对每项打分，返回得分最高的所有项
+///
+/// # Error
+///
+/// * 如果没有任何匹配平台或名称且得分为正的项
+pub(super) fn pick_by_name<'a, T, I>(iter: I, platform: &Platform) -> Result<Vec<&'a T>>
+where
+    T: Named + 'a,
+    I: Iterator<Item = &'a T>,
+{
+    trace!("picking by name on platform: {:?}", platform);
+    let scored = iter
+        .filter_map(|item| {
+            let score = platform.score(item.name())?;
+            debug!("score of {}: {}", item.name(), score);
+            Some((score, item))
+        })
+        .filter(|(score, _)| score.matched() && score.total() > 0)
+        .map(|(score, item)| (score.total(), item))
+        .collect::<Vec<_>>();
+    let max = scored
+        .iter()
+        .map(|(total, _)| *total)
+        .max()
+        .ok_or_else(|| NoMatchedAsset(format!("for {:?}", platform)))?;
+    let picked = scored
+        .into_iter()
+        .filter(|(total, _)| *total == max)
+        .map(|(_, item)| item)
+        .collect::<Vec<_>>();
+    if log_enabled!(log::Level::Debug) {
+        let names = picked.iter().map(|a| a.name()).collect::<Vec<_>>();
+        debug!("picked {} by the highest score {}", names.join(","), max);
+    }
+    for item in picked.iter().filter(|a| platform.is_emulated(a.name())) {
+        warn!(
+            "picked {} without a native {} build. it runs by emulation like rosetta",
+            item.name(),
+            ARCH
+        );
+    }
+    Ok(picked)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+struct WorkflowRuns {
+    workflow_runs: Vec<WorkflowRun>,
+}
+
+/// [List workflow runs for a workflow](https://docs.github.com/en/rest/actions/workflow-runs#list-workflow-runs-for-a-workflow)中的run
+#[derive(Serialize, Deserialize, Debug, Clone, Getters, PartialEq, Eq)]
+#[getset(get = "pub")]
+pub struct WorkflowRun {
+    /// used as the version of the `github-actions` source
+    id: i64,
+
+    run_number: i64,
+
+    head_branch: Option<String>,
+
+    head_sha: String,
+
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+struct Artifacts {
+    artifacts: Vec<Artifact>,
+}
+
+/// [List workflow run artifacts](https://docs.github.com/en/rest/actions/artifacts#list-workflow-run-artifacts)中的artifact
+#[derive(Serialize, Deserialize, Debug, Clone, Getters, PartialEq, Eq)]
+#[getset(get = "pub")]
+pub struct Artifact {
+    id: i64,
+
+    name: String,
+
+    size_in_bytes: i64,
+
+    /// the api url of the zip archive. requires a token to download
+    archive_download_url: String,
+
+    expired: bool,
+}
+
+/// [List repository tags](https://docs.github.com/en/rest/repos/repos#list-repository-tags)中的tag
+#[derive(Serialize, Deserialize, Debug, Clone, Getters, PartialEq, Eq)]
+#[getset(get = "pub")]
+pub struct Tag {
+    name: String,
+
+    /// the api url redirecting to the tarball without a file extension
+    tarball_url: String,
+
+    zipball_url: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Getters, PartialEq, Eq)]
@@ -397,6 +1584,10 @@ pub struct Asset {
 
     #[serde(rename = "browser_download_url")]
     browser_download_url: String,
+
+    /// the digest like `sha256:...`. none for the assets uploaded before github computed it
+    #[serde(rename = "digest", default)]
+    digest: Option<String>,
 }
 
 #[cfg(test)]
@@ -504,7 +1695,175 @@ mod tests {
     }
 
     #[test]
-    fn test_pick_by_name() -> Result<()> {
+    fn test_rate_limited() -> Result<()> {
+        let now = "2022-04-17T00:00:00Z".parse::<DateTime<Utc>>()?;
+        let headers = |pairs: &[(&'static str, &'static str)]| {
+            let mut headers = HeaderMap::new();
+            for (k, v) in pairs {
+                headers.insert(*k, v.parse().unwrap());
+            }
+            headers
+        };
+        let reset = headers(&[
+            ("x-ratelimit-remaining", "0"),
+            ("x-ratelimit-reset", "1650153600"),
+        ]);
+
+        assert_eq!(
+            RateLimited::from_response(StatusCode::FORBIDDEN, &reset, now),
+            Some(RateLimited {
+                reset: Some("2022-04-17T00:00:00Z".parse()?)
+            })
+        );
+        assert_eq!(
+            RateLimited::from_response(StatusCode::OK, &reset, now),
+            None
+        );
+        let remaining = headers(&[("x-ratelimit-remaining", "10")]);
+        assert_eq!(
+            RateLimited::from_response(StatusCode::FORBIDDEN, &remaining, now),
+            None
+        );
+        assert_eq!(
+            RateLimited::from_response(StatusCode::TOO_MANY_REQUESTS, &HeaderMap::new(), now),
+            Some(RateLimited { reset: None })
+        );
+        let retry = headers(&[("retry-after", "30")]);
+        assert_eq!(
+            RateLimited::from_response(StatusCode::FORBIDDEN, &retry, now),
+            Some(RateLimited {
+                reset: Some(now + chrono::Duration::seconds(30))
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_match_release() -> Result<()> {
+        let rels: Vec<Release> =
+            serde_json::from_str::<ResponseResult>(&read_to_string("tests/clash_releases.json")?)?
+                .to()?;
+        let tag = |ver| match_release(&rels, ver).map(|rel| rel.tag_name().as_str());
+
+        assert_eq!(tag("v1.10.0"), Some("v1.10.0"));
+        assert_eq!(tag("1.10.0"), Some("v1.10.0"));
+        assert_eq!(tag("1.10"), Some("v1.10.0"));
+        assert_eq!(tag("1.7"), Some("v1.7.1"));
+        assert_eq!(tag("1"), Some("v1.10.0"));
+        assert_eq!(tag("0.11"), Some("v0.11.1"));
+        assert_eq!(tag("Premium 2022.04.11"), Some("premium"));
+        assert_eq!(tag("1.1"), Some("v1.1.0"));
+        assert_eq!(tag("1.6.1"), None);
+        assert_eq!(tag("v"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_latest_unskipped_release() -> Result<()> {
+        let rels: Vec<Release> =
+            serde_json::from_str::<ResponseResult>(&read_to_string("tests/clash_releases.json")?)?
+                .to()?;
+        let tag = |skipped: &[&str]| {
+            let skipped = skipped.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+            latest_unskipped_release(&rels, &skipped).map(|rel| rel.tag_name().as_str())
+        };
+
+        assert_eq!(tag(&[]), Some("v1.10.0"));
+        assert_eq!(tag(&["1.10.0"]), Some("v1.9.0"));
+        assert_eq!(tag(&["v1.10.0", "v1.9.0"]), Some("v1.8.0"));
+        assert_eq!(tag(&["v1.9.0"]), Some("v1.10.0"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_latest_tag() -> Result<()> {
+        let tags = serde_json::from_value::<Vec<Tag>>(json!([
+            "v1.9.0",
+            "v1.10.0",
+            "nightly",
+            "v1.10.0-rc1",
+            "v1.2.3"
+        ]
+        .iter()
+        .map(|name| json!({
+            "name": name,
+            "tarball_url": format!("https://api.github.com/repos/a/b/tarball/{}", name),
+            "zipball_url": format!("https://api.github.com/repos/a/b/zipball/{}", name),
+        }))
+        .collect::<Vec<_>>()))?;
+        assert_eq!(
+            latest_tag(tags.clone()).map(|tag| tag.name),
+            Some("v1.10.0".to_owned())
+        );
+        assert_eq!(latest_tag(tags[2..3].to_vec()), None);
+
+        let bin = GithubBinaryBuilder::default()
+            .client(CLIENT.clone())
+            .binary(BinaryBuilder::default().source("github-tag:a/b")?.build()?)
+            .build()?;
+        assert_eq!(
+            bin.get_url("v1.10.0").await?.as_str(),
+            "https://github.com/a/b/archive/v1.10.0.tar.gz"
+        );
+        assert_eq!(bin.changelog("v1.10.0").await?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_pick_artifact() -> Result<()> {
+        let artifacts = [
+            ("nvim-macos", false),
+            ("nvim-linux64", false),
+            ("nvim-windows", true),
+        ]
+        .iter()
+        .enumerate()
+        .map(|(id, (name, expired))| {
+            json!({
+                "id": id,
+                "name": name,
+                "size_in_bytes": 1024,
+                "archive_download_url": format!(
+                    "https://api.github.com/repos/neovim/neovim/actions/artifacts/{}/zip",
+                    id
+                ),
+                "expired": expired,
+            })
+        })
+        .collect::<Vec<_>>();
+        let artifacts = serde_json::from_value::<Artifacts>(json!({
+            "total_count": artifacts.len(),
+            "artifacts": artifacts,
+        }))?
+        .artifacts;
+
+        let bin = |pick_regex: Option<&str>| {
+            let mut builder = BinaryBuilder::default();
+            builder
+                .name("nvim")
+                .source("github-actions:neovim/neovim/nightly.yml@master")?;
+            if let Some(re) = pick_regex {
+                builder.pick_regex(re);
+            }
+            GithubBinaryBuilder::default()
+                .client(CLIENT.clone())
+                .binary(builder.build()?)
+                .build()
+        };
+        let picked = bin(Some("{{name}}-mac"))?.pick_artifact(&artifacts)?;
+        assert_eq!(picked.name(), "nvim-macos");
+        #[cfg(target_os = "linux")]
+        {
+            let picked = bin(None)?.pick_artifact(&artifacts)?;
+            assert_eq!(picked.name(), "nvim-linux64");
+        }
+        assert!(bin(Some("windows"))?.pick_artifact(&artifacts).is_err());
+        assert!(bin(None)?.pick_artifact(&artifacts[2..]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_asset_digest() -> Result<()> {
         let bin = GithubBinaryBuilder::default()
             .client(CLIENT.clone())
             .binary(
@@ -513,53 +1872,316 @@ mod tests {
                     .build()?,
             )
             .build()?;
+        let rel: Release = serde_json::from_str::<ResponseResult>(&read_to_string(
+            "tests/clash_latest_release.json",
+        )?)?
+        .to()?;
+        let mut asset = rel.assets()[0].clone();
+        assert_eq!(asset.digest, None);
+        let url = bin.asset_url(&asset)?;
+        assert_eq!(bin.digest_of(&url), None);
+
+        asset.digest = Some("sha256:abc".to_owned());
+        let url = bin.asset_url(&asset)?;
+        assert_eq!(bin.digest_of(&url).as_deref(), Some("sha256:abc"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_checksum_assets() -> Result<()> {
+        let rel: Release = serde_json::from_str::<ResponseResult>(&read_to_string(
+            "tests/clash_latest_release.json",
+        )?)?
+        .to()?;
+        let assets = [
+            "a.tar.gz",
+            "b.zip",
+            "b.zip.sha256",
+            "a.tar.gz.asc",
+            "checksums.txt",
+            "SHA256SUMS",
+            "SHA256SUMS.sig",
+            "a.tar.gz.sha256",
+            "huge_checksums.txt",
+        ]
+        .into_iter()
+        .map(|name| {
+            let mut asset = rel.assets()[0].clone();
+            asset.name = name.to_owned();
+            asset.size = if name.starts_with("huge") {
+                MAX_CHECKSUMS_SIZE + 1
+            } else {
+                100
+            };
+            asset
+        })
+        .collect::<Vec<_>>();
+        let names = |name| {
+            checksum_assets(&assets, name)
+                .into_iter()
+                .map(|(a, single)| (a.name.as_str(), single))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names("a.tar.gz"),
+            [
+                ("a.tar.gz.sha256", true),
+                ("checksums.txt", false),
+                ("SHA256SUMS", false)
+            ]
+        );
+        assert_eq!(
+            names("b.zip"),
+            [
+                ("b.zip.sha256", true),
+                ("checksums.txt", false),
+                ("SHA256SUMS", false)
+            ]
+        );
+        Ok(())
+    }
 
-        let conditions = &[
-            vec![bin.bin().name().to_string()],
-            vec!["linux".to_string()],
-            get_archs(),
-        ];
+    #[test]
+    fn test_signer_workflow() -> Result<()> {
+        let out = json!([{
+            "verificationResult": {
+                "signature": {"certificate": {
+                    "subjectAlternativeName": "https://github.com/a/foo/.github/workflows/release.yml@refs/tags/v1.0.0",
+                    "buildSignerURI": "https://github.com/a/foo/.github/workflows/release.yml@refs/tags/v1.0.0",
+                }},
+            },
+        }]);
+        assert_eq!(
+            signer_workflow(out.to_string().as_bytes())?,
+            "https://github.com/a/foo/.github/workflows/release.yml@refs/tags/v1.0.0"
+        );
+        assert!(signer_workflow(b"[]").is_err());
+        assert!(signer_workflow(b"no attestations").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_private_asset_url() -> Result<()> {
+        let mut builder = GithubBinaryBuilder::default();
+        builder.client(CLIENT.clone()).binary(
+            BinaryBuilder::default()
+                .source("github:Dreamacro/clash")?
+                .build()?,
+        );
         let rel: Release = serde_json::from_str::<ResponseResult>(&read_to_string(
             "tests/clash_latest_release.json",
         )?)?
         .to()?;
-        let res = pick_by_name(rel.assets().iter(), conditions)?;
-        assert_eq!(res.clone().count(), 2);
+        let asset = &rel.assets()[0];
+
+        let bin = builder.build()?;
+        let url = bin.asset_url(asset)?;
+        assert_eq!(url.as_str(), asset.browser_download_url);
+        assert!(bin.download_headers(&url).is_empty());
 
-        let conditions = &[
-            vec![bin.bin().name().to_string()],
-            vec!["linux".to_string()],
-        ];
-        let res = pick_by_name(rel.assets().iter(), conditions)?;
-        assert_eq!(res.clone().count(), 13);
+        let bin = builder.token(Some(ApiToken::new("t")?)).build()?;
+        let url = bin.asset_url(asset)?;
+        assert_eq!(
+            url.as_str(),
+            format!(
+                "https://api.github.com/repos/Dreamacro/clash/releases/assets/{}#{}",
+                asset.id, asset.name
+            )
+        );
+        assert_eq!(
+            bin.download_headers(&url)[ACCEPT],
+            "application/octet-stream"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_name_pattern() -> Result<()> {
+        let re = Regex::new(&name_pattern("clash-linux-amd64-v1.10.0.gz"))?;
+        assert!(re.is_match("clash-linux-amd64-v1.11.2.gz"));
+        assert!(!re.is_match("clash-linux-amd64-v3-v1.11.2.gz"));
+        assert!(!re.is_match("clash-linux-amd64-v1.11.2.gz.sha256"));
+
+        let re = Regex::new(&name_pattern("a+b (1).zip"))?;
+        assert!(re.is_match("a+b (22).zip"));
+        Ok(())
+    }
 
-        // btm
+    #[test]
+    fn test_ignore_regex() -> Result<()> {
+        let rel: Release = serde_json::from_str::<ResponseResult>(&read_to_string(
+            "tests/clash_latest_release.json",
+        )?)?
+        .to()?;
         let bin = GithubBinaryBuilder::default()
             .client(CLIENT.clone())
             .binary(
                 BinaryBuilder::default()
-                    .source("github:ClementTsang/bottom")?
+                    .name("clash")
+                    .source("github:Dreamacro/clash")?
+                    .pick_regex("{{name}}-.+-amd64")
+                    .ignore_regex("-v3-|windows")
                     .build()?,
             )
             .build()?;
+        let mut names = bin
+            .pick_assets(&rel)?
+            .into_iter()
+            .map(|a| a.name())
+            .collect::<Vec<_>>();
+        names.sort_unstable();
+        assert_eq!(
+            names,
+            [
+                "clash-darwin-amd64-v1.10.0.gz",
+                "clash-freebsd-amd64-v1.10.0.gz",
+                "clash-linux-amd64-v1.10.0.gz"
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resolve_asset_by_pattern() -> Result<()> {
         let rel: Release = serde_json::from_str::<ResponseResult>(&read_to_string(
-            "tests/bottom_latest_release.json",
+            "tests/clash_latest_release.json",
         )?)?
         .to()?;
-        let conditions = &[
-            vec![
-                bin.bin().name().to_owned(),
-                rel.tag_name().to_owned(),
-                rel.name().to_owned(),
-            ],
-            vec!["linux".to_string()],
-            get_archs(),
-        ];
-        let res = pick_by_name(rel.assets().iter(), conditions)?;
-        assert_eq!(res.clone().count(), 4);
+        let bin = |pattern: &str| {
+            GithubBinaryBuilder::default()
+                .client(CLIENT.clone())
+                .binary(
+                    BinaryBuilder::default()
+                        .name("clash")
+                        .source("github:Dreamacro/clash")?
+                        .build()?,
+                )
+                .asset_pattern(Some(pattern.to_owned()))
+                .build()
+        };
+
+        let bin1 = bin(&name_pattern("clash-freebsd-arm64-v1.9.0.gz"))?;
+        let asset = bin1.resolve_asset(&rel).await?;
+        assert_eq!(asset.name(), "clash-freebsd-arm64-v1.10.0.gz");
+        assert_eq!(bin1.chosen_pattern(), None);
+
+        // fallback to pick if the pattern matches nothing
+        let bin2 = bin("^not-found$")?;
+        let asset = bin2.resolve_asset(&rel).await?;
+        assert_eq!(asset, bin2.pick_asset(&rel)?);
+        assert_eq!(bin2.chosen_pattern(), Some(name_pattern(asset.name())));
+        Ok(())
+    }
+
+    fn linux_x86_64(names: &[&str]) -> Platform {
+        Platform {
+            names: names.iter().map(|s| s.to_string()).collect(),
+            os: "linux".to_owned(),
+            archs: ["x86_64", "amd64", "x64", "intel", "linux64"]
+                .map(String::from)
+                .to_vec(),
+            emulated_archs: vec![],
+            target_env: "gnu".to_owned(),
+        }
+    }
+
+    fn macos_aarch64(names: &[&str]) -> Platform {
+        Platform {
+            names: names.iter().map(|s| s.to_string()).collect(),
+            os: "macos".to_owned(),
+            archs: ["arm64", "armv8", "aarch64"].map(String::from).to_vec(),
+            emulated_archs: ROSETTA_ARCHS.iter().map(|s| s.to_string()).collect(),
+            target_env: String::new(),
+        }
+    }
+
+    fn picked_names(platform: &Platform, path: &str) -> Result<Vec<String>> {
+        let rel: Release = serde_json::from_str::<ResponseResult>(&read_to_string(path)?)?.to()?;
+        let mut names = pick_by_name(rel.assets().iter(), platform)?
+            .into_iter()
+            .map(|a| a.name().to_owned())
+            .collect::<Vec<_>>();
+        names.sort();
+        Ok(names)
+    }
+
+    #[test]
+    fn test_pick_by_name() -> Result<()> {
+        let names = picked_names(&linux_x86_64(&["clash"]), "tests/clash_latest_release.json")?;
+        assert_eq!(
+            names,
+            [
+                "clash-linux-amd64-v1.10.0.gz",
+                "clash-linux-amd64-v3-v1.10.0.gz"
+            ]
+        );
+
+        let mut platform = linux_x86_64(&["btm", "0.6.8", "0.6.8 Release"]);
+        let path = "tests/bottom_latest_release.json";
+        assert_eq!(
+            picked_names(&platform, path)?,
+            [
+                "bottom_x86_64-unknown-linux-gnu.tar.gz",
+                "bottom_x86_64-unknown-linux-gnu2-17.tar.gz"
+            ]
+        );
+        platform.target_env = "musl".to_owned();
+        assert_eq!(
+            picked_names(&platform, path)?,
+            ["bottom_x86_64-unknown-linux-musl.tar.gz"]
+        );
+
+        platform.archs = vec!["s390x".to_owned()];
+        assert!(picked_names(&platform, path).is_err());
         Ok(())
     }
 
+    #[test]
+    fn test_score() {
+        let platform = linux_x86_64(&["bottom"]);
+        let score = |name| platform.score(name).map(|s| s.total());
+        assert_eq!(score("bottom_x86_64-apple-darwin.tar.gz"), None);
+        assert_eq!(score("bottom_aarch64-unknown-linux-gnu.tar.gz"), None);
+        assert_eq!(score("x86_64-linux-android.tar.gz"), None);
+        assert!(score("bottom_0.6.8_amd64.deb") < score("bottom_x86_64-linux.tar.gz"));
+        assert!(score("bottom-linux-x86_64.tar.gz") > score("bottom-linux-x86_64"));
+        assert!(score("bottom-linux-x86_64.tar.gz.sha256") < Some(0));
+        assert!(
+            score("bottom_x86_64-unknown-linux-gnueabihf.tar.gz")
+                > score("bottom_x86_64-unknown-linux-musl.tar.gz")
+        );
+
+        assert!(contains_word("clash-linux-amd64-v3", "amd64"));
+        assert!(!contains_word("clash-windows-amd64", "win"));
+        assert!(!contains_word("mips64le", "mips64"));
+        assert!(contains_prefix("x86_64-linux-gnueabihf", "gnu"));
+    }
+
+    #[test]
+    fn test_score_apple_silicon() {
+        let platform = macos_aarch64(&["fd"]);
+        let score = |name| platform.score(name).map(|s| s.total());
+        let native = score("fd-v1.0.0-aarch64-apple-darwin.tar.gz");
+        let universal = score("fd-v1.0.0-universal-apple-darwin.tar.gz");
+        let rosetta = score("fd-v1.0.0-x86_64-apple-darwin.tar.gz");
+        assert!(native > universal);
+        assert!(universal > rosetta);
+        assert!(rosetta > score("fd-v1.0.0-apple-darwin.tar.gz"));
+        assert_eq!(score("fd-v1.0.0-x86_64-unknown-linux-gnu.tar.gz"), None);
+        assert_eq!(score("fd-v1.0.0-riscv64-apple-darwin.tar.gz"), None);
+
+        assert!(platform.is_emulated("fd-macos-x86_64+rosetta.zip"));
+        assert!(!platform.is_emulated("fd-macos-arm64-x86_64.zip"));
+        assert!(!platform.is_emulated("fd-macos-universal.zip"));
+
+        // universal只用于macos
+        let linux = linux_x86_64(&["fd"]);
+        assert_eq!(
+            linux.score("fd-linux-universal.tar.gz").map(|s| s.arch),
+            Some(0)
+        );
+    }
+
     #[tokio::test]
     async fn test_pick_assets() -> Result<()> {
         let bin = GithubBinaryBuilder::default()
@@ -606,6 +2228,24 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_get_url_by_asset_url() -> Result<()> {
+        let bin = GithubBinaryBuilder::default()
+            .client(CLIENT.clone())
+            .binary(
+                BinaryBuilder::default()
+                    .name("clash")
+                    .source("github:Dreamacro/clash")?
+                    .asset_url("https://example.com/{{name}}-{{version}}.gz")
+                    .build()?,
+            )
+            .build()?;
+
+        let url = bin.get_url("v1.10.0").await?;
+        assert_eq!(url.as_str(), "https://example.com/clash-v1.10.0.gz");
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_pick_by_hook() -> Result<()> {
         let bin = GithubBinaryBuilder::default()
@@ -0,0 +1,243 @@
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use chrono::DateTime;
+use derive_builder::Builder;
+use getset::Getters;
+use log::{debug, trace};
+use reqwest::Client;
+use serde_json::json;
+use url::Url;
+
+use crate::{
+    config::{Binary, Source},
+    util::{platform_values, Templater},
+};
+
+use super::{ApiCache, Visible};
+
+/// 在[super::SourceRegistry]中注册的名称，如`from = "script:https://a.b/c.py"`
+pub const SCRIPT_SOURCE: &str = "script";
+
+/// 未配置`version-header`时依次尝试的headers
+const DEFAULT_VERSION_HEADERS: &[&str] = &["etag", "last-modified"];
+
+/// 单文件脚本的位置
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Location {
+    /// 可包含`{{version}}`的url模板
+    Url(String),
+    /// `gist:<id>/<file>`，版本为gist的revision
+    Gist { id: String, file: String },
+}
+
+impl Location {
+    fn parse(value: &str) -> Result<Self> {
+        match value.strip_prefix("gist:") {
+            Some(gist) => {
+                let (id, file) = gist
+                    .split_once('/')
+                    .filter(|(id, file)| !id.is_empty() && !file.is_empty())
+                    .ok_or_else(|| anyhow!("invalid gist `{}`. expected `gist:id/file`", value))?;
+                Ok(Location::Gist {
+                    id: id.to_owned(),
+                    file: file.to_owned(),
+                })
+            }
+            None => Ok(Location::Url(value.to_owned())),
+        }
+    }
+}
+
+/// 从url或gist下载的单文件脚本
+#[derive(Debug, Clone, Getters, Builder)]
+#[getset(get = "pub")]
+#[builder(setter(into))]
+pub struct ScriptBinary {
+    client: Client,
+
+    binary: Binary,
+
+    #[builder(default)]
+    templater: Templater,
+
+    /// the gist api responses shared in a run
+    #[builder(default)]
+    #[getset(skip)]
+    api_cache: ApiCache,
+}
+
+impl ScriptBinary {
+    fn location(&self) -> Result<Location> {
+        match self.binary.source() {
+            Source::Custom { name, value } if name == SCRIPT_SOURCE => Location::parse(value),
+            source => bail!("unsupported source {} by script", source),
+        }
+    }
+
+    fn render_url(&self, url: &str, ver: &str) -> Result<Url> {
+        let data = platform_values(json!({
+            "name": self.binary.name(),
+            "version": ver,
+        }))?;
+        let url = self.templater.render(url, &data)?;
+        url.trim().parse().map_err(Into::into)
+    }
+
+    async fn fetch_gist(&self, path: &str) -> Result<serde_json::Value> {
+        let url = format!("https://api.github.com/gists/{}", path).parse::<Url>()?;
+        self.api_cache
+            .get_or_fetch(&url, || async {
+                trace!("fetching gist {}", url);
+                self.client
+                    .get(url.clone())
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json::<serde_json::Value>()
+                    .await
+                    .map_err(Into::into)
+            })
+            .await
+    }
+
+    /// 请求url的headers获取版本
+    async fn header_version(&self, url: &Url) -> Result<String> {
+        let resp = self
+            .client
+            .head(url.clone())
+            .send()
+            .await?
+            .error_for_status()?;
+        let names = match self.binary.version_header() {
+            Some(name) => vec![name.as_str()],
+            None => DEFAULT_VERSION_HEADERS.to_vec(),
+        };
+        names
+            .iter()
+            .find_map(|name| {
+                let val = resp.headers().get(*name)?.to_str().ok()?;
+                debug!("found header {}: {} of {}", name, val, url);
+                version_of_header(name, val)
+            })
+            .ok_or_else(|| anyhow!("not found headers {:?} of {} for the version", names, url))
+    }
+}
+
+#[async_trait]
+impl Visible for ScriptBinary {
+    async fn latest_ver(&self) -> Result<String> {
+        match self.location()? {
+            Location::Gist { id, .. } => {
+                let gist = self.fetch_gist(&id).await?;
+                gist["history"][0]["version"]
+                    .as_str()
+                    .map(ToOwned::to_owned)
+                    .ok_or_else(|| anyhow!("not found the revision of gist {}", id))
+            }
+            Location::Url(url) if url.contains("{{") => {
+                bail!(
+                    "the url template of {} requires a `version` in config",
+                    self.binary.name()
+                )
+            }
+            Location::Url(url) => self.header_version(&url.trim().parse()?).await,
+        }
+    }
+
+    async fn get_url(&self, ver: &str) -> Result<Url> {
+        match self.location()? {
+            Location::Gist { id, file } => {
+                let gist = self.fetch_gist(&format!("{}/{}", id, ver)).await?;
+                let files = gist["files"]
+                    .as_object()
+                    .ok_or_else(|| anyhow!("not found files of gist {} {}", id, ver))?;
+                files
+                    .get(&file)
+                    .and_then(|f| f["raw_url"].as_str())
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "not found file {} in gist {}. available: {:?}",
+                            file,
+                            id,
+                            files.keys().collect::<Vec<_>>()
+                        )
+                    })?
+                    .parse()
+                    .map_err(Into::into)
+            }
+            Location::Url(url) => self.render_url(&url, ver),
+        }
+    }
+
+    fn digest_of(&self, _url: &Url) -> Option<String> {
+        self.binary.checksum().clone()
+    }
+
+    fn bin(&self) -> &Binary {
+        &self.binary
+    }
+}
+
+/// 将header的值转换为可作为目录名的版本
+///
+/// `last-modified`转换为`%Y%m%d%H%M%S`，etag去掉弱校验前缀与引号
+fn version_of_header(name: &str, val: &str) -> Option<String> {
+    let val = val.trim();
+    if name.eq_ignore_ascii_case("last-modified") {
+        if let Ok(date) = DateTime::parse_from_rfc2822(val) {
+            return Some(date.format("%Y%m%d%H%M%S").to_string());
+        }
+    }
+    let val = val.trim_start_matches("W/").trim_matches('"');
+    let ver = val
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-') {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect::<String>();
+    (!ver.is_empty()).then_some(ver)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_location() -> Result<()> {
+        assert_eq!(
+            Location::parse("https://a.b/{{version}}/c.py")?,
+            Location::Url("https://a.b/{{version}}/c.py".to_owned())
+        );
+        assert_eq!(
+            Location::parse("gist:abc/c.py")?,
+            Location::Gist {
+                id: "abc".to_owned(),
+                file: "c.py".to_owned()
+            }
+        );
+        assert!(Location::parse("gist:abc").is_err());
+        assert!(Location::parse("gist:/c.py").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_version_of_header() {
+        assert_eq!(
+            version_of_header("etag", "W/\"5f1-abc\"").as_deref(),
+            Some("5f1-abc")
+        );
+        assert_eq!(
+            version_of_header("Last-Modified", "Wed, 21 Oct 2015 07:28:00 GMT").as_deref(),
+            Some("20151021072800")
+        );
+        assert_eq!(
+            version_of_header("x-version", "1.0 beta").as_deref(),
+            Some("1.0-beta")
+        );
+        assert_eq!(version_of_header("etag", "\"\""), None);
+    }
+}
@@ -0,0 +1,192 @@
+use std::env::consts::OS;
+
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use derive_builder::Builder;
+use getset::Getters;
+use indexmap::IndexMap;
+use log::{debug, warn};
+use reqwest::Client;
+use serde_json::json;
+use url::Url;
+
+use crate::{
+    config::{Binary, PlatformUrls, Source},
+    util::{get_archs, platform_values, Templater},
+};
+
+use super::{
+    github::{pick_by_name, Named, Platform},
+    NoMatchedAsset, Visible,
+};
+
+/// 普通http服务器上按平台区分的文件
+#[derive(Debug, Clone, Getters, Builder)]
+#[getset(get = "pub")]
+#[builder(setter(into))]
+pub struct UrlsBinary {
+    client: Client,
+
+    binary: Binary,
+
+    #[builder(default)]
+    templater: Templater,
+
+    /// the arch aliases in config for [get_archs]
+    #[builder(default)]
+    arch_aliases: IndexMap<String, Vec<String>>,
+}
+
+/// 渲染后的url与其文件名
+#[derive(Debug)]
+struct Candidate {
+    name: String,
+    url: Url,
+}
+
+impl Named for Candidate {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl UrlsBinary {
+    fn urls(&self) -> Result<&PlatformUrls> {
+        match self.binary.source() {
+            Source::Urls(urls) => Ok(urls),
+            source => Err(anyhow!("unsupported source {} by urls", source)),
+        }
+    }
+
+    fn render(&self, url: &str, ver: &str) -> Result<Url> {
+        let data = platform_values(json!({
+            "name": self.binary.name(),
+            "version": ver,
+        }))?;
+        let url = self.templater.render(url, &data)?;
+        url.trim().parse().map_err(Into::into)
+    }
+
+    /// 当前平台可用的key，依次为`os-arch`与`os`
+    fn platform_keys(&self) -> Vec<String> {
+        let oss = match OS {
+            "macos" => vec!["macos", "darwin"],
+            os => vec![os],
+        };
+        let archs = get_archs(&self.arch_aliases);
+        oss.iter()
+            .flat_map(|os| archs.iter().map(move |arch| format!("{}-{}", os, arch)))
+            .chain(oss.iter().map(|os| os.to_string()))
+            .collect()
+    }
+}
+
+#[async_trait]
+impl Visible for UrlsBinary {
+    /// 没有`version-from`时无法获取最新版本
+    async fn latest_ver(&self) -> Result<String> {
+        bail!(
+            "the urls source of {} requires a `version` or `version-from` in config",
+            self.binary.name()
+        )
+    }
+
+    async fn get_url(&self, ver: &str) -> Result<Url> {
+        match self.urls()? {
+            PlatformUrls::Map(map) => {
+                let keys = self.platform_keys();
+                let url = keys
+                    .iter()
+                    .find_map(|key| {
+                        map.iter()
+                            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+                            .map(|(_, url)| url)
+                    })
+                    .ok_or_else(|| NoMatchedAsset(format!("in urls for {:?}", keys)))?;
+                debug!("found the url {} of the platform for {}", url, ver);
+                self.render(url, ver)
+            }
+            PlatformUrls::List(urls) => {
+                let candidates = urls
+                    .iter()
+                    .map(|url| {
+                        let url = self.render(url, ver)?;
+                        let name = url
+                            .path_segments()
+                            .and_then(|mut seg| seg.next_back())
+                            .unwrap_or_default()
+                            .to_owned();
+                        Ok(Candidate { name, url })
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let platform = Platform::current(
+                    vec![self.binary.name().to_owned(), ver.to_owned()],
+                    &self.arch_aliases,
+                );
+                let picked = pick_by_name(candidates.iter(), &platform)?;
+                if picked.len() > 1 {
+                    warn!(
+                        "picked the first of {} urls: {:?}",
+                        picked.len(),
+                        picked.iter().map(|c| c.url.as_str()).collect::<Vec<_>>()
+                    );
+                }
+                Ok(picked[0].url.clone())
+            }
+        }
+    }
+
+    fn bin(&self) -> &Binary {
+        &self.binary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::consts::ARCH;
+
+    use crate::config::BinaryBuilder;
+
+    use super::*;
+
+    fn urls_binary(urls: &str) -> Result<UrlsBinary> {
+        let bin = BinaryBuilder::default()
+            .name("foo")
+            .source(format!("urls:{}", urls).as_str())?
+            .build()?;
+        UrlsBinaryBuilder::default()
+            .client(Client::new())
+            .binary(bin)
+            .build()
+            .map_err(Into::into)
+    }
+
+    #[tokio::test]
+    async fn test_get_url() -> Result<()> {
+        let bin = urls_binary(&format!(
+            "other-x=https://a.b/o,{}-{}=https://a.b/{{{{version}}}}/foo.tgz",
+            OS, ARCH
+        ))?;
+        assert_eq!(
+            bin.get_url("1.0").await?.as_str(),
+            "https://a.b/1.0/foo.tgz"
+        );
+
+        let bin = urls_binary(&format!("{}=https://a.b/{{{{name}}}}", OS))?;
+        assert_eq!(bin.get_url("1.0").await?.as_str(), "https://a.b/foo");
+
+        let bin = urls_binary("other=https://a.b/o")?;
+        let e = bin.get_url("1.0").await.unwrap_err();
+        assert!(e.is::<NoMatchedAsset>());
+
+        let bin = urls_binary(&format!(
+            "https://a.b/foo-{{{{version}}}}-other-sparc.tgz,https://a.b/foo-{{{{version}}}}-{}-{}.tgz",
+            OS, ARCH
+        ))?;
+        assert_eq!(
+            bin.get_url("1.0").await?.as_str(),
+            format!("https://a.b/foo-1.0-{}-{}.tgz", OS, ARCH)
+        );
+        Ok(())
+    }
+}
@@ -2,11 +2,16 @@ use anyhow::Result;
 use chrono::{DateTime, Local};
 use derive_builder::Builder;
 use getset::{Getters, Setters};
+use serde::{Deserialize, Serialize};
 use sqlx::SqlitePool;
 
+use crate::config::Source;
+
 // static RB: Lazy<Rbatis> = Lazy::new(Rbatis::new);
 
-#[derive(sqlx::FromRow, Debug, Clone, PartialEq, Eq, Getters, Setters, Builder)]
+#[derive(
+    sqlx::FromRow, Debug, Clone, PartialEq, Eq, Getters, Setters, Builder, Serialize, Deserialize,
+)]
 #[getset(get = "pub", set = "pub")]
 #[builder(setter(into))]
 pub struct UpdatedInfo {
@@ -20,6 +25,114 @@ pub struct UpdatedInfo {
     updated_time: DateTime<Local>,
     #[builder(default = "Local::now()")]
     create_time: DateTime<Local>,
+    /// the version rolled back from
+    #[builder(default)]
+    rollback_from: Option<String>,
+}
+
+impl UpdatedInfo {
+    /// 解析记录的source，兼容旧版本记录的json格式
+    pub fn parse_source(&self) -> Result<Source> {
+        self.source.parse::<Source>().or_else(|e| {
+            serde_json::from_str::<Source>(&self.source).map_err(|_| {
+                e.context(format!(
+                    "failed to parse source `{}` of {}",
+                    self.source, self.name
+                ))
+            })
+        })
+    }
+}
+
+impl UpdatedInfoBuilder {
+    /// 使用[Source]的字符串格式记录source
+    pub fn typed_source(&mut self, source: &Source) -> &mut Self {
+        self.source(source.to_string())
+    }
+}
+
+/// 固定不再更新的binary版本
+#[derive(
+    sqlx::FromRow, Debug, Clone, PartialEq, Eq, Getters, Setters, Builder, Serialize, Deserialize,
+)]
+#[getset(get = "pub", set = "pub")]
+#[builder(setter(into))]
+pub struct PinnedInfo {
+    name: String,
+    version: String,
+    #[builder(default = "Local::now()")]
+    create_time: DateTime<Local>,
+}
+
+/// 安装时版本目录中的一个文件与其digest，用于verify
+#[derive(
+    sqlx::FromRow, Debug, Clone, PartialEq, Eq, Getters, Setters, Builder, Serialize, Deserialize,
+)]
+#[getset(get = "pub", set = "pub")]
+#[builder(setter(into))]
+pub struct InstalledFile {
+    #[builder(default = "0")]
+    id: u32,
+    name: String,
+    version: String,
+    /// the path relative to the version dir
+    path: String,
+    digest: String,
+}
+
+/// 一次安装操作的结果与耗时
+#[derive(sqlx::FromRow, Debug, Clone, PartialEq, Eq, Getters, Setters, Builder, Serialize)]
+#[getset(get = "pub", set = "pub")]
+#[builder(setter(into))]
+pub struct InstallStat {
+    #[builder(default = "0")]
+    id: u32,
+    name: String,
+    version: String,
+    /// one of install, update, rollback and repair
+    action: String,
+    /// the filename of the downloaded asset
+    asset: String,
+    duration_ms: i64,
+    /// 0 if the cached asset is used
+    downloaded_bytes: i64,
+    success: bool,
+    #[builder(default)]
+    error: Option<String>,
+    #[builder(default = "Local::now()")]
+    create_time: DateTime<Local>,
+}
+
+/// 安装时链接路径上已存在的文件被`install --force`替换或`install --adopt`接管的记录
+#[derive(sqlx::FromRow, Debug, Clone, PartialEq, Eq, Getters, Setters, Builder, Serialize)]
+#[getset(get = "pub", set = "pub")]
+#[builder(setter(into))]
+pub struct LinkConflict {
+    /// the link path of the existing file
+    path: String,
+    name: String,
+    /// one of replace and adopt
+    action: String,
+    /// where the adopted file is backed up
+    #[builder(default)]
+    backup: Option<String>,
+    #[builder(default = "Local::now()")]
+    create_time: DateTime<Local>,
+}
+
+/// 由os提供并代替下载使用的可执行文件
+#[derive(sqlx::FromRow, Debug, Clone, PartialEq, Eq, Getters, Setters, Builder, Serialize)]
+#[getset(get = "pub", set = "pub")]
+#[builder(setter(into))]
+pub struct ExternalBinary {
+    name: String,
+    /// the path of the executable found on PATH
+    path: String,
+    version: String,
+    /// one of apt, pacman, rpm, brew and path
+    provider: String,
+    #[builder(default = "Local::now()")]
+    create_time: DateTime<Local>,
 }
 
 #[derive(Debug, Clone)]
@@ -43,9 +156,51 @@ impl Mapper {
             .map_err(Into::into)
     }
 
+    /// 获取name当前使用的info，即最近更新的info
+    pub async fn select_current_by_name(&self, name: &str) -> Result<Option<UpdatedInfo>> {
+        sqlx::query_as::<_, UpdatedInfo>(
+            "select * from updated_info where name = ? order by updated_time desc, id desc limit 1",
+        )
+        .bind(name)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// 获取name的所有info，按最近更新的顺序排列，第一个为当前使用的info
+    pub async fn select_history_by_name(&self, name: &str) -> Result<Vec<UpdatedInfo>> {
+        sqlx::query_as::<_, UpdatedInfo>(
+            "select * from updated_info where name = ? order by updated_time desc, id desc",
+        )
+        .bind(name)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// 仅保留name最近更新的keep个info，返回删除的info
+    pub async fn delete_old_by_name(&self, name: &str, keep: usize) -> Result<Vec<UpdatedInfo>> {
+        let mut tx = self.pool.begin().await?;
+        let olds = sqlx::query_as::<_, UpdatedInfo>(
+            "select * from updated_info where name = ? order by updated_time desc, id desc limit -1 offset ?",
+        )
+        .bind(name)
+        .bind(keep as u32)
+        .fetch_all(&mut tx)
+        .await?;
+        for info in &olds {
+            sqlx::query("delete from updated_info where id = ?")
+                .bind(info.id)
+                .execute(&mut tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(olds)
+    }
+
     pub async fn insert(&self, info: &UpdatedInfo) -> Result<u32> {
         sqlx::query(
-            "insert into updated_info(name, version, source, url, updated_time, create_time) values(?, ?, ?, ?, ?, ?)",
+            "insert into updated_info(name, version, source, url, updated_time, create_time, rollback_from) values(?, ?, ?, ?, ?, ?, ?)",
         )
         .bind(&info.name)
         .bind(&info.version)
@@ -53,12 +208,36 @@ impl Mapper {
         .bind(&info.url())
         .bind(&info.updated_time)
         .bind(&info.create_time)
+        .bind(&info.rollback_from)
         .execute(&self.pool)
         .await
         .map(|e| e.last_insert_rowid() as u32)
         .map_err(Into::into)
     }
 
+    /// 标记id对应的info为从版本from回滚的当前使用info
+    pub async fn update_rollback(&self, id: u32, from: &str) -> Result<usize> {
+        sqlx::query("update updated_info set rollback_from = ?, updated_time = ? where id = ?")
+            .bind(from)
+            .bind(Local::now())
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map(|r| r.rows_affected() as usize)
+            .map_err(Into::into)
+    }
+
+    /// 标记id对应的info为当前使用的info
+    pub async fn touch_by_id(&self, id: u32) -> Result<usize> {
+        sqlx::query("update updated_info set rollback_from = null, updated_time = ? where id = ?")
+            .bind(Local::now())
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map(|r| r.rows_affected() as usize)
+            .map_err(Into::into)
+    }
+
     pub async fn delete_by_name(&self, name: &str) -> Result<usize> {
         sqlx::query("delete from updated_info where name = ?")
             .bind(name)
@@ -67,6 +246,430 @@ impl Mapper {
             .map(|r| r.rows_affected() as usize)
             .map_err(Into::into)
     }
+
+    pub async fn delete_by_id(&self, id: u32) -> Result<usize> {
+        sqlx::query("delete from updated_info where id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .map(|r| r.rows_affected() as usize)
+            .map_err(Into::into)
+    }
+
+    /// 获取table的所有列名，如果table不存在则为空
+    pub async fn select_columns(&self, table: &str) -> Result<Vec<String>> {
+        sqlx::query_scalar::<_, String>("select name from pragma_table_info(?)")
+            .bind(table)
+            .fetch_all(&self.pool)
+            .await
+            .map_err(Into::into)
+    }
+
+    pub async fn select_files(&self, name: &str, version: &str) -> Result<Vec<InstalledFile>> {
+        sqlx::query_as::<_, InstalledFile>(
+            "select * from installed_file where name = ? and version = ? order by path",
+        )
+        .bind(name)
+        .bind(version)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// 替换name与version的所有files
+    pub async fn replace_files(
+        &self,
+        name: &str,
+        version: &str,
+        files: &[InstalledFile],
+    ) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("delete from installed_file where name = ? and version = ?")
+            .bind(name)
+            .bind(version)
+            .execute(&mut tx)
+            .await?;
+        for file in files {
+            sqlx::query(
+                "insert into installed_file(name, version, path, digest) values(?, ?, ?, ?)",
+            )
+            .bind(&file.name)
+            .bind(&file.version)
+            .bind(&file.path)
+            .bind(&file.digest)
+            .execute(&mut tx)
+            .await?;
+        }
+        tx.commit().await.map_err(Into::into)
+    }
+
+    pub async fn delete_files(&self, name: &str, version: &str) -> Result<usize> {
+        sqlx::query("delete from installed_file where name = ? and version = ?")
+            .bind(name)
+            .bind(version)
+            .execute(&self.pool)
+            .await
+            .map(|r| r.rows_affected() as usize)
+            .map_err(Into::into)
+    }
+
+    pub async fn delete_files_by_name(&self, name: &str) -> Result<usize> {
+        sqlx::query("delete from installed_file where name = ?")
+            .bind(name)
+            .execute(&self.pool)
+            .await
+            .map(|r| r.rows_affected() as usize)
+            .map_err(Into::into)
+    }
+
+    pub async fn insert_stat(&self, stat: &InstallStat) -> Result<u32> {
+        sqlx::query(
+            "insert into install_stat(name, version, action, asset, duration_ms, downloaded_bytes, success, error, create_time) values(?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&stat.name)
+        .bind(&stat.version)
+        .bind(&stat.action)
+        .bind(&stat.asset)
+        .bind(stat.duration_ms)
+        .bind(stat.downloaded_bytes)
+        .bind(stat.success)
+        .bind(&stat.error)
+        .bind(stat.create_time)
+        .execute(&self.pool)
+        .await
+        .map(|e| e.last_insert_rowid() as u32)
+        .map_err(Into::into)
+    }
+
+    /// 获取name最近的limit个安装结果，按最近的顺序排列
+    pub async fn select_stats_by_name(&self, name: &str, limit: u32) -> Result<Vec<InstallStat>> {
+        sqlx::query_as::<_, InstallStat>(
+            "select * from install_stat where name = ? order by create_time desc, id desc limit ?",
+        )
+        .bind(name)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    pub async fn select_all_pinned(&self) -> Result<Vec<PinnedInfo>> {
+        sqlx::query_as::<_, PinnedInfo>("select * from pinned_info")
+            .fetch_all(&self.pool)
+            .await
+            .map_err(Into::into)
+    }
+
+    pub async fn select_pinned_by_name(&self, name: &str) -> Result<Option<PinnedInfo>> {
+        sqlx::query_as::<_, PinnedInfo>("select * from pinned_info where name = ?")
+            .bind(name)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(Into::into)
+    }
+
+    /// 插入pinned info，如果已存在name则替换
+    pub async fn insert_pinned(&self, info: &PinnedInfo) -> Result<()> {
+        sqlx::query(
+            "insert or replace into pinned_info(name, version, create_time) values(?, ?, ?)",
+        )
+        .bind(&info.name)
+        .bind(&info.version)
+        .bind(&info.create_time)
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+        .map_err(Into::into)
+    }
+
+    pub async fn delete_pinned_by_name(&self, name: &str) -> Result<usize> {
+        sqlx::query("delete from pinned_info where name = ?")
+            .bind(name)
+            .execute(&self.pool)
+            .await
+            .map(|r| r.rows_affected() as usize)
+            .map_err(Into::into)
+    }
+
+    pub async fn is_disabled(&self, name: &str) -> Result<bool> {
+        sqlx::query_scalar::<_, u32>("select count(*) from disabled_info where name = ?")
+            .bind(name)
+            .fetch_one(&self.pool)
+            .await
+            .map(|n| n != 0)
+            .map_err(Into::into)
+    }
+
+    /// 插入disabled的name，如果已存在则忽略
+    pub async fn insert_disabled(&self, name: &str) -> Result<()> {
+        sqlx::query("insert or ignore into disabled_info(name, create_time) values(?, ?)")
+            .bind(name)
+            .bind(Local::now())
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+            .map_err(Into::into)
+    }
+
+    pub async fn delete_disabled_by_name(&self, name: &str) -> Result<usize> {
+        sqlx::query("delete from disabled_info where name = ?")
+            .bind(name)
+            .execute(&self.pool)
+            .await
+            .map(|r| r.rows_affected() as usize)
+            .map_err(Into::into)
+    }
+
+    pub async fn select_asset_pattern(&self, name: &str) -> Result<Option<String>> {
+        sqlx::query_scalar::<_, String>("select pattern from asset_pattern where name = ?")
+            .bind(name)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(Into::into)
+    }
+
+    /// 插入name的asset pattern，如果已存在则替换
+    pub async fn insert_asset_pattern(&self, name: &str, pattern: &str) -> Result<()> {
+        sqlx::query(
+            "insert or replace into asset_pattern(name, pattern, create_time) values(?, ?, ?)",
+        )
+        .bind(name)
+        .bind(pattern)
+        .bind(Local::now())
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+        .map_err(Into::into)
+    }
+
+    pub async fn delete_asset_pattern_by_name(&self, name: &str) -> Result<usize> {
+        sqlx::query("delete from asset_pattern where name = ?")
+            .bind(name)
+            .execute(&self.pool)
+            .await
+            .map(|r| r.rows_affected() as usize)
+            .map_err(Into::into)
+    }
+
+    pub async fn select_link_conflicts_by_name(&self, name: &str) -> Result<Vec<LinkConflict>> {
+        sqlx::query_as::<_, LinkConflict>(
+            "select * from link_conflict where name = ? order by create_time",
+        )
+        .bind(name)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// 插入link conflict，如果已存在path则替换
+    pub async fn insert_link_conflict(&self, conflict: &LinkConflict) -> Result<()> {
+        sqlx::query(
+            "insert or replace into link_conflict(path, name, action, backup, create_time) values(?, ?, ?, ?, ?)",
+        )
+        .bind(conflict.path())
+        .bind(conflict.name())
+        .bind(conflict.action())
+        .bind(conflict.backup())
+        .bind(conflict.create_time())
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+        .map_err(Into::into)
+    }
+
+    pub async fn delete_link_conflicts_by_name(&self, name: &str) -> Result<usize> {
+        sqlx::query("delete from link_conflict where name = ?")
+            .bind(name)
+            .execute(&self.pool)
+            .await
+            .map(|r| r.rows_affected() as usize)
+            .map_err(Into::into)
+    }
+
+    pub async fn select_artifacts_by_name(&self, name: &str) -> Result<Vec<String>> {
+        sqlx::query_scalar::<_, String>(
+            "select path from installed_artifact where name = ? order by path",
+        )
+        .bind(name)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// 记录name在data dir外创建的文件paths，已存在的path会被替换
+    pub async fn insert_artifacts(&self, name: &str, paths: &[String]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for path in paths {
+            sqlx::query(
+                "insert or replace into installed_artifact(path, name, create_time) values(?, ?, ?)",
+            )
+            .bind(path)
+            .bind(name)
+            .bind(Local::now())
+            .execute(&mut tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn delete_artifacts_by_name(&self, name: &str) -> Result<usize> {
+        sqlx::query("delete from installed_artifact where name = ?")
+            .bind(name)
+            .execute(&self.pool)
+            .await
+            .map(|r| r.rows_affected() as usize)
+            .map_err(Into::into)
+    }
+
+    /// 所有缓存文件的path与最近访问时间，按访问时间从旧到新
+    pub async fn select_cache_accesses(&self) -> Result<Vec<(String, DateTime<Local>)>> {
+        sqlx::query_as::<_, (String, DateTime<Local>)>(
+            "select path, access_time from cache_access order by access_time",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// 更新name的缓存文件path的访问时间为现在
+    pub async fn touch_cache(&self, path: &str, name: &str) -> Result<()> {
+        sqlx::query("insert or replace into cache_access(path, name, access_time) values(?, ?, ?)")
+            .bind(path)
+            .bind(name)
+            .bind(Local::now())
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+            .map_err(Into::into)
+    }
+
+    pub async fn delete_cache_access(&self, path: &str) -> Result<usize> {
+        sqlx::query("delete from cache_access where path = ?")
+            .bind(path)
+            .execute(&self.pool)
+            .await
+            .map(|r| r.rows_affected() as usize)
+            .map_err(Into::into)
+    }
+
+    /// name的版本version首次安装时记录的asset digest
+    pub async fn select_asset_digest(&self, name: &str, version: &str) -> Result<Option<String>> {
+        sqlx::query_scalar::<_, String>(
+            "select digest from asset_digest where name = ? and version = ?",
+        )
+        .bind(name)
+        .bind(version)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// 记录name的版本version的asset digest，如果已存在则替换
+    pub async fn insert_asset_digest(&self, name: &str, version: &str, digest: &str) -> Result<()> {
+        sqlx::query(
+            "insert or replace into asset_digest(name, version, digest, create_time) values(?, ?, ?, ?)",
+        )
+        .bind(name)
+        .bind(version)
+        .bind(digest)
+        .bind(Local::now())
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+        .map_err(Into::into)
+    }
+
+    pub async fn select_external_by_name(&self, name: &str) -> Result<Option<ExternalBinary>> {
+        sqlx::query_as::<_, ExternalBinary>("select * from external_binary where name = ?")
+            .bind(name)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(Into::into)
+    }
+
+    /// 记录由os提供的bin，如果已存在则替换
+    pub async fn insert_external(&self, external: &ExternalBinary) -> Result<()> {
+        sqlx::query(
+            "insert or replace into external_binary(name, path, version, provider, create_time) values(?, ?, ?, ?, ?)",
+        )
+        .bind(external.name())
+        .bind(external.path())
+        .bind(external.version())
+        .bind(external.provider())
+        .bind(external.create_time())
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+        .map_err(Into::into)
+    }
+
+    pub async fn delete_external_by_name(&self, name: &str) -> Result<usize> {
+        sqlx::query("delete from external_binary where name = ?")
+            .bind(name)
+            .execute(&self.pool)
+            .await
+            .map(|r| r.rows_affected() as usize)
+            .map_err(Into::into)
+    }
+
+    /// bin被跳过的所有版本
+    pub async fn select_skipped_by_name(&self, name: &str) -> Result<Vec<String>> {
+        sqlx::query_scalar::<_, String>(
+            "select version from skipped_version where name = ? order by create_time, version",
+        )
+        .bind(name)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// 记录跳过的版本，已存在时忽略
+    pub async fn insert_skipped(&self, name: &str, version: &str) -> Result<()> {
+        sqlx::query(
+            "insert or ignore into skipped_version(name, version, create_time) values(?, ?, ?)",
+        )
+        .bind(name)
+        .bind(version)
+        .bind(Local::now())
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+        .map_err(Into::into)
+    }
+
+    pub async fn delete_skipped(&self, name: &str, version: &str) -> Result<usize> {
+        sqlx::query("delete from skipped_version where name = ? and version = ?")
+            .bind(name)
+            .bind(version)
+            .execute(&self.pool)
+            .await
+            .map(|r| r.rows_affected() as usize)
+            .map_err(Into::into)
+    }
+
+    /// 信任的配置path的hooks digest
+    pub async fn select_trusted_digest(&self, path: &str) -> Result<Option<String>> {
+        sqlx::query_scalar::<_, String>("select digest from trusted_config where path = ?")
+            .bind(path)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(Into::into)
+    }
+
+    /// 信任配置path中digest的hooks，如果已存在则替换
+    pub async fn insert_trusted_config(&self, path: &str, digest: &str) -> Result<()> {
+        sqlx::query(
+            "insert or replace into trusted_config(path, digest, create_time) values(?, ?, ?)",
+        )
+        .bind(path)
+        .bind(digest)
+        .bind(Local::now())
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+        .map_err(Into::into)
+    }
 }
 #[cfg(test)]
 mod tests {
@@ -79,8 +682,6 @@ mod tests {
     use std::thread;
     use tokio::{fs::read_to_string, runtime::Runtime};
 
-    use crate::config::Source;
-
     use super::*;
 
     pub static TOKIO_RT: Lazy<Runtime> = Lazy::new(|| {
@@ -90,7 +691,10 @@ mod tests {
             .unwrap()
     });
 
-    static MAPPER: Lazy<Mapper> = Lazy::new(|| {
+    static MAPPER: Lazy<Mapper> = Lazy::new(new_mapper);
+
+    /// 创建一个独立的in memory db mapper
+    fn new_mapper() -> Mapper {
         thread::spawn(|| {
             let pool = TOKIO_RT
                 .block_on(async {
@@ -113,7 +717,7 @@ mod tests {
         })
         .join()
         .unwrap()
-    });
+    }
 
     #[test]
     fn test_select_name() -> Result<()> {
@@ -147,7 +751,7 @@ mod tests {
             assert_eq!(infos[0].id, 1);
             assert_eq!(infos[0].name, "btm");
             assert_eq!(infos[0].version, "v0.6.0");
-            assert_eq!(infos[0].source, r#"{"github":{"owner":"ClementTsang","repo":"bottom"}}"#);
+            assert_eq!(infos[0].source, "github:ClementTsang/bottom");
             assert_eq!(infos[0].url, r#"https://github.com/ClementTsang/bottom/releases/download/0.6.8/bottom_x86_64-unknown-linux-gnu.tar.gz"#);
             assert_eq!(infos[0].create_time, parse_date("2020-06-17 20:10:23")?);
             assert_eq!(infos[0].updated_time, parse_date("2020-06-17 20:10:23")?);
@@ -167,14 +771,33 @@ mod tests {
         })
     }
 
+    #[test]
+    fn test_parse_source() -> Result<()> {
+        let source = "github:a/b".parse::<Source>()?;
+        let mut info = UpdatedInfoBuilder::default()
+            .name("a")
+            .version("v0.1.0")
+            .url("")
+            .typed_source(&source)
+            .build()?;
+        assert_eq!(info.source(), "github:a/b");
+        assert_eq!(info.parse_source()?, source);
+
+        info.set_source(serde_json::to_string(&source)?);
+        assert_eq!(info.parse_source()?, source);
+
+        info.set_source("a/b".to_owned());
+        assert!(info.parse_source().is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_insert() -> Result<()> {
         TOKIO_RT.block_on(async {
-            let source = serde_json::to_string(&"github:a/b".parse::<Source>()?)?;
             let info = UpdatedInfoBuilder::default()
                 .name("tldr")
                 .version("v0.3.0")
-                .source(&source)
+                .typed_source(&"github:a/b".parse::<Source>()?)
                 .url("https://github.com/dbrgn/tealdeer/releases/download/v1.5.0/tealdeer-linux-x86_64-musl")
                 .build()?;
 
@@ -189,6 +812,411 @@ mod tests {
         })
     }
 
+    #[test]
+    fn test_update_rollback() -> Result<()> {
+        let mapper = new_mapper();
+        TOKIO_RT.block_on(async {
+            let name = "__rollback__";
+            for ver in ["v0.1.0", "v0.2.0"] {
+                let info = UpdatedInfoBuilder::default()
+                    .name(name)
+                    .version(ver)
+                    .source("")
+                    .url("")
+                    .build()?;
+                mapper.insert(&info).await?;
+            }
+            let cur = mapper.select_current_by_name(name).await?.unwrap();
+            assert_eq!(cur.version(), "v0.2.0");
+
+            let infos = mapper.select_list_by_name(name).await?;
+            let old = infos.iter().find(|i| i.version() == "v0.1.0").unwrap();
+            assert_eq!(mapper.update_rollback(*old.id(), cur.version()).await?, 1);
+
+            let cur = mapper.select_current_by_name(name).await?.unwrap();
+            assert_eq!(cur.version(), "v0.1.0");
+            assert_eq!(cur.rollback_from().as_deref(), Some("v0.2.0"));
+            Ok::<_, Error>(())
+        })
+    }
+
+    #[test]
+    fn test_delete_old_by_name() -> Result<()> {
+        let mapper = new_mapper();
+        TOKIO_RT.block_on(async {
+            let name = "__history__";
+            for ver in ["v0.1.0", "v0.2.0", "v0.3.0"] {
+                let info = UpdatedInfoBuilder::default()
+                    .name(name)
+                    .version(ver)
+                    .source("")
+                    .url("")
+                    .build()?;
+                mapper.insert(&info).await?;
+            }
+            let infos = mapper.select_list_by_name(name).await?;
+            let old = infos.iter().find(|i| i.version() == "v0.1.0").unwrap();
+            mapper.touch_by_id(*old.id()).await?;
+
+            let versions = |infos: Vec<UpdatedInfo>| {
+                infos
+                    .into_iter()
+                    .map(|i| i.version().to_owned())
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(
+                versions(mapper.select_history_by_name(name).await?),
+                ["v0.1.0", "v0.3.0", "v0.2.0"]
+            );
+
+            assert!(mapper.delete_old_by_name(name, 3).await?.is_empty());
+            assert_eq!(
+                versions(mapper.delete_old_by_name(name, 1).await?),
+                ["v0.3.0", "v0.2.0"]
+            );
+            assert_eq!(
+                versions(mapper.select_history_by_name(name).await?),
+                ["v0.1.0"]
+            );
+            Ok::<_, Error>(())
+        })
+    }
+
+    #[test]
+    fn test_stats() -> Result<()> {
+        let mapper = new_mapper();
+        TOKIO_RT.block_on(async {
+            let name = "__stats__";
+            for (ver, err) in [("v0.1.0", None), ("v0.2.0", Some("timeout"))] {
+                let stat = InstallStatBuilder::default()
+                    .name(name)
+                    .version(ver)
+                    .action("install")
+                    .asset("a.tar.gz")
+                    .duration_ms(1200)
+                    .downloaded_bytes(1024)
+                    .success(err.is_none())
+                    .error(err.map(ToOwned::to_owned))
+                    .build()?;
+                mapper.insert_stat(&stat).await?;
+            }
+
+            let stats = mapper.select_stats_by_name(name, 10).await?;
+            assert_eq!(stats.len(), 2);
+            assert_eq!(stats[0].version(), "v0.2.0");
+            assert!(!stats[0].success());
+            assert_eq!(stats[0].error().as_deref(), Some("timeout"));
+            assert!(stats[1].success());
+
+            assert_eq!(mapper.select_stats_by_name(name, 1).await?.len(), 1);
+            Ok::<_, Error>(())
+        })
+    }
+
+    #[test]
+    fn test_pinned() -> Result<()> {
+        TOKIO_RT.block_on(async {
+            let name = "__pinned__";
+            assert_eq!(MAPPER.select_pinned_by_name(name).await?, None);
+
+            let info = PinnedInfoBuilder::default()
+                .name(name)
+                .version("v0.1.0")
+                .build()?;
+            MAPPER.insert_pinned(&info).await?;
+            assert_eq!(
+                MAPPER
+                    .select_pinned_by_name(name)
+                    .await?
+                    .map(|i| i.version().to_owned()),
+                Some("v0.1.0".to_owned())
+            );
+
+            let info = PinnedInfoBuilder::default()
+                .name(name)
+                .version("v0.2.0")
+                .build()?;
+            MAPPER.insert_pinned(&info).await?;
+            assert_eq!(
+                MAPPER
+                    .select_pinned_by_name(name)
+                    .await?
+                    .map(|i| i.version().to_owned()),
+                Some("v0.2.0".to_owned())
+            );
+
+            assert_eq!(MAPPER.delete_pinned_by_name(name).await?, 1);
+            assert_eq!(MAPPER.select_pinned_by_name(name).await?, None);
+            Ok::<_, Error>(())
+        })
+    }
+
+    #[test]
+    fn test_disabled() -> Result<()> {
+        let mapper = new_mapper();
+        TOKIO_RT.block_on(async {
+            let name = "__disabled__";
+            assert!(!mapper.is_disabled(name).await?);
+            mapper.insert_disabled(name).await?;
+            mapper.insert_disabled(name).await?;
+            assert!(mapper.is_disabled(name).await?);
+
+            assert_eq!(mapper.delete_disabled_by_name(name).await?, 1);
+            assert!(!mapper.is_disabled(name).await?);
+            assert_eq!(mapper.delete_disabled_by_name(name).await?, 0);
+            Ok::<_, Error>(())
+        })
+    }
+
+    #[test]
+    fn test_asset_pattern() -> Result<()> {
+        let mapper = new_mapper();
+        TOKIO_RT.block_on(async {
+            let name = "__asset_pattern__";
+            assert_eq!(mapper.select_asset_pattern(name).await?, None);
+            mapper.insert_asset_pattern(name, "a-gnu").await?;
+            mapper.insert_asset_pattern(name, "a-musl").await?;
+            assert_eq!(
+                mapper.select_asset_pattern(name).await?.as_deref(),
+                Some("a-musl")
+            );
+
+            assert_eq!(mapper.delete_asset_pattern_by_name(name).await?, 1);
+            assert_eq!(mapper.select_asset_pattern(name).await?, None);
+            Ok::<_, Error>(())
+        })
+    }
+
+    #[test]
+    fn test_link_conflict() -> Result<()> {
+        let mapper = new_mapper();
+        TOKIO_RT.block_on(async {
+            let name = "__link_conflict__";
+            assert!(mapper.select_link_conflicts_by_name(name).await?.is_empty());
+            let conflict = LinkConflictBuilder::default()
+                .path("/bin/a")
+                .name(name)
+                .action("replace")
+                .build()?;
+            mapper.insert_link_conflict(&conflict).await?;
+            let conflict = LinkConflictBuilder::default()
+                .path("/bin/a")
+                .name(name)
+                .action("adopt")
+                .backup(Some("/bin/.a.adopted".to_owned()))
+                .build()?;
+            mapper.insert_link_conflict(&conflict).await?;
+            assert_eq!(
+                mapper.select_link_conflicts_by_name(name).await?,
+                vec![conflict]
+            );
+
+            assert_eq!(mapper.delete_link_conflicts_by_name(name).await?, 1);
+            assert!(mapper.select_link_conflicts_by_name(name).await?.is_empty());
+            Ok::<_, Error>(())
+        })
+    }
+
+    #[test]
+    fn test_external() -> Result<()> {
+        let mapper = new_mapper();
+        TOKIO_RT.block_on(async {
+            let name = "__external__";
+            assert_eq!(mapper.select_external_by_name(name).await?, None);
+            let external = ExternalBinaryBuilder::default()
+                .name(name)
+                .path("/usr/bin/a")
+                .version("1.0.0")
+                .provider("apt")
+                .build()?;
+            mapper.insert_external(&external).await?;
+            assert_eq!(mapper.select_external_by_name(name).await?, Some(external));
+
+            assert_eq!(mapper.delete_external_by_name(name).await?, 1);
+            assert_eq!(mapper.select_external_by_name(name).await?, None);
+            Ok::<_, Error>(())
+        })
+    }
+
+    #[test]
+    fn test_skipped() -> Result<()> {
+        let mapper = new_mapper();
+        TOKIO_RT.block_on(async {
+            let name = "__skipped__";
+            assert!(mapper.select_skipped_by_name(name).await?.is_empty());
+            mapper.insert_skipped(name, "1.0.0").await?;
+            mapper.insert_skipped(name, "1.0.0").await?;
+            mapper.insert_skipped(name, "1.1.0").await?;
+            assert_eq!(
+                mapper.select_skipped_by_name(name).await?,
+                vec!["1.0.0".to_owned(), "1.1.0".to_owned()]
+            );
+
+            assert_eq!(mapper.delete_skipped(name, "1.0.0").await?, 1);
+            assert_eq!(mapper.delete_skipped(name, "1.0.0").await?, 0);
+            assert_eq!(
+                mapper.select_skipped_by_name(name).await?,
+                vec!["1.1.0".to_owned()]
+            );
+            Ok::<_, Error>(())
+        })
+    }
+
+    #[test]
+    fn test_artifacts() -> Result<()> {
+        let mapper = new_mapper();
+        TOKIO_RT.block_on(async {
+            let name = "__artifacts__";
+            assert!(mapper.select_artifacts_by_name(name).await?.is_empty());
+            let paths = vec!["/bin/b".to_owned(), "/share/a".to_owned()];
+            mapper.insert_artifacts(name, &paths).await?;
+            mapper.insert_artifacts(name, &paths[..1]).await?;
+            assert_eq!(mapper.select_artifacts_by_name(name).await?, paths);
+
+            assert_eq!(mapper.delete_artifacts_by_name(name).await?, 2);
+            assert!(mapper.select_artifacts_by_name(name).await?.is_empty());
+            Ok::<_, Error>(())
+        })
+    }
+
+    #[test]
+    fn test_cache_access() -> Result<()> {
+        let mapper = new_mapper();
+        TOKIO_RT.block_on(async {
+            let paths = ["/__cache__/a.zip", "/__cache__/b.zip"];
+            for path in paths {
+                mapper.touch_cache(path, "__cache__").await?;
+            }
+            mapper.touch_cache(paths[0], "__cache__").await?;
+            let accessed = mapper
+                .select_cache_accesses()
+                .await?
+                .into_iter()
+                .map(|(path, _)| path)
+                .filter(|path| path.starts_with("/__cache__/"))
+                .collect::<Vec<_>>();
+            assert_eq!(accessed, [paths[1], paths[0]]);
+
+            for path in paths {
+                assert_eq!(mapper.delete_cache_access(path).await?, 1);
+            }
+            assert_eq!(mapper.delete_cache_access(paths[0]).await?, 0);
+            Ok::<_, Error>(())
+        })
+    }
+
+    #[test]
+    fn test_asset_digest() -> Result<()> {
+        let mapper = new_mapper();
+        TOKIO_RT.block_on(async {
+            let name = "__asset_digest__";
+            assert_eq!(mapper.select_asset_digest(name, "1.0").await?, None);
+            mapper.insert_asset_digest(name, "1.0", "sha256:a").await?;
+            mapper.insert_asset_digest(name, "1.0", "sha256:b").await?;
+            mapper.insert_asset_digest(name, "2.0", "sha256:c").await?;
+            assert_eq!(
+                mapper.select_asset_digest(name, "1.0").await?.as_deref(),
+                Some("sha256:b")
+            );
+            assert_eq!(
+                mapper.select_asset_digest(name, "2.0").await?.as_deref(),
+                Some("sha256:c")
+            );
+            Ok::<_, Error>(())
+        })
+    }
+
+    #[test]
+    fn test_trusted_config() -> Result<()> {
+        let mapper = new_mapper();
+        TOKIO_RT.block_on(async {
+            let path = "/__trusted__/config.toml";
+            assert_eq!(mapper.select_trusted_digest(path).await?, None);
+            mapper.insert_trusted_config(path, "a").await?;
+            mapper.insert_trusted_config(path, "b").await?;
+            assert_eq!(
+                mapper.select_trusted_digest(path).await?.as_deref(),
+                Some("b")
+            );
+            Ok::<_, Error>(())
+        })
+    }
+
+    #[test]
+    fn test_select_all_pinned() -> Result<()> {
+        let mapper = new_mapper();
+        TOKIO_RT.block_on(async {
+            assert!(mapper.select_all_pinned().await?.is_empty());
+            for name in ["a", "b"] {
+                let info = PinnedInfoBuilder::default()
+                    .name(name)
+                    .version("v0.1.0")
+                    .build()?;
+                mapper.insert_pinned(&info).await?;
+            }
+            let infos = mapper.select_all_pinned().await?;
+            assert_eq!(
+                infos.iter().map(|i| i.name().as_str()).collect::<Vec<_>>(),
+                ["a", "b"]
+            );
+            Ok::<_, Error>(())
+        })
+    }
+
+    #[test]
+    fn test_select_columns() -> Result<()> {
+        TOKIO_RT.block_on(async {
+            let cols = MAPPER.select_columns("pinned_info").await?;
+            assert_eq!(cols, ["name", "version", "create_time"]);
+            assert!(MAPPER.select_columns("__no_table__").await?.is_empty());
+            Ok::<_, Error>(())
+        })
+    }
+
+    #[test]
+    fn test_files() -> Result<()> {
+        let mapper = new_mapper();
+        TOKIO_RT.block_on(async {
+            let (name, ver) = ("__files__", "v0.1.0");
+            let file = |path: &str, digest: &str| {
+                InstalledFileBuilder::default()
+                    .name(name)
+                    .version(ver)
+                    .path(path)
+                    .digest(digest)
+                    .build()
+            };
+            mapper
+                .replace_files(name, ver, &[file("b", "1")?, file("a", "2")?])
+                .await?;
+            let files = mapper.select_files(name, ver).await?;
+            assert_eq!(
+                files.iter().map(|f| f.path().as_str()).collect::<Vec<_>>(),
+                ["a", "b"]
+            );
+
+            mapper.replace_files(name, ver, &[file("c", "3")?]).await?;
+            let files = mapper.select_files(name, ver).await?;
+            assert_eq!(files.len(), 1);
+            assert_eq!(files[0].digest(), "3");
+
+            assert_eq!(mapper.delete_files_by_name(name).await?, 1);
+            assert!(mapper.select_files(name, ver).await?.is_empty());
+            Ok::<_, Error>(())
+        })
+    }
+
+    #[test]
+    fn test_delete_by_id() -> Result<()> {
+        let mapper = new_mapper();
+        TOKIO_RT.block_on(async {
+            assert_eq!(mapper.delete_by_id(2).await?, 1);
+            assert_eq!(mapper.delete_by_id(2).await?, 0);
+            assert!(mapper.select_list_by_name("tldr").await?.is_empty());
+            Ok::<_, Error>(())
+        })
+    }
+
     #[test]
     fn feature() -> Result<()> {
         TOKIO_RT.block_on(async {
@@ -1,21 +1,30 @@
+use std::cmp::Ordering;
 use std::env::consts::OS;
 use std::fmt::Display;
+#[cfg(unix)]
 use std::os::unix::prelude::PermissionsExt;
 use std::path::PathBuf;
 use std::process::Stdio;
 use std::sync::Arc;
+use std::time::Duration;
 use std::{env::consts::ARCH, path::Path};
 
 use anyhow::bail;
 use anyhow::{anyhow, Result};
-use globset::GlobBuilder;
+use directories::BaseDirs;
+use globset::{Glob, GlobBuilder, GlobSetBuilder};
+use handlebars::{handlebars_helper, Handlebars};
+use indexmap::IndexMap;
 use log::{debug, error, info, log_enabled, trace};
+use once_cell::sync::Lazy;
 use parking_lot::Mutex;
 use serde::Serialize;
 use serde_json::json;
 use tokio::process::Command;
 use walkdir::WalkDir;
 
+use crate::checksum;
+
 /// get strings of [ARCH][std::env::consts::ARCH].
 ///
 /// [ref: zinit/zinit-install.zsh](https://github.com/zdharma-continuum/zinit/blob/c888917edbafa3772870ad1f320da7a5f169cc6f/zinit-install.zsh#L1453)
@@ -41,17 +50,31 @@ use walkdir::WalkDir;
 /// armv5l  "(arm5|armv5)"
 /// armv5l-2 "arm"
 /// ```
-pub fn get_archs() -> Vec<String> {
-    match ARCH {
-        "x86" => vec!["386", "686", "linux32"],
-        "x86_64" => vec!["x86_64", "amd64", "intel", "linux64"],
-        "aarch64" => vec!["arm64"],
-        s => panic!("unsupported arch: {}", s),
-    }
-    .into_iter()
-    .chain([ARCH])
-    .map(|s| s.trim().to_string())
-    .collect::<_>()
+///
+/// 配置中`archs`指定的别名会替换ARCH内置的别名，未知的ARCH只使用它自身
+pub fn get_archs(aliases: &IndexMap<String, Vec<String>>) -> Vec<String> {
+    let names = match aliases.get(ARCH) {
+        Some(names) => names.iter().map(|s| s.trim().to_owned()).collect(),
+        None => match ARCH {
+            "x86" => vec!["386", "686", "linux32"],
+            "x86_64" => vec!["x86_64", "amd64", "x64", "intel", "linux64"],
+            "aarch64" => vec!["arm64", "armv8"],
+            "arm" => vec!["armv7", "armhf"],
+            "riscv64" => vec!["riscv64gc"],
+            s => {
+                debug!("use only the arch {} without builtin aliases", s);
+                vec![]
+            }
+        }
+        .into_iter()
+        .map(str::to_owned)
+        .collect::<Vec<_>>(),
+    };
+    names
+        .into_iter()
+        .chain([ARCH.to_owned()])
+        .filter(|s| !s.is_empty())
+        .collect()
 }
 
 /// 尝试从base中找到一个符合glob_pat的可执行的bin文件path
@@ -80,30 +103,11 @@ pub fn find_one_bin_with_glob(base: impl AsRef<Path>, glob_pat: &str) -> Result<
         .collect::<Result<Vec<_>, _>>()?;
     match paths.len() {
         1 => {
-            use std::fs;
             let path = paths[0].path().to_owned();
             debug!("found a bin file {} in {}", path.display(), base.display());
 
-            const EXEC: u32 = 0o0111;
-            // set permission exec
-            fs::metadata(&path)
-                .map(|d| d.permissions())
-                .and_then(|mut perm| {
-                    let old = perm.mode();
-                    // if old & EXEC != 0 {
-                    //     return Ok(())
-                    // }
-                    let new = perm.mode() | EXEC;
-                    perm.set_mode(new);
-
-                    trace!(
-                        "set new mode {:#o} +x from old mode {:#o} for {}",
-                        new,
-                        old,
-                        path.display()
-                    );
-                    fs::set_permissions(&path, perm)
-                })?;
+            #[cfg(unix)]
+            set_executable(&path)?;
 
             Ok(path)
         }
@@ -134,24 +138,377 @@ pub fn find_one_bin_with_glob(base: impl AsRef<Path>, glob_pat: &str) -> Result<
     }
 }
 
+/// 从base中找到所有匹配任一glob的文件path
+///
+/// glob会匹配相对base的路径或文件名
+pub fn find_files_with_globs(base: impl AsRef<Path>, globs: &[String]) -> Result<Vec<PathBuf>> {
+    let base = base.as_ref();
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(Glob::new(glob)?);
+    }
+    let set = builder.build()?;
+
+    let paths = WalkDir::new(base)
+        .min_depth(1)
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.into_path())
+        .filter(|p| {
+            p.strip_prefix(base).is_ok_and(|p| set.is_match(p))
+                || p.file_name().is_some_and(|name| set.is_match(name))
+        })
+        .collect::<Vec<_>>();
+    trace!(
+        "found {} files with globs {:?} in {}",
+        paths.len(),
+        globs,
+        base.display()
+    );
+    Ok(paths)
+}
+
+/// 为path设置可执行权限`+x`
+#[cfg(unix)]
+pub fn set_executable(path: impl AsRef<Path>) -> Result<()> {
+    use std::fs;
+    let path = path.as_ref();
+
+    const EXEC: u32 = 0o0111;
+    // set permission exec
+    fs::metadata(path)
+        .map(|d| d.permissions())
+        .and_then(|mut perm| {
+            let old = perm.mode();
+            let new = perm.mode() | EXEC;
+            perm.set_mode(new);
+
+            trace!(
+                "set new mode {:#o} +x from old mode {:#o} for {}",
+                new,
+                old,
+                path.display()
+            );
+            fs::set_permissions(path, perm)
+        })
+        .map_err(Into::into)
+}
+
+/// 计算目录path中所有文件的大小，如果path不存在则为0
+pub fn dir_size(path: impl AsRef<Path>) -> u64 {
+    WalkDir::new(path)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.metadata().ok())
+        .filter(|d| d.is_file())
+        .map(|d| d.len())
+        .sum()
+}
+
+/// 展开开头的`~`与`${VAR}`形式的环境变量，`$$`转义为字面的`$`
+///
+/// 其它的`$`保持不变，如shell中的`$1`与`$(cmd)`
+///
+/// # Error
+///
+/// * 如果没有home dir或环境变量不存在
+pub fn expand_env(s: &str) -> Result<String> {
+    expand_env_with(s, |name| std::env::var(name).ok())
+}
+
+/// 使用lookup代替进程的环境变量的[expand_env]
+pub fn expand_env_with(s: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String> {
+    let mut expanded = String::new();
+    let mut rest = s;
+    if rest == "~" || rest.starts_with("~/") || rest.starts_with("~\\") {
+        let dirs = BaseDirs::new().ok_or_else(|| anyhow!("not found home dir"))?;
+        expanded.push_str(&dirs.home_dir().to_string_lossy());
+        rest = &rest[1..];
+    }
+    while let Some(i) = rest.find('$') {
+        expanded.push_str(&rest[..i]);
+        rest = &rest[i..];
+        if let Some(r) = rest.strip_prefix("$$") {
+            expanded.push('$');
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix("${") {
+            let end = r
+                .find('}')
+                .ok_or_else(|| anyhow!("unclosed `${{` in {}", s))?;
+            let name = &r[..end];
+            let val = lookup(name).ok_or_else(|| {
+                anyhow!("failed to expand env var `{}` in {}: not found", name, s)
+            })?;
+            expanded.push_str(&val);
+            rest = &r[end + 1..];
+        } else {
+            expanded.push('$');
+            rest = &rest[1..];
+        }
+    }
+    expanded.push_str(rest);
+    if expanded != s {
+        trace!("expanded {} to {}", s, expanded);
+    }
+    Ok(expanded)
+}
+
+/// 检查dir是否在PATH环境变量中，比较时忽略末尾的分隔符与symlink
+pub fn is_on_path(dir: impl AsRef<Path>) -> bool {
+    let dir = dir.as_ref();
+    let canonical = std::fs::canonicalize(dir).ok();
+    std::env::var_os("PATH").is_some_and(|paths| {
+        std::env::split_paths(&paths).any(|p| {
+            p.components().eq(dir.components())
+                || (canonical.is_some() && std::fs::canonicalize(&p).ok() == canonical)
+        })
+    })
+}
+
+/// 格式化字节数为人类可读的大小，如`1.5 MiB`
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut i = 0;
+    while size >= 1024.0 && i < UNITS.len() - 1 {
+        size /= 1024.0;
+        i += 1;
+    }
+    if i == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[i])
+    }
+}
+
+/// 解析`500M`, `1.5 GiB`这样的大小为字节数，没有单位时为字节
+pub fn parse_size(s: &str) -> Result<u64> {
+    let s = s.trim();
+    let pos = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (num, unit) = s.split_at(pos);
+    let num = num
+        .parse::<f64>()
+        .map_err(|e| anyhow!("invalid size `{}`: {}", s, e))?;
+    let exp = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 0,
+        "k" | "kb" | "kib" => 1,
+        "m" | "mb" | "mib" => 2,
+        "g" | "gb" | "gib" => 3,
+        "t" | "tb" | "tib" => 4,
+        unit => bail!("unknown unit `{}` of size `{}`", unit, s),
+    };
+    Ok((num * 1024f64.powi(exp)) as u64)
+}
+
+/// 解析`3d`, `12h`, `30m`, `1w`或`45s`这样的时长，没有单位时为秒
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    let s = s.trim();
+    let pos = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (num, unit) = s.split_at(pos);
+    let num = num
+        .parse::<u64>()
+        .map_err(|e| anyhow!("invalid duration `{}`: {}", s, e))?;
+    let secs = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "s" => 1,
+        "m" | "min" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        unit => bail!("unknown unit `{}` of duration `{}`", unit, s),
+    };
+    Ok(Duration::from_secs(num * secs))
+}
+
+/// 从`--version`这样的输出中解析第一个`1.2.3`或`1.2`格式的版本
+pub fn parse_version(out: &str) -> Option<String> {
+    static VERSION: Lazy<regex::Regex> =
+        Lazy::new(|| regex::Regex::new(r"\d+\.\d+(?:\.\d+)?").unwrap());
+    find_version(out, &VERSION)
+}
+
+/// 使用re在out中查找版本，有分组时使用第一个分组否则使用整个匹配
+pub fn find_version(out: &str, re: &regex::Regex) -> Option<String> {
+    let caps = re.captures(out)?;
+    caps.get(1)
+        .or_else(|| caps.get(0))
+        .map(|m| m.as_str().to_owned())
+}
+
+/// 忽略前缀`v`比较两个版本是否相同
+pub fn is_same_version(a: &str, b: &str) -> bool {
+    a.trim_start_matches('v') == b.trim_start_matches('v')
+}
+
+/// 按大小滚动的文件，写入将超过max_size时将`x.log`依次重命名为`x.log.1`..`x.log.{keep}`
+/// 后重新创建
+#[derive(Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    keep: usize,
+    file: std::fs::File,
+    size: u64,
+}
+
+impl RotatingFile {
+    /// 以追加的方式打开path，不存在的父目录将被创建
+    pub fn open(path: impl AsRef<Path>, max_size: u64, keep: usize) -> Result<Self> {
+        let path = path.as_ref().to_owned();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_size,
+            keep,
+            file,
+            size,
+        })
+    }
+
+    fn rotated_path(&self, i: usize) -> PathBuf {
+        let mut s = self.path.as_os_str().to_owned();
+        s.push(format!(".{}", i));
+        PathBuf::from(s)
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        for i in (1..=self.keep).rev() {
+            let from = if i == 1 {
+                self.path.clone()
+            } else {
+                self.rotated_path(i - 1)
+            };
+            match std::fs::rename(&from, self.rotated_path(i)) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        self.file = std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl std::io::Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let n = self.file.write(buf)?;
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// 计算path文件的md5 digest，为每个字节的十进制数拼接的格式
+///
+/// 兼容已写入缓存的`.md5`文件，解压标记与db中记录的digest
+pub fn md5_file(path: impl AsRef<Path>) -> Result<String> {
+    let hex = checksum::digest_file(path, "md5")?;
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| Ok(u8::from_str_radix(&hex[i..i + 2], 16)?.to_string()))
+        .collect()
+}
+
+/// 计算目录base中所有文件相对base的路径与md5 digest，按路径排序
+///
+/// 忽略文件名在excludes中的文件
+pub fn file_digests(base: impl AsRef<Path>, excludes: &[&str]) -> Result<Vec<(PathBuf, String)>> {
+    let base = base.as_ref();
+    let mut digests = WalkDir::new(base)
+        .min_depth(1)
+        .sort_by_file_name()
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .filter(|e| e.file_type().is_file())
+        .filter(|e| {
+            !e.file_name()
+                .to_str()
+                .is_some_and(|name| excludes.contains(&name))
+        })
+        .map(|e| {
+            let digest = md5_file(e.path())?;
+            Ok((e.path().strip_prefix(base)?.to_owned(), digest))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    digests.sort();
+    trace!("found {} file digests in {}", digests.len(), base.display());
+    Ok(digests)
+}
+
+/// 执行命令的选项
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CmdOptions {
+    /// the interpreter like `bash -c` to run the whole cmd as its last arg
+    pub shebang: Option<String>,
+    /// run as the user by [Self::escalation]
+    pub user: Option<String>,
+    /// the program to run as another user. fail to run as another user if none
+    pub escalation: Option<Escalation>,
+    pub env: Vec<(String, String)>,
+    /// kill the cmd after the timeout
+    pub timeout: Option<Duration>,
+    /// run the cmd by [shell_args] if no shebang
+    pub shell: bool,
+}
+
+/// 在work dir中执行cmd
+///
+/// # Error
+///
+/// * 如果cmd的退出状态不为0
 pub async fn run_cmd(cmd: &str, work_dir: impl AsRef<Path>) -> Result<()> {
+    run_cmd_with(cmd, work_dir, &CmdOptions::default()).await
+}
+
+/// 使用选项opts在work dir中执行cmd
+///
+/// # Error
+///
+/// * 如果cmd的退出状态不为0
+/// * 如果cmd超时，此时cmd被kill
+pub async fn run_cmd_with(cmd: &str, work_dir: impl AsRef<Path>, opts: &CmdOptions) -> Result<()> {
     info!(
         "running command `{}` in word dir {}",
         cmd,
         work_dir.as_ref().display()
     );
-    let args = shell_words::split(cmd)?;
-    if args.is_empty() {
-        bail!("empty args: {}", cmd);
-    }
+    let args = cmd_args(cmd, opts)?;
     trace!("running command args: {:?}", args);
     let child = Command::new(&args[0])
         .current_dir(work_dir)
         .args(&args[1..])
+        .envs(opts.env.iter().map(|(k, v)| (k, v)))
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
+        .kill_on_drop(true)
         .spawn()?;
-    let output = child.wait_with_output().await?;
+    let output = match opts.timeout {
+        Some(timeout) => tokio::time::timeout(timeout, child.wait_with_output())
+            .await
+            .map_err(|_| anyhow!("killed the command `{}` after {:?}", cmd, timeout))??,
+        None => child.wait_with_output().await?,
+    };
     trace!(
         "`{}` stdout: {}, stderr: {}",
         cmd,
@@ -159,21 +516,121 @@ pub async fn run_cmd(cmd: &str, work_dir: impl AsRef<Path>) -> Result<()> {
         std::str::from_utf8(&output.stderr)?,
     );
     if !output.status.success() {
-        error!(
+        bail!(
             "failed to run a command `{}` status {}: {}",
             cmd,
             output.status,
-            std::str::from_utf8(&output.stderr)?
+            String::from_utf8_lossy(&output.stderr).trim()
         );
     }
     Ok(())
 }
 
+/// 执行一个cmd字符串的shell：windows上为`cmd /C`，其它为`$SHELL -c`，未设置SHELL时为`sh -c`
+pub fn shell_args() -> Vec<String> {
+    if cfg!(windows) {
+        return vec!["cmd".to_owned(), "/C".to_owned()];
+    }
+    let shell = std::env::var("SHELL")
+        .ok()
+        .filter(|s| !s.trim().is_empty())
+        .unwrap_or_else(|| "sh".to_owned());
+    vec![shell, "-c".to_owned()]
+}
+
+/// 按shebang，shell与user组合出执行cmd的args
+fn cmd_args(cmd: &str, opts: &CmdOptions) -> Result<Vec<String>> {
+    let mut args = match &opts.shebang {
+        Some(shebang) => {
+            let mut args = shell_words::split(shebang)?;
+            args.push(cmd.to_owned());
+            args
+        }
+        None if opts.shell => shell_args().into_iter().chain([cmd.to_owned()]).collect(),
+        None => shell_words::split(cmd)?,
+    };
+    if args.is_empty() {
+        bail!("empty args: {}", cmd);
+    }
+    if let Some(user) = opts.user.as_deref().filter(|u| !is_current_user(u)) {
+        let escalation = opts.escalation.ok_or_else(|| {
+            anyhow!(
+                "the command `{}` requires the user {} but sudo is unavailable or disabled",
+                cmd,
+                user
+            )
+        })?;
+        let prefix = escalation.prefix(user);
+        info!("running `{}` as {} by {}", cmd, user, escalation.program());
+        args.splice(0..0, prefix);
+    }
+    Ok(args)
+}
+
+/// user是否为当前进程的effective user，user可以是名称或uid
+#[cfg(unix)]
+fn is_current_user(user: &str) -> bool {
+    // SAFETY: geteuid always succeeds
+    let euid = unsafe { libc::geteuid() };
+    if let Ok(uid) = user.parse::<libc::uid_t>() {
+        return uid == euid;
+    }
+    let name = match std::ffi::CString::new(user) {
+        Ok(name) => name,
+        Err(_) => return false,
+    };
+    // SAFETY: name is a valid c string and the returned entry is read before any other call
+    let pw = unsafe { libc::getpwnam(name.as_ptr()) };
+    !pw.is_null() && unsafe { (*pw).pw_uid } == euid
+}
+
+#[cfg(not(unix))]
+fn is_current_user(user: &str) -> bool {
+    std::env::var("USERNAME").ok().as_deref() == Some(user)
+}
+
+/// 以其它用户执行命令的程序
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Escalation {
+    Sudo,
+    Doas,
+}
+
+impl Escalation {
+    /// 在PATH中依次查找sudo与doas
+    pub fn find() -> Option<Self> {
+        [Self::Sudo, Self::Doas]
+            .into_iter()
+            .find(|e| which::which(e.program()).is_ok())
+    }
+
+    pub fn program(&self) -> &'static str {
+        match self {
+            Self::Sudo => "sudo",
+            Self::Doas => "doas",
+        }
+    }
+
+    /// 以user执行args的前缀
+    fn prefix(&self, user: &str) -> Vec<String> {
+        match self {
+            Self::Sudo => vec!["sudo", "-u", user, "--"],
+            Self::Doas => vec!["doas", "-u", user],
+        }
+        .into_iter()
+        .map(str::to_owned)
+        .collect()
+    }
+}
+
+/// 在val中加入当前平台的模板变量：`os`, `arch`, `libc`(同`target_env`)与`target`
 pub fn platform_values(mut val: serde_json::Value) -> Result<serde_json::Value> {
     let mut base = json!({
         "os": OS,
         "arch": ARCH,
         "target_env": get_target_env(),
+        "libc": get_target_env(),
+        "target": get_target(),
     });
     base.as_object_mut()
         .and_then(|o| val.as_object_mut().map(|v| o.append(v)))
@@ -181,24 +638,82 @@ pub fn platform_values(mut val: serde_json::Value) -> Result<serde_json::Value>
     Ok(base)
 }
 
+/// 运行时的c库或abi：linux上为gnu或musl，windows上为msvc，其它为空
+///
+/// 不使用编译时的`cfg(target_env)`，以免在alpine上运行gnu编译的程序时选择gnu的asset
 pub fn get_target_env() -> &'static str {
-    #[cfg(target_env = "gnu")]
-    {
-        "gnu"
-    }
-    #[cfg(target_env = "musl")]
-    {
-        "musl"
-    }
-    #[cfg(target_env = "msvc")]
-    {
-        "msvc"
+    static TARGET_ENV: Lazy<&'static str> = Lazy::new(|| match OS {
+        "linux" if is_musl() => "musl",
+        "linux" => "gnu",
+        "windows" => "msvc",
+        _ => "",
+    });
+    *TARGET_ENV
+}
+
+/// 通过musl的动态链接器判断系统是否使用musl
+fn is_musl() -> bool {
+    let found = ["/lib", "/usr/lib"].iter().any(|dir| {
+        std::fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .filter_map(|e| e.ok())
+                    .any(|e| e.file_name().to_string_lossy().starts_with("ld-musl-"))
+            })
+            .unwrap_or(false)
+    });
+    debug!("detected musl libc: {}", found);
+    found
+}
+
+/// 当前平台的rust target triple，如`x86_64-unknown-linux-gnu`
+pub fn get_target() -> String {
+    let arch = match ARCH {
+        "x86" => "i686",
+        s => s,
+    };
+    match OS {
+        "linux" => format!("{}-unknown-linux-{}", arch, get_target_env()),
+        "macos" => format!("{}-apple-darwin", arch),
+        "windows" => format!("{}-pc-windows-{}", arch, get_target_env()),
+        os => format!("{}-unknown-{}", arch, os),
     }
 }
 
-#[derive(Debug, Clone, Default)]
+/// 渲染模板，注册了以下helpers：
+///
+/// * `lower`,`upper`: `{{lower name}}`
+/// * `replace`: `{{replace name "-" "_"}}`
+/// * `trim_prefix`,`trim_suffix`: `{{trim_prefix version "v"}}`
+/// * `contains`: `{{#if (contains name "cli")}}..{{/if}}`
+/// * `semver`: 比较版本如`{{#if (semver version ">=1.2")}}..{{/if}}`，支持`= != > >= < <=`
+#[derive(Debug, Clone)]
 pub struct Templater {
-    h: Arc<Mutex<handlebars::Handlebars<'static>>>,
+    h: Arc<Mutex<Handlebars<'static>>>,
+}
+
+impl Default for Templater {
+    fn default() -> Self {
+        handlebars_helper!(lower: |s: str| s.to_lowercase());
+        handlebars_helper!(upper: |s: str| s.to_uppercase());
+        handlebars_helper!(replace: |s: str, from: str, to: str| s.replace(from, to));
+        handlebars_helper!(trim_prefix: |s: str, prefix: str| s.strip_prefix(prefix).unwrap_or(s));
+        handlebars_helper!(trim_suffix: |s: str, suffix: str| s.strip_suffix(suffix).unwrap_or(s));
+        handlebars_helper!(contains: |s: str, sub: str| s.contains(sub));
+        handlebars_helper!(semver: |ver: str, req: str| matches_version(ver, req));
+
+        let mut h = Handlebars::new();
+        h.register_helper("lower", Box::new(lower));
+        h.register_helper("upper", Box::new(upper));
+        h.register_helper("replace", Box::new(replace));
+        h.register_helper("trim_prefix", Box::new(trim_prefix));
+        h.register_helper("trim_suffix", Box::new(trim_suffix));
+        h.register_helper("contains", Box::new(contains));
+        h.register_helper("semver", Box::new(semver));
+        Self {
+            h: Arc::new(Mutex::new(h)),
+        }
+    }
 }
 
 impl Templater {
@@ -211,6 +726,57 @@ impl Templater {
     }
 }
 
+/// 比较两个版本如`v1.2.3-rc1`，缺少的部分为0，有pre release的版本更小
+pub fn cmp_version(a: &str, b: &str) -> Ordering {
+    fn parse(v: &str) -> (Vec<u64>, Option<&str>) {
+        let v = v.trim().trim_start_matches(['v', 'V']);
+        let (nums, pre) = match v.split_once('-') {
+            Some((nums, pre)) => (nums, Some(pre)),
+            None => (v, None),
+        };
+        let nums = nums
+            .split('.')
+            .map(|s| {
+                let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+                s[..digits].parse().unwrap_or_default()
+            })
+            .collect();
+        (nums, pre)
+    }
+
+    let ((a, a_pre), (b, b_pre)) = (parse(a), parse(b));
+    for i in 0..a.len().max(b.len()) {
+        let (x, y) = (a.get(i).unwrap_or(&0), b.get(i).unwrap_or(&0));
+        if x != y {
+            return x.cmp(y);
+        }
+    }
+    match (a_pre, b_pre) {
+        (None, None) => Ordering::Equal,
+        (None, Some(_)) => Ordering::Greater,
+        (Some(_), None) => Ordering::Less,
+        (Some(a), Some(b)) => a.cmp(b),
+    }
+}
+
+/// 版本ver是否满足req如`>=1.2`，没有比较符时为`=`
+fn matches_version(ver: &str, req: &str) -> bool {
+    let req = req.trim();
+    let (op, target) = ["!=", ">=", "<=", "==", ">", "<", "="]
+        .into_iter()
+        .find_map(|op| req.strip_prefix(op).map(|t| (op, t)))
+        .unwrap_or(("=", req));
+    let ord = cmp_version(ver, target);
+    match op {
+        "!=" => ord != Ordering::Equal,
+        ">=" => ord != Ordering::Less,
+        "<=" => ord != Ordering::Greater,
+        ">" => ord == Ordering::Greater,
+        "<" => ord == Ordering::Less,
+        _ => ord == Ordering::Equal,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -222,6 +788,219 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_find_files_with_globs() -> Result<()> {
+        let paths = find_files_with_globs("tests", &["bin_*".to_owned()])?;
+        assert_eq!(paths.len(), 2);
+        let paths = find_files_with_globs("tests", &["b/*".to_owned(), "*.zip".to_owned()])?;
+        assert_eq!(paths.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_md5_file() -> Result<()> {
+        assert_eq!(
+            md5_file("tests/a.tar.gz")?,
+            "1521001292049243791702469381191154415328"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_digests() -> Result<()> {
+        let digests = file_digests("tests/b", &[])?;
+        assert!(!digests.is_empty());
+        assert!(digests.iter().all(|(p, _)| p.is_relative()));
+
+        let (name, _) = &digests[0];
+        let name = name.file_name().and_then(|s| s.to_str()).unwrap();
+        assert!(file_digests("tests/b", &[name])?.len() < digests.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_on_path() -> Result<()> {
+        let paths = std::env::var_os("PATH").unwrap_or_default();
+        if let Some(dir) = std::env::split_paths(&paths).find(|p| p.is_dir()) {
+            assert!(is_on_path(&dir));
+            assert!(is_on_path(dir.join("")));
+        }
+        let dir = tempfile::tempdir()?;
+        assert!(!is_on_path(dir.path()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_expand_env() -> Result<()> {
+        let expand_env = |s| {
+            expand_env_with(s, |name| {
+                (name == "BINARIES_TEST_PREFIX").then(|| "/opt".to_owned())
+            })
+        };
+        assert_eq!(expand_env("${BINARIES_TEST_PREFIX}/tools")?, "/opt/tools");
+        assert_eq!(
+            expand_env("a ${BINARIES_TEST_PREFIX}${BINARIES_TEST_PREFIX}")?,
+            "a /opt/opt"
+        );
+        let home = BaseDirs::new().unwrap().home_dir().to_owned();
+        assert_eq!(expand_env("~/a")?, home.join("a").to_string_lossy());
+        assert_eq!(expand_env("~")?, home.to_string_lossy());
+        assert_eq!(expand_env("/a/~")?, "/a/~");
+
+        // literal
+        assert_eq!(expand_env("echo $1 $(pwd) $")?, "echo $1 $(pwd) $");
+        assert_eq!(
+            expand_env("$${BINARIES_TEST_PREFIX}")?,
+            "${BINARIES_TEST_PREFIX}"
+        );
+        assert_eq!(expand_env("$$$$")?, "$$");
+
+        assert!(expand_env("${BINARIES_TEST_NOT_EXISTS}/a").is_err());
+        assert!(expand_env("${BINARIES_TEST_PREFIX").is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_run_cmd_with() -> Result<()> {
+        // 其它测试会修改进程的PATH，命令只使用这里的PATH查找
+        let path = ("PATH".to_owned(), "/usr/bin:/bin".to_owned());
+        let dir = tempfile::tempdir()?;
+        let opts = CmdOptions {
+            shebang: Some("sh -c".to_owned()),
+            env: vec![
+                path.clone(),
+                ("BINARIES_TEST_CMD".to_owned(), "a".to_owned()),
+            ],
+            ..Default::default()
+        };
+        run_cmd_with("echo $BINARIES_TEST_CMD | tr a b > out", dir.path(), &opts).await?;
+        assert_eq!(std::fs::read_to_string(dir.path().join("out"))?, "b\n");
+
+        let opts = CmdOptions {
+            env: vec![path.clone()],
+            ..Default::default()
+        };
+        assert!(run_cmd_with("false", dir.path(), &opts).await.is_err());
+
+        let opts = CmdOptions {
+            timeout: Some(Duration::from_millis(100)),
+            env: vec![path.clone()],
+            ..Default::default()
+        };
+        let err = run_cmd_with("sleep 10", dir.path(), &opts)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("killed"), "{}", err);
+
+        // SAFETY: geteuid always succeeds
+        let euid = unsafe { libc::geteuid() };
+        assert!(is_current_user(&euid.to_string()));
+        assert!(!is_current_user("__binaries_no_such_user__"));
+
+        let mut opts = CmdOptions {
+            user: Some("nobody".to_owned()),
+            ..Default::default()
+        };
+        assert!(cmd_args("ls -a", &opts).is_err());
+        opts.escalation = Some(Escalation::Doas);
+        assert_eq!(
+            cmd_args("ls -a", &opts)?,
+            ["doas", "-u", "nobody", "ls", "-a"]
+        );
+
+        let opts = CmdOptions {
+            shell: true,
+            env: vec![path],
+            ..Default::default()
+        };
+        run_cmd_with("echo a | tr a b > piped", dir.path(), &opts).await?;
+        assert_eq!(std::fs::read_to_string(dir.path().join("piped"))?, "b\n");
+        assert_eq!(
+            cmd_args("ls -a", &opts)?.last().map(String::as_str),
+            Some("ls -a")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_human_size() {
+        assert_eq!(human_size(0), "0 B");
+        assert_eq!(human_size(1023), "1023 B");
+        assert_eq!(human_size(1536), "1.5 KiB");
+        assert_eq!(human_size(3 * 1024 * 1024), "3.0 MiB");
+    }
+
+    #[test]
+    fn test_parse_size() -> Result<()> {
+        assert_eq!(parse_size("100")?, 100);
+        assert_eq!(parse_size("2K")?, 2048);
+        assert_eq!(parse_size("1.5 GiB")?, 1536 * 1024 * 1024);
+        assert_eq!(parse_size("500mb")?, 500 * 1024 * 1024);
+        assert!(parse_size("1 PB").is_err());
+        assert!(parse_size("GiB").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_duration() -> Result<()> {
+        assert_eq!(parse_duration("30")?, Duration::from_secs(30));
+        assert_eq!(parse_duration("3d")?, Duration::from_secs(3 * 24 * 3600));
+        assert_eq!(parse_duration("12h")?, Duration::from_secs(12 * 3600));
+        assert_eq!(parse_duration("1w")?, Duration::from_secs(7 * 24 * 3600));
+        assert!(parse_duration("3y").is_err());
+        assert!(parse_duration("d").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(
+            parse_version("ripgrep 13.0.0 (rev af6b6c543b)").as_deref(),
+            Some("13.0.0")
+        );
+        assert_eq!(parse_version("jq-1.6").as_deref(), Some("1.6"));
+        assert_eq!(parse_version("unknown"), None);
+        let re = regex::Regex::new(r"build (\d+)").unwrap();
+        assert_eq!(find_version("foo 1.0 build 42", &re).as_deref(), Some("42"));
+        assert!(is_same_version("v1.6", "1.6"));
+        assert!(!is_same_version("1.6.1", "1.6"));
+    }
+
+    #[test]
+    fn test_rotating_file() -> Result<()> {
+        use std::io::Write;
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("logs/a.log");
+        let mut file = RotatingFile::open(&path, 10, 2)?;
+        for line in ["aaaaaa\n", "bbbbbb\n", "cccccc\n", "dddddd\n"] {
+            file.write_all(line.as_bytes())?;
+        }
+        let read = |p: &Path| std::fs::read_to_string(p);
+        assert_eq!(read(&path)?, "dddddd\n");
+        assert_eq!(read(&dir.path().join("logs/a.log.1"))?, "cccccc\n");
+        assert_eq!(read(&dir.path().join("logs/a.log.2"))?, "bbbbbb\n");
+        assert!(!dir.path().join("logs/a.log.3").exists());
+
+        // 重新打开时继续追加已有的大小
+        let mut file = RotatingFile::open(&path, 10, 2)?;
+        file.write_all(b"ee\n")?;
+        assert_eq!(read(&path)?, "dddddd\nee\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_archs() {
+        let archs = get_archs(&IndexMap::new());
+        assert_eq!(archs.last().map(String::as_str), Some(ARCH));
+
+        let aliases = [(ARCH.to_owned(), vec!["a".to_owned(), " ".to_owned()])]
+            .into_iter()
+            .collect();
+        assert_eq!(get_archs(&aliases), ["a", ARCH]);
+    }
+
     #[test]
     fn test_val() -> Result<()> {
         let val = platform_values(json!({
@@ -230,10 +1009,48 @@ mod tests {
         }))?;
         assert_eq!(val["name"], "a");
         assert_eq!(val["repo"], "b");
+        assert_eq!(val["libc"], val["target_env"]);
         #[cfg(target_os = "linux")]
         {
             assert_eq!(val["os"], "linux");
+            let target = val["target"].as_str().unwrap();
+            assert!(
+                target.ends_with("-unknown-linux-gnu") || target.ends_with("-unknown-linux-musl")
+            );
         }
         Ok(())
     }
+
+    #[test]
+    fn test_templater_helpers() -> Result<()> {
+        let t = Templater::default();
+        let data = json!({"name": "Foo-Cli", "version": "v1.10.2"});
+        let render = |s| t.render(s, &data);
+        assert_eq!(render("{{lower name}}")?, "foo-cli");
+        assert_eq!(render("{{replace (lower name) \"-\" \"_\"}}")?, "foo_cli");
+        assert_eq!(render("{{trim_prefix version \"v\"}}")?, "1.10.2");
+        assert_eq!(
+            render("{{#if (contains name \"Cli\")}}cli{{else}}lib{{/if}}")?,
+            "cli"
+        );
+        assert_eq!(
+            render("{{#if (semver version \">=1.9\")}}new{{else}}old{{/if}}")?,
+            "new"
+        );
+        assert_eq!(
+            render("{{#if (semver version \"<1.10.2\")}}old{{else}}new{{/if}}")?,
+            "new"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_cmp_version() {
+        assert_eq!(cmp_version("v1.2.3", "1.2.3"), Ordering::Equal);
+        assert_eq!(cmp_version("1.10", "1.9.9"), Ordering::Greater);
+        assert_eq!(cmp_version("1.2", "1.2.0"), Ordering::Equal);
+        assert_eq!(cmp_version("1.2.0-rc1", "1.2.0"), Ordering::Less);
+        assert!(matches_version("0.9.0", "!=1.0"));
+        assert!(matches_version("1.0.0", "1"));
+    }
 }
@@ -0,0 +1,536 @@
+//! [zsync](http://zsync.moria.org.uk/)控制文件的解析与增量下载
+//!
+//! 在旧版本的文件seed中查找与新文件相同的块，只需要通过range请求下载剩余的部分
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    io::{Read, Seek, SeekFrom, Write},
+    ops::Range,
+};
+
+use anyhow::{anyhow, bail, Result};
+use getset::{CopyGetters, Getters};
+use md4::Md4;
+use sha1::{Digest, Sha1};
+
+/// 目标文件的最大长度
+pub const MAX_LENGTH: u64 = 4 << 30;
+
+/// 最大的块大小，zsyncmake默认为2048
+const MAX_BLOCKSIZE: usize = 1 << 24;
+
+/// 每次从seed中读取的字节数
+const READ_CHUNK: usize = 1 << 20;
+
+/// `.zsync`控制文件，包括目标文件的长度，sha1与每个块的checksum
+#[derive(Debug, Getters, CopyGetters)]
+pub struct ControlFile {
+    #[getset(get_copy = "pub")]
+    blocksize: usize,
+
+    #[getset(get_copy = "pub")]
+    length: u64,
+
+    /// 连续匹配的块数。为2时每个块的checksum较短，需要与下一个块一起匹配
+    seq_matches: usize,
+
+    rsum_bytes: usize,
+
+    checksum_bytes: usize,
+
+    /// 目标文件的sha1 hex
+    #[getset(get = "pub")]
+    sha1: String,
+
+    blocks: Vec<BlockSum>,
+}
+
+#[derive(Debug)]
+struct BlockSum {
+    rsum: u32,
+    checksum: Vec<u8>,
+}
+
+impl ControlFile {
+    /// 解析以空行分隔的header与二进制的块checksums
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let end = data
+            .windows(2)
+            .position(|w| w == b"\n\n")
+            .ok_or_else(|| anyhow!("not found the end of the zsync header"))?;
+        let header = std::str::from_utf8(&data[..end])?;
+        let fields = header
+            .lines()
+            .map(|line| {
+                line.split_once(':')
+                    .map(|(k, v)| (k.trim().to_lowercase(), v.trim()))
+                    .ok_or_else(|| anyhow!("invalid zsync header line `{}`", line))
+            })
+            .collect::<Result<HashMap<_, _>>>()?;
+        let field = |k: &str| {
+            fields
+                .get(k)
+                .copied()
+                .ok_or_else(|| anyhow!("not found `{}` in the zsync header", k))
+        };
+
+        let blocksize = field("blocksize")?.parse::<usize>()?;
+        let length = field("length")?.parse::<u64>()?;
+        let lens = field("hash-lengths")?
+            .split(',')
+            .map(|s| s.trim().parse::<usize>())
+            .collect::<Result<Vec<_>, _>>()?;
+        let (seq_matches, rsum_bytes, checksum_bytes) = match lens[..] {
+            [seq, rsum, checksum]
+                if (1..=2).contains(&seq)
+                    && (1..=4).contains(&rsum)
+                    && (3..=16).contains(&checksum) =>
+            {
+                (seq, rsum, checksum)
+            }
+            _ => bail!("unsupported zsync hash lengths {:?}", lens),
+        };
+        if blocksize == 0 || blocksize > MAX_BLOCKSIZE {
+            bail!("invalid zsync blocksize {}", blocksize);
+        }
+        if length > MAX_LENGTH {
+            bail!("too large zsync length {} over {}", length, MAX_LENGTH);
+        }
+        let sha1 = field("sha-1")?.to_lowercase();
+
+        let count = length.div_ceil(blocksize as u64) as usize;
+        let entry = rsum_bytes + checksum_bytes;
+        let body = &data[end + 2..];
+        if body.len() < count * entry {
+            bail!(
+                "truncated zsync checksums: {} bytes for {} blocks",
+                body.len(),
+                count
+            );
+        }
+        let blocks = body
+            .chunks_exact(entry)
+            .take(count)
+            .map(|e| {
+                let mut rsum = [0; 4];
+                rsum[4 - rsum_bytes..].copy_from_slice(&e[..rsum_bytes]);
+                BlockSum {
+                    rsum: u32::from_be_bytes(rsum),
+                    checksum: e[rsum_bytes..].to_vec(),
+                }
+            })
+            .collect();
+        Ok(Self {
+            blocksize,
+            length,
+            seq_matches,
+            rsum_bytes,
+            checksum_bytes,
+            sha1,
+            blocks,
+        })
+    }
+
+    /// 在seed中查找目标文件的每个块，返回找到的块在seed中的offset
+    ///
+    /// seed被流式读取，内存中只保留当前位置附近的数据
+    pub fn find_blocks(&self, seed: impl Read) -> Result<Vec<Option<u64>>> {
+        let (bs, count) = (self.blocksize, self.blocks.len());
+        let mut found = vec![None; count];
+        let mask = match self.rsum_bytes {
+            4 => u32::MAX,
+            n => (1 << (8 * n)) - 1,
+        };
+        let mut index = HashMap::<_, Vec<_>>::new();
+        for (i, block) in self.blocks.iter().enumerate() {
+            index.entry(block.rsum).or_default().push(i);
+        }
+        // 与zsync一样，超出seed的部分按0填充
+        let mut seed = SeedBuf::new(seed);
+        // 滚动r1需要下一个块之后的字节
+        let ahead = 2 * bs as u64 + 1;
+
+        let mut remaining = count;
+        let mut x = 0;
+        seed.fill(x, ahead)?;
+        let (mut r0, mut r1) = (
+            Rsum::of(&seed.window(0, bs)),
+            Rsum::of(&seed.window(bs as u64, bs)),
+        );
+        while remaining > 0 {
+            seed.fill(x, x + ahead)?;
+            if !seed.contains(x) {
+                break;
+            }
+            let mut matched = false;
+            for &i in index.get(&(r0.value() & mask)).into_iter().flatten() {
+                // 最后的块没有下一个块可以连续匹配
+                let seq = self.seq_matches > 1 && i + 1 < count;
+                if found[i].is_some()
+                    || (seq && r1.value() & mask != self.blocks[i + 1].rsum)
+                    || self.checksum(&seed.window(x, bs)) != self.blocks[i].checksum
+                    || (seq
+                        && self.checksum(&seed.window(x + bs as u64, bs))
+                            != self.blocks[i + 1].checksum)
+                {
+                    continue;
+                }
+                found[i] = Some(x);
+                remaining -= 1;
+                if seq && found[i + 1].is_none() {
+                    found[i + 1] = Some(x + bs as u64);
+                    remaining -= 1;
+                }
+                matched = true;
+            }
+            if matched {
+                x += bs as u64;
+                seed.fill(x, x + ahead)?;
+                r0 = Rsum::of(&seed.window(x, bs));
+                r1 = Rsum::of(&seed.window(x + bs as u64, bs));
+            } else {
+                let bs64 = bs as u64;
+                r0.roll(seed.at(x), seed.at(x + bs64), bs);
+                r1.roll(seed.at(x + bs64), seed.at(x + 2 * bs64), bs);
+                x += 1;
+            }
+        }
+        Ok(found)
+    }
+
+    /// 没有在seed中找到的块合并后的字节范围
+    pub fn missing_ranges(&self, found: &[Option<u64>]) -> Vec<Range<u64>> {
+        let bs = self.blocksize as u64;
+        let mut ranges: Vec<Range<u64>> = vec![];
+        for (i, _) in found.iter().enumerate().filter(|(_, f)| f.is_none()) {
+            let start = i as u64 * bs;
+            let end = (start + bs).min(self.length);
+            match ranges.last_mut() {
+                Some(last) if last.end == start => last.end = end,
+                _ => ranges.push(start..end),
+            }
+        }
+        ranges
+    }
+
+    /// 将seed中找到的块写入目标文件out中对应的位置，超出seed的部分以0填充
+    pub fn copy_found(
+        &self,
+        mut seed: impl Read + Seek,
+        found: &[Option<u64>],
+        mut out: impl Write + Seek,
+    ) -> Result<()> {
+        let mut block = vec![0; self.blocksize];
+        for (i, offset) in found.iter().enumerate() {
+            if let Some(offset) = offset {
+                let start = i as u64 * self.blocksize as u64;
+                let len = (self.length - start).min(self.blocksize as u64) as usize;
+                let block = &mut block[..len];
+                block.fill(0);
+                seed.seek(SeekFrom::Start(*offset))?;
+                let mut n = 0;
+                while n < len {
+                    match seed.read(&mut block[n..])? {
+                        0 => break,
+                        m => n += m,
+                    }
+                }
+                out.seek(SeekFrom::Start(start))?;
+                out.write_all(block)?;
+            }
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    /// 检查组装后的目标文件的长度与sha1
+    pub fn verify(&self, mut file: impl Read) -> Result<()> {
+        let mut hasher = Sha1::new();
+        let len = std::io::copy(&mut file, &mut hasher)?;
+        if len != self.length {
+            bail!(
+                "inconsistent length {} of the assembled file. expected {}",
+                len,
+                self.length
+            );
+        }
+        let sha1 = format!("{:x}", hasher.finalize());
+        if sha1 != self.sha1 {
+            bail!(
+                "inconsistent sha1 {} of the assembled file. expected {}",
+                sha1,
+                self.sha1
+            );
+        }
+        Ok(())
+    }
+
+    fn checksum(&self, block: &[u8]) -> Vec<u8> {
+        Md4::digest(block)[..self.checksum_bytes].to_vec()
+    }
+}
+
+/// 流式读取的seed，缓存从base开始的数据
+struct SeedBuf<R> {
+    reader: R,
+    buf: Vec<u8>,
+    /// the offset of the first byte of buf in the seed
+    base: u64,
+    eof: bool,
+}
+
+impl<R: Read> SeedBuf<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buf: vec![],
+            base: 0,
+            eof: false,
+        }
+    }
+
+    /// 读取seed直到缓存了`[start, end)`或到达末尾。丢弃足够多的start之前的数据
+    fn fill(&mut self, start: u64, end: u64) -> Result<()> {
+        // 在末尾跳过一个块时start可能超出已读取的数据
+        let skip = ((start - self.base) as usize).min(self.buf.len());
+        if skip >= READ_CHUNK.max(self.buf.len() / 2) {
+            self.buf.drain(..skip);
+            self.base += skip as u64;
+        }
+        while !self.eof && self.base + (self.buf.len() as u64) < end {
+            let want = (end - self.base - self.buf.len() as u64).max(READ_CHUNK as u64);
+            if (&mut self.reader).take(want).read_to_end(&mut self.buf)? < want as usize {
+                self.eof = true;
+            }
+        }
+        Ok(())
+    }
+
+    fn contains(&self, i: u64) -> bool {
+        i >= self.base && i < self.base + self.buf.len() as u64
+    }
+
+    /// 位置i的字节，超出seed的部分为0。i需要已被[Self::fill]
+    fn at(&self, i: u64) -> u8 {
+        self.buf.get((i - self.base) as usize).copied().unwrap_or(0)
+    }
+
+    /// 从start开始长为len的数据，超出seed的部分以0填充
+    fn window(&self, start: u64, len: usize) -> Cow<'_, [u8]> {
+        window(&self.buf, (start - self.base) as usize, len)
+    }
+}
+
+/// data中从start开始长为len的数据，超出的部分以0填充
+fn window(data: &[u8], start: usize, len: usize) -> Cow<'_, [u8]> {
+    match data.get(start..start + len) {
+        Some(w) => Cow::Borrowed(w),
+        None => {
+            let mut w = data.get(start..).unwrap_or_default().to_vec();
+            w.resize(len, 0);
+            Cow::Owned(w)
+        }
+    }
+}
+
+/// zsync中rsync的滚动checksum
+#[derive(Debug, Clone, Copy)]
+struct Rsum {
+    a: u16,
+    b: u16,
+}
+
+impl Rsum {
+    fn of(block: &[u8]) -> Self {
+        let len = block.len();
+        block
+            .iter()
+            .enumerate()
+            .fold(Self { a: 0, b: 0 }, |r, (i, &c)| Self {
+                a: r.a.wrapping_add(c as u16),
+                b: r.b.wrapping_add(((len - i) as u16).wrapping_mul(c as u16)),
+            })
+    }
+
+    /// 移出块首的字节old并移入new
+    fn roll(&mut self, old: u8, new: u8, len: usize) {
+        self.a = self.a.wrapping_sub(old as u16).wrapping_add(new as u16);
+        self.b = self
+            .b
+            .wrapping_sub((len as u16).wrapping_mul(old as u16))
+            .wrapping_add(self.a);
+    }
+
+    fn value(&self) -> u32 {
+        (self.a as u32) << 16 | self.b as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// 按zsyncmake的格式生成target的控制文件
+    fn make(
+        target: &[u8],
+        bs: usize,
+        seq: usize,
+        rsum_bytes: usize,
+        checksum_bytes: usize,
+    ) -> Vec<u8> {
+        let mut data = format!(
+            "zsync: 0.6.2\nFilename: a.tar.gz\nBlocksize: {}\nLength: {}\nHash-Lengths: {},{},{}\nSHA-1: {:x}\n\n",
+            bs,
+            target.len(),
+            seq,
+            rsum_bytes,
+            checksum_bytes,
+            Sha1::digest(target)
+        )
+        .into_bytes();
+        for i in (0..target.len()).step_by(bs) {
+            let block = window(target, i, bs);
+            data.extend(&Rsum::of(&block).value().to_be_bytes()[4 - rsum_bytes..]);
+            data.extend(&Md4::digest(&block)[..checksum_bytes]);
+        }
+        data
+    }
+
+    /// 使用seed中找到的块与下载的范围fetched在内存中组装目标文件，并检查sha1
+    fn assemble(
+        control: &ControlFile,
+        seed: &[u8],
+        found: &[Option<u64>],
+        fetched: &[(u64, Vec<u8>)],
+    ) -> Result<Vec<u8>> {
+        let mut out = Cursor::new(vec![0; control.length() as usize]);
+        control.copy_found(Cursor::new(seed), found, &mut out)?;
+        for (start, data) in fetched {
+            out.seek(SeekFrom::Start(*start))?;
+            out.write_all(data)?;
+        }
+        let out = out.into_inner();
+        control.verify(&out[..])?;
+        Ok(out)
+    }
+
+    /// 按找到的块之外的范围从target中取数据
+    fn fetch(control: &ControlFile, found: &[Option<u64>], target: &[u8]) -> Vec<(u64, Vec<u8>)> {
+        control
+            .missing_ranges(found)
+            .iter()
+            .map(|r| (r.start, target[r.start as usize..r.end as usize].to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn test_rsum_roll() {
+        let data = (0..100u8).map(|i| i.wrapping_mul(37)).collect::<Vec<_>>();
+        let mut r = Rsum::of(&data[..16]);
+        for x in 0..50 {
+            r.roll(data[x], data[x + 16], 16);
+            assert_eq!(r.value(), Rsum::of(&data[x + 1..x + 17]).value());
+        }
+    }
+
+    #[test]
+    fn test_find_and_assemble() -> Result<()> {
+        let seed = (0..10_000u32)
+            .map(|i| (i * 7919 % 251) as u8)
+            .collect::<Vec<_>>();
+        // 在开头插入，中间修改并截断末尾
+        let mut target = b"new header".to_vec();
+        target.extend(&seed[..4000]);
+        target.extend(b"changed");
+        target.extend(&seed[4100..9500]);
+
+        for seq in [1, 2] {
+            let control = ControlFile::parse(&make(&target, 256, seq, 3, 8))?;
+            assert_eq!(control.length(), target.len() as u64);
+
+            let found = control.find_blocks(&seed[..])?;
+            let reused = found.iter().filter(|f| f.is_some()).count();
+            assert!(reused * 256 > 8000, "reused {} blocks", reused);
+
+            let fetched = fetch(&control, &found, &target);
+            assert_eq!(assemble(&control, &seed, &found, &fetched)?, target);
+
+            // 缺少下载的范围时sha1不一致
+            assert!(assemble(&control, &seed, &found, &[]).is_err());
+        }
+
+        let control = ControlFile::parse(&make(&target, 256, 1, 4, 16))?;
+        let found = control.find_blocks(&[][..])?;
+        assert_eq!(control.missing_ranges(&found), vec![0..target.len() as u64]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_blocks_over_chunks() -> Result<()> {
+        let seed = (0..3 * READ_CHUNK as u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect::<Vec<_>>();
+        // 修改跨过读取块边界的数据
+        let mut target = seed.clone();
+        target[READ_CHUNK - 100..READ_CHUNK + 100].fill(0);
+        target.splice(2 * READ_CHUNK..2 * READ_CHUNK, *b"inserted");
+
+        let control = ControlFile::parse(&make(&target, 2048, 2, 2, 3))?;
+        let found = control.find_blocks(&seed[..])?;
+        let missing = found.iter().filter(|f| f.is_none()).count();
+        assert!(missing <= 4, "missing {} blocks", missing);
+        let fetched = fetch(&control, &found, &target);
+        assert_eq!(assemble(&control, &seed, &found, &fetched)?, target);
+        Ok(())
+    }
+
+    /// 按zsyncmake 0.6.2的输出格式独立生成的fixture，默认的2048块大小与`2,2,3` hash lengths
+    #[test]
+    fn test_zsyncmake_fixture() -> Result<()> {
+        let seed = std::fs::read("tests/zsync/old.txt")?;
+        let target = std::fs::read("tests/zsync/new.txt")?;
+        let control = ControlFile::parse(&std::fs::read("tests/zsync/new.txt.zsync")?)?;
+        assert_eq!(control.blocksize(), 2048);
+        assert_eq!(control.length(), target.len() as u64);
+        assert_eq!(
+            (
+                control.seq_matches,
+                control.rsum_bytes,
+                control.checksum_bytes
+            ),
+            (2, 2, 3)
+        );
+        assert_eq!(control.sha1(), &format!("{:x}", Sha1::digest(&target)));
+
+        let found = control.find_blocks(&seed[..])?;
+        assert_eq!(found.iter().filter(|f| f.is_some()).count(), 2);
+        let fetched = fetch(&control, &found, &target);
+        assert_eq!(assemble(&control, &seed, &found, &fetched)?, target);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(ControlFile::parse(b"zsync: 0.6.2\n").is_err());
+        assert!(
+            ControlFile::parse(b"Blocksize: 2048\nLength: 10\nHash-Lengths: 1,4,16\n\n").is_err()
+        );
+        let data = b"Blocksize: 2048\nLength: 4097\nHash-Lengths: 1,4,16\nSHA-1: ab\n\n";
+        let mut data = data.to_vec();
+        data.extend([0; 40]);
+        assert!(ControlFile::parse(&data).is_err());
+        data.extend([0; 20]);
+        assert!(ControlFile::parse(&data).is_ok());
+
+        let data = format!(
+            "Blocksize: 2048\nLength: {}\nHash-Lengths: 1,4,16\nSHA-1: ab\n\n",
+            MAX_LENGTH + 1
+        );
+        assert!(ControlFile::parse(data.as_bytes()).is_err());
+        let data = b"Blocksize: 1099511627776\nLength: 1\nHash-Lengths: 1,4,16\nSHA-1: ab\n\n";
+        assert!(ControlFile::parse(data).is_err());
+    }
+}